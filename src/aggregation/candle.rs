@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::mem;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use redis::{self, Commands};
+use serde_json;
+
+use exchange::Exchange;
+use orderbook::Trade;
+
+/// OHLCV bar of a single symbol over one interval
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Candle {
+    /// Pair symbol (e.g. XBTUSD)
+    pub symbol: String,
+    /// Exchange the trades were executed on
+    pub exchange: Exchange,
+    /// Start of the interval as UNIX epoch time in seconds. Intervals are aligned to the epoch
+    pub interval_start_ts: f64,
+    /// Price of the first trade
+    pub open: f64,
+    /// Highest trade price
+    pub high: f64,
+    /// Lowest trade price
+    pub low: f64,
+    /// Price of the last trade
+    pub close: f64,
+    /// Sum of the trade sizes
+    pub volume: f64,
+    /// Set when the candle was flushed before its interval ended (i.e. at shutdown)
+    pub partial: bool,
+}
+
+impl Candle {
+    fn open(trade: &Trade, interval_start_ts: f64) -> Candle {
+        Candle {
+            symbol: trade.symbol.clone(),
+            exchange: trade.exchange,
+            interval_start_ts,
+            open: trade.price,
+            high: trade.price,
+            low: trade.price,
+            close: trade.price,
+            volume: trade.size,
+            partial: false,
+        }
+    }
+
+    fn update(&mut self, trade: &Trade) {
+        self.high = self.high.max(trade.price);
+        self.low = self.low.min(trade.price);
+        self.close = trade.price;
+        self.volume += trade.size;
+    }
+}
+
+/// Name of an interval as used in Redis channels (i.e. `30s`, `1m`, `5m`, `1h`, `1d`)
+pub fn interval_name(interval: Duration) -> String {
+    let secs = interval.as_secs();
+
+    if secs % 86400 == 0 {
+        format!("{}d", secs / 86400)
+    } else if secs % 3600 == 0 {
+        format!("{}h", secs / 3600)
+    } else if secs % 60 == 0 {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// Redis channel candles are published to (i.e. `bitmex:candle:1m:XBTUSD`)
+pub fn candle_channel(candle: &Candle, interval: Duration) -> String {
+    format!("{}:candle:{}:{}", candle.exchange, interval_name(interval), candle.symbol)
+}
+
+/// Groups trades into candles of every configured interval. A candle is closed by the first trade of
+/// its symbol that falls past the end of its interval, so intervals without trades produce no candle.
+pub struct CandleAggregator {
+    /// Widths of the candles we build. Must be whole seconds
+    intervals: Vec<Duration>,
+    /// Candle currently being built, for every exchange, symbol and interval
+    open: HashMap<(Exchange, String, Duration), Candle>,
+}
+
+impl CandleAggregator {
+    /// Creates an aggregator building candles of every interval (i.e. 1 minute, 5 minutes, and 1 hour)
+    pub fn new(intervals: Vec<Duration>) -> CandleAggregator {
+        assert!(intervals.iter().all(|interval| interval.as_secs() > 0), "Candle intervals must be at least a second");
+
+        CandleAggregator {
+            intervals,
+            open: HashMap::new(),
+        }
+    }
+
+    /// Adds a trade to the candles of its symbol. Returns the candles it closed.
+    pub fn add(&mut self, trade: &Trade) -> Vec<(Duration, Candle)> {
+        let mut closed = vec![];
+
+        for interval in &self.intervals {
+            let width = interval.as_secs() as f64;
+            let interval_start_ts = (trade.ts / width).floor() * width;
+            let key = (trade.exchange, trade.symbol.clone(), *interval);
+
+            match self.open.entry(key) {
+                Entry::Vacant(entry) => {
+                    entry.insert(Candle::open(trade, interval_start_ts));
+                },
+                Entry::Occupied(mut entry) => {
+                    let candle = entry.get_mut();
+
+                    if candle.interval_start_ts == interval_start_ts {
+                        candle.update(trade);
+                    } else if candle.interval_start_ts < interval_start_ts {
+                        closed.push((*interval, mem::replace(candle, Candle::open(trade, interval_start_ts))));
+                    }
+                    // Otherwise, the trade arrived late for a candle we've already closed and is dropped
+                },
+            }
+        }
+
+        closed
+    }
+
+    /// Closes every open candle, marking them as partial
+    pub fn flush(&mut self) -> Vec<(Duration, Candle)> {
+        self.open.drain()
+            .map(|((_, _, interval), mut candle)| {
+                candle.partial = true;
+                (interval, candle)
+            })
+            .collect()
+    }
+
+    /// Aggregates trades on a dedicated thread, publishing candles to Redis as they close. Once every
+    /// sender of `trades` is dropped, the open candles are published as partial and the thread exits.
+    pub fn run(mut self, trades: mpsc::Receiver<Trade>, r: redis::Connection) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            for trade in trades {
+                for (interval, candle) in self.add(&trade) {
+                    publish(&r, interval, &candle);
+                }
+            }
+
+            for (interval, candle) in self.flush() {
+                publish(&r, interval, &candle);
+            }
+        })
+    }
+}
+
+/// Publishes a candle. A failed publish is logged, and the aggregator carries on with the next candle.
+fn publish(r: &redis::Connection, interval: Duration, candle: &Candle) {
+    let channel = candle_channel(candle, interval);
+
+    if let Err(e) = r.publish::<&str, &str, u8>(&channel, &serde_json::to_string(candle).unwrap()) {
+        error!("Failed to publish candle to redis PUBSUB channel {}: {}", channel, e);
+    }
+}
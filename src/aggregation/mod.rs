@@ -0,0 +1,2 @@
+/// OHLCV candles built from the trade stream
+pub mod candle;
@@ -0,0 +1,176 @@
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use toml;
+
+use exchange::{Asset, AssetParseError};
+use storage::{StorageBackend, StorageError, TectonicBackend};
+
+/// Exchange settings loaded from a TOML file. Every section is optional, and anything left out
+/// keeps the value from the exchange's `default_settings`. Example:
+///
+/// ```toml
+/// [redis]
+/// url = "redis://127.0.0.1:6379/0"
+/// password = "hunter2"
+///
+/// [tectonic]
+/// host = "127.0.0.1"
+/// port = 9001
+///
+/// [channels]
+/// names = ["orderBookL2", "trade"]
+///
+/// [assets]
+/// pairs = [["BTC", "USD"], ["ETH", "USD"]]
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct Config {
+    /// Redis server deltas are published to
+    #[serde(default)]
+    pub redis: RedisConfig,
+    /// TectonicDB server deltas are warehoused in
+    #[serde(default)]
+    pub tectonic: TectonicConfig,
+    /// Websocket channels to subscribe to
+    #[serde(default)]
+    pub channels: ChannelConfig,
+    /// Asset pairs to collect
+    #[serde(default)]
+    pub assets: AssetConfig,
+}
+
+/// `[redis]` section
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct RedisConfig {
+    /// Redis connection URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub url: Option<String>,
+    /// Redis password. Replaces the password in the URL, if any
+    pub password: Option<String>,
+}
+
+/// `[tectonic]` section
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct TectonicConfig {
+    /// TectonicDB host. Defaults to `127.0.0.1`
+    pub host: Option<String>,
+    /// TectonicDB port. Defaults to `9001`
+    pub port: Option<u16>,
+}
+
+/// `[channels]` section
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct ChannelConfig {
+    /// Channel names, as the exchange calls them (i.e. `orderBookL2` on BitMEX)
+    pub names: Option<Vec<String>>,
+}
+
+/// `[assets]` section
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+pub struct AssetConfig {
+    /// Asset pairs as `[ASSET, MARKET]` tickers (i.e. `["BTC", "USD"]`)
+    pub pairs: Option<Vec<[String; 2]>>,
+}
+
+impl Config {
+    /// Reads and parses a TOML configuration file
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        Config::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parses a TOML configuration
+    pub fn parse(contents: &str) -> Result<Config, ConfigError> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    /// Asset pairs from the `[assets]` section, or `None` if there aren't any configured.
+    /// Fails on the first ticker that isn't a known [`Asset`].
+    pub fn asset_pairs(&self) -> Result<Option<Vec<[Asset; 2]>>, ConfigError> {
+        let pairs = match self.assets.pairs {
+            Some(ref pairs) => pairs,
+            None => return Ok(None),
+        };
+
+        pairs.iter()
+            .map(|pair| Ok([pair[0].parse::<Asset>()?, pair[1].parse::<Asset>()?]))
+            .collect::<Result<Vec<_>, ConfigError>>()
+            .map(Some)
+    }
+
+    /// TectonicDB storage for the `[tectonic]` section, or `None` if neither the host nor the port are configured
+    pub fn storage(&self, exchange: &str) -> Result<Option<Box<dyn StorageBackend>>, ConfigError> {
+        if self.tectonic.host.is_none() && self.tectonic.port.is_none() {
+            return Ok(None);
+        }
+
+        let backend = TectonicBackend::new(self.tectonic.host.clone(), self.tectonic.port, exchange)?;
+
+        Ok(Some(Box::new(backend)))
+    }
+}
+
+/// Errors encountered while loading a configuration file
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Failed to read the configuration file
+    Io(io::Error),
+    /// The file isn't valid TOML, or doesn't match the expected structure
+    Toml(toml::de::Error),
+    /// Asset ticker that doesn't match any [`Asset`]
+    UnknownAsset(String),
+    /// Failed to connect to the configured storage
+    Storage(StorageError),
+    /// The exchange's default settings couldn't be built
+    Settings(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "Failed to read configuration: {}", e),
+            ConfigError::Toml(e) => write!(f, "Invalid configuration: {}", e),
+            ConfigError::UnknownAsset(ticker) => write!(f, "Unknown asset \"{}\" in configuration", ticker),
+            ConfigError::Storage(e) => write!(f, "Failed to set up configured storage: {}", e),
+            ConfigError::Settings(e) => write!(f, "Failed to build default settings: {}", e),
+        }
+    }
+}
+
+impl error::Error for ConfigError {
+    fn description(&self) -> &str {
+        match self {
+            ConfigError::Io(_) => "Failed to read configuration",
+            ConfigError::Toml(_) => "Invalid configuration",
+            ConfigError::UnknownAsset(_) => "Unknown asset in configuration",
+            ConfigError::Storage(_) => "Failed to set up configured storage",
+            ConfigError::Settings(_) => "Failed to build default settings",
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> Self {
+        ConfigError::Toml(e)
+    }
+}
+
+impl From<AssetParseError> for ConfigError {
+    fn from(e: AssetParseError) -> Self {
+        ConfigError::UnknownAsset(e.0)
+    }
+}
+
+impl From<StorageError> for ConfigError {
+    fn from(e: StorageError) -> Self {
+        ConfigError::Storage(e)
+    }
+}
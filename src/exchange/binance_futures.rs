@@ -0,0 +1,607 @@
+use std::collections::HashMap;
+use std::thread;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use redis;
+use reqwest;
+use serde_json;
+use ws;
+use ws::util::Token;
+use ws::{Error, Handler, Handshake, Message, Sender};
+
+use config::{Config, ConfigError};
+use exchange::{self, Asset, AssetExchange, ConnectionHealth, AssetError, Exchange, ExchangeError, FuturesAsset, MarketType};
+use exchange::binance::SequenceCheck;
+use orderbook;
+use storage::{StorageBackend, TectonicBackend};
+
+const EXPIRE: Token = Token(1);
+
+/// Exchange related metadata. The fields are used to establish
+/// a successful connection with the exchange via websockets.
+#[derive(Clone)]
+pub struct WSExchange {
+    /// Full URL to connect to. Example: `wss://fstream.binance.com/stream`
+    pub host: String,
+    /// REST API base URL. Used to fetch depth snapshots. Example: `https://fapi.binance.com`
+    pub rest_host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    pub snapshot_received: bool,
+
+    /// Collection metadata
+    pub metadata: MetaData,
+    /// Type of market we're collecting. Deltas are published to the `<exchange>:<market type>` Redis channel
+    pub market_type: MarketType,
+
+    /// Streams we subscribe to for every contract (i.e. `depth@100ms`, `aggTrade`)
+    pub single_channels: Vec<String>,
+    /// Number of levels to request from the REST depth snapshot
+    pub snapshot_depth: u32,
+
+    /// Connection health, shared with the running websocket handler
+    pub health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    pub storage: Box<dyn StorageBackend>,
+
+    /// Redis URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub redis_url: String,
+    /// Redis client (before connection). Opened from `redis_url` when connecting
+    pub r: redis::Client,
+    /// Redis password: If this is present, it replaces the password in `redis_url` (if any) when connecting
+    pub r_password: Option<String>,
+}
+
+/// Create two identical structs and transfer the data over when we start the websocket.
+pub struct WSExchangeSender {
+    /// Full URL to connect to. Example: `wss://fstream.binance.com/stream`
+    host: String,
+    /// REST API base URL
+    rest_host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    snapshot_received: bool,
+
+    /// Collection metadata
+    metadata: MetaData,
+
+    /// Streams we subscribe to for every contract
+    single_channels: Vec<String>,
+    /// Number of levels to request from the REST depth snapshot
+    snapshot_depth: u32,
+    /// Sequence tracking for every contract's diff depth stream, keyed by symbol (i.e. `BTCUSDT`)
+    sequences: HashMap<String, FuturesDepthSequence>,
+
+    /// Connection health
+    health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    storage: Box<dyn StorageBackend>,
+    /// Redis client (used to send deltas as PUBSUB)
+    r: Arc<Mutex<redis::Connection>>,
+
+    /// Websocket sender
+    out: Sender,
+}
+
+/// Meta data for our data source. This is useful for data warehousing and accessing the data.
+/// All types contained within are considered optional. This may be expanded in the future.
+#[derive(Clone)]
+pub struct MetaData {
+    /// Exchange name. We will warehouse the data under this name
+    pub exchange: Arc<String>,
+
+    /// Vector of asset pairs we're going to warehouse. Every pair names a perpetual contract (i.e. `[BTC, USDT]`)
+    pub asset_pair: Option<Vec<[exchange::Asset; 2]>>,
+
+    /// Type of market we're warehousing. Set from `WSExchange::market_type` when connecting
+    pub market_type: Option<MarketType>,
+
+    /// Starting datetime of our data collection
+    start_date: Option<DateTime<Utc>>,
+
+    /// Ending datetime of our data collection
+    end_date: Option<DateTime<Utc>>,
+}
+
+impl WSExchange {
+    /// Health of the connection to the exchange
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+}
+
+impl AssetExchange for WSExchange {
+    fn default_settings() -> Result<Box<Self>, String> {
+        Ok(Box::new(Self {
+            host: "wss://fstream.binance.com/stream".into(),
+            rest_host: "https://fapi.binance.com".into(),
+
+            snapshot_received: false,
+
+            metadata: MetaData {
+                exchange: Arc::new("binance_futures".into()),
+                asset_pair: Some(vec![
+                    FuturesAsset::BTC.usdt_perpetual(),
+                    FuturesAsset::ETH.usdt_perpetual(),]),
+                market_type: None,
+                start_date: None,
+                end_date: None,
+            },
+            market_type: MarketType::LinearPerpetual,
+
+            single_channels: vec![
+                "depth@100ms".into(),
+                "aggTrade".into()],
+            snapshot_depth: 1000,
+
+            health: ConnectionHealth::new(Exchange::BinanceFutures),
+
+            storage: Box::new(TectonicBackend::new(None, None, "binance_futures").map_err(|e| format!("Unable to connect to TectonicDB: {}", e))?),
+            redis_url: exchange::DEFAULT_REDIS_URL.into(),
+            r: redis::Client::open(exchange::DEFAULT_REDIS_URL).unwrap(),
+            r_password: None,
+        }))
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        if let Some(pairs) = config.asset_pairs()? {
+            self.metadata.asset_pair = Some(pairs);
+        }
+        if let Some(ref channels) = config.channels.names {
+            self.single_channels = channels.clone();
+        }
+        if let Some(ref url) = config.redis.url {
+            self.redis_url = url.clone();
+        }
+        if config.redis.password.is_some() {
+            self.r_password = config.redis.password.clone();
+        }
+        if let Some(storage) = config.storage("binance_futures")? {
+            self.storage = storage;
+        }
+
+        Ok(())
+    }
+
+    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
+        // The password is sent as part of the URL, so the client authenticates on its own
+        self.r = exchange::redis_client(&self.redis_url, self.r_password.as_ref().map(|password| password.as_str()))?;
+
+        self.r.get_connection()
+    }
+
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    fn start(&self) -> Result<(), ExchangeError> {
+        WSExchange::run_with_settings(Some(self))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
+        // Try to use the settings the user passes before resorting to default settings.
+        let mut settings = match settings {
+            Some(settings) => settings.clone(),
+            None => *WSExchange::default_settings().map_err(ConfigError::Settings)?,
+        };
+
+        let r = Arc::new(Mutex::new(settings.init_redis()?));
+
+        ws::connect(settings.host.clone(), |out| WSExchangeSender {
+            host: settings.host.clone(),
+            rest_host: settings.rest_host.clone(),
+
+            snapshot_received: settings.snapshot_received.clone(),
+            metadata: MetaData { market_type: Some(settings.market_type), ..settings.metadata.clone() },
+
+            single_channels: settings.single_channels.clone(),
+            snapshot_depth: settings.snapshot_depth,
+            sequences: HashMap::new(),
+
+            health: settings.health.clone(),
+            storage: settings.storage.clone(),
+            r: r.clone(),
+
+            out,
+        })?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SubscribeMessage {
+    method: String,
+    params: Vec<String>,
+    id: u64,
+}
+
+/// Every message sent over the combined stream endpoint is wrapped in this envelope
+#[derive(Deserialize)]
+struct StreamMessage {
+    /// Stream name (i.e. `btcusdt@depth@100ms`)
+    stream: String,
+    data: serde_json::Value,
+}
+
+/// Diff depth stream event. Unlike spot, every event carries the final update ID of the event before it.
+#[derive(Deserialize)]
+struct DepthUpdate {
+    /// Event time in milliseconds
+    #[serde(rename = "E")]
+    event_time: u64,
+    /// Symbol (i.e. `BTCUSDT`)
+    #[serde(rename = "s")]
+    symbol: String,
+    /// First update ID in event
+    #[serde(rename = "U")]
+    first_update_id: u64,
+    /// Final update ID in event
+    #[serde(rename = "u")]
+    final_update_id: u64,
+    /// Final update ID of the previous event
+    #[serde(rename = "pu")]
+    previous_update_id: u64,
+    /// Bids as `[price, quantity]`
+    #[serde(rename = "b")]
+    bids: Vec<[String; 2]>,
+    /// Asks as `[price, quantity]`
+    #[serde(rename = "a")]
+    asks: Vec<[String; 2]>,
+}
+
+/// Aggregate trade stream event. Fills of the same taker order at the same price are combined
+#[derive(Deserialize)]
+struct AggTradeEvent {
+    /// Symbol (i.e. `BTCUSDT`)
+    #[serde(rename = "s")]
+    symbol: String,
+    /// Aggregate trade ID
+    #[serde(rename = "a")]
+    agg_trade_id: u64,
+    #[serde(rename = "p")]
+    price: String,
+    #[serde(rename = "q")]
+    quantity: String,
+    /// Trade time in milliseconds
+    #[serde(rename = "T")]
+    trade_time: u64,
+    /// Is the buyer the market maker?
+    #[serde(rename = "m")]
+    buyer_is_maker: bool,
+}
+
+/// REST depth snapshot (`GET /fapi/v1/depth`)
+#[derive(Deserialize)]
+struct DepthSnapshot {
+    #[serde(rename = "lastUpdateId")]
+    last_update_id: u64,
+    bids: Vec<[String; 2]>,
+    asks: Vec<[String; 2]>,
+}
+
+/// Tracks the update IDs of a single contract's diff depth stream. Binance futures require us to drop
+/// events where `u < lastUpdateId`, apply the first event where `U <= lastUpdateId <= u`, then expect
+/// the `pu` of every event after that to match the `u` of the previous one.
+#[derive(Clone, Debug)]
+pub struct FuturesDepthSequence {
+    /// Final update ID of the last applied event, or `lastUpdateId` of the snapshot
+    pub last_update_id: u64,
+    /// Set once we've applied the first event following the snapshot
+    pub synced: bool,
+}
+
+impl FuturesDepthSequence {
+    /// Starts tracking from the `lastUpdateId` of a REST depth snapshot
+    pub fn new(last_update_id: u64) -> Self {
+        FuturesDepthSequence {
+            last_update_id,
+            synced: false,
+        }
+    }
+
+    /// Checks an event's `U`, `u` and `pu` fields against the sequence, advancing it if the event applies
+    pub fn check(&mut self, first_update_id: u64, final_update_id: u64, previous_update_id: u64) -> SequenceCheck {
+        let in_sequence = if self.synced {
+            previous_update_id == self.last_update_id
+        } else {
+            if final_update_id < self.last_update_id {
+                return SequenceCheck::Drop;
+            }
+
+            first_update_id <= self.last_update_id
+        };
+
+        if !in_sequence {
+            return SequenceCheck::Gap;
+        }
+
+        self.last_update_id = final_update_id;
+        self.synced = true;
+
+        SequenceCheck::Apply
+    }
+}
+
+/// Converts `[price, quantity]` levels into deltas. A quantity of zero removes the level.
+fn levels_to_deltas(symbol: &str, levels: &[[String; 2]], side: u8, ts: f64, seq: &mut u32) -> Vec<orderbook::Delta> {
+    levels.iter()
+        .filter_map(|level| {
+            let price = level[0].parse::<f32>().ok()?;
+            let size = level[1].parse::<f32>().ok()?;
+            *seq += 1;
+
+            Some(orderbook::Delta {
+                symbol: symbol.to_string(),
+                price,
+                size,
+                seq: *seq,
+                event: side ^ if size == 0.0 {
+                    orderbook::REMOVE
+                } else {
+                    orderbook::UPDATE
+                },
+                ts,
+                received_ts: None,
+            })
+        })
+        .collect()
+}
+
+impl WSExchangeSender {
+    /// Publishes orderbook deltas to redis
+    fn publish(&self, deltas: &Vec<orderbook::Delta>) {
+        if deltas.is_empty() {
+            return;
+        }
+
+        exchange::record_symbol_messages(&self.health, deltas.iter().map(|delta| delta.symbol.as_str()));
+        // Lock the connection until we are able to aquire it
+        exchange::publish_deltas(&*self.r.lock().unwrap(), &exchange::market_channel(&self.metadata.exchange, self.metadata.market_type), deltas, &self.health);
+    }
+
+    /// Fetches a depth snapshot over REST and publishes its levels so that the book is seeded
+    /// before any diff events are applied.
+    fn seed_book(&mut self, symbol: &str) -> Option<FuturesDepthSequence> {
+        let url = format!("{}/fapi/v1/depth?symbol={}&limit={}", self.rest_host, symbol, self.snapshot_depth);
+
+        let snapshot: DepthSnapshot = match reqwest::get(&url).and_then(|mut response| response.json()) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                println!("Failed to fetch Binance futures depth snapshot for {}: {}", symbol, e);
+                return None;
+            }
+        };
+
+        let ts = Utc::now().timestamp_millis() as f64 * 0.001f64;
+        let mut seq = 0;
+        let mut deltas = levels_to_deltas(symbol, &snapshot.asks, orderbook::ASK, ts, &mut seq);
+        deltas.extend(levels_to_deltas(symbol, &snapshot.bids, orderbook::BID, ts, &mut seq));
+
+        self.publish(&deltas);
+        self.snapshot_received = true;
+
+        Some(FuturesDepthSequence::new(snapshot.last_update_id))
+    }
+
+    /// Applies a diff depth event, seeding the book from a REST snapshot first if necessary
+    fn on_depth(&mut self, update: DepthUpdate) {
+        if !self.sequences.contains_key(&update.symbol) {
+            match self.seed_book(&update.symbol) {
+                Some(sequence) => { self.sequences.insert(update.symbol.clone(), sequence); },
+                None => return,
+            }
+        }
+
+        let check = self.sequences.get_mut(&update.symbol)
+            .unwrap()
+            .check(update.first_update_id, update.final_update_id, update.previous_update_id);
+
+        match check {
+            SequenceCheck::Apply => (),
+            SequenceCheck::Drop => return,
+            SequenceCheck::Gap => {
+                // Forget the sequence so that the next event reseeds the book
+                println!("Binance futures depth stream for {} is out of sequence. Reseeding the book...", update.symbol);
+                self.sequences.remove(&update.symbol);
+                return;
+            }
+        }
+
+        let ts = update.event_time as f64 * 0.001f64;
+        let mut seq = 0;
+        let mut deltas = levels_to_deltas(&update.symbol, &update.asks, orderbook::ASK, ts, &mut seq);
+        deltas.extend(levels_to_deltas(&update.symbol, &update.bids, orderbook::BID, ts, &mut seq));
+
+        self.publish(&deltas);
+    }
+}
+
+impl Handler for WSExchangeSender {
+    fn on_open(&mut self, _: Handshake) -> Result<(), Error> {
+        self.health.track_connection(&self.out);
+
+        // Measure the round-trip time of the new connection
+        self.health.ping_latency(&self.out)?;
+
+        // Set a timeout for 5 seconds of inactivity
+        // Issue: currently, this reruns every five seconds. Comment out while we fix.
+        //self.out.timeout(5_000, EXPIRE).unwrap();
+
+        let pairs = self.metadata.asset_pair.clone().expect("No asset pairs passed to Binance futures structure");
+
+        for pair in &pairs {
+            let normalized_pair = match exchange::get_asset_pair(pair, Exchange::BinanceFutures) {
+                Ok(normalized_pair) => normalized_pair,
+                Err(_) => continue,
+            };
+            let db_name = format!("{}_{}", self.metadata.exchange.deref(), normalized_pair);
+
+            // Create the database if it doesn't exist yet. This avoids many issues
+            // relating to inserting to a non-existant database.
+            self.storage.create(&db_name)?;
+        }
+
+        let msg = SubscribeMessage {
+            method: "SUBSCRIBE".into(),
+            params: stream_names(&pairs, &self.single_channels),
+            id: 1,
+        };
+
+        println!("Sending message {}", serde_json::to_string(&msg).unwrap());
+        self.out.send(serde_json::to_string(&msg).unwrap())
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<(), Error> {
+        self.health.record_message();
+
+        let message = match serde_json::from_slice::<StreamMessage>(&msg.into_data()) {
+            Ok(message) => message,
+            // Subscription responses (`{"result": null, "id": 1}`) don't carry a stream
+            Err(_) => return Ok(()),
+        };
+
+        if message.stream.contains("@depth") {
+            // Depth events are applied on the socket thread, since the sequence checks
+            // depend on the order in which they arrive.
+            match serde_json::from_value::<DepthUpdate>(message.data) {
+                Ok(update) => self.on_depth(update),
+                Err(e) => println!("Error: {}", e),
+            }
+
+            return Ok(());
+        }
+
+        if !message.stream.ends_with("@aggTrade") {
+            return Ok(());
+        }
+
+        let redis_ref = self.r.clone();
+        let health = self.health.clone();
+        let channel = exchange::market_channel(&self.metadata.exchange, self.metadata.market_type);
+
+        thread::spawn(move || {
+            let trade = match serde_json::from_value::<AggTradeEvent>(message.data) {
+                Ok(trade) => trade,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return;
+                }
+            };
+
+            let trades = vec![orderbook::Trade {
+                symbol: trade.symbol,
+                price: trade.price.parse::<f64>().unwrap(),
+                size: trade.quantity.parse::<f64>().unwrap(),
+                // When the buyer is the maker, the taker (aggressor) sold into the bid
+                side: if trade.buyer_is_maker {
+                    orderbook::TradeSide::Sell
+                } else {
+                    orderbook::TradeSide::Buy
+                },
+                ts: trade.trade_time as f64 * 0.001f64,
+                exchange: Exchange::BinanceFutures,
+                trade_id: Some(trade.agg_trade_id.to_string()),
+                received_ts: None,
+            }];
+
+            exchange::record_symbol_messages(&health, trades.iter().map(|trade| trade.symbol.as_str()));
+            exchange::publish_trades(&*redis_ref.lock().unwrap(), &format!("{}:trades", channel), &trades, &health);
+        });
+
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> Result<Option<ws::Frame>, Error> {
+        self.health.on_frame(frame)
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut *self.storage);
+            return;
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Binance futures Socket is closing. Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            rest_host: self.rest_host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            single_channels: self.single_channels.clone(),
+            snapshot_depth: self.snapshot_depth,
+            sequences: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+    }
+
+    fn on_timeout(&mut self, _: Token) -> Result<(), ws::Error> {
+        if self.health.is_stopped() {
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Binance futures Socket timed out (5s of inactivity). Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            rest_host: self.rest_host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            single_channels: self.single_channels.clone(),
+            snapshot_depth: self.snapshot_depth,
+            sequences: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+
+        Ok(())
+    }
+}
+
+/// Builds the name of a Binance futures stream (i.e. `btcusdt@aggTrade`) for the given contract
+pub fn stream_name(pair: &[Asset; 2], stream: &str) -> Result<String, AssetError> {
+    let symbol = exchange::get_asset_pair(pair, Exchange::BinanceFutures)?;
+
+    Ok(format!("{}@{}", symbol.to_lowercase(), stream))
+}
+
+/// Builds every stream name for the combination of contracts and streams we want to subscribe to.
+/// Contracts that aren't listed on Binance futures are skipped.
+pub fn stream_names(pairs: &Vec<[Asset; 2]>, streams: &Vec<String>) -> Vec<String> {
+    let mut names = Vec::with_capacity(pairs.len() * streams.len());
+
+    for pair in pairs {
+        for stream in streams {
+            match stream_name(pair, stream) {
+                Ok(name) => names.push(name),
+                Err(e) => println!("Skipping Binance futures stream {}: {}", stream, e),
+            }
+        }
+    }
+
+    names
+}
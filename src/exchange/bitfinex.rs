@@ -0,0 +1,592 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::prelude::*;
+use redis;
+use serde_json::{self, Value};
+use ws;
+use ws::util::Token;
+use ws::{Error, Handler, Handshake, Message, Sender};
+
+use config::{Config, ConfigError};
+use exchange::{self, Asset, AssetExchange, ConnectionHealth, Exchange, ExchangeError, MarketType};
+use orderbook;
+use storage::{StorageBackend, TectonicBackend};
+
+const EXPIRE: Token = Token(1);
+/// Token for the periodic heartbeat staleness check
+const HEARTBEAT_CHECK: Token = Token(2);
+
+/// Bitfinex sends a heartbeat on every channel every 15 seconds. We consider a channel
+/// stale once it misses two of them.
+const STALE_AFTER_MS: u64 = 30_000;
+/// How often we check our channels for staleness
+const HEARTBEAT_CHECK_MS: u64 = 15_000;
+
+/// Exchange related metadata. The fields are used to establish
+/// a successful connection with the exchange via websockets.
+#[derive(Clone)]
+pub struct WSExchange {
+    /// Full URL to connect to. Example: `wss://api-public.bitfinex.com/ws/2`
+    pub host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    pub snapshot_received: bool,
+
+    /// Collection metadata
+    pub metadata: MetaData,
+    /// Type of market we're collecting. Deltas are published to the `<exchange>:<market type>` Redis channel
+    pub market_type: MarketType,
+
+    /// Subscribe to the raw (`R0`) order-by-order book instead of the price aggregated (`P0`) book
+    pub raw_book: bool,
+    /// Number of price points (or orders, for the raw book) to receive. Bitfinex accepts 1, 25, 100, and 250
+    pub book_length: u32,
+
+    /// Connection health, shared with the running websocket handler
+    pub health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    pub storage: Box<dyn StorageBackend>,
+
+    /// Redis URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub redis_url: String,
+    /// Redis client (before connection). Opened from `redis_url` when connecting
+    pub r: redis::Client,
+    /// Redis password: If this is present, it replaces the password in `redis_url` (if any) when connecting
+    pub r_password: Option<String>,
+}
+
+/// Create two identical structs and transfer the data over when we start the websocket.
+pub struct WSExchangeSender {
+    /// Full URL to connect to. Example: `wss://api-public.bitfinex.com/ws/2`
+    host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    snapshot_received: bool,
+
+    /// Collection metadata
+    metadata: MetaData,
+
+    /// Subscribe to the raw (`R0`) book instead of the price aggregated (`P0`) book
+    raw_book: bool,
+    /// Number of price points (or orders) to receive
+    book_length: u32,
+    /// Subscribed channels, keyed by the `chanId` Bitfinex assigned to them
+    channels: HashMap<u64, Channel>,
+    /// Orders of every raw book we're subscribed to, keyed by `chanId`
+    raw_books: HashMap<u64, RawBook>,
+
+    /// Connection health
+    health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    storage: Box<dyn StorageBackend>,
+    /// Redis client (used to send deltas as PUBSUB)
+    r: Arc<Mutex<redis::Connection>>,
+
+    /// Websocket sender
+    out: Sender,
+}
+
+/// Meta data for our data source. This is useful for data warehousing and accessing the data.
+/// All types contained within are considered optional. This may be expanded in the future.
+#[derive(Clone)]
+pub struct MetaData {
+    /// Exchange name. We will warehouse the data under this name
+    pub exchange: Arc<String>,
+
+    /// Vector of asset pairs we're going to warehouse
+    pub asset_pair: Option<Vec<[exchange::Asset; 2]>>,
+
+    /// Type of market we're warehousing. Set from `WSExchange::market_type` when connecting
+    pub market_type: Option<MarketType>,
+
+    /// Starting datetime of our data collection
+    start_date: Option<DateTime<Utc>>,
+
+    /// Ending datetime of our data collection
+    end_date: Option<DateTime<Utc>>,
+}
+
+impl WSExchange {
+    /// Health of the connection to the exchange
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+}
+
+impl AssetExchange for WSExchange {
+    fn default_settings() -> Result<Box<Self>, String> {
+        Ok(Box::new(Self {
+            host: "wss://api-public.bitfinex.com/ws/2".into(),
+
+            snapshot_received: false,
+
+            metadata: MetaData {
+                exchange: Arc::new("bitfinex".into()),
+                asset_pair: Some(vec![
+                    [Asset::BTC, Asset::USD],]),
+                market_type: None,
+                start_date: None,
+                end_date: None,
+            },
+            market_type: MarketType::Spot,
+
+            raw_book: false,
+            book_length: 25,
+
+            health: ConnectionHealth::new(Exchange::Bitfinex),
+
+            storage: Box::new(TectonicBackend::new(None, None, "bitfinex").map_err(|e| format!("Unable to connect to TectonicDB: {}", e))?),
+            redis_url: exchange::DEFAULT_REDIS_URL.into(),
+            r: redis::Client::open(exchange::DEFAULT_REDIS_URL).unwrap(),
+            r_password: None,
+        }))
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        if let Some(pairs) = config.asset_pairs()? {
+            self.metadata.asset_pair = Some(pairs);
+        }
+        if let Some(ref url) = config.redis.url {
+            self.redis_url = url.clone();
+        }
+        if config.redis.password.is_some() {
+            self.r_password = config.redis.password.clone();
+        }
+        if let Some(storage) = config.storage("bitfinex")? {
+            self.storage = storage;
+        }
+
+        Ok(())
+    }
+
+    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
+        // The password is sent as part of the URL, so the client authenticates on its own
+        self.r = exchange::redis_client(&self.redis_url, self.r_password.as_ref().map(|password| password.as_str()))?;
+
+        self.r.get_connection()
+    }
+
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    fn start(&self) -> Result<(), ExchangeError> {
+        WSExchange::run_with_settings(Some(self))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
+        // Try to use the settings the user passes before resorting to default settings.
+        let mut settings = match settings {
+            Some(settings) => settings.clone(),
+            None => *WSExchange::default_settings().map_err(ConfigError::Settings)?,
+        };
+
+        let r = Arc::new(Mutex::new(settings.init_redis()?));
+
+        ws::connect(settings.host.clone(), |out| WSExchangeSender {
+            host: settings.host.clone(),
+
+            snapshot_received: settings.snapshot_received.clone(),
+            metadata: MetaData { market_type: Some(settings.market_type), ..settings.metadata.clone() },
+
+            raw_book: settings.raw_book,
+            book_length: settings.book_length,
+            channels: HashMap::new(),
+            raw_books: HashMap::new(),
+
+            health: settings.health.clone(),
+            storage: settings.storage.clone(),
+            r: r.clone(),
+
+            out,
+        })?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SubscribeMessage {
+    event: String,
+    channel: String,
+    symbol: String,
+    /// Book precision. Only valid for the `book` channel
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prec: Option<String>,
+    /// Book length. Only valid for the `book` channel
+    #[serde(skip_serializing_if = "Option::is_none")]
+    len: Option<String>,
+}
+
+/// Subscription confirmation. This is the only message that tells us which
+/// symbol a `chanId` belongs to.
+#[derive(Deserialize)]
+struct SubscribedMessage {
+    channel: String,
+    #[serde(rename = "chanId")]
+    chan_id: u64,
+    /// Symbol with its type prefix (i.e. `tBTCUSD`)
+    symbol: String,
+}
+
+/// A channel we've subscribed to
+struct Channel {
+    /// Channel name (`book` or `trades`)
+    name: String,
+    /// Pair symbol without the type prefix (i.e. `BTCUSD`)
+    symbol: String,
+    /// Last time we received any message (including heartbeats) on this channel
+    last_message: Instant,
+}
+
+/// Orders of a raw (`R0`) book. Bitfinex only sends the order ID when an order is removed,
+/// so we keep track of every order's price to know which level it is removed from.
+#[derive(Default)]
+pub struct RawBook {
+    /// Price and amount of every order, keyed by order ID
+    orders: HashMap<u64, (f64, f64)>,
+    /// Total amount at every price level, keyed by the bits of the price
+    levels: HashMap<u64, f64>,
+}
+
+impl RawBook {
+    /// Applies an `[order ID, price, amount]` entry and returns the price and new total
+    /// amount of the level it touched. A price of zero removes the order.
+    pub fn apply(&mut self, order_id: u64, price: f64, amount: f64) -> Option<(f64, f64)> {
+        // Take the order out of its level first. This handles removals and amendments alike.
+        let previous = self.orders.remove(&order_id);
+
+        if let Some((previous_price, previous_amount)) = previous {
+            *self.levels.entry(previous_price.to_bits()).or_insert(0.0) -= previous_amount;
+        }
+
+        let level_price = if price == 0.0 {
+            previous?.0
+        } else {
+            self.orders.insert(order_id, (price, amount));
+            *self.levels.entry(price.to_bits()).or_insert(0.0) += amount;
+
+            price
+        };
+
+        let total = self.levels.get(&level_price.to_bits()).cloned().unwrap_or(0.0);
+        // Orders may cancel each other out to some floating point residue
+        if total.abs() < 1e-12 {
+            self.levels.remove(&level_price.to_bits());
+            return Some((level_price, 0.0));
+        }
+
+        Some((level_price, total))
+    }
+}
+
+/// Bitfinex prefixes trading pairs with `t` (and funding currencies with `f`)
+fn strip_symbol(symbol: &str) -> String {
+    symbol.trim_left_matches(|c| c == 't' || c == 'f').to_string()
+}
+
+/// Converts an amount to a delta. Bitfinex signs amounts to indicate the side of the book:
+/// positive amounts are bids, and negative amounts are asks.
+fn level_delta(symbol: &str, price: f64, amount: f64, removed: bool, seq: u32, ts: f64) -> orderbook::Delta {
+    orderbook::Delta {
+        symbol: symbol.into(),
+        price: price as f32,
+        size: if removed { 0.0 } else { amount.abs() as f32 },
+        seq,
+        event: if amount > 0.0 {
+            orderbook::BID
+        } else {
+            orderbook::ASK
+        } ^ if removed {
+            orderbook::REMOVE
+        } else {
+            orderbook::UPDATE
+        },
+        ts,
+        received_ts: None,
+    }
+}
+
+impl WSExchangeSender {
+    /// Converts a single book entry to a delta. Aggregated book entries are `[price, count, amount]`,
+    /// whereas raw book entries are `[order ID, price, amount]`.
+    fn book_entry(&mut self, chan_id: u64, symbol: &str, entry: &Value, seq: u32, ts: f64) -> Option<orderbook::Delta> {
+        let entry = entry.as_array()?;
+        let amount = entry.get(2)?.as_f64()?;
+
+        if self.raw_book {
+            let order_id = entry.get(0)?.as_u64()?;
+            let price = entry.get(1)?.as_f64()?;
+            let (level_price, total) = self.raw_books.entry(chan_id)
+                .or_insert_with(RawBook::default)
+                .apply(order_id, price, amount)?;
+
+            // The side of a removed order is still given by the sign of its amount
+            return Some(level_delta(symbol, level_price, if total == 0.0 { amount } else { total }, total == 0.0, seq, ts));
+        }
+
+        let price = entry.get(0)?.as_f64()?;
+        let count = entry.get(1)?.as_u64()?;
+
+        // A count of zero removes the level. The amount is then 1 for bids and -1 for asks.
+        Some(level_delta(symbol, price, amount, count == 0, seq, ts))
+    }
+
+    /// Closes the connection if any channel stopped receiving heartbeats. Our `on_close`
+    /// handler will then open a new connection and resubscribe.
+    fn check_heartbeats(&mut self) -> Result<(), Error> {
+        let stale_after = Duration::from_millis(STALE_AFTER_MS);
+        let stale: Vec<&Channel> = self.channels.values()
+            .filter(|channel| channel.last_message.elapsed() > stale_after)
+            .collect();
+
+        if stale.is_empty() {
+            return self.out.timeout(HEARTBEAT_CHECK_MS, HEARTBEAT_CHECK);
+        }
+
+        for channel in stale {
+            println!("Bitfinex {} channel for {} is stale (no heartbeat in {}ms)", channel.name, channel.symbol, STALE_AFTER_MS);
+        }
+
+        self.out.close(ws::CloseCode::Away)
+    }
+}
+
+impl Handler for WSExchangeSender {
+    fn on_open(&mut self, _: Handshake) -> Result<(), Error> {
+        self.health.track_connection(&self.out);
+
+        // Measure the round-trip time of the new connection
+        self.health.ping_latency(&self.out)?;
+
+        // Set a timeout for 5 seconds of inactivity
+        // Issue: currently, this reruns every five seconds. Comment out while we fix.
+        //self.out.timeout(5_000, EXPIRE).unwrap();
+        self.out.timeout(HEARTBEAT_CHECK_MS, HEARTBEAT_CHECK)?;
+
+        for pair in self.metadata.asset_pair.as_ref().expect("No asset pairs passed to Bitfinex structure") {
+            let normalized_pair = match exchange::get_asset_pair(pair, Exchange::Bitfinex) {
+                Ok(normalized_pair) => normalized_pair,
+                Err(e) => {
+                    println!("Skipping Bitfinex subscription: {}", e);
+                    continue;
+                }
+            };
+            let db_name = format!("{}_{}", self.metadata.exchange.deref(), normalized_pair);
+
+            // Create the database if it doesn't exist yet. This avoids many issues
+            // relating to inserting to a non-existant database.
+            self.storage.create(&db_name)?;
+
+            let book = SubscribeMessage {
+                event: "subscribe".into(),
+                channel: "book".into(),
+                symbol: format!("t{}", normalized_pair),
+                prec: Some(if self.raw_book { "R0" } else { "P0" }.into()),
+                len: Some(self.book_length.to_string()),
+            };
+            let trades = SubscribeMessage {
+                event: "subscribe".into(),
+                channel: "trades".into(),
+                symbol: format!("t{}", normalized_pair),
+                prec: None,
+                len: None,
+            };
+
+            for msg in &[book, trades] {
+                println!("Sending message {}", serde_json::to_string(msg).unwrap());
+                self.out.send(serde_json::to_string(msg).unwrap())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<(), Error> {
+        self.health.record_message();
+
+        // Like Poloniex, we process Bitfinex messages in order on the socket thread. Data messages
+        // only carry a `chanId`, which we can only resolve once the subscription confirmation
+        // preceding them has been processed.
+        let ts = Utc::now().timestamp_millis() as f64 * 0.001f64;
+
+        let message = match serde_json::from_slice::<Value>(&msg.into_data()) {
+            Ok(Value::Array(message)) => message,
+            Ok(event) => {
+                if event["event"] == "subscribed" {
+                    if let Ok(subscribed) = serde_json::from_value::<SubscribedMessage>(event) {
+                        self.channels.insert(subscribed.chan_id, Channel {
+                            name: subscribed.channel,
+                            symbol: strip_symbol(&subscribed.symbol),
+                            last_message: Instant::now(),
+                        });
+                    }
+                } else if event["event"] == "error" {
+                    println!("Bitfinex error: {}", event["msg"]);
+                }
+
+                return Ok(());
+            },
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(())
+            }
+        };
+
+        // Data messages come in the form of `[chanId, data]` or `[chanId, type, data]`
+        let chan_id = match message.get(0).and_then(|id| id.as_u64()) {
+            Some(chan_id) => chan_id,
+            None => return Ok(()),
+        };
+        let (name, symbol) = match self.channels.get_mut(&chan_id) {
+            Some(channel) => {
+                channel.last_message = Instant::now();
+                (channel.name.clone(), channel.symbol.clone())
+            },
+            None => return Ok(()),
+        };
+
+        // Heartbeats only serve to keep the channel from going stale
+        if message.get(1).map(|kind| kind == "hb").unwrap_or(false) {
+            return Ok(());
+        }
+
+        if name == "book" {
+            let data = match message.get(1).and_then(|data| data.as_array()) {
+                Some(data) => data,
+                None => return Ok(()),
+            };
+
+            // Snapshots are a list of entries, whereas updates are a single entry
+            let entries: Vec<&Value> = if data.get(0).map(|entry| entry.is_array()).unwrap_or(false) {
+                self.snapshot_received = true;
+                data.iter().collect()
+            } else {
+                vec![&message[1]]
+            };
+
+            let mut deltas: Vec<orderbook::Delta> = Vec::with_capacity(entries.len());
+
+            // Begin sequence counting at 1 in order to reconstruct a proper sequence count
+            for (seq, entry) in entries.iter().enumerate() {
+                if let Some(delta) = self.book_entry(chan_id, &symbol, entry, seq as u32 + 1, ts) {
+                    deltas.push(delta);
+                }
+            }
+
+            // Lock the connection until we are able to aquire it
+            if !deltas.is_empty() {
+                exchange::record_symbol_messages(&self.health, deltas.iter().map(|delta| delta.symbol.as_str()));
+                exchange::publish_deltas(&*self.r.lock().unwrap(), &exchange::market_channel(&self.metadata.exchange, self.metadata.market_type), &deltas, &self.health);
+            }
+        } else if name == "trades" {
+            // Only executed trades are published. `tu` repeats the same trade along with its ID
+            // once it has been stored, and the initial snapshot contains past trades.
+            if message.get(1).map(|kind| kind != "te").unwrap_or(true) {
+                return Ok(());
+            }
+
+            // Trades are sent as `[ID, MTS, AMOUNT, PRICE]`
+            let trade = &message[2];
+            let (price, amount) = match (trade[3].as_f64(), trade[2].as_f64()) {
+                (Some(price), Some(amount)) => (price, amount),
+                _ => return Ok(()),
+            };
+
+            let trades = vec![orderbook::Trade {
+                symbol,
+                price,
+                size: amount.abs(),
+                side: if amount > 0.0 {
+                    orderbook::TradeSide::Buy
+                } else {
+                    orderbook::TradeSide::Sell
+                },
+                ts: trade[1].as_f64().map(|ms| ms * 0.001f64).unwrap_or(ts),
+                exchange: Exchange::Bitfinex,
+                trade_id: trade[0].as_u64().map(|id| id.to_string()),
+                received_ts: None,
+            }];
+
+            exchange::record_symbol_messages(&self.health, trades.iter().map(|trade| trade.symbol.as_str()));
+            exchange::publish_trades(&*self.r.lock().unwrap(), &format!("{}:trades", exchange::market_channel(&self.metadata.exchange, self.metadata.market_type)), &trades, &self.health);
+        }
+
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> Result<Option<ws::Frame>, Error> {
+        self.health.on_frame(frame)
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut *self.storage);
+            return;
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Bitfinex Socket is closing. Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            raw_book: self.raw_book,
+            book_length: self.book_length,
+            // Channel IDs are assigned again when we resubscribe
+            channels: HashMap::new(),
+            raw_books: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+    }
+
+    fn on_timeout(&mut self, event: Token) -> Result<(), ws::Error> {
+        if self.health.is_stopped() {
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
+        if event == HEARTBEAT_CHECK {
+            return self.check_heartbeats();
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Bitfinex Socket timed out (5s of inactivity). Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            raw_book: self.raw_book,
+            book_length: self.book_length,
+            channels: HashMap::new(),
+            raw_books: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+
+        Ok(())
+    }
+}
@@ -1,69 +1,214 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::thread;
-use std::ops::Deref;
 use std::sync::{Arc, Mutex, mpsc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 use chrono::prelude::*;
+use hex;
+use hmac::{Hmac, Mac};
 use redis::{self, Commands};
 use reqwest;
+use serde::de::DeserializeOwned;
 use serde_json;
+use sha2::Sha256;
+use url::Url;
 use ws;
 use ws::util::Token;
 use ws::{Error, Handler, Handshake, Message, Sender};
 
-use exchange::{self, Asset, AssetExchange, Exchange};
-use orderbook;
+use config::{Config, ConfigError};
+use exchange::{self, Asset, AssetExchange, ConnectionHealth, DeduplicationWindow, DeltaFilter, Exchange, ExchangeError, MarketType, RateLimiter, ReconnectPolicy};
+use exchange::{publish, publish_deltas, publish_trades, record_symbol_messages, redis_channel_name};
+use orderbook::{self, DeltaEvent};
+use storage::{StorageBackend, TectonicBackend};
+
+/// Function called with every delta a collector publishes (see [`WSExchange::callback`])
+pub type DeltaCallback = Arc<dyn Fn(&orderbook::Delta) + Send + Sync>;
+
+/// Live book of every symbol, keyed by symbol (see [`WSExchange::books`])
+pub type Books = Arc<RwLock<HashMap<String, orderbook::Level2Orderbook>>>;
+
+const KEEPALIVE: Token = Token(1);
+const REFRESH_INSTRUMENTS: Token = Token(3);
+
+/// Tables that are only sent to authenticated connections (see [`WSExchange::api_key`]). Subscribing
+/// to them without credentials is refused by BitMEX.
+pub const PRIVATE_TABLES: &[&str] = &[
+    "affiliate", "execution", "order", "margin", "position", "privateNotifications", "transact", "wallet",
+];
+
+/// Seconds our authentication message stays valid for
+const AUTH_EXPIRY_SECS: i64 = 60;
+
+/// BitMEX environment to collect from. The testnet mirrors production on its own hosts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Environment {
+    /// `www.bitmex.com`
+    Production,
+    /// `testnet.bitmex.com`
+    Testnet,
+}
+
+impl Environment {
+    /// Websocket endpoint of the environment
+    pub fn host(&self) -> String {
+        match *self {
+            Environment::Production => "wss://www.bitmex.com/realtime".into(),
+            Environment::Testnet => "wss://testnet.bitmex.com/realtime".into(),
+        }
+    }
+
+    /// Base URL of the environment's REST API
+    pub fn rest_host(&self) -> String {
+        match *self {
+            Environment::Production => "https://www.bitmex.com/api/v1".into(),
+            Environment::Testnet => "https://testnet.bitmex.com/api/v1".into(),
+        }
+    }
 
-const EXPIRE: Token = Token(1);
+    /// REST endpoint listing the symbol, listing date and tick size of every instrument, one page of
+    /// [`INSTRUMENT_PAGE_SIZE`] instruments at a time starting from the `start`th
+    pub fn instrument_url(&self, start: usize) -> String {
+        format!("{}/instrument?columns=symbol,listing,tickSize&start={}&count={}", self.rest_host(), start, INSTRUMENT_PAGE_SIZE)
+    }
+
+    /// Appends `_testnet` to `name` on the testnet, so that its data is kept apart from production's.
+    /// Used for the storage's database names and the Redis channel.
+    pub fn suffix(&self, name: &str) -> String {
+        match *self {
+            Environment::Production => name.to_string(),
+            Environment::Testnet => format!("{}_testnet", name),
+        }
+    }
+}
 
 /// Exchange related metadata. The fields are used to establish
 /// a successful connection with the exchange via websockets.
 #[derive(Clone)]
 pub struct WSExchange {
-    /// Full URL to connect to. Example: `wss://www.bitmex.com/realtime`
-    pub host: String,
+    /// Environment we connect to. Selects the websocket and REST endpoints, and suffixes the
+    /// storage's database names and the Redis channel with `_testnet` on the testnet.
+    pub environment: Environment,
 
     /// Indicate whether or not we've received the snapshot message yet
     pub snapshot_received: bool,
 
+    /// Called with every delta (including trades, flagged with `TRADE`) as it's published. Lets
+    /// custom sinks, metrics or signals process the feed alongside Redis.
+    pub callback: Option<DeltaCallback>,
+
+    /// API key used to authenticate the connection. Both `api_key` and `api_secret` are required to
+    /// subscribe to the private tables (see [`PRIVATE_TABLES`]); without them, only public tables are available.
+    pub api_key: Option<String>,
+    /// API secret the authentication message is signed with. It is never sent to BitMEX
+    pub api_secret: Option<String>,
+
     /// Collection metadata
     pub metadata: MetaData,
+    /// Type of market we're collecting. Appended to `redis_channel` (i.e. `bitmex:inverse_perpetual`)
+    pub market_type: MarketType,
 
-    /// Channel name with no argument we want to subscribe to
+    /// Channel name with no argument we want to subscribe to. `liquidation` publishes liquidation
+    /// orders on the `liquidation_redis_channel`. Private tables (i.e. `execution`,
+    /// `position` or `order`) require `api_key` and `api_secret`, and their messages are published as
+    /// received on the `private_redis_channel` (see [`private_channel_name`])
     pub single_channels: Vec<String>,
     /// Channel name as map key/value pair
     pub dual_channels: Vec<String>,
 
     /// BitMEX requires asset indexes to calculate asset price
     pub asset_indexes: HashMap<String, u64>,
-    /// Allows us to calculate the price of a given asset in combination with [`asset_indexes`]
+    /// Allows us to calculate the price of a given asset in combination with [`asset_indexes`]. Starts out as the
+    /// instrument's tick size, and is corrected to the one level IDs are encoded with (see [`encoded_tick_size`])
+    /// once BitMEX sends levels along with their price
     pub asset_tick_size: HashMap<String, f32>,
 
-    /// TectonicDB connection
-    pub tectonic: orderbook::tectonic::TectonicConnection,
+    /// Connection health, shared with the running websocket handler
+    pub health: ConnectionHealth,
+
+    /// Book of every symbol we collect. Seeded from the symbol's `orderBookL2` partial, and kept up to date
+    /// with the deltas that follow. Shared by every connection (and reconnection) opened from these settings.
+    pub books: Books,
+
+    /// Storage backend the collected deltas are warehoused in
+    pub storage: Box<dyn StorageBackend>,
 
-    /// Redis client (before connection)
+    /// Redis URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub redis_url: String,
+    /// Redis client (before connection). Opened from `redis_url` when connecting
     pub r: redis::Client,
-    /// Redis password: If this is present, we will send an AUTH message to the server on connect
+    /// Redis password: If this is present, it replaces the password in `redis_url` (if any) when connecting
     pub r_password: Option<String>,
+    /// Redis PUBSUB channel deltas are published to, followed by the market type (i.e. `bitmex:inverse_perpetual`).
+    /// `{symbol}` is replaced with the symbol of the deltas (i.e. `bitmex:{symbol}` publishes XBTUSD deltas to
+    /// `bitmex:XBTUSD:inverse_perpetual`). Trades are published to the same channel, suffixed with `:trades`,
+    /// and the book of every symbol, once its partial arrives, with `:snapshot:{symbol}` (i.e.
+    /// `bitmex:inverse_perpetual:snapshot:XBTUSD`) as an [`orderbook::Level2Snapshot`].
+    pub redis_channel: String,
+    /// Prefix of the Redis PUBSUB channels messages of private tables are published to, followed by the table
+    /// name (i.e. `bitmex_private_execution`). Kept apart from `redis_channel` so that account data never
+    /// ends up in the public feed.
+    pub private_redis_channel: String,
+    /// Redis PUBSUB channel funding rates are published to, as [`orderbook::FundingRate`]s. May contain a
+    /// `{symbol}` placeholder (i.e. `bitmex_funding:{symbol}`).
+    pub funding_redis_channel: String,
+    /// Redis PUBSUB channel the lifecycle of liquidation orders is published to, as [`orderbook::Liquidation`]s.
+    /// May contain a `{symbol}` placeholder.
+    pub liquidation_redis_channel: String,
+    /// Store funding rates as well, under the `funding_<symbol>` symbol (i.e. the `bitmex_funding_XBTUSD`
+    /// TectonicDB database). See [`orderbook::FundingRate::to_delta`]
+    pub store_funding: bool,
+    /// Number of recently published deltas remembered to drop the ones BitMEX replays
+    pub dedup_capacity: usize,
+    /// Deltas that don't pass the filter are neither stored nor published. Books are still kept whole
+    pub delta_filter: DeltaFilter,
+
+    /// Backoff policy we follow when reconnecting after the websocket drops
+    pub reconnect_policy: ReconnectPolicy,
+    /// Limits the instrument requests made whenever a connection opens. Shared by every connection
+    /// (and reconnection) opened from these settings, including their clones.
+    pub rate_limiter: Arc<Mutex<RateLimiter>>,
+
+    /// Resubscribe to a symbol's book whenever an update doesn't match the levels we have. BitMEX doesn't
+    /// number its messages, so an update or delete of a level we never received is how we spot a gap.
+    pub gap_detection: bool,
+
+    /// We send BitMEX a `ping` once we haven't received anything for this many milliseconds
+    pub ping_interval_ms: u64,
+    /// We reconnect if nothing (not even the `pong`) arrives within this many milliseconds of a `ping`
+    pub pong_timeout_ms: u64,
+
+    /// Interval, in milliseconds, at which the instruments (`asset_indexes` and `asset_tick_size`) are fetched
+    /// again, so that instruments listed after we connected can be decoded. 0 only fetches them on connect
+    pub instrument_refresh_ms: u64,
+    /// Backoff policy we follow when fetching the instruments fails
+    pub instrument_retry_policy: ReconnectPolicy,
 
     /// Thread channel. We will use this to communicate with a secondary connection
     /// opened after a 15 minute count to ensure a stable connection. This channel is
-    /// managed by SocketManager
+    /// managed by [`SocketManager`]. When set, deltas (and trades, as deltas with the `TRADE`
+    /// flag) are sent here instead of Redis, and the connection doesn't reconnect on its own.
     pub channel: Option<mpsc::Sender<orderbook::Delta>>,
 }
 
 /// Create two identical structs and transfer the data over when we start the websocket.
 pub struct WSExchangeSender {
-    /// Full URL to connect to. Example: `wss://www.bitmex.com/realtime`
-    host: String,
+    /// Environment we're connected to
+    environment: Environment,
 
-    /// Indicate whether or not we've received the snapshot message yet
+    /// Indicate whether or not we've received the `orderBookL2` partial yet. Every connection starts
+    /// without one (including reconnections), and holds a symbol's orderbook updates back until its partial
+    /// arrives, since they can't be applied to a book we don't have. The partial is then published as a snapshot
+    /// (see [`WSExchange::redis_channel`]) so that consumers can rebuild their book from scratch, followed by the updates.
     snapshot_received: bool,
 
-    /// Optional function that can be called as a callback per message received.
-    /// Usually, this will send a delta, but we will make it generic to allow for flexability
-    //callback: Option<Box<Fn(&orderbook::Delta)>>,
+    /// Called with every delta as it's published
+    callback: Option<DeltaCallback>,
+
+    /// API key and secret the connection authenticates with, if any
+    api_key: Option<String>,
+    api_secret: Option<String>,
 
     /// Collection metadata
     metadata: MetaData,
@@ -78,10 +223,52 @@ pub struct WSExchangeSender {
     /// Allows us to calculate the price of a given asset in combination with [`asset_indexes`]
     asset_tick_size: Arc<RwLock<HashMap<String, f32>>>,
 
-    /// TectonicDB connection
-    tectonic: orderbook::tectonic::TectonicConnection,
+    /// Connection health
+    health: ConnectionHealth,
+
+    /// Book of every symbol we collect
+    books: Books,
+
+    /// Storage backend the collected deltas are warehoused in. Shared with the worker handling messages
+    storage: Arc<Mutex<Box<dyn StorageBackend>>>,
     /// Redis client (used to send deltas as PUBSUB)
     r: Arc<Mutex<redis::Connection>>,
+    /// Redis PUBSUB channel deltas are published to. May contain a `{symbol}` placeholder
+    redis_channel: String,
+    /// Prefix of the Redis PUBSUB channels of the private tables
+    private_redis_channel: String,
+    /// Redis PUBSUB channel funding rates are published to
+    funding_redis_channel: String,
+    /// Redis PUBSUB channel liquidation orders are published to
+    liquidation_redis_channel: String,
+    /// Store funding rates alongside the deltas
+    store_funding: bool,
+    /// Recently published deltas. Kept across reconnects, since that's when BitMEX replays updates
+    dedup: Arc<Mutex<DeduplicationWindow>>,
+    /// Drops the deltas consumers aren't interested in
+    delta_filter: DeltaFilter,
+
+    /// Limits our REST requests. Shared across reconnections
+    rate_limiter: Arc<Mutex<RateLimiter>>,
+
+    /// Resubscribe to a symbol's book whenever an update doesn't match the levels we have
+    gap_detection: bool,
+    /// Level IDs of the books we've received the partial of, and the messages waiting for theirs
+    book: BookTracker,
+
+    /// Pings BitMEX when the connection is idle, and spots when it stopped answering
+    keepalive: Keepalive,
+    /// Interval, in milliseconds, at which the instruments are fetched again. 0 disables the refresh
+    instrument_refresh_ms: u64,
+    /// Backoff policy we follow when fetching the instruments fails
+    instrument_retry_policy: ReconnectPolicy,
+
+    /// Set when the connection is managed by a [`SocketManager`]
+    channel: Option<mpsc::Sender<orderbook::Delta>>,
+    /// Queue of the worker parsing and publishing this connection's messages. Started when the connection opens
+    worker: Option<mpsc::SyncSender<RawMessage>>,
+    /// Thread the worker runs on, joined when the connection closes
+    worker_thread: Option<thread::JoinHandle<()>>,
 
     /// Websocket sender
     out: Sender,
@@ -94,6 +281,9 @@ pub struct MetaData {
     /// Vector of asset pairs we're going to warehouse
     pub asset_pair: Option<Vec<[exchange::Asset; 2]>>,
 
+    /// Type of market we're warehousing. Set from `WSExchange::market_type` when connecting
+    pub market_type: Option<MarketType>,
+
     /// Starting datetime of our data collection
     start_date: Option<DateTime<Utc>>,
 
@@ -101,66 +291,384 @@ pub struct MetaData {
     end_date: Option<DateTime<Utc>>,
 }
 
-/// Master bitmex message. This may contain a delta or a snapshot
+/// Master bitmex message. The schema of `data` depends on the table (i.e. [`BitMEXOrderbookData`] for `orderBookL2`)
 #[derive(Serialize, Deserialize, Debug)]
-struct BitMEXMessage {
+struct BitMEXMessage<T> {
     /// Specifies where update originates from (i.e. channel)
     table: String,
     /// Tells if action is a snapshot or delta
     action: String,
     /// Snapshot or delta data
-    data: Vec<BitMEXData>,
+    data: Vec<T>,
+}
+
+/// Table and error of a message that doesn't start with its table (see [`message_table`]), i.e. subscription
+/// responses and errors
+#[derive(Deserialize)]
+struct BitMEXHeader {
+    #[serde(default)]
+    table: String,
+    /// Set when BitMEX refuses a request (i.e. a failed authentication)
+    error: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BitMEXFilter {
+    symbol: Option<String>,
 }
 
-/// BitMEX websocket data. All deltas and snapshot updates are sent as such
+/// `orderBookL2` message. It's parsed once, on the socket thread: the [`BookTracker`] needs its level IDs,
+/// and the worker builds the deltas from its levels
+#[derive(Deserialize)]
+pub struct BookMessage {
+    #[serde(default)]
+    action: String,
+    /// Only present on partials (i.e. `{"symbol": "XBTUSD"}`)
+    filter: Option<BitMEXFilter>,
+    #[serde(default)]
+    data: Vec<BitMEXOrderbookData>,
+}
+
+impl BookMessage {
+    /// Parses the `orderBookL2` message
+    fn parse(raw: &RawMessage) -> Option<BookMessage> {
+        match serde_json::from_slice::<BookMessage>(&raw.data) {
+            Ok(book) => Some(book),
+            Err(e) => {
+                error!("Failed to parse BitMEX {} message: {}", raw.table, e);
+                None
+            }
+        }
+    }
+
+    /// Symbol the message belongs to. Partials name it in their filter, which is there even when the book is empty
+    fn symbol(&self) -> Option<String> {
+        self.filter.as_ref()
+            .and_then(|filter| filter.symbol.clone())
+            .or_else(|| self.data.first().map(|level| level.symbol.clone()))
+    }
+}
+
+/// Reads the table from the start of a message rather than parsing it, since BitMEX sends it first
+/// (i.e. `{"table":"orderBookL2",...`). Returns `None` for messages that don't start with a table.
+pub fn message_table(data: &[u8]) -> Option<String> {
+    const PREFIX: &[u8] = br#"{"table":""#;
+
+    if !data.starts_with(PREFIX) {
+        return None;
+    }
+
+    let table = &data[PREFIX.len()..];
+    let end = table.iter().position(|&byte| byte == b'"')?;
+
+    String::from_utf8(table[..end].to_vec()).ok()
+}
+
+/// Level of the `orderBookL2` table. All deltas and snapshot updates are sent as such
 #[derive(Serialize, Deserialize, Debug)]
-struct BitMEXData {
+struct BitMEXOrderbookData {
     /// Asset-pair name
     symbol: String,
     /// Orderbook side (bid/ask)
     side: String,
     /// Price comes encoded in this value.
     id: Option<u64>,
-    /// Order size. If not present, then it is a level removal
+    /// Order size. Left out of deletes, which remove the level
     size: Option<f32>,
     /// Only present on insert and snapshot events
-    price: Option<f32>
+    price: Option<f32>,
+    /// Time BitMEX processed the event (i.e. `2018-08-28T20:14:11.154Z`)
+    timestamp: Option<String>,
 }
 
-#[derive(Serialize, Deserialize)]
-struct AssetInformation {
+/// Execution of the `trade` table
+#[derive(Serialize, Deserialize, Debug)]
+struct BitMEXTradeData {
+    /// Asset-pair name
     symbol: String,
+    /// Side of the taker (`Buy` or `Sell`)
+    side: String,
+    size: Option<f32>,
+    price: Option<f32>,
+    /// Trade match ID
+    #[serde(rename = "trdMatchID")]
+    trd_match_id: Option<String>,
+    /// Time BitMEX processed the event (i.e. `2018-08-28T20:14:11.154Z`)
+    timestamp: Option<String>,
+}
+
+/// Liquidation order of the `liquidation` table. Updates only carry the fields that changed,
+/// and deletes only the order ID and symbol.
+#[derive(Serialize, Deserialize, Debug)]
+struct BitMEXLiquidationData {
+    #[serde(rename = "orderID")]
+    order_id: String,
+    symbol: Option<String>,
+    /// Side of the liquidation order (`Buy` or `Sell`)
+    side: Option<String>,
+    price: Option<f64>,
+    /// Quantity left to be liquidated
+    #[serde(rename = "leavesQty")]
+    leaves_qty: Option<f64>,
+}
+
+/// Message of the `funding` table. The partial holds the last funding of every contract
+#[derive(Deserialize)]
+struct BitMEXFundingMessage {
+    data: Vec<BitMEXFunding>,
+}
+
+/// Funding of a perpetual contract
+#[derive(Deserialize)]
+struct BitMEXFunding {
+    symbol: String,
+    /// Time of the funding
     timestamp: String,
+    /// Time between fundings, as an offset from 2000-01-01 (i.e. `2000-01-01T08:00:00.000Z` is eight hours)
+    #[serde(rename = "fundingInterval")]
+    funding_interval: String,
+    #[serde(rename = "fundingRate")]
+    funding_rate: f64,
+    #[serde(rename = "fundingRateDaily")]
+    funding_rate_daily: Option<f64>,
+}
+
+impl BitMEXFunding {
+    fn to_funding_rate(&self) -> Option<orderbook::FundingRate> {
+        let ts = parse_timestamp(&self.timestamp)?;
+
+        Some(orderbook::FundingRate {
+            symbol: self.symbol.clone(),
+            exchange: Exchange::BitMEX,
+            rate: self.funding_rate,
+            daily_rate: self.funding_rate_daily,
+            next_funding_ts: ts + parse_funding_interval(&self.funding_interval)?,
+            ts,
+        })
+    }
+}
+
+/// Parses the funding intervals BitMEX sends as an offset from 2000-01-01 into seconds
+/// (i.e. `2000-01-01T08:00:00.000Z` to 28800)
+pub fn parse_funding_interval(interval: &str) -> Option<f64> {
+    // 2000-01-01T00:00:00Z as seconds since the UNIX epoch
+    const EPOCH_2000: f64 = 946684800.0;
+
+    parse_timestamp(interval).map(|ts| ts - EPOCH_2000)
+}
+
+/// Decodes the price of an `orderBookL2` level from its ID, given the instrument's index in the instrument list
+/// and the tick size its IDs are encoded with (see [`encoded_tick_size`]). Returns `None` if the ID doesn't belong
+/// to the instrument.
+pub fn level_price(id: u64, index: u64, tick_size: f32) -> Option<f32> {
+    (100_000_000 * index).checked_sub(id).map(|ticks| ticks as f32 * tick_size)
+}
+
+/// Tick size the level IDs of an instrument are encoded with, given a level BitMEX sent along with its price.
+/// This usually is the instrument's tick size, but not always: XBTUSD's IDs keep the 0.01 tick it was listed
+/// with, whatever tick size it currently trades in. Returns `None` if the ID doesn't belong to the instrument.
+pub fn encoded_tick_size(id: u64, index: u64, price: f32) -> Option<f32> {
+    match (100_000_000 * index).checked_sub(id) {
+        Some(0) | None => None,
+        Some(ticks) => Some(price / ticks as f32),
+    }
+}
+
+/// Parses the ISO 8601 timestamps BitMEX sends into seconds since the UNIX epoch
+pub fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(|ts| ts.timestamp_millis() as f64 * 0.001f64)
+}
 
+/// Number of instruments requested at once. This is the most BitMEX returns per request
+pub const INSTRUMENT_PAGE_SIZE: usize = 500;
+
+/// Instrument, as listed by the `/instrument` endpoint
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Instrument {
+    /// Instrument symbol (i.e. XBTUSD)
+    pub symbol: String,
+    /// Time the instrument was listed (i.e. `2016-05-04T12:00:00.000Z`)
+    pub listing: Option<String>,
+    /// Minimum price increment
     #[serde(rename = "tickSize")]
-    tick_size: f32,
+    pub tick_size: f32,
+}
+
+/// GETs a JSON document from the REST API. Requests go through the rate limiter, and failed ones (including
+/// error statuses, i.e. when BitMEX rate limits us) are retried following `retry_policy`. Returns the last
+/// error once the policy gives up.
+pub fn fetch_json<T: DeserializeOwned>(
+    url: &str,
+    rate_limiter: &Mutex<RateLimiter>,
+    retry_policy: &ReconnectPolicy,
+) -> Result<T, reqwest::Error> {
+    let mut attempts = 0;
+
+    loop {
+        let wait = rate_limiter.lock().unwrap().acquire();
+        if wait > Duration::from_secs(0) {
+            debug!("Waiting {}ms before requesting {}", wait.as_secs() * 1000 + wait.subsec_millis() as u64, url);
+            thread::sleep(wait);
+        }
+
+        // The status is checked first, so that error pages aren't reported as malformed JSON
+        let result = reqwest::get(url)
+            .and_then(|response| response.error_for_status())
+            .and_then(|mut response| response.json());
+
+        match result {
+            Ok(document) => return Ok(document),
+            Err(e) => {
+                if retry_policy.exhausted(attempts) {
+                    return Err(e);
+                }
+
+                let delay = retry_policy.delay(attempts);
+                warn!("Request to {} failed: {}. Retrying in {}ms...", url, e, delay.as_secs() * 1000 + delay.subsec_millis() as u64);
+                thread::sleep(delay);
+
+                attempts += 1;
+            },
+        }
+    }
+}
+
+/// Fetches every instrument BitMEX ever listed, page by page (see [`fetch_json`])
+pub fn fetch_instruments(
+    environment: Environment,
+    rate_limiter: &Mutex<RateLimiter>,
+    retry_policy: &ReconnectPolicy,
+) -> Result<Vec<Instrument>, reqwest::Error> {
+    let mut instruments: Vec<Instrument> = vec![];
+
+    loop {
+        let page: Vec<Instrument> = fetch_json(&environment.instrument_url(instruments.len()), rate_limiter, retry_policy)?;
+        let last_page = page.len() < INSTRUMENT_PAGE_SIZE;
+
+        instruments.extend(page);
+
+        if last_page {
+            return Ok(instruments);
+        }
+    }
+}
+
+/// Replaces the index and tick size of the instruments (see [`instrument_indexes`]). Instruments missing from
+/// the list keep theirs
+pub fn load_instruments(
+    instruments: &[Instrument],
+    asset_indexes: &RwLock<HashMap<String, u64>>,
+    asset_tick_size: &RwLock<HashMap<String, f32>>,
+) {
+    let (indexes, tick_sizes) = instrument_indexes(instruments);
+
+    asset_indexes.write().unwrap().extend(indexes);
+    asset_tick_size.write().unwrap().extend(tick_sizes);
+}
+
+/// Index and tick size of every instrument, keyed by symbol. An instrument's index is its position in the order
+/// instruments were listed in (whatever order they were fetched in). Instruments without a listing date are indexed last.
+pub fn instrument_indexes(instruments: &[Instrument]) -> (HashMap<String, u64>, HashMap<String, f32>) {
+    let listing = |instrument: &Instrument| instrument.listing.as_ref()
+        .and_then(|listing| parse_timestamp(listing))
+        .unwrap_or(::std::f64::INFINITY);
+
+    let mut listed: Vec<&Instrument> = instruments.iter().collect();
+    listed.sort_by(|a, b| listing(a).partial_cmp(&listing(b)).unwrap_or(::std::cmp::Ordering::Equal));
+
+    let mut indexes = HashMap::new();
+    let mut tick_sizes = HashMap::new();
+
+    for (index, instrument) in listed.into_iter().enumerate() {
+        indexes.insert(instrument.symbol.clone(), index as u64);
+        tick_sizes.insert(instrument.symbol.clone(), instrument.tick_size);
+    }
+
+    (indexes, tick_sizes)
+}
+
+impl WSExchange {
+    /// Health of the connection to the exchange
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    /// Settings as used by a connection: the storage and Redis channel are suffixed for the environment,
+    /// and the market type is appended to the Redis channel
+    fn with_environment(mut self) -> WSExchange {
+        self.storage.set_exchange(&self.environment.suffix("bitmex"));
+        self.redis_channel = exchange::market_channel(&self.environment.suffix(&self.redis_channel), Some(self.market_type));
+        self.private_redis_channel = self.environment.suffix(&self.private_redis_channel);
+        self.funding_redis_channel = self.environment.suffix(&self.funding_redis_channel);
+        self.liquidation_redis_channel = self.environment.suffix(&self.liquidation_redis_channel);
+        self.metadata.market_type = Some(self.market_type);
+
+        self
+    }
 }
 
 impl AssetExchange for WSExchange {
     fn default_settings() -> Result<Box<Self>, String> {
         let settings = Self {
-            host: "wss://www.bitmex.com/realtime".into(),
+            environment: Environment::Production,
 
             snapshot_received: false,
 
-            //callback: None,
+            callback: None,
+
+            api_key: None,
+            api_secret: None,
 
             metadata: MetaData {
                 asset_pair: Some(vec![
                     [Asset::BTC, Asset::USD],]),
+                market_type: None,
                 start_date: None,
                 end_date: None,
             },
+            market_type: MarketType::InversePerpetual,
 
             single_channels: vec![],
-            dual_channels: vec!["orderBookL2".into(), "trade".into()],
+            dual_channels: vec!["orderBookL2".into(), "trade".into(), "funding".into()],
 
             asset_indexes: HashMap::new(),
             asset_tick_size: HashMap::new(),
 
-            tectonic: orderbook::tectonic::TectonicConnection::new(None, None).expect("Unable to connect to TectonicDB"),
-            r: redis::Client::open("redis://localhost").unwrap(),
+            health: ConnectionHealth::new(Exchange::BitMEX),
+
+            books: Arc::new(RwLock::new(HashMap::new())),
+
+            storage: Box::new(TectonicBackend::new(None, None, "bitmex").map_err(|e| format!("Unable to connect to TectonicDB: {}", e))?),
+            redis_url: exchange::DEFAULT_REDIS_URL.into(),
+            r: redis::Client::open(exchange::DEFAULT_REDIS_URL).unwrap(),
             r_password: None,
+            redis_channel: "bitmex".into(),
+            private_redis_channel: "bitmex_private".into(),
+            funding_redis_channel: "bitmex_funding".into(),
+            liquidation_redis_channel: "bitmex_liquidation".into(),
+            store_funding: false,
+            dedup_capacity: exchange::DEFAULT_DEDUP_CAPACITY,
+            delta_filter: DeltaFilter::default(),
+
+            reconnect_policy: ReconnectPolicy::default(),
+            // BitMEX allows 30 unauthenticated requests per minute
+            rate_limiter: Arc::new(Mutex::new(RateLimiter::new(0.5, 10))),
+
+            gap_detection: true,
+
+            // As recommended by BitMEX
+            ping_interval_ms: 5_000,
+            pong_timeout_ms: 5_000,
+
+            instrument_refresh_ms: 60 * 60 * 1_000,
+            instrument_retry_policy: ReconnectPolicy {
+                base_delay_ms: 1_000,
+                max_delay_ms: 30_000,
+                max_attempts: Some(5),
+                ..Default::default()
+            },
 
             channel: None,
         };
@@ -168,59 +676,214 @@ impl AssetExchange for WSExchange {
         Ok(Box::new(settings))
     }
 
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        if let Some(pairs) = config.asset_pairs()? {
+            self.metadata.asset_pair = Some(pairs);
+        }
+        if let Some(ref channels) = config.channels.names {
+            self.single_channels = channels.clone();
+        }
+        if let Some(ref url) = config.redis.url {
+            self.redis_url = url.clone();
+        }
+        if config.redis.password.is_some() {
+            self.r_password = config.redis.password.clone();
+        }
+        if let Some(storage) = config.storage("bitmex")? {
+            self.storage = storage;
+        }
+
+        Ok(())
+    }
+
     fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
-        let redis_connection = self.r.clone()
-            .get_connection()
-            .unwrap();
-
-        // Send an auth message if we have a password
-        match &self.r_password {
-            Some(password) => {
-                redis::cmd("AUTH").arg(password)
-                    .execute(&redis_connection);
-            },
-            None => (),
-        };
+        // The password is sent as part of the URL, so the client authenticates on its own
+        self.r = exchange::redis_client(&self.redis_url, self.r_password.as_ref().map(|password| password.as_str()))?;
 
-        Ok(redis_connection)
+        self.r.get_connection()
     }
 
-    fn run(settings: Option<&Self>) {
-        // Try to use the settings the user passes before resorting to default settings.
-        let mut settings = settings.cloned().unwrap_or(*WSExchange::default_settings().unwrap());
+    fn subscribe_funding(&mut self) -> bool {
+        if !self.dual_channels.iter().any(|channel| channel == "funding") {
+            self.dual_channels.push("funding".into());
+        }
+
+        true
+    }
 
-        ws::connect(settings.host.clone(), |out| WSExchangeSender {
-            host: settings.host.clone(),
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
 
-            snapshot_received: settings.snapshot_received.clone(),
+    fn start(&self) -> Result<(), ExchangeError> {
+        WSExchange::run_with_settings(Some(self))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
+        // Try to use the settings the user passes before resorting to default settings.
+        let mut settings = match settings {
+            Some(settings) => settings.clone(),
+            None => *WSExchange::default_settings().map_err(ConfigError::Settings)?,
+        }.with_environment();
+
+        // Shared by every connection, so that reconnections pick up where the previous connection left off
+        let asset_indexes = Arc::new(RwLock::new(settings.asset_indexes.clone()));
+        let asset_tick_size = Arc::new(RwLock::new(settings.asset_tick_size.clone()));
+        let storage = Arc::new(Mutex::new(settings.storage.clone()));
+        let r = Arc::new(Mutex::new(settings.init_redis()?));
+        let dedup = Arc::new(Mutex::new(DeduplicationWindow::new(settings.dedup_capacity)));
+
+        // Nothing is carried over from a previous connection's book: every connection subscribes in `on_open`,
+        // and holds orderbook updates back until it has received and published a fresh partial.
+        supervise(&settings.health, &settings.reconnect_policy, &settings.health.shutdown, || ws::connect(settings.environment.host(), |out| WSExchangeSender {
+            environment: settings.environment,
+
+            // Even if the settings say otherwise, the new connection hasn't received its partial yet
+            snapshot_received: false,
+            callback: settings.callback.clone(),
+            api_key: settings.api_key.clone(),
+            api_secret: settings.api_secret.clone(),
             metadata: settings.metadata.clone(),
 
             single_channels: settings.single_channels.clone(),
             dual_channels: settings.dual_channels.clone(),
             
-            asset_indexes: Arc::new(RwLock::new(settings.asset_indexes.clone())),
-            asset_tick_size: Arc::new(RwLock::new(settings.asset_tick_size.clone())),
-
-            tectonic: settings.tectonic.clone(),
-            r: Arc::new(Mutex::new(settings.init_redis().expect("Failed to connect to Redis server."))),
+            asset_indexes: asset_indexes.clone(),
+            asset_tick_size: asset_tick_size.clone(),
+
+            health: settings.health.clone(),
+            books: settings.books.clone(),
+            storage: storage.clone(),
+            r: r.clone(),
+            redis_channel: settings.redis_channel.clone(),
+            private_redis_channel: settings.private_redis_channel.clone(),
+            funding_redis_channel: settings.funding_redis_channel.clone(),
+            liquidation_redis_channel: settings.liquidation_redis_channel.clone(),
+            store_funding: settings.store_funding,
+            dedup: dedup.clone(),
+            delta_filter: settings.delta_filter.clone(),
+
+            rate_limiter: settings.rate_limiter.clone(),
+
+            gap_detection: settings.gap_detection,
+            book: BookTracker::new(settings.gap_detection),
+
+            keepalive: Keepalive::new(settings.ping_interval_ms, settings.pong_timeout_ms),
+            instrument_refresh_ms: settings.instrument_refresh_ms,
+            instrument_retry_policy: settings.instrument_retry_policy.clone(),
+
+            channel: settings.channel.clone(),
+            worker: None,
+            worker_thread: None,
 
             out,
-        }).unwrap();
+        }))?;
+
+        Ok(())
     }
 }
 
+/// Keeps a connection going: `connect` is expected to block for as long as the connection is up. Whenever
+/// it returns (the connection closed, timed out or failed), we wait following `reconnect_policy` and
+/// call it again, until `shutdown` is set (see [`ConnectionHealth::stop`]). Errors once the policy gives up.
+///
+/// Reconnecting from here rather than from within the connection's handler means that the previous
+/// connection (and its event loop) is gone by the time we open the next one.
+pub fn supervise<F>(
+    health: &ConnectionHealth,
+    reconnect_policy: &ReconnectPolicy,
+    shutdown: &AtomicBool,
+    mut connect: F,
+) -> Result<(), Error>
+    where F: FnMut() -> Result<(), Error>
+{
+    let mut attempts = 0;
+
+    while !shutdown.load(Ordering::SeqCst) {
+        let started = Instant::now();
+
+        if let Err(e) = connect() {
+            error!("BitMEX Socket failed: {}", e);
+        }
+
+        if shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+
+        // The backoff only starts over once the connection has stayed up for a while. Otherwise,
+        // an exchange that accepts connections and immediately drops them would be hammered.
+        if reconnect_policy.is_stable(started.elapsed()) {
+            attempts = 0;
+        }
+
+        if reconnect_policy.exhausted(attempts) {
+            return Err(Error::new(
+                ws::ErrorKind::Internal,
+                format!("BitMEX gave up after {} reconnection attempts", attempts)));
+        }
+
+        let delay = reconnect_policy.delay(attempts);
+        warn!("BitMEX Socket closed. Opening a new connection in {}ms...", delay.as_secs() * 1000 + delay.subsec_millis() as u64);
+        thread::sleep(delay);
+
+        attempts += 1;
+        health.record_reconnect();
+    }
+
+    info!("BitMEX Socket closed for shutdown");
+    Ok(())
+}
+
 #[derive(Serialize, Deserialize)]
 struct BitMEXSubscription {
     op: String,
     args: Vec<String>,
 }
 
+/// `authKeyExpires` request. The arguments are the API key, the expiry and the signature
+#[derive(Serialize)]
+struct BitMEXAuth {
+    op: String,
+    args: (String, i64, String),
+}
+
+/// Signs a request the way BitMEX expects: hex encoded HMAC-SHA256 of `{verb}{path}{expires}{body}`,
+/// keyed with the API secret
+pub fn signature(api_secret: &str, verb: &str, path: &str, expires: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(api_secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.input(format!("{}{}{}{}", verb, path, expires, body).as_bytes());
+
+    hex::encode(mac.result().code())
+}
+
+/// Builds the message authenticating a websocket connection, valid until `expires` (in seconds since the UNIX epoch)
+pub fn auth_message(api_key: &str, api_secret: &str, expires: i64) -> String {
+    let msg = BitMEXAuth {
+        op: "authKeyExpires".into(),
+        args: (api_key.to_string(), expires, signature(api_secret, "GET", "/realtime", expires, "")),
+    };
+
+    serde_json::to_string(&msg).unwrap()
+}
+
+/// Whether BitMEX only sends the table to authenticated connections
+pub fn is_private_table(table: &str) -> bool {
+    PRIVATE_TABLES.contains(&table)
+}
+
 impl Handler for WSExchangeSender {
     fn on_open(&mut self, _: Handshake) -> Result<(), Error> {
-        // Set a timeout for 5 seconds of inactivity
-        // Issue: currently, this reruns every five seconds. Disable for the meanwhile 
-        // while we fix this issue
-        // self.out.timeout(5_000, EXPIRE).unwrap();
+        let (worker, worker_thread) = self.start_worker();
+        self.worker = Some(worker);
+        self.worker_thread = Some(worker_thread);
+        self.health.track_connection(&self.out);
+
+        // Measure the round-trip time of the new connection
+        self.health.ping_latency(&self.out)?;
+
+        // Keep the connection alive. The check is rescheduled for as long as messages keep arriving,
+        // and only pings BitMEX once the connection has gone idle.
+        self.out.timeout(self.keepalive.idle_ms, KEEPALIVE)?;
 
         let mut msg = BitMEXSubscription {
             op: "subscribe".into(),
@@ -233,171 +896,1139 @@ impl Handler for WSExchangeSender {
 
         for key in &self.dual_channels {
             for pair in self.metadata.asset_pair.as_ref().expect("No assets supplied to BitMEX struct") {
-                msg.args.push(format!("{}:{}", key, exchange::get_asset_pair(pair, Exchange::BitMEX)));
+                // Skip pairs BitMEX doesn't list instead of taking down the whole handler
+                match exchange::get_asset_pair(pair, Exchange::BitMEX) {
+                    Ok(normalized_pair) => msg.args.push(format!("{}:{}", key, normalized_pair)),
+                    Err(e) => warn!("Skipping subscription to {}: {}", key, e),
+                }
             }
         }
 
-        println!("{}", serde_json::to_string(&msg).unwrap());
-
-        // Now that we've built our message, let's get the indicies of the assets we can trade
-        let response: Vec<AssetInformation> = reqwest::get("https://www.bitmex.com/api/v1/instrument?columns=symbol,tickSize&start=0&count=500")
-            .expect("Failed to send request")
-            .json()
-            .expect("Failed to serialize response to JSON");
-
-        for (index, asset) in response.iter().enumerate() {
-            // Dereference Arc and mutate after locking the RwLock
-            self.asset_indexes.deref()
-                .write()
-                .unwrap()
-                .insert(asset.symbol.clone(), index as u64);
+        debug!("BitMEX subscription message: {}", serde_json::to_string(&msg).unwrap());
 
-            self.asset_tick_size.deref()
-                .write()
-                .unwrap()
-                .insert(asset.symbol.clone(), asset.tick_size);
+        // Now that we've built our message, let's get the indicies of the assets we can trade. Reconnections
+        // share the rate limiter, so a connection that keeps dropping can't exhaust the REST rate limit. If the
+        // instruments can't be fetched, we keep the ones we have, and skip the levels of the others.
+        match fetch_instruments(self.environment, &self.rate_limiter, &self.instrument_retry_policy) {
+            Ok(instruments) => load_instruments(&instruments, &self.asset_indexes, &self.asset_tick_size),
+            Err(e) => error!("Failed to fetch BitMEX instruments: {}", e),
+        }
 
-            if !self.tectonic.exists(format!("bitmex_{}", asset.symbol.clone()))? && 
-                asset.symbol.clone() == exchange::get_asset_pair(
-                    &[exchange::Asset::BTC, exchange::Asset::USD], 
-                    exchange::Exchange::BitMEX)
-                {
+        // Instruments listed from now on are picked up by the refresh
+        if self.instrument_refresh_ms > 0 {
+            self.out.timeout(self.instrument_refresh_ms, REFRESH_INSTRUMENTS)?;
+        }
 
-                // Create tectonic database if it doesn't exist yet. This avoids many issues
+        for pair in self.metadata.asset_pair.as_ref().expect("No assets supplied to BitMEX struct") {
+            if let Ok(normalized_pair) = exchange::get_asset_pair(pair, Exchange::BitMEX) {
+                // Create the database if it doesn't exist yet. This avoids many issues
                 // relating to inserting to a non-existant database.
-                let _ = self.tectonic.create(format!("bitmex_{}", asset.symbol.clone()));
+                self.storage.lock().unwrap().create(&format!("{}_{}", self.environment.suffix("bitmex"), normalized_pair))?;
             }
         }
 
+        // Private tables can only be subscribed to once the connection is authenticated. BitMEX handles
+        // messages in order, so authenticating right before subscribing is enough.
+        match (self.api_key.as_ref(), self.api_secret.as_ref()) {
+            (Some(api_key), Some(api_secret)) => {
+                let expires = Utc::now().timestamp() + AUTH_EXPIRY_SECS;
+                self.out.send(auth_message(api_key, api_secret, expires))?;
+            },
+            (api_key, api_secret) => {
+                if api_key.is_some() != api_secret.is_some() {
+                    warn!("BitMEX needs both an API key and secret to authenticate. Connecting unauthenticated");
+                }
+
+                for channel in msg.args.iter().filter(|channel| is_private_table(channel)) {
+                    warn!("Subscribing to the private {} table without an API key and secret", channel);
+                }
+            },
+        }
+
         // Send our constructed message to the server
         self.out.send(serde_json::to_string(&msg).unwrap())
     }
 
     fn on_message(&mut self, msg: Message) -> Result<(), Error> {
-        let redis_ref = self.r.clone();
-        let asset_tick_ref = self.asset_tick_size.clone();
-        let asset_index_ref = self.asset_indexes.clone();
+        self.health.record_message();
+        self.keepalive.on_message();
 
-        // Spawn thread to ensure accurate timestamps
-        thread::spawn(move || {
-            match serde_json::from_slice::<BitMEXMessage>(&msg.into_data()) {
-                Ok(message) => {
-                    // Skip snapshots and other misc. data
-                    if message.table == "" || message.table == "partial" {
-                        return;
-                    }
-                    // Define a timestamp for the messages received
-                    let ts = Utc::now().timestamp_millis() as f64 * 0.001f64;
-                    let mut deltas: Vec<orderbook::Delta> = Vec::with_capacity(message.data.len());
-
-                    for update in message.data {
-                        // Let's make sure we don't parse any values with no ID
-                        if update.id.is_none() {
-                            continue;
-                        }
+        let data = msg.into_data();
+
+        // Response to our keepalive. Receiving it is all we needed
+        if data == b"pong" {
+            return Ok(());
+        }
 
-                        let is_bid = match update.side == "Buy" {
-                            true => orderbook::BID,
-                            false => orderbook::ASK,
-                        };
-                        let is_trade = match message.action == "Trade" {
-                            true => orderbook::TRADE,
-                            false => orderbook::UPDATE,
-                        };
-                    
-                        let delta = if update.symbol == "XBTUSD" {
-                            orderbook::Delta {
-                                symbol: String::from("XBTUSD"),
-                                price: (8800000000 - update.id.unwrap()) as f32 * 0.01,
-                                size: update.size.unwrap_or(0.0),
-                                seq: 0,
-                                event: is_bid ^ is_trade,
-                                ts,
-                            }
-                        } else {
-                            // Avoids borrowing [`update.symbol`] by changing the order the elements are assigned
-                            orderbook::Delta {
-                                price: ((100000000 * asset_index_ref.as_ref()
-                                    .read()
-                                    .unwrap()[&update.symbol]) - update.id.unwrap()
-                                ) as f32 * asset_tick_ref.as_ref()
-                                    .read()
-                                    .unwrap()[&update.symbol],
-
-                                symbol: update.symbol,
-                                size: update.size.unwrap_or(0.0),
-                                seq: 0,
-                                event: is_bid ^ is_trade,
-                                ts,
-                            }
-                        };
-
-                        deltas.push(delta);
+        let table = match message_table(&data) {
+            Some(table) => table,
+            // Subscription responses and errors are rare enough to be parsed here
+            None => match serde_json::from_slice::<BitMEXHeader>(&data) {
+                Ok(header) => {
+                    if let Some(e) = header.error {
+                        error!("BitMEX refused a request: {}", e);
                     }
 
-                    // Lock the connection until we are able to aquire it
-                    let _ = redis_ref.as_ref()
-                        .lock()
-                        .unwrap()
-                        .publish::<&str, &str, u8>("bitmex", &serde_json::to_string(&deltas).unwrap())
-                        .expect("Failed to publish message to redis PUBSUB");
+                    header.table
                 },
+                Err(_) => String::new(),
+            },
+        };
 
-                Err(e) => {
-                    println!("Error encountered: {}", e);
-                    return;
+        let raw = RawMessage {
+            table,
+            data,
+            ts: Utc::now().timestamp_millis() as f64 * 0.001f64,
+            book: None,
+        };
+
+        if raw.table == "orderBookL2" {
+            // Whether or not the book can be applied depends on the order messages arrive in,
+            // so the partial is tracked here rather than in the worker.
+            self.on_book_message(raw)
+        } else {
+            // Messages are parsed and published by the connection's worker, in the order they arrive
+            self.send_to_worker(raw)
+        }
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> Result<Option<ws::Frame>, Error> {
+        // Control frames (i.e. the `pong` to the latency ping) show the connection is alive as well
+        self.keepalive.on_message();
+
+        self.health.on_frame(frame)
+    }
+
+    fn on_close(&mut self, code: ws::CloseCode, reason: &str) {
+        // Managed connections are replaced by their `SocketManager`, others by `supervise` once the
+        // event loop exits. Either way, the new connection is opened from outside of this handler.
+        if self.channel.is_some() {
+            warn!("BitMEX Socket is closing ({:?} {}). The socket manager will hand off to a new connection", code, reason);
+        } else {
+            warn!("BitMEX Socket is closing ({:?} {})", code, reason);
+        }
+
+        // Dropping the queue lets the worker exit once it has handled the messages we've already received
+        self.worker = None;
+        if let Some(worker_thread) = self.worker_thread.take() {
+            let _ = worker_thread.join();
+        }
+
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut **self.storage.lock().unwrap());
+        }
+    }
+
+    fn on_timeout(&mut self, event: Token) -> Result<(), ws::Error> {
+        if self.health.is_stopped() {
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
+        if event == REFRESH_INSTRUMENTS {
+            self.refresh_instruments();
+            return self.out.timeout(self.instrument_refresh_ms, REFRESH_INSTRUMENTS);
+        }
+
+        if event == KEEPALIVE {
+            match self.keepalive.on_timeout(self.health.since_last_message()) {
+                KeepaliveAction::Wait(delay_ms) => return self.out.timeout(delay_ms, KEEPALIVE),
+                KeepaliveAction::Ping(delay_ms) => {
+                    self.out.send("ping")?;
+                    return self.out.timeout(delay_ms, KEEPALIVE);
                 },
+                KeepaliveAction::Expired => (),
             }
+        }
+
+        // Closing the connection is enough: we're reconnected from outside of this handler
+        warn!("BitMEX Socket timed out (no pong within {}ms of our ping). Closing it...", self.keepalive.pong_timeout_ms);
+        self.out.close(ws::CloseCode::Away)
+    }
+}
+
+impl WSExchangeSender {
+    /// Starts the worker handling this connection's messages. Managed connections hand their deltas and
+    /// trades to the [`SocketManager`], others deduplicate, store and publish them here.
+    fn start_worker(&self) -> (mpsc::SyncSender<RawMessage>, thread::JoinHandle<()>) {
+        let r = self.r.clone();
+        let redis_channel = self.redis_channel.clone();
+        let private_redis_channel = self.private_redis_channel.clone();
+        let funding_redis_channel = self.funding_redis_channel.clone();
+        let liquidation_redis_channel = self.liquidation_redis_channel.clone();
+        let store_funding = self.store_funding;
+        let storage = self.storage.clone();
+        let dedup = self.dedup.clone();
+        let delta_filter = self.delta_filter.clone();
+        let health = self.health.clone();
+        let channel = self.channel.clone();
+        let callback = self.callback.clone();
+        let books = self.books.clone();
+
+        spawn_worker(self.asset_indexes.clone(), self.asset_tick_size.clone(), move |parsed| match parsed {
+            // Funding rates are published on their own channel, even when the connection is managed
+            ParsedMessage::Funding(funding) => {
+                if store_funding {
+                    let deltas: Vec<orderbook::Delta> = funding.iter().map(orderbook::FundingRate::to_delta).collect();
+
+                    if let Err(e) = storage.lock().unwrap().insert(&deltas) {
+                        error!("Failed to store BitMEX funding rates: {}", e);
+                    }
+                }
+
+                if let Err(e) = publish(&*r.lock().unwrap(), &funding_redis_channel, &funding, |funding| funding.symbol.as_str()) {
+                    health.record_publish_error();
+                    error!("Failed to publish BitMEX funding rates to redis PUBSUB: {}", e);
+                }
+            },
+
+            // So are liquidations
+            ParsedMessage::Liquidations(liquidations) => {
+                if let Err(e) = publish(&*r.lock().unwrap(), &liquidation_redis_channel, &liquidations, |liquidation| liquidation.symbol.as_str()) {
+                    health.record_publish_error();
+                    error!("Failed to publish BitMEX liquidations to redis PUBSUB: {}", e);
+                }
+            },
+
+            // Private tables are kept apart from the market data, on a channel per table
+            ParsedMessage::Private { table, message } => {
+                let private_channel = private_channel_name(&private_redis_channel, &table);
+
+                if let Err(e) = r.lock().unwrap().publish::<&str, &str, u8>(&private_channel, &message) {
+                    health.record_publish_error();
+                    error!("Failed to publish BitMEX private message to redis PUBSUB: {}", e);
+                }
+            },
+
+            ParsedMessage::Trades(trades) => {
+                record_symbol_messages(&health, trades.iter().map(|trade| trade.symbol.as_str()));
+
+                if let Some(ref channel) = channel {
+                    for trade in &trades {
+                        let _ = channel.send(orderbook::Delta::from(trade));
+                    }
+                    return;
+                }
+
+                let trade_deltas: Vec<orderbook::Delta> = trades.iter().map(orderbook::Delta::from).collect();
+
+                if let Err(e) = storage.lock().unwrap().insert(&trade_deltas) {
+                    error!("Failed to store BitMEX trades: {}", e);
+                }
+                notify(callback.as_ref(), &trade_deltas);
+
+                let trade_channel = format!("{}:trades", redis_channel);
+
+                publish_trades(&*r.lock().unwrap(), &trade_channel, &trades, &health);
+            },
+
+            // Snapshots are stored like any other delta, but published as whole books on their own channel so that
+            // consumers can start their book over
+            ParsedMessage::Snapshot(deltas) => {
+                record_symbol_messages(&health, deltas.iter().map(|delta| delta.symbol.as_str()));
+                let snapshots = seed_books(&books, &deltas);
+
+                if let Some(ref channel) = channel {
+                    for delta in deltas {
+                        let _ = channel.send(delta);
+                    }
+                    return;
+                }
+
+                if let Err(e) = storage.lock().unwrap().insert(&deltas) {
+                    error!("Failed to store BitMEX snapshot: {}", e);
+                }
+                notify(callback.as_ref(), &deltas);
+
+                for snapshot in snapshots {
+                    let snapshot_channel = redis_channel_name(&format!("{}:snapshot:{{symbol}}", redis_channel), &snapshot.symbol);
+
+                    if let Err(e) = r.lock().unwrap().publish::<&str, &str, u8>(&snapshot_channel, &serde_json::to_string(&snapshot).unwrap()) {
+                        health.record_publish_error();
+                        error!("Failed to publish BitMEX snapshot to redis PUBSUB: {}", e);
+                    }
+                }
+            },
+
+            ParsedMessage::Deltas(mut deltas) => {
+                record_symbol_messages(&health, deltas.iter().map(|delta| delta.symbol.as_str()));
+                apply_to_books(&books, &deltas);
+                delta_filter.apply(&mut deltas, &health);
+
+                // The socket manager deduplicates, stores and publishes deltas itself
+                if let Some(ref channel) = channel {
+                    for delta in deltas {
+                        let _ = channel.send(delta);
+                    }
+                    return;
+                }
+
+                {
+                    let mut dedup = dedup.lock().unwrap();
+
+                    deltas.retain(|delta| if dedup.is_duplicate(delta) {
+                        health.record_duplicate();
+                        false
+                    } else {
+                        true
+                    });
+                }
+
+                if deltas.is_empty() {
+                    return;
+                }
+
+                if let Err(e) = storage.lock().unwrap().insert(&deltas) {
+                    error!("Failed to store BitMEX deltas: {}", e);
+                }
+                notify(callback.as_ref(), &deltas);
+
+                publish_deltas(&*r.lock().unwrap(), &redis_channel, &deltas, &health);
+            },
+        })
+    }
+
+    /// Hands an `orderBookL2` message to the worker once it can be applied to the book. Messages that arrive
+    /// before their symbol's partial are held back until it does. If a message doesn't match the book, we
+    /// resubscribe to get a new partial. The message is parsed here, and handed to the worker along with its data.
+    fn on_book_message(&mut self, mut raw: RawMessage) -> Result<(), Error> {
+        raw.book = BookMessage::parse(&raw);
+
+        let action = match raw.book {
+            Some(ref book) => book.action.clone(),
+            None => return Ok(()),
+        };
+
+        match self.book.track(raw) {
+            BookMessages::Ready(messages) => {
+                if action == "partial" {
+                    self.snapshot_received = true;
+                }
+
+                for message in messages {
+                    self.send_to_worker(message)?;
+                }
+
+                Ok(())
+            },
+            BookMessages::Buffered | BookMessages::Dropped => Ok(()),
+            BookMessages::Gap(symbol) => {
+                error!("BitMEX {} of a level missing from the {} book. Resubscribing...", action, symbol);
+                self.snapshot_received = false;
+
+                self.resubscribe_book(&symbol)
+            },
+        }
+    }
+
+    /// Fetches the instruments again on a separate thread, so that the connection keeps reading from the socket
+    /// in the meantime. Levels of an instrument we don't know of yet are skipped until the refresh completes.
+    fn refresh_instruments(&self) {
+        let environment = self.environment;
+        let rate_limiter = self.rate_limiter.clone();
+        let retry_policy = self.instrument_retry_policy.clone();
+        let asset_indexes = self.asset_indexes.clone();
+        let asset_tick_size = self.asset_tick_size.clone();
+
+        thread::spawn(move || match fetch_instruments(environment, &rate_limiter, &retry_policy) {
+            Ok(instruments) => {
+                load_instruments(&instruments, &asset_indexes, &asset_tick_size);
+                debug!("Refreshed {} BitMEX instruments", instruments.len());
+            },
+            Err(e) => error!("Failed to refresh BitMEX instruments: {}", e),
         });
+    }
+
+    /// Queues a message for the connection's worker
+    fn send_to_worker(&self, raw: RawMessage) -> Result<(), Error> {
+        match self.worker {
+            Some(ref worker) => worker.send(raw)
+                .map_err(|_| Error::new(ws::ErrorKind::Internal, "BitMEX message worker exited")),
+            None => Ok(()),
+        }
+    }
+
+    /// Unsubscribes from a symbol's book and subscribes to it again, so that BitMEX sends a new partial
+    fn resubscribe_book(&mut self, symbol: &str) -> Result<(), Error> {
+        self.book.reset(symbol);
+
+        for op in &["unsubscribe", "subscribe"] {
+            let msg = BitMEXSubscription {
+                op: op.to_string(),
+                args: vec![format!("orderBookL2:{}", symbol)],
+            };
+
+            self.out.send(serde_json::to_string(&msg).unwrap())?;
+        }
 
         Ok(())
     }
+}
 
-    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
-        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
-        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
-        println!("BitMEX Socket is closing. Opening a new connection...");
+/// What to do when the [`Keepalive`] check fires
+#[derive(Debug, PartialEq)]
+pub enum KeepaliveAction {
+    /// Messages are still arriving. Check again in this many milliseconds
+    Wait(u64),
+    /// The connection went idle: send a `ping`, and check again in this many milliseconds
+    Ping(u64),
+    /// Nothing arrived since our `ping`: the connection is dead
+    Expired,
+}
 
-        ws::connect(self.host.clone(), |out| WSExchangeSender{
-            host: self.host.clone(),
-            snapshot_received: false,
-            metadata: self.metadata.clone(),
+/// Decides when to ping BitMEX, and when to give up on a connection that stopped answering. We only ping once
+/// nothing has arrived for `idle_ms`, and give up if nothing (not even the `pong`) arrives within `pong_timeout_ms`.
+///
+/// A single check is scheduled at a time: whenever it fires, [`Keepalive::on_timeout`] says when the next one is due.
+/// Messages don't reschedule it, they only push the next `ping` back.
+#[derive(Clone, Debug)]
+pub struct Keepalive {
+    /// How long the connection can go without a message before we ping it
+    pub idle_ms: u64,
+    /// How long we wait for anything to arrive after a `ping`
+    pub pong_timeout_ms: u64,
+    /// Whether we've sent a `ping` and haven't received anything since
+    awaiting_pong: bool,
+}
 
-            single_channels: self.single_channels.clone(),
-            dual_channels: self.dual_channels.clone(),
+impl Keepalive {
+    /// Creates a keepalive for a connection that was just opened. The first check is due in `idle_ms`
+    pub fn new(idle_ms: u64, pong_timeout_ms: u64) -> Keepalive {
+        Keepalive {
+            idle_ms,
+            pong_timeout_ms,
+            awaiting_pong: false,
+        }
+    }
 
-            asset_indexes: self.asset_indexes.clone(),
-            asset_tick_size: self.asset_tick_size.clone(),
+    /// To be called with every message (or frame) we receive
+    pub fn on_message(&mut self) {
+        self.awaiting_pong = false;
+    }
 
-            tectonic: self.tectonic.clone(),
-            r: self.r.clone(),
+    /// To be called when the check fires, with the time elapsed since the last message (`None` if nothing arrived yet)
+    pub fn on_timeout(&mut self, since_last_message: Option<Duration>) -> KeepaliveAction {
+        if self.awaiting_pong {
+            return KeepaliveAction::Expired;
+        }
 
-            out,
-        }).unwrap();
+        let idle = Duration::from_millis(self.idle_ms);
+
+        match since_last_message {
+            // Check again once the connection has been idle for long enough
+            Some(elapsed) if elapsed < idle => {
+                let remaining = idle - elapsed;
+                KeepaliveAction::Wait(remaining.as_secs() * 1000 + remaining.subsec_millis() as u64 + 1)
+            },
+            _ => {
+                self.awaiting_pong = true;
+                KeepaliveAction::Ping(self.pong_timeout_ms)
+            },
+        }
     }
+}
 
-    fn on_timeout(&mut self, _: Token) -> Result<(), ws::Error> {
-        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
-        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
-        println!("BitMEX Socket timed out (5s of inactivity). Opening a new connection...");
+/// Number of `orderBookL2` messages held back per symbol while we wait for its partial. Once full, the oldest are dropped
+pub const MAX_PENDING_BOOK_MESSAGES: usize = 1_000;
+
+/// What became of an `orderBookL2` message handed to a [`BookTracker`]
+pub enum BookMessages {
+    /// Messages to handle, in order. A partial is followed by the messages that arrived before it
+    Ready(Vec<RawMessage>),
+    /// The message arrived before its symbol's partial, and is held back until it does
+    Buffered,
+    /// The message can't be applied (i.e. it has no levels)
+    Dropped,
+    /// The message doesn't match the symbol's book, meaning we missed something. A new partial is needed
+    Gap(String),
+}
 
-        ws::connect(self.host.clone(), |out| WSExchangeSender{
-            host: self.host.clone(),
-            snapshot_received: false,
-            metadata: self.metadata.clone(),
+/// Keeps track of the level IDs in the book of every symbol, so that we know whether messages apply to it.
+/// BitMEX doesn't number its messages, so an update or delete of a level we never received is how we spot a gap.
+///
+/// Messages that arrive before their symbol's partial are held back and replayed right after it, leaving out
+/// the ones that don't match its levels: the partial already reflects those.
+pub struct BookTracker {
+    /// Whether messages that don't match the book are reported as gaps, rather than let through
+    gap_detection: bool,
+    /// Level IDs in the book of every symbol we've received the partial of
+    book_ids: HashMap<String, HashSet<u64>>,
+    /// Messages received before their symbol's partial, oldest first
+    pending: HashMap<String, VecDeque<RawMessage>>,
+}
+
+impl BookTracker {
+    /// Creates a tracker that hasn't received any partial yet
+    pub fn new(gap_detection: bool) -> BookTracker {
+        BookTracker {
+            gap_detection,
+            book_ids: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Tracks an `orderBookL2` message, parsing it unless it already was (see [`RawMessage::book`])
+    pub fn track(&mut self, mut raw: RawMessage) -> BookMessages {
+        if raw.book.is_none() {
+            raw.book = BookMessage::parse(&raw);
+        }
+
+        let (partial, symbol) = match raw.book {
+            Some(ref book) => (book.action == "partial", book.symbol()),
+            None => return BookMessages::Dropped,
+        };
+
+        if partial {
+            let symbol = match symbol {
+                Some(symbol) => symbol,
+                None => return BookMessages::Ready(vec![raw]),
+            };
+
+            let ids = raw.book.iter()
+                .flat_map(|book| book.data.iter().filter_map(|level| level.id))
+                .collect();
+            self.book_ids.insert(symbol.clone(), ids);
+
+            let mut ready = vec![raw];
+
+            for raw in self.pending.remove(&symbol).unwrap_or_default() {
+                if self.apply(&symbol, &raw) {
+                    ready.push(raw);
+                } else {
+                    debug!("Dropping BitMEX {} received before the {} partial, which already reflects it", book_action(&raw), symbol);
+                }
+            }
+
+            return BookMessages::Ready(ready);
+        }
+
+        let symbol = match symbol {
+            Some(symbol) => symbol,
+            None => return BookMessages::Dropped,
+        };
+
+        if !self.has_partial(&symbol) {
+            let pending = self.pending.entry(symbol).or_insert_with(VecDeque::new);
+
+            if pending.len() >= MAX_PENDING_BOOK_MESSAGES {
+                pending.pop_front();
+            }
+            pending.push_back(raw);
+
+            return BookMessages::Buffered;
+        }
+
+        if !self.apply(&symbol, &raw) && self.gap_detection {
+            self.reset(&symbol);
+            return BookMessages::Gap(symbol);
+        }
+
+        BookMessages::Ready(vec![raw])
+    }
+
+    /// Whether we've received the symbol's partial (and haven't lost track of its book since)
+    pub fn has_partial(&self, symbol: &str) -> bool {
+        self.book_ids.contains_key(symbol)
+    }
+
+    /// Forgets about a symbol's book and the messages held back for it, until its next partial
+    pub fn reset(&mut self, symbol: &str) {
+        self.book_ids.remove(symbol);
+        self.pending.remove(symbol);
+    }
+
+    /// Applies the message's level IDs to the symbol's book. Returns false if they don't match it
+    fn apply(&mut self, symbol: &str, raw: &RawMessage) -> bool {
+        let ids = match self.book_ids.get_mut(symbol) {
+            Some(ids) => ids,
+            None => return false,
+        };
+        let book = match raw.book {
+            Some(ref book) => book,
+            None => return true,
+        };
+        let mut level_ids = book.data.iter().filter_map(|level| level.id);
+
+        match book.action.as_str() {
+            "insert" => {
+                ids.extend(level_ids);
+                true
+            },
+            "update" => level_ids.all(|id| ids.contains(&id)),
+            "delete" => level_ids.fold(true, |matches, id| ids.remove(&id) && matches),
+            _ => true,
+        }
+    }
+}
+
+/// Action of a tracked `orderBookL2` message (i.e. `update`)
+fn book_action(raw: &RawMessage) -> &str {
+    raw.book.as_ref().map_or("", |book| book.action.as_str())
+}
+
+/// Drops deltas that were already delivered while two connections overlap during a handoff. Deltas are
+/// keyed on their symbol, price, size and event, along with BitMEX's timestamp when it sends one. Most book
+/// updates aren't timestamped, and the time each connection received an update differs, so that's left out.
+/// Outside of a handoff, every delta is let through.
+#[derive(Default)]
+pub struct HandoffDedup {
+    /// Keys of the deltas seen since the handoff began
+    seen: Option<HashSet<(String, u32, u32, u8, Option<u64>)>>,
+}
+
+impl HandoffDedup {
+    /// Starts deduplicating deltas
+    pub fn begin(&mut self) {
+        self.seen = Some(HashSet::new());
+    }
+
+    /// Stops deduplicating deltas, once only one connection is left
+    pub fn end(&mut self) {
+        self.seen = None;
+    }
+
+    /// Whether we're in the middle of a handoff
+    pub fn active(&self) -> bool {
+        self.seen.is_some()
+    }
+
+    /// Returns false if an identical delta was already seen during the current handoff
+    pub fn is_new(&mut self, delta: &orderbook::Delta) -> bool {
+        match self.seen.as_mut() {
+            Some(seen) => seen.insert((
+                delta.symbol.clone(),
+                delta.price.to_bits(),
+                delta.size.to_bits(),
+                delta.event,
+                delta.exchange_ts().map(f64::to_bits))),
+            None => true,
+        }
+    }
+}
+
+/// Connection opened by a [`SocketManager`]
+struct ManagedConnection {
+    /// When the connection was opened
+    opened: Instant,
+    /// Handle used to close the connection
+    out: Sender,
+    /// Set once the connection's event loop has exited
+    closed: Arc<AtomicBool>,
+}
+
+impl ManagedConnection {
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+}
+
+/// Redis channel messages of a private table are published to (i.e. `bitmex_private_execution`)
+pub fn private_channel_name(prefix: &str, table: &str) -> String {
+    format!("{}_{}", prefix, table)
+}
+
+/// Replaces the book of every symbol in the snapshot with the snapshot's levels. Returns the snapshot of
+/// every book seeded, as of the latest level
+pub fn seed_books(
+    books: &RwLock<HashMap<String, orderbook::Level2Orderbook>>,
+    snapshot: &[orderbook::Delta],
+) -> Vec<orderbook::Level2Snapshot> {
+    let mut seeded: HashMap<String, (orderbook::Level2Orderbook, f64)> = HashMap::new();
+
+    for delta in snapshot {
+        let &mut (ref mut book, ref mut ts) = seeded.entry(delta.symbol.clone())
+            .or_insert_with(|| (orderbook::Level2Orderbook::new(&delta.symbol, Exchange::BitMEX), delta.ts));
 
-            single_channels: self.single_channels.clone(),
-            dual_channels: self.dual_channels.clone(),
+        book.apply(delta);
+        *ts = ts.max(delta.ts);
+    }
+
+    let snapshots = seeded.values()
+        .map(|&(ref book, ts)| book.snapshot(ts))
+        .collect();
+
+    books.write().unwrap().extend(seeded.into_iter().map(|(symbol, (book, _))| (symbol, book)));
+
+    snapshots
+}
+
+/// Applies deltas to the books of their symbols. Deltas of a symbol we don't have the snapshot of are ignored
+pub fn apply_to_books(books: &RwLock<HashMap<String, orderbook::Level2Orderbook>>, deltas: &[orderbook::Delta]) {
+    let mut books = books.write().unwrap();
+
+    for delta in deltas {
+        if let Some(book) = books.get_mut(&delta.symbol) {
+            book.apply(delta);
+        }
+    }
+}
+
+/// Calls `callback` with every delta, in order
+pub fn notify(callback: Option<&DeltaCallback>, deltas: &[orderbook::Delta]) {
+    if let Some(callback) = callback {
+        for delta in deltas {
+            callback(delta);
+        }
+    }
+}
+
+/// Number of messages a connection's worker can fall behind by. Once the queue is full, the
+/// connection stops reading from the socket until the worker catches up.
+pub const WORKER_QUEUE_CAPACITY: usize = 10_000;
+
+/// Message handed from `on_message` to the connection's worker
+pub struct RawMessage {
+    /// Table the message belongs to (i.e. `orderBookL2`). Empty if the message has none
+    pub table: String,
+    /// Message as received
+    pub data: Vec<u8>,
+    /// Time the message was received, used for the events BitMEX doesn't timestamp
+    pub ts: f64,
+    /// `orderBookL2` message, once parsed by the [`BookTracker`]. The worker only parses the data when it's `None`
+    pub book: Option<BookMessage>,
+}
+
+/// What a [`RawMessage`] contained
+pub enum ParsedMessage {
+    /// Funding rates, from the `funding` table
+    Funding(Vec<orderbook::FundingRate>),
+    /// Trades, from the `trade` table
+    Trades(Vec<orderbook::Trade>),
+    /// Orderbook deltas, from the `orderBookL2` table
+    Deltas(Vec<orderbook::Delta>),
+    /// Every level of a book, from an `orderBookL2` partial
+    Snapshot(Vec<orderbook::Delta>),
+    /// Liquidation orders, from the `liquidation` table
+    Liquidations(Vec<orderbook::Liquidation>),
+    /// Message of a private table (see [`PRIVATE_TABLES`]), as received
+    Private {
+        /// Table the message belongs to (i.e. `execution`)
+        table: String,
+        /// JSON message
+        message: String,
+    },
+}
+
+/// Starts a worker that parses messages in the order they're queued and hands them to `handle`.
+/// The worker exits once every queue sender has been dropped (i.e. when the connection closes).
+pub fn spawn_worker<F>(
+    asset_indexes: Arc<RwLock<HashMap<String, u64>>>,
+    asset_tick_size: Arc<RwLock<HashMap<String, f32>>>,
+    mut handle: F,
+) -> (mpsc::SyncSender<RawMessage>, thread::JoinHandle<()>)
+    where F: FnMut(ParsedMessage) + Send + 'static
+{
+    let (sender, receiver) = mpsc::sync_channel::<RawMessage>(WORKER_QUEUE_CAPACITY);
+
+    let worker = thread::spawn(move || {
+        for raw in receiver {
+            if let Some(parsed) = parse_message(&raw, &asset_indexes, &asset_tick_size) {
+                handle(parsed);
+            }
+        }
+    });
+
+    (sender, worker)
+}
+
+/// Parses a message into funding rates, trades, liquidations or deltas. Returns `None` for messages we don't
+/// collect (i.e. subscription responses) and the ones that fail to parse.
+pub fn parse_message(
+    raw: &RawMessage,
+    asset_indexes: &RwLock<HashMap<String, u64>>,
+    asset_tick_size: &RwLock<HashMap<String, f32>>,
+) -> Option<ParsedMessage> {
+    if raw.table == "funding" {
+        return match serde_json::from_slice::<BitMEXFundingMessage>(&raw.data) {
+            Ok(message) => Some(ParsedMessage::Funding(message.data.iter()
+                .filter_map(|funding| funding.to_funding_rate())
+                .collect())),
+            Err(e) => {
+                error!("Failed to parse BitMEX funding message: {}", e);
+                None
+            }
+        };
+    }
+
+    // Subscription responses and other misc. data have no table
+    if raw.table == "" {
+        return None;
+    }
+
+    match raw.table.as_str() {
+        "trade" => parse_data(raw).and_then(|message| parse_trades(message, raw.ts)),
+        "liquidation" => parse_data(raw).and_then(|message| parse_liquidations(message, raw.ts)),
+        table if is_private_table(table) => Some(ParsedMessage::Private {
+            table: table.to_string(),
+            message: String::from_utf8_lossy(&raw.data).into_owned(),
+        }),
+        _ => match raw.book {
+            Some(ref book) => Some(parse_deltas(book, raw.ts, asset_indexes, asset_tick_size)),
+            None => BookMessage::parse(raw).map(|book| parse_deltas(&book, raw.ts, asset_indexes, asset_tick_size)),
+        },
+    }
+}
+
+/// Parses the message with the schema of its table
+fn parse_data<T: DeserializeOwned>(raw: &RawMessage) -> Option<BitMEXMessage<T>> {
+    match serde_json::from_slice::<BitMEXMessage<T>>(&raw.data) {
+        Ok(message) => Some(message),
+        Err(e) => {
+            error!("Failed to parse BitMEX {} message: {}", raw.table, e);
+            None
+        }
+    }
+}
+
+fn parse_trades(message: BitMEXMessage<BitMEXTradeData>, ts: f64) -> Option<ParsedMessage> {
+    // The initial trade partial only contains historical trades
+    if message.action == "partial" {
+        return None;
+    }
+
+    Some(ParsedMessage::Trades(message.data.into_iter()
+        .filter_map(|trade| {
+            let exchange_ts = trade.timestamp.as_ref().and_then(|timestamp| parse_timestamp(timestamp));
+
+            Some(orderbook::Trade {
+                price: trade.price? as f64,
+                size: trade.size? as f64,
+                side: if trade.side == "Buy" {
+                    orderbook::TradeSide::Buy
+                } else {
+                    orderbook::TradeSide::Sell
+                },
+                ts: exchange_ts.unwrap_or(ts),
+                symbol: trade.symbol,
+                exchange: Exchange::BitMEX,
+                trade_id: trade.trd_match_id,
+                received_ts: exchange_ts.map(|_| ts),
+            })
+        })
+        .collect()))
+}
+
+fn parse_liquidations(message: BitMEXMessage<BitMEXLiquidationData>, ts: f64) -> Option<ParsedMessage> {
+    let action = match message.action.as_str() {
+        // The partial lists the liquidations already in progress, which consumers haven't seen inserted
+        "partial" | "insert" => orderbook::LiquidationAction::Insert,
+        "update" => orderbook::LiquidationAction::Update,
+        "delete" => orderbook::LiquidationAction::Delete,
+        _ => return None,
+    };
+
+    // Rows without a symbol can't be published on a symbol's channel
+    Some(ParsedMessage::Liquidations(message.data.into_iter()
+        .filter_map(|liquidation| Some(orderbook::Liquidation {
+            order_id: liquidation.order_id,
+            action,
+            symbol: liquidation.symbol?,
+            side: liquidation.side,
+            price: liquidation.price,
+            leaves_qty: liquidation.leaves_qty,
+            ts,
+        }))
+        .collect()))
+}
+
+fn parse_deltas(
+    message: &BookMessage,
+    ts: f64,
+    asset_indexes: &RwLock<HashMap<String, u64>>,
+    asset_tick_size: &RwLock<HashMap<String, f32>>,
+) -> ParsedMessage {
+    let mut deltas: Vec<orderbook::Delta> = Vec::with_capacity(message.data.len());
+    // Tick sizes learned from the levels that come with a price, for the levels of this message that don't
+    let mut encoded_tick_sizes: HashMap<String, f32> = HashMap::new();
+    let indexes = asset_indexes.read().unwrap();
+    let tick_sizes = asset_tick_size.read().unwrap();
+
+    for update in &message.data {
+        // Let's make sure we don't parse any values with no ID
+        let id = match update.id {
+            Some(id) => id,
+            None => continue,
+        };
+
+        // Book updates usually aren't timestamped by BitMEX, in which case they keep the time we received them
+        let exchange_ts = update.timestamp.as_ref().and_then(|timestamp| parse_timestamp(timestamp));
+
+        // Trades come from the `trade` table (see `parse_trades`), so every level here is a book event
+        let bid = update.side == "Buy";
+
+        // Deletes are the only action that leaves the size out. Anything else without one can't be applied.
+        // Inserted levels are updates of a level that had no size
+        let (event, size) = match (message.action.as_str(), update.size) {
+            ("delete", _) => (if bid { DeltaEvent::BidRemove } else { DeltaEvent::AskRemove }, 0.0),
+            ("partial", Some(size)) | ("insert", Some(size)) | ("update", Some(size)) => {
+                (if bid { DeltaEvent::BidUpdate } else { DeltaEvent::AskUpdate }, size)
+            },
+            ("partial", None) | ("insert", None) | ("update", None) => {
+                warn!("Skipping BitMEX {} of {} level {} without a size", message.action, update.symbol, id);
+                continue;
+            },
+            (action, _) => {
+                warn!("Skipping BitMEX {} of {} level {}: unknown action", action, update.symbol, id);
+                continue;
+            },
+        };
+
+        let index = indexes.get(&update.symbol).cloned();
 
-            asset_indexes: self.asset_indexes.clone(),
-            asset_tick_size: self.asset_tick_size.clone(),
+        let price = match (update.price, index) {
+            // Partials and inserts come with the price, which tells us how the level IDs are encoded
+            (Some(price), Some(index)) => {
+                let known = encoded_tick_sizes.get(&update.symbol).or(tick_sizes.get(&update.symbol)).cloned();
 
-            tectonic: self.tectonic.clone(),
-            r: self.r.clone(),
+                match encoded_tick_size(id, index, price) {
+                    Some(tick_size) if known.map_or(true, |known| (tick_size - known).abs() > known * 1e-3) => {
+                        encoded_tick_sizes.insert(update.symbol.clone(), tick_size);
+                    },
+                    _ => (),
+                }
 
+                Some(price)
+            },
+            (None, Some(index)) => encoded_tick_sizes.get(&update.symbol).or(tick_sizes.get(&update.symbol))
+                .and_then(|&tick_size| level_price(id, index, tick_size)),
+            _ => None,
+        };
+        let price = match price {
+            Some(price) => price,
+            None => {
+                warn!("Unable to decode the price of BitMEX {} level {}", update.symbol, id);
+                continue;
+            },
+        };
+
+        deltas.push(orderbook::Delta {
+            symbol: update.symbol.clone(),
+            price,
+            size,
+            seq: 0,
+            event: event.bits(),
+            ts: exchange_ts.unwrap_or(ts),
+            received_ts: exchange_ts.map(|_| ts),
+        });
+    }
+
+    drop(tick_sizes);
+    if !encoded_tick_sizes.is_empty() {
+        asset_tick_size.write().unwrap().extend(encoded_tick_sizes);
+    }
+
+    if message.action == "partial" {
+        ParsedMessage::Snapshot(deltas)
+    } else {
+        ParsedMessage::Deltas(deltas)
+    }
+}
+
+/// Keeps the BitMEX feed going across connection drops. Every `handoff_after`, a backup connection is
+/// opened next to the primary one. Both run for `overlap` so that no delta is lost, after which the backup
+/// takes over and the primary is closed. Deltas delivered by both connections are only published once.
+///
+/// Connections send their deltas to the manager, which publishes them to the `redis_channel` of the settings.
+/// Trades are published there as well (as deltas with the `TRADE` flag) instead of on the `:trades` channel.
+pub struct SocketManager {
+    /// Settings every connection is opened with
+    settings: WSExchange,
+
+    /// How long a connection is used before handing off to a new one
+    pub handoff_after: Duration,
+    /// How long the old and new connections run side by side during a handoff
+    pub overlap: Duration,
+
+    /// Connections send their deltas through here
+    sender: mpsc::Sender<orderbook::Delta>,
+    receiver: mpsc::Receiver<orderbook::Delta>,
+
+    /// Deduplicates deltas during handoffs
+    dedup: HandoffDedup,
+}
+
+impl SocketManager {
+    /// Creates a manager that hands off to a new connection every 15 minutes, with a 30 second overlap
+    pub fn new(settings: WSExchange) -> SocketManager {
+        let (sender, receiver) = mpsc::channel();
+
+        SocketManager {
+            settings: settings.with_environment(),
+
+            handoff_after: Duration::from_secs(15 * 60),
+            overlap: Duration::from_secs(30),
+
+            sender,
+            receiver,
+
+            dedup: HandoffDedup::default(),
+        }
+    }
+
+    /// Opens a new connection on its own thread, sending its deltas to the manager
+    fn open(&mut self) -> Result<ManagedConnection, Error> {
+        let mut settings = self.settings.clone();
+        settings.channel = Some(self.sender.clone());
+
+        let url = Url::parse(&settings.environment.host()).map_err(|e| Error::new(ws::ErrorKind::Internal, e.to_string()))?;
+        let r = Arc::new(Mutex::new(settings.init_redis()
+            .map_err(|e| Error::new(ws::ErrorKind::Internal, format!("Failed to connect to Redis server: {}", e)))?));
+        let closed = Arc::new(AtomicBool::new(false));
+        let (handle_tx, handle_rx) = mpsc::channel();
+
+        let closed_ref = closed.clone();
+
+        thread::spawn(move || {
+            let socket = ws::WebSocket::new(|out| WSExchangeSender {
+                environment: settings.environment,
+
+                snapshot_received: false,
+                callback: settings.callback.clone(),
+                api_key: settings.api_key.clone(),
+                api_secret: settings.api_secret.clone(),
+                metadata: settings.metadata.clone(),
+
+                single_channels: settings.single_channels.clone(),
+                dual_channels: settings.dual_channels.clone(),
+
+                asset_indexes: Arc::new(RwLock::new(settings.asset_indexes.clone())),
+                asset_tick_size: Arc::new(RwLock::new(settings.asset_tick_size.clone())),
+
+                health: settings.health.clone(),
+                books: settings.books.clone(),
+                storage: Arc::new(Mutex::new(settings.storage.clone())),
+                r: r.clone(),
+                redis_channel: settings.redis_channel.clone(),
+                private_redis_channel: settings.private_redis_channel.clone(),
+                funding_redis_channel: settings.funding_redis_channel.clone(),
+                liquidation_redis_channel: settings.liquidation_redis_channel.clone(),
+                store_funding: settings.store_funding,
+                // The manager deduplicates what its connections send it
+                dedup: Arc::new(Mutex::new(DeduplicationWindow::new(settings.dedup_capacity))),
+                delta_filter: settings.delta_filter.clone(),
+
+                rate_limiter: settings.rate_limiter.clone(),
+
+                gap_detection: settings.gap_detection,
+                book: BookTracker::new(settings.gap_detection),
+
+                keepalive: Keepalive::new(settings.ping_interval_ms, settings.pong_timeout_ms),
+                instrument_refresh_ms: settings.instrument_refresh_ms,
+                instrument_retry_policy: settings.instrument_retry_policy.clone(),
+
+                channel: settings.channel.clone(),
+                worker: None,
+                worker_thread: None,
+
+                out,
+            });
+
+            let result = socket.and_then(|mut socket| {
+                let _ = handle_tx.send(Ok(socket.broadcaster()));
+                socket.connect(url)?;
+                socket.run()
+            });
+
+            if let Err(e) = result {
+                let _ = handle_tx.send(Err(e));
+            }
+
+            closed_ref.store(true, Ordering::SeqCst);
+        });
+
+        let out = handle_rx.recv()
+            .map_err(|_| Error::new(ws::ErrorKind::Internal, "BitMEX connection thread exited"))??;
+
+        Ok(ManagedConnection {
+            opened: Instant::now(),
             out,
-        }).unwrap();
+            closed,
+        })
+    }
+
+    /// Runs the manager. This blocks for as long as the feed is being collected, i.e. until [`ConnectionHealth::stop`] is called on its settings' health.
+    pub fn run(mut self) -> Result<(), Error> {
+        let r = self.settings.init_redis()
+            .map_err(|e| Error::new(ws::ErrorKind::Internal, format!("Failed to connect to Redis server: {}", e)))?;
+
+        let mut primary = self.open()?;
+        let mut backup: Option<ManagedConnection> = None;
+        let mut attempts = 0;
+
+        while !self.settings.health.is_stopped() {
+            // Drain whatever the connections sent us since the last iteration
+            let mut deltas: Vec<orderbook::Delta> = vec![];
+
+            if let Ok(delta) = self.receiver.recv_timeout(Duration::from_millis(100)) {
+                deltas.push(delta);
+                deltas.extend(self.receiver.try_iter());
+            }
+
+            let dedup = &mut self.dedup;
+            deltas.retain(|delta| dedup.is_new(delta));
+
+            if !deltas.is_empty() {
+                if let Err(e) = self.settings.storage.insert(&deltas) {
+                    error!("BitMEX socket manager failed to store deltas: {}", e);
+                }
+                notify(self.settings.callback.as_ref(), &deltas);
+
+                publish_deltas(&r, &self.settings.redis_channel, &deltas, &self.settings.health);
+            }
+
+            // Open a backup when the primary is due for a handoff, or if it dropped on its own
+            if backup.is_none() && (primary.is_closed() || primary.opened.elapsed() >= self.handoff_after) {
+                if attempts > 0 {
+                    thread::sleep(self.settings.reconnect_policy.delay(attempts - 1));
+                }
+
+                match self.open() {
+                    Ok(connection) => {
+                        info!("BitMEX socket manager opened a backup connection");
+                        self.dedup.begin();
+                        backup = Some(connection);
+                        attempts = 0;
+                    },
+                    Err(e) => {
+                        error!("BitMEX socket manager failed to open a backup connection: {}", e);
+                        self.settings.health.record_reconnect();
+                        attempts += 1;
+
+                        if self.settings.reconnect_policy.exhausted(attempts) {
+                            return Err(e);
+                        }
+                    },
+                }
+            }
+
+            // Cut over once both connections have overlapped long enough. There's nothing
+            // to overlap with if the primary already dropped.
+            let cutover = match &backup {
+                Some(connection) => primary.is_closed() || connection.opened.elapsed() >= self.overlap,
+                None => false,
+            };
+
+            if cutover {
+                if !primary.is_closed() {
+                    let _ = primary.out.close(ws::CloseCode::Normal);
+                }
+
+                primary = backup.take().unwrap();
+                self.dedup.end();
+                self.settings.health.record_reconnect();
+
+                info!("BitMEX socket manager handed off to the backup connection");
+            }
+        }
+
+        // The connections close on their own as well, but there's no need to wait for them
+        for connection in Some(primary).into_iter().chain(backup) {
+            if !connection.is_closed() {
+                let _ = connection.out.close(ws::CloseCode::Normal);
+            }
+        }
+
+        exchange::flush_storage(Exchange::BitMEX, &mut *self.settings.storage);
 
         Ok(())
     }
-}
\ No newline at end of file
+}
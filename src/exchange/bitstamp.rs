@@ -0,0 +1,459 @@
+use std::thread;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use redis;
+use serde_json::{self, Value};
+use ws;
+use ws::util::Token;
+use ws::{Error, Handler, Handshake, Message, Sender};
+
+use config::{Config, ConfigError};
+use exchange::{self, Asset, AssetExchange, ConnectionHealth, Exchange, ExchangeError, MarketType};
+use orderbook;
+use storage::{StorageBackend, TectonicBackend};
+
+const EXPIRE: Token = Token(1);
+
+/// Exchange related metadata. The fields are used to establish
+/// a successful connection with the exchange via websockets.
+#[derive(Clone)]
+pub struct WSExchange {
+    /// Full URL to connect to. Example: `wss://ws.bitstamp.net`
+    pub host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    pub snapshot_received: bool,
+
+    /// Collection metadata
+    pub metadata: MetaData,
+    /// Type of market we're collecting. Deltas are published to the `<exchange>:<market type>` Redis channel
+    pub market_type: MarketType,
+
+    /// Channel name prefixes we subscribe to for every asset pair (i.e. `diff_order_book`, `live_trades`)
+    pub single_channels: Vec<String>,
+
+    /// Connection health, shared with the running websocket handler
+    pub health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    pub storage: Box<dyn StorageBackend>,
+
+    /// Redis URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub redis_url: String,
+    /// Redis client (before connection). Opened from `redis_url` when connecting
+    pub r: redis::Client,
+    /// Redis password: If this is present, it replaces the password in `redis_url` (if any) when connecting
+    pub r_password: Option<String>,
+}
+
+/// Create two identical structs and transfer the data over when we start the websocket.
+pub struct WSExchangeSender {
+    /// Full URL to connect to. Example: `wss://ws.bitstamp.net`
+    host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    snapshot_received: bool,
+
+    /// Collection metadata
+    metadata: MetaData,
+
+    /// Channel name prefixes we subscribe to for every asset pair
+    single_channels: Vec<String>,
+
+    /// Connection health
+    health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    storage: Box<dyn StorageBackend>,
+    /// Redis client (used to send deltas as PUBSUB)
+    r: Arc<Mutex<redis::Connection>>,
+
+    /// Websocket sender
+    out: Sender,
+}
+
+/// Meta data for our data source. This is useful for data warehousing and accessing the data.
+/// All types contained within are considered optional. This may be expanded in the future.
+#[derive(Clone)]
+pub struct MetaData {
+    /// Exchange name. We will warehouse the data under this name
+    pub exchange: Arc<String>,
+
+    /// Vector of asset pairs we're going to warehouse
+    pub asset_pair: Option<Vec<[exchange::Asset; 2]>>,
+
+    /// Type of market we're warehousing. Set from `WSExchange::market_type` when connecting
+    pub market_type: Option<MarketType>,
+
+    /// Starting datetime of our data collection
+    start_date: Option<DateTime<Utc>>,
+
+    /// Ending datetime of our data collection
+    end_date: Option<DateTime<Utc>>,
+}
+
+impl WSExchange {
+    /// Health of the connection to the exchange
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+}
+
+impl AssetExchange for WSExchange {
+    fn default_settings() -> Result<Box<Self>, String> {
+        Ok(Box::new(Self {
+            host: "wss://ws.bitstamp.net".into(),
+
+            snapshot_received: false,
+
+            metadata: MetaData {
+                exchange: Arc::new("bitstamp".into()),
+                asset_pair: Some(vec![
+                    [Asset::BTC, Asset::USD],]),
+                market_type: None,
+                start_date: None,
+                end_date: None,
+            },
+            market_type: MarketType::Spot,
+
+            single_channels: vec![
+                "diff_order_book".into(),
+                "live_trades".into()],
+
+            health: ConnectionHealth::new(Exchange::Bitstamp),
+
+            storage: Box::new(TectonicBackend::new(None, None, "bitstamp").map_err(|e| format!("Unable to connect to TectonicDB: {}", e))?),
+            redis_url: exchange::DEFAULT_REDIS_URL.into(),
+            r: redis::Client::open(exchange::DEFAULT_REDIS_URL).unwrap(),
+            r_password: None,
+        }))
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        if let Some(pairs) = config.asset_pairs()? {
+            self.metadata.asset_pair = Some(pairs);
+        }
+        if let Some(ref channels) = config.channels.names {
+            self.single_channels = channels.clone();
+        }
+        if let Some(ref url) = config.redis.url {
+            self.redis_url = url.clone();
+        }
+        if config.redis.password.is_some() {
+            self.r_password = config.redis.password.clone();
+        }
+        if let Some(storage) = config.storage("bitstamp")? {
+            self.storage = storage;
+        }
+
+        Ok(())
+    }
+
+    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
+        // The password is sent as part of the URL, so the client authenticates on its own
+        self.r = exchange::redis_client(&self.redis_url, self.r_password.as_ref().map(|password| password.as_str()))?;
+
+        self.r.get_connection()
+    }
+
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    fn start(&self) -> Result<(), ExchangeError> {
+        WSExchange::run_with_settings(Some(self))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
+        // Try to use the settings the user passes before resorting to default settings.
+        let mut settings = match settings {
+            Some(settings) => settings.clone(),
+            None => *WSExchange::default_settings().map_err(ConfigError::Settings)?,
+        };
+
+        let r = Arc::new(Mutex::new(settings.init_redis()?));
+
+        ws::connect(settings.host.clone(), |out| WSExchangeSender {
+            host: settings.host.clone(),
+
+            snapshot_received: settings.snapshot_received.clone(),
+            metadata: MetaData { market_type: Some(settings.market_type), ..settings.metadata.clone() },
+
+            single_channels: settings.single_channels.clone(),
+
+            health: settings.health.clone(),
+            storage: settings.storage.clone(),
+            r: r.clone(),
+
+            out,
+        })?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SubscribeMessage {
+    event: String,
+    data: SubscribeData,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SubscribeData {
+    channel: String,
+}
+
+/// Every message pushed by Bitstamp has this form
+#[derive(Deserialize)]
+struct EventMessage {
+    /// `data` for orderbook updates, `trade` for trades, and `bts:*` for control messages
+    event: String,
+    /// Channel the data belongs to (i.e. `diff_order_book_btcusd`)
+    channel: String,
+    data: Value,
+}
+
+/// Diff order book channel data
+#[derive(Deserialize)]
+struct BookData {
+    /// Timestamp in microseconds
+    microtimestamp: String,
+    /// Bid levels as `[price, amount]`
+    bids: Vec<[String; 2]>,
+    /// Ask levels as `[price, amount]`
+    asks: Vec<[String; 2]>,
+}
+
+/// Live trades channel data
+#[derive(Deserialize)]
+struct TradeData {
+    id: u64,
+    price: f64,
+    amount: f64,
+    /// 0 for buy, 1 for sell
+    #[serde(rename = "type")]
+    kind: u8,
+    /// Timestamp in microseconds
+    microtimestamp: String,
+}
+
+/// Converts microsecond timestamp strings sent by Bitstamp to seconds
+fn parse_ts(ts: &str) -> f64 {
+    ts.parse::<f64>()
+        .map(|ts| ts * 0.000_001f64)
+        .unwrap_or(Utc::now().timestamp_millis() as f64 * 0.001f64)
+}
+
+/// Strips the channel name prefix to get the symbol (i.e. `diff_order_book_btcusd` to `btcusd`)
+fn channel_symbol(channel: &str) -> String {
+    channel.rsplit('_').next().unwrap_or("").to_string()
+}
+
+impl Handler for WSExchangeSender {
+    fn on_open(&mut self, _: Handshake) -> Result<(), Error> {
+        self.health.track_connection(&self.out);
+
+        // Measure the round-trip time of the new connection
+        self.health.ping_latency(&self.out)?;
+
+        // Set a timeout for 5 seconds of inactivity
+        // Issue: currently, this reruns every five seconds. Comment out while we fix.
+        //self.out.timeout(5_000, EXPIRE).unwrap();
+
+        for pair in self.metadata.asset_pair.as_ref().expect("No asset pairs passed to Bitstamp structure") {
+            let normalized_pair = match exchange::get_asset_pair(pair, Exchange::Bitstamp) {
+                Ok(normalized_pair) => normalized_pair,
+                Err(e) => {
+                    println!("Skipping Bitstamp subscription: {}", e);
+                    continue;
+                }
+            };
+            let db_name = format!("{}_{}", self.metadata.exchange.deref(), normalized_pair);
+
+            // Create the database if it doesn't exist yet. This avoids many issues
+            // relating to inserting to a non-existant database.
+            self.storage.create(&db_name)?;
+
+            // Bitstamp only accepts a single channel per subscription message
+            for channel in &self.single_channels {
+                let msg = SubscribeMessage {
+                    event: "bts:subscribe".into(),
+                    data: SubscribeData {
+                        channel: format!("{}_{}", channel, normalized_pair),
+                    },
+                };
+
+                println!("Sending message {}", serde_json::to_string(&msg).unwrap());
+                self.out.send(serde_json::to_string(&msg).unwrap())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<(), Error> {
+        self.health.record_message();
+
+        let message = match serde_json::from_slice::<EventMessage>(&msg.into_data()) {
+            Ok(message) => message,
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            }
+        };
+
+        // Bitstamp asks us to reconnect ahead of maintenance. Closing the socket ourselves
+        // lands us in `on_close`, which opens a new connection.
+        if message.event == "bts:request_reconnect" {
+            println!("Bitstamp requested a reconnect");
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
+        let redis_ref = self.r.clone();
+        let health = self.health.clone();
+        let channel = exchange::market_channel(&self.metadata.exchange, self.metadata.market_type);
+
+        thread::spawn(move || {
+            let symbol = channel_symbol(&message.channel);
+
+            if message.event == "data" && message.channel.starts_with("diff_order_book") {
+                let book = match serde_json::from_value::<BookData>(message.data) {
+                    Ok(book) => book,
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        return;
+                    }
+                };
+
+                let ts = parse_ts(&book.microtimestamp);
+                let mut deltas: Vec<orderbook::Delta> = Vec::with_capacity(book.asks.len() + book.bids.len());
+
+                // Begin sequence counting at 1 in order to reconstruct a proper sequence count
+                let mut seq = 1;
+
+                for (levels, side) in vec![(book.asks, orderbook::ASK), (book.bids, orderbook::BID)] {
+                    for level in levels {
+                        let (price, size) = match (level[0].parse::<f32>(), level[1].parse::<f32>()) {
+                            (Ok(price), Ok(size)) => (price, size),
+                            _ => continue,
+                        };
+
+                        deltas.push(orderbook::Delta {
+                            symbol: symbol.clone(),
+                            price,
+                            size,
+                            seq,
+                            event: side ^ if size == 0.0 {
+                                orderbook::REMOVE
+                            } else {
+                                orderbook::UPDATE
+                            },
+                            ts,
+                            received_ts: None,
+                        });
+
+                        seq += 1;
+                    }
+                }
+
+                if deltas.is_empty() {
+                    return;
+                }
+
+                exchange::record_symbol_messages(&health, deltas.iter().map(|delta| delta.symbol.as_str()));
+                // Lock the connection until we are able to aquire it
+                exchange::publish_deltas(&*redis_ref.lock().unwrap(), &channel, &deltas, &health);
+
+            } else if message.event == "trade" {
+                let trade = match serde_json::from_value::<TradeData>(message.data) {
+                    Ok(trade) => trade,
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        return;
+                    }
+                };
+
+                let trades = vec![orderbook::Trade {
+                    symbol,
+                    price: trade.price,
+                    size: trade.amount,
+                    side: if trade.kind == 0 {
+                        orderbook::TradeSide::Buy
+                    } else {
+                        orderbook::TradeSide::Sell
+                    },
+                    ts: parse_ts(&trade.microtimestamp),
+                    exchange: Exchange::Bitstamp,
+                    trade_id: Some(trade.id.to_string()),
+                    received_ts: None,
+                }];
+
+                exchange::record_symbol_messages(&health, trades.iter().map(|trade| trade.symbol.as_str()));
+                exchange::publish_trades(&*redis_ref.lock().unwrap(), &format!("{}:trades", channel), &trades, &health);
+            }
+        });
+
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> Result<Option<ws::Frame>, Error> {
+        self.health.on_frame(frame)
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut *self.storage);
+            return;
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Bitstamp Socket is closing. Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            single_channels: self.single_channels.clone(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+    }
+
+    fn on_timeout(&mut self, _: Token) -> Result<(), ws::Error> {
+        if self.health.is_stopped() {
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Bitstamp Socket timed out (5s of inactivity). Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            single_channels: self.single_channels.clone(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,725 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::thread;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use base64;
+use chrono::prelude::*;
+use flate2::read::DeflateDecoder;
+use redis;
+use reqwest;
+use serde_json;
+use url::Url;
+use ws;
+use ws::util::Token;
+use ws::{Error, Handler, Handshake, Message, Sender};
+
+use config::{Config, ConfigError};
+use exchange::{self, Asset, AssetExchange, ConnectionHealth, Exchange, ExchangeError, MarketType};
+use orderbook;
+use storage::{StorageBackend, TectonicBackend};
+
+const EXPIRE: Token = Token(1);
+
+/// Name of the SignalR hub serving the v3 streams
+const HUB: &str = "c3";
+
+/// Exchange related metadata. The fields are used to establish
+/// a successful connection with the exchange via websockets.
+#[derive(Clone)]
+pub struct WSExchange {
+    /// SignalR endpoint. We negotiate a connection token with it before connecting.
+    /// Example: `https://socket-v3.bittrex.com/signalr`
+    pub host: String,
+    /// REST API base URL. Used to fetch orderbook snapshots. Example: `https://api.bittrex.com/v3`
+    pub rest_host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    pub snapshot_received: bool,
+
+    /// Collection metadata
+    pub metadata: MetaData,
+    /// Type of market we're collecting. Deltas are published to the `<exchange>:<market type>` Redis channel
+    pub market_type: MarketType,
+
+    /// Orderbook depth we subscribe to and fetch snapshots for. One of 1, 25 or 500
+    pub depth: u32,
+
+    /// Connection health, shared with the running websocket handler
+    pub health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    pub storage: Box<dyn StorageBackend>,
+
+    /// Redis URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub redis_url: String,
+    /// Redis client (before connection). Opened from `redis_url` when connecting
+    pub r: redis::Client,
+    /// Redis password: If this is present, it replaces the password in `redis_url` (if any) when connecting
+    pub r_password: Option<String>,
+}
+
+/// Create two identical structs and transfer the data over when we start the websocket.
+pub struct WSExchangeSender {
+    /// SignalR endpoint. Example: `https://socket-v3.bittrex.com/signalr`
+    host: String,
+    /// REST API base URL. Example: `https://api.bittrex.com/v3`
+    rest_host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    snapshot_received: bool,
+
+    /// Collection metadata
+    metadata: MetaData,
+
+    /// Orderbook depth
+    depth: u32,
+    /// Sequence tracking for every market's book (i.e. `BTC-USD`)
+    books: HashMap<String, SequenceSync>,
+    /// ID of the next hub invocation
+    invocation_id: u64,
+
+    /// Connection health
+    health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    storage: Box<dyn StorageBackend>,
+    /// Redis client (used to send deltas as PUBSUB)
+    r: Arc<Mutex<redis::Connection>>,
+
+    /// Websocket sender
+    out: Sender,
+}
+
+/// Meta data for our data source. This is useful for data warehousing and accessing the data.
+/// All types contained within are considered optional. This may be expanded in the future.
+#[derive(Clone)]
+pub struct MetaData {
+    /// Exchange name. We will warehouse the data under this name
+    pub exchange: Arc<String>,
+
+    /// Vector of asset pairs we're going to warehouse
+    pub asset_pair: Option<Vec<[exchange::Asset; 2]>>,
+
+    /// Type of market we're warehousing. Set from `WSExchange::market_type` when connecting
+    pub market_type: Option<MarketType>,
+
+    /// Starting datetime of our data collection
+    start_date: Option<DateTime<Utc>>,
+
+    /// Ending datetime of our data collection
+    end_date: Option<DateTime<Utc>>,
+}
+
+impl WSExchange {
+    /// Health of the connection to the exchange
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+}
+
+impl AssetExchange for WSExchange {
+    fn default_settings() -> Result<Box<Self>, String> {
+        Ok(Box::new(Self {
+            host: "https://socket-v3.bittrex.com/signalr".into(),
+            rest_host: "https://api.bittrex.com/v3".into(),
+
+            snapshot_received: false,
+
+            metadata: MetaData {
+                exchange: Arc::new("bittrex".into()),
+                asset_pair: Some(vec![
+                    [Asset::BTC, Asset::USD],
+                    [Asset::ETH, Asset::USD],]),
+                market_type: None,
+                start_date: None,
+                end_date: None,
+            },
+            market_type: MarketType::Spot,
+
+            depth: 25,
+
+            health: ConnectionHealth::new(Exchange::Bittrex),
+
+            storage: Box::new(TectonicBackend::new(None, None, "bittrex").map_err(|e| format!("Unable to connect to TectonicDB: {}", e))?),
+            redis_url: exchange::DEFAULT_REDIS_URL.into(),
+            r: redis::Client::open(exchange::DEFAULT_REDIS_URL).unwrap(),
+            r_password: None,
+        }))
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        if let Some(pairs) = config.asset_pairs()? {
+            self.metadata.asset_pair = Some(pairs);
+        }
+        if let Some(ref url) = config.redis.url {
+            self.redis_url = url.clone();
+        }
+        if config.redis.password.is_some() {
+            self.r_password = config.redis.password.clone();
+        }
+        if let Some(storage) = config.storage("bittrex")? {
+            self.storage = storage;
+        }
+
+        Ok(())
+    }
+
+    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
+        // The password is sent as part of the URL, so the client authenticates on its own
+        self.r = exchange::redis_client(&self.redis_url, self.r_password.as_ref().map(|password| password.as_str()))?;
+
+        self.r.get_connection()
+    }
+
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    fn start(&self) -> Result<(), ExchangeError> {
+        WSExchange::run_with_settings(Some(self))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
+        // Try to use the settings the user passes before resorting to default settings.
+        let mut settings = match settings {
+            Some(settings) => settings.clone(),
+            None => *WSExchange::default_settings().map_err(ConfigError::Settings)?,
+        };
+        let url = negotiate(&settings.host)?;
+
+        let r = Arc::new(Mutex::new(settings.init_redis()?));
+
+        ws::connect(url, |out| WSExchangeSender {
+            host: settings.host.clone(),
+            rest_host: settings.rest_host.clone(),
+
+            snapshot_received: settings.snapshot_received.clone(),
+            metadata: MetaData { market_type: Some(settings.market_type), ..settings.metadata.clone() },
+
+            depth: settings.depth,
+            books: HashMap::new(),
+            invocation_id: 1,
+
+            health: settings.health.clone(),
+            storage: settings.storage.clone(),
+            r: r.clone(),
+
+            out,
+        })?;
+
+        Ok(())
+    }
+}
+
+/// Response to the SignalR `negotiate` request
+#[derive(Deserialize)]
+struct NegotiateResponse {
+    #[serde(rename = "ConnectionToken")]
+    connection_token: String,
+}
+
+/// SignalR hub invocation (i.e. `{"H": "c3", "M": "Subscribe", "A": [["trade_BTC-USD"]], "I": 1}`)
+#[derive(Serialize)]
+struct HubInvocation {
+    #[serde(rename = "H")]
+    hub: String,
+    #[serde(rename = "M")]
+    method: String,
+    #[serde(rename = "A")]
+    arguments: Vec<Vec<String>>,
+    #[serde(rename = "I")]
+    id: u64,
+}
+
+/// Every message sent by the SignalR server. Keepalives are empty objects, and
+/// responses to our invocations only carry `R` and `I`.
+#[derive(Deserialize)]
+struct PersistentMessage {
+    /// Hub messages
+    #[serde(rename = "M", default)]
+    messages: Vec<HubMessage>,
+    /// Error raised by one of our invocations
+    #[serde(rename = "E")]
+    error: Option<String>,
+}
+
+/// Message pushed by a hub (i.e. `{"H": "C3", "M": "orderBook", "A": ["<payload>"]}`)
+#[derive(Deserialize)]
+struct HubMessage {
+    /// Stream the message belongs to (`orderBook`, `trade` or `heartbeat`)
+    #[serde(rename = "M")]
+    method: String,
+    /// Base64 encoded, deflate compressed payloads
+    #[serde(rename = "A", default)]
+    arguments: Vec<String>,
+}
+
+/// Orderbook level, as sent in snapshots and deltas
+#[derive(Clone, Debug, Deserialize)]
+pub struct BookLevel {
+    /// Level size. Zero removes the level
+    pub quantity: String,
+    /// Level price
+    pub rate: String,
+}
+
+/// Decoded `orderBook` payload
+#[derive(Clone, Debug, Deserialize)]
+pub struct BookDelta {
+    /// Market (i.e. `BTC-USD`)
+    #[serde(rename = "marketSymbol")]
+    pub market_symbol: String,
+    /// Sequence of the delta. Every delta must directly follow the one before it
+    pub sequence: u64,
+    /// Changed bid levels
+    #[serde(rename = "bidDeltas", default)]
+    pub bid_deltas: Vec<BookLevel>,
+    /// Changed ask levels
+    #[serde(rename = "askDeltas", default)]
+    pub ask_deltas: Vec<BookLevel>,
+}
+
+/// REST orderbook snapshot (`GET /markets/{marketSymbol}/orderbook`). Its sequence is sent in the `Sequence` header
+#[derive(Deserialize)]
+struct BookSnapshot {
+    bid: Vec<BookLevel>,
+    ask: Vec<BookLevel>,
+}
+
+/// Decoded `trade` payload
+#[derive(Deserialize)]
+struct TradeDelta {
+    #[serde(rename = "marketSymbol")]
+    market_symbol: String,
+    deltas: Vec<TradeData>,
+}
+
+#[derive(Deserialize)]
+struct TradeData {
+    id: String,
+    /// ISO 8601 timestamp (i.e. `2021-01-05T18:33:25.31Z`)
+    #[serde(rename = "executedAt")]
+    executed_at: String,
+    quantity: String,
+    rate: String,
+    /// `BUY` or `SELL`
+    #[serde(rename = "takerSide")]
+    taker_side: String,
+}
+
+/// We've missed at least one delta. The book must be reseeded from a new snapshot
+#[derive(Debug, PartialEq)]
+pub struct SequenceGap;
+
+/// Lines `orderBook` deltas up with a REST snapshot. Deltas are buffered until the snapshot's sequence
+/// is known, after which deltas at or before it are dropped, and every other delta must follow the last one applied.
+#[derive(Clone, Debug, Default)]
+pub struct SequenceSync {
+    /// Sequence of the last delta applied to the book, or of the snapshot. `None` until seeded
+    pub sequence: Option<u64>,
+    /// Deltas received before the snapshot
+    buffer: Vec<BookDelta>,
+}
+
+impl SequenceSync {
+    /// Creates a book that buffers deltas until it's seeded
+    pub fn new() -> Self {
+        SequenceSync::default()
+    }
+
+    /// Whether or not we have a snapshot to apply deltas to
+    pub fn is_seeded(&self) -> bool {
+        self.sequence.is_some()
+    }
+
+    /// Seeds the book with the sequence of a REST snapshot, and returns the buffered deltas that apply on top of it
+    pub fn seed(&mut self, sequence: u64) -> Result<Vec<BookDelta>, SequenceGap> {
+        self.sequence = Some(sequence);
+
+        let buffer: Vec<BookDelta> = self.buffer.drain(..).collect();
+        let mut deltas = Vec::with_capacity(buffer.len());
+
+        for delta in buffer {
+            deltas.extend(self.push(delta)?);
+        }
+
+        Ok(deltas)
+    }
+
+    /// Buffers the delta if we haven't been seeded yet. Otherwise, returns the delta if it applies
+    /// to the book, nothing if it's older than the book, or an error if we've missed a delta.
+    pub fn push(&mut self, delta: BookDelta) -> Result<Vec<BookDelta>, SequenceGap> {
+        let sequence = match self.sequence {
+            Some(sequence) => sequence,
+            None => {
+                self.buffer.push(delta);
+                return Ok(vec![]);
+            }
+        };
+
+        if delta.sequence <= sequence {
+            return Ok(vec![]);
+        }
+        if exchange::is_sequence_gap(sequence, delta.sequence) {
+            return Err(SequenceGap);
+        }
+
+        self.sequence = Some(delta.sequence);
+
+        Ok(vec![delta])
+    }
+}
+
+/// `connectionData` parameter naming the hubs we use
+fn connection_data() -> String {
+    format!("[{{\"name\":\"{}\"}}]", HUB)
+}
+
+/// Negotiates a SignalR connection and returns the websocket URL to connect to
+fn negotiate(host: &str) -> Result<String, reqwest::Error> {
+    let mut url = Url::parse(&format!("{}/negotiate", host)).expect("Invalid Bittrex SignalR host");
+    url.query_pairs_mut()
+        .append_pair("clientProtocol", "1.5")
+        .append_pair("connectionData", &connection_data());
+
+    let response: NegotiateResponse = reqwest::get(url.as_str())?.json()?;
+
+    Ok(connect_url(host, &response.connection_token))
+}
+
+/// Websocket URL of the SignalR `connect` endpoint for a negotiated connection token
+pub fn connect_url(host: &str, connection_token: &str) -> String {
+    let mut url = Url::parse(&format!("{}/connect", host)).expect("Invalid Bittrex SignalR host");
+    let scheme = if url.scheme() == "http" { "ws" } else { "wss" };
+    let _ = url.set_scheme(scheme);
+
+    url.query_pairs_mut()
+        .append_pair("transport", "webSockets")
+        .append_pair("clientProtocol", "1.5")
+        .append_pair("connectionToken", connection_token)
+        .append_pair("connectionData", &connection_data())
+        .append_pair("tid", "10");
+
+    url.into_string()
+}
+
+/// Decodes a hub message payload. Payloads are deflate compressed (without a zlib header), then base64 encoded
+pub fn decode_payload(payload: &str) -> Result<Vec<u8>, io::Error> {
+    let compressed = base64::decode(payload)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let mut buf = vec![];
+    DeflateDecoder::new(&compressed[..]).read_to_end(&mut buf)?;
+
+    Ok(buf)
+}
+
+/// Converts levels into deltas. A quantity of zero removes the level.
+fn levels_to_deltas(symbol: &str, levels: &[BookLevel], side: u8, ts: f64, seq: &mut u32) -> Vec<orderbook::Delta> {
+    levels.iter()
+        .filter_map(|level| {
+            let price = level.rate.parse::<f32>().ok()?;
+            let size = level.quantity.parse::<f32>().ok()?;
+            *seq += 1;
+
+            Some(orderbook::Delta {
+                symbol: symbol.to_string(),
+                price,
+                size,
+                seq: *seq,
+                event: side ^ if size == 0.0 {
+                    orderbook::REMOVE
+                } else {
+                    orderbook::UPDATE
+                },
+                ts,
+                received_ts: None,
+            })
+        })
+        .collect()
+}
+
+impl WSExchangeSender {
+    /// Publishes orderbook deltas to redis
+    fn publish(&self, deltas: &Vec<orderbook::Delta>) {
+        if deltas.is_empty() {
+            return;
+        }
+
+        exchange::record_symbol_messages(&self.health, deltas.iter().map(|delta| delta.symbol.as_str()));
+        // Lock the connection until we are able to aquire it
+        exchange::publish_deltas(&*self.r.lock().unwrap(), &exchange::market_channel(&self.metadata.exchange, self.metadata.market_type), deltas, &self.health);
+    }
+
+    /// Invokes a method of the hub
+    fn invoke(&mut self, method: &str, arguments: Vec<String>) -> Result<(), Error> {
+        let msg = HubInvocation {
+            hub: HUB.into(),
+            method: method.into(),
+            arguments: vec![arguments],
+            id: self.invocation_id,
+        };
+        self.invocation_id += 1;
+
+        println!("Sending message {}", serde_json::to_string(&msg).unwrap());
+        self.out.send(serde_json::to_string(&msg).unwrap())
+    }
+
+    /// Fetches an orderbook snapshot over REST and publishes its levels. Returns the snapshot's sequence
+    fn fetch_snapshot(&mut self, symbol: &str) -> Option<u64> {
+        let url = format!("{}/markets/{}/orderbook?depth={}", self.rest_host, symbol, self.depth);
+
+        let (sequence, snapshot) = match reqwest::get(&url).and_then(|mut response| {
+            let sequence = response.headers()
+                .get("Sequence")
+                .and_then(|sequence| sequence.to_str().ok())
+                .and_then(|sequence| sequence.parse::<u64>().ok());
+
+            response.json::<BookSnapshot>().map(|snapshot| (sequence, snapshot))
+        }) {
+            Ok((Some(sequence), snapshot)) => (sequence, snapshot),
+            Ok((None, _)) => {
+                println!("Bittrex orderbook snapshot for {} is missing its sequence", symbol);
+                return None;
+            },
+            Err(e) => {
+                println!("Failed to fetch Bittrex orderbook snapshot for {}: {}", symbol, e);
+                return None;
+            }
+        };
+
+        let ts = Utc::now().timestamp_millis() as f64 * 0.001f64;
+        let mut seq = 0;
+        let mut deltas = levels_to_deltas(symbol, &snapshot.ask, orderbook::ASK, ts, &mut seq);
+        deltas.extend(levels_to_deltas(symbol, &snapshot.bid, orderbook::BID, ts, &mut seq));
+
+        self.publish(&deltas);
+        self.snapshot_received = true;
+
+        Some(sequence)
+    }
+
+    /// Lines a delta up with the market's book, seeding it from a REST snapshot first if necessary
+    fn on_book_delta(&mut self, delta: BookDelta) {
+        let symbol = delta.market_symbol.clone();
+
+        let mut result = self.books.entry(symbol.clone())
+            .or_insert_with(SequenceSync::new)
+            .push(delta);
+
+        if !self.books[&symbol].is_seeded() {
+            result = match self.fetch_snapshot(&symbol) {
+                Some(sequence) => self.books.get_mut(&symbol).unwrap().seed(sequence),
+                None => Ok(vec![]),
+            };
+        }
+
+        let deltas = match result {
+            Ok(deltas) => deltas,
+            Err(SequenceGap) => {
+                // Forget the book so that the next delta reseeds it
+                println!("Bittrex orderbook for {} is out of sequence. Reseeding the book...", symbol);
+                self.books.remove(&symbol);
+                return;
+            }
+        };
+
+        for delta in deltas {
+            let ts = Utc::now().timestamp_millis() as f64 * 0.001f64;
+            let mut seq = 0;
+            let mut book_deltas = levels_to_deltas(&symbol, &delta.ask_deltas, orderbook::ASK, ts, &mut seq);
+            book_deltas.extend(levels_to_deltas(&symbol, &delta.bid_deltas, orderbook::BID, ts, &mut seq));
+
+            self.publish(&book_deltas);
+        }
+    }
+}
+
+impl Handler for WSExchangeSender {
+    fn on_open(&mut self, _: Handshake) -> Result<(), Error> {
+        self.health.track_connection(&self.out);
+
+        // Measure the round-trip time of the new connection
+        self.health.ping_latency(&self.out)?;
+
+        // Set a timeout for 5 seconds of inactivity
+        // Issue: currently, this reruns every five seconds. Comment out while we fix.
+        //self.out.timeout(5_000, EXPIRE).unwrap();
+
+        let mut streams = vec!["heartbeat".to_string()];
+
+        for pair in self.metadata.asset_pair.as_ref().expect("No asset pairs passed to Bittrex structure") {
+            let normalized_pair = match exchange::get_asset_pair(pair, Exchange::Bittrex) {
+                Ok(normalized_pair) => normalized_pair,
+                Err(e) => {
+                    println!("Skipping Bittrex subscription: {}", e);
+                    continue;
+                }
+            };
+            let db_name = format!("{}_{}", self.metadata.exchange.deref(), normalized_pair);
+
+            // Create the database if it doesn't exist yet. This avoids many issues
+            // relating to inserting to a non-existant database.
+            self.storage.create(&db_name)?;
+
+            streams.push(format!("orderbook_{}_{}", normalized_pair, self.depth));
+            streams.push(format!("trade_{}", normalized_pair));
+        }
+
+        self.invoke("Subscribe", streams)
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<(), Error> {
+        self.health.record_message();
+
+        let message = match serde_json::from_slice::<PersistentMessage>(&msg.into_data()) {
+            Ok(message) => message,
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            }
+        };
+
+        if let Some(error) = message.error {
+            println!("Bittrex error: {}", error);
+            return Ok(());
+        }
+
+        for hub_message in message.messages {
+            for payload in &hub_message.arguments {
+                let data = match decode_payload(payload) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        println!("Failed to decode Bittrex payload: {}", e);
+                        continue;
+                    }
+                };
+
+                match hub_message.method.as_str() {
+                    // Sequences are checked on the socket thread, since every delta has to follow the one before it
+                    "orderBook" => match serde_json::from_slice::<BookDelta>(&data) {
+                        Ok(delta) => self.on_book_delta(delta),
+                        Err(e) => println!("Error: {}", e),
+                    },
+                    "trade" => {
+                        let redis_ref = self.r.clone();
+                        let health = self.health.clone();
+                        let channel = exchange::market_channel(&self.metadata.exchange, self.metadata.market_type);
+
+                        thread::spawn(move || {
+                            let trades = match serde_json::from_slice::<TradeDelta>(&data) {
+                                Ok(trades) => trades,
+                                Err(e) => {
+                                    println!("Error: {}", e);
+                                    return;
+                                }
+                            };
+
+                            let trades: Vec<orderbook::Trade> = trades.deltas.iter()
+                                .filter_map(|trade| Some(orderbook::Trade {
+                                    symbol: trades.market_symbol.clone(),
+                                    price: trade.rate.parse::<f64>().ok()?,
+                                    size: trade.quantity.parse::<f64>().ok()?,
+                                    side: if trade.taker_side == "BUY" {
+                                        orderbook::TradeSide::Buy
+                                    } else {
+                                        orderbook::TradeSide::Sell
+                                    },
+                                    ts: DateTime::parse_from_rfc3339(&trade.executed_at)
+                                        .map(|ts| ts.timestamp_millis() as f64 * 0.001f64)
+                                        .unwrap_or(Utc::now().timestamp_millis() as f64 * 0.001f64),
+                                    exchange: Exchange::Bittrex,
+                                    trade_id: Some(trade.id.clone()),
+                                    received_ts: None,
+                                }))
+                                .collect();
+
+                            if trades.is_empty() {
+                                return;
+                            }
+
+                            exchange::record_symbol_messages(&health, trades.iter().map(|trade| trade.symbol.as_str()));
+                            exchange::publish_trades(&*redis_ref.lock().unwrap(), &format!("{}:trades", channel), &trades, &health);
+                        });
+                    },
+                    // Heartbeats only keep the connection alive
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> Result<Option<ws::Frame>, Error> {
+        self.health.on_frame(frame)
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut *self.storage);
+            return;
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Bittrex Socket is closing. Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        // Connection tokens can't be reused, so every connection is negotiated again
+        let url = negotiate(&self.host).expect("Failed to negotiate Bittrex SignalR connection");
+
+        ws::connect(url, |out| WSExchangeSender{
+            host: self.host.clone(),
+            rest_host: self.rest_host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            depth: self.depth,
+            books: HashMap::new(),
+            invocation_id: 1,
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+    }
+
+    fn on_timeout(&mut self, _: Token) -> Result<(), ws::Error> {
+        if self.health.is_stopped() {
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Bittrex Socket timed out (5s of inactivity). Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        let url = negotiate(&self.host).expect("Failed to negotiate Bittrex SignalR connection");
+
+        ws::connect(url, |out| WSExchangeSender{
+            host: self.host.clone(),
+            rest_host: self.rest_host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            depth: self.depth,
+            books: HashMap::new(),
+            invocation_id: 1,
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,679 @@
+use std::thread;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use redis;
+use serde::de::{self, Deserialize, Deserializer};
+use serde_json::{self, Value};
+use ws;
+use ws::util::Token;
+use ws::{Error, Handler, Handshake, Message, Sender};
+
+use config::{Config, ConfigError};
+use exchange::{self, Asset, AssetExchange, ConnectionHealth, Exchange, ExchangeError, MarketType};
+use orderbook;
+use storage::{StorageBackend, TectonicBackend};
+
+const EXPIRE: Token = Token(1);
+/// Token for the heartbeat ping
+const PING: Token = Token(2);
+
+/// Bybit drops connections that don't send a ping at least every 20 seconds (30 seconds on the legacy endpoint)
+const PING_INTERVAL_MS: u64 = 20_000;
+
+/// Product lines offered by Bybit. Each one is served from its own websocket endpoint.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BybitMarketType {
+    /// Spot trading (i.e. `BTCUSDT`)
+    Spot,
+    /// USDT and USDC margined perpetuals and futures (i.e. `BTCUSDT`)
+    Linear,
+    /// Coin margined perpetuals and futures (i.e. `BTCUSD`)
+    Inverse,
+    /// Coin margined (USD) perpetuals on the legacy `realtime` endpoint. The book is served as
+    /// `orderBookL2_25` or `orderBook_200.100ms` topics with insert/update/delete arrays.
+    Realtime,
+}
+
+impl BybitMarketType {
+    /// Type of market the endpoint serves. Perpetuals are the most traded contracts on both futures endpoints.
+    pub fn market_type(&self) -> MarketType {
+        match self {
+            BybitMarketType::Spot => MarketType::Spot,
+            BybitMarketType::Linear => MarketType::LinearPerpetual,
+            BybitMarketType::Inverse | BybitMarketType::Realtime => MarketType::InversePerpetual,
+        }
+    }
+
+    /// Public websocket endpoint for the market type
+    pub fn host(&self) -> String {
+        match self {
+            BybitMarketType::Spot => "wss://stream.bybit.com/v5/public/spot".into(),
+            BybitMarketType::Linear => "wss://stream.bybit.com/v5/public/linear".into(),
+            BybitMarketType::Inverse => "wss://stream.bybit.com/v5/public/inverse".into(),
+            BybitMarketType::Realtime => "wss://stream.bybit.com/realtime".into(),
+        }
+    }
+
+    /// Book and trade topics of the market type, without the symbol. `deep_book` selects the
+    /// deepest book offered (200 levels) instead of the default (50 levels, or 25 on `Realtime`).
+    pub fn channels(&self, deep_book: bool) -> Vec<String> {
+        match (self, deep_book) {
+            (BybitMarketType::Realtime, false) => vec!["orderBookL2_25".into(), "trade".into()],
+            (BybitMarketType::Realtime, true) => vec!["orderBook_200.100ms".into(), "trade".into()],
+            (_, false) => vec!["orderbook.50".into(), "publicTrade".into()],
+            (_, true) => vec!["orderbook.200".into(), "publicTrade".into()],
+        }
+    }
+}
+
+/// Exchange related metadata. The fields are used to establish
+/// a successful connection with the exchange via websockets.
+#[derive(Clone)]
+pub struct WSExchange {
+    /// Full URL to connect to. This must be the endpoint of `market_type` (see [`BybitMarketType::host`]).
+    /// Example: `wss://stream.bybit.com/v5/public/linear`
+    pub host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    pub snapshot_received: bool,
+
+    /// Collection metadata
+    pub metadata: MetaData,
+
+    /// Product line we're collecting
+    pub market_type: BybitMarketType,
+    /// Topics we subscribe to for every asset pair (i.e. `orderbook.50`, `publicTrade`).
+    /// See [`BybitMarketType::channels`] for the topics of each market type.
+    pub single_channels: Vec<String>,
+
+    /// Connection health, shared with the running websocket handler
+    pub health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    pub storage: Box<dyn StorageBackend>,
+
+    /// Redis URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub redis_url: String,
+    /// Redis client (before connection). Opened from `redis_url` when connecting
+    pub r: redis::Client,
+    /// Redis password: If this is present, it replaces the password in `redis_url` (if any) when connecting
+    pub r_password: Option<String>,
+}
+
+/// Create two identical structs and transfer the data over when we start the websocket.
+pub struct WSExchangeSender {
+    /// Full URL to connect to. Example: `wss://stream.bybit.com/v5/public/linear`
+    host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    snapshot_received: bool,
+
+    /// Collection metadata
+    metadata: MetaData,
+
+    /// Product line we're collecting
+    market_type: BybitMarketType,
+    /// Topics we subscribe to for every asset pair
+    single_channels: Vec<String>,
+
+    /// Connection health
+    health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    storage: Box<dyn StorageBackend>,
+    /// Redis client (used to send deltas as PUBSUB)
+    r: Arc<Mutex<redis::Connection>>,
+
+    /// Websocket sender
+    out: Sender,
+}
+
+/// Meta data for our data source. This is useful for data warehousing and accessing the data.
+/// All types contained within are considered optional. This may be expanded in the future.
+#[derive(Clone)]
+pub struct MetaData {
+    /// Exchange name. We will warehouse the data under this name
+    pub exchange: Arc<String>,
+
+    /// Vector of asset pairs we're going to warehouse
+    pub asset_pair: Option<Vec<[exchange::Asset; 2]>>,
+
+    /// Type of market we're warehousing. Set from `WSExchange::market_type` when connecting
+    /// (see [`BybitMarketType::market_type`])
+    pub market_type: Option<MarketType>,
+
+    /// Starting datetime of our data collection
+    start_date: Option<DateTime<Utc>>,
+
+    /// Ending datetime of our data collection
+    end_date: Option<DateTime<Utc>>,
+}
+
+impl WSExchange {
+    /// Health of the connection to the exchange
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+}
+
+impl AssetExchange for WSExchange {
+    fn default_settings() -> Result<Box<Self>, String> {
+        Ok(Box::new(Self {
+            host: BybitMarketType::Linear.host(),
+
+            snapshot_received: false,
+
+            metadata: MetaData {
+                exchange: Arc::new("bybit".into()),
+                asset_pair: Some(vec![
+                    [Asset::BTC, Asset::USDT],]),
+                market_type: None,
+                start_date: None,
+                end_date: None,
+            },
+
+            market_type: BybitMarketType::Linear,
+            single_channels: BybitMarketType::Linear.channels(false),
+
+            health: ConnectionHealth::new(Exchange::Bybit),
+
+            storage: Box::new(TectonicBackend::new(None, None, "bybit").map_err(|e| format!("Unable to connect to TectonicDB: {}", e))?),
+            redis_url: exchange::DEFAULT_REDIS_URL.into(),
+            r: redis::Client::open(exchange::DEFAULT_REDIS_URL).unwrap(),
+            r_password: None,
+        }))
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        if let Some(pairs) = config.asset_pairs()? {
+            self.metadata.asset_pair = Some(pairs);
+        }
+        if let Some(ref channels) = config.channels.names {
+            self.single_channels = channels.clone();
+        }
+        if let Some(ref url) = config.redis.url {
+            self.redis_url = url.clone();
+        }
+        if config.redis.password.is_some() {
+            self.r_password = config.redis.password.clone();
+        }
+        if let Some(storage) = config.storage("bybit")? {
+            self.storage = storage;
+        }
+
+        Ok(())
+    }
+
+    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
+        // The password is sent as part of the URL, so the client authenticates on its own
+        self.r = exchange::redis_client(&self.redis_url, self.r_password.as_ref().map(|password| password.as_str()))?;
+
+        self.r.get_connection()
+    }
+
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    fn start(&self) -> Result<(), ExchangeError> {
+        WSExchange::run_with_settings(Some(self))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
+        // Try to use the settings the user passes before resorting to default settings.
+        let mut settings = match settings {
+            Some(settings) => settings.clone(),
+            None => *WSExchange::default_settings().map_err(ConfigError::Settings)?,
+        };
+
+        let r = Arc::new(Mutex::new(settings.init_redis()?));
+
+        ws::connect(settings.host.clone(), |out| WSExchangeSender {
+            host: settings.host.clone(),
+
+            snapshot_received: settings.snapshot_received.clone(),
+            metadata: MetaData { market_type: Some(settings.market_type.market_type()), ..settings.metadata.clone() },
+
+            market_type: settings.market_type,
+            single_channels: settings.single_channels.clone(),
+
+            health: settings.health.clone(),
+            storage: settings.storage.clone(),
+            r: r.clone(),
+
+            out,
+        })?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SubscribeMessage {
+    op: String,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    args: Vec<String>,
+}
+
+/// Topic data pushed by Bybit. Responses to our own requests (`subscribe`, `ping`)
+/// carry `op` and `success` instead.
+#[derive(Deserialize)]
+struct EventMessage {
+    /// Topic the data belongs to (i.e. `orderbook.50.BTCUSDT`)
+    topic: Option<String>,
+    /// `snapshot` or `delta`
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    /// Timestamp in milliseconds
+    ts: Option<u64>,
+    /// Timestamp in microseconds. Only sent on the legacy endpoint
+    timestamp_e6: Option<Value>,
+    data: Option<Value>,
+
+    op: Option<String>,
+    success: Option<bool>,
+    ret_msg: Option<String>,
+}
+
+/// Orderbook topic data
+#[derive(Deserialize)]
+struct BookData {
+    /// Symbol (i.e. `BTCUSDT`)
+    s: String,
+    /// Bid levels as `[price, size]`
+    b: Vec<[String; 2]>,
+    /// Ask levels as `[price, size]`
+    a: Vec<[String; 2]>,
+    /// Update ID. An update ID of 1 on a delta means Bybit restarted its service, and the
+    /// message should be treated as a new snapshot.
+    u: u64,
+}
+
+/// Public trade topic data
+#[derive(Deserialize)]
+struct TradeData {
+    /// Timestamp in milliseconds
+    #[serde(rename = "T")]
+    time: u64,
+    /// Symbol (i.e. `BTCUSDT`)
+    s: String,
+    /// Taker side (`Buy` or `Sell`)
+    #[serde(rename = "S")]
+    side: String,
+    /// Size
+    v: String,
+    /// Price
+    p: String,
+    /// Trade ID
+    i: String,
+}
+
+/// Legacy endpoint orderbook level. Deletes only carry the `id`, `symbol`, `side`, and `price`.
+#[derive(Deserialize)]
+struct LegacyLevel {
+    symbol: String,
+    /// Sent as a string on the book topics (i.e. `"2999.00"`)
+    #[serde(deserialize_with = "string_or_number")]
+    price: f64,
+    /// `Buy` or `Sell`
+    side: String,
+    #[serde(default)]
+    size: f64,
+}
+
+/// Legacy endpoint orderbook delta
+#[derive(Deserialize)]
+struct LegacyBookDelta {
+    #[serde(default)]
+    delete: Vec<LegacyLevel>,
+    #[serde(default)]
+    update: Vec<LegacyLevel>,
+    #[serde(default)]
+    insert: Vec<LegacyLevel>,
+}
+
+/// Legacy endpoint trade topic data
+#[derive(Deserialize)]
+struct LegacyTradeData {
+    symbol: String,
+    /// Taker side (`Buy` or `Sell`)
+    side: String,
+    size: f64,
+    #[serde(deserialize_with = "string_or_number")]
+    price: f64,
+    trade_time_ms: u64,
+    trade_id: String,
+}
+
+/// Bybit sends prices as strings on some topics and as numbers on others
+fn string_or_number<'de, D>(deserializer: D) -> Result<f64, D::Error>
+    where D: Deserializer<'de>
+{
+    match Value::deserialize(deserializer)? {
+        Value::String(value) => value.parse::<f64>().map_err(de::Error::custom),
+        Value::Number(value) => value.as_f64().ok_or_else(|| de::Error::custom("invalid number")),
+        _ => Err(de::Error::custom("expected a string or number")),
+    }
+}
+
+/// Flattens the data of a legacy `orderBookL2_25`/`orderBook_200.100ms` message into deltas. Snapshots are
+/// sent as a list of levels (wrapped in `order_book` on some products), and deltas as insert/update/delete
+/// arrays. Deletes become size-0 deltas with the `REMOVE` flag.
+pub fn legacy_book_deltas(data: Value, snapshot: bool, ts: f64) -> Result<Vec<orderbook::Delta>, serde_json::Error> {
+    let levels: Vec<(LegacyLevel, bool)> = if snapshot {
+        let data = match data {
+            Value::Object(mut data) => data.remove("order_book").unwrap_or(Value::Null),
+            data => data,
+        };
+
+        serde_json::from_value::<Vec<LegacyLevel>>(data)?
+            .into_iter()
+            .map(|level| (level, false))
+            .collect()
+    } else {
+        let delta = serde_json::from_value::<LegacyBookDelta>(data)?;
+
+        delta.delete.into_iter().map(|level| (level, true))
+            .chain(delta.update.into_iter().chain(delta.insert).map(|level| (level, false)))
+            .collect()
+    };
+
+    // Begin sequence counting at 1 in order to reconstruct a proper sequence count
+    Ok(levels.into_iter()
+        .enumerate()
+        .map(|(idx, (level, delete))| orderbook::Delta {
+            price: level.price as f32,
+            size: if delete { 0.0 } else { level.size as f32 },
+            seq: idx as u32 + 1,
+            event: if level.side == "Buy" {
+                    orderbook::BID
+                } else {
+                    orderbook::ASK
+                } ^ if delete {
+                    orderbook::REMOVE
+                } else {
+                    orderbook::UPDATE
+                },
+            symbol: level.symbol,
+            ts,
+            received_ts: None,
+        })
+        .collect())
+}
+
+impl Handler for WSExchangeSender {
+    fn on_open(&mut self, _: Handshake) -> Result<(), Error> {
+        self.health.track_connection(&self.out);
+
+        // Measure the round-trip time of the new connection
+        self.health.ping_latency(&self.out)?;
+
+        // Set a timeout for 5 seconds of inactivity
+        // Issue: currently, this reruns every five seconds. Comment out while we fix.
+        //self.out.timeout(5_000, EXPIRE).unwrap();
+        self.out.timeout(PING_INTERVAL_MS, PING)?;
+
+        let mut msg = SubscribeMessage {
+            op: "subscribe".into(),
+            args: vec![],
+        };
+
+        for pair in self.metadata.asset_pair.as_ref().expect("No asset pairs passed to Bybit structure") {
+            let normalized_pair = match exchange::get_asset_pair(pair, Exchange::Bybit) {
+                Ok(normalized_pair) => normalized_pair,
+                Err(e) => {
+                    println!("Skipping Bybit subscription: {}", e);
+                    continue;
+                }
+            };
+            let db_name = format!("{}_{}", self.metadata.exchange.deref(), normalized_pair);
+
+            // Create the database if it doesn't exist yet. This avoids many issues
+            // relating to inserting to a non-existant database.
+            self.storage.create(&db_name)?;
+
+            for channel in &self.single_channels {
+                msg.args.push(format!("{}.{}", channel, normalized_pair));
+            }
+        }
+
+        println!("Sending message {}", serde_json::to_string(&msg).unwrap());
+        self.out.send(serde_json::to_string(&msg).unwrap())
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<(), Error> {
+        self.health.record_message();
+
+        let message = match serde_json::from_slice::<EventMessage>(&msg.into_data()) {
+            Ok(message) => message,
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            }
+        };
+
+        let (topic, kind, ts, data) = match message {
+            EventMessage { topic: Some(topic), kind, ts, timestamp_e6, data: Some(data), .. } => {
+                let ts = ts.map(|ts| ts as f64 * 0.001f64).or_else(|| match timestamp_e6 {
+                    Some(Value::Number(ts)) => ts.as_f64().map(|ts| ts * 0.000_001f64),
+                    Some(Value::String(ts)) => ts.parse::<f64>().ok().map(|ts| ts * 0.000_001f64),
+                    _ => None,
+                });
+
+                (topic, kind.unwrap_or_default(), ts, data)
+            },
+            EventMessage { op, success: Some(false), ret_msg, .. } => {
+                println!("Bybit {} failed: {}", op.unwrap_or_default(), ret_msg.unwrap_or_default());
+                return Ok(());
+            },
+            _ => return Ok(()),
+        };
+
+        let ts = ts.unwrap_or(Utc::now().timestamp_millis() as f64 * 0.001f64);
+
+        if topic.to_lowercase().starts_with("orderbook") && kind == "snapshot" {
+            self.snapshot_received = true;
+        }
+
+        let redis_ref = self.r.clone();
+        let health = self.health.clone();
+        let channel = exchange::market_channel(&self.metadata.exchange, self.metadata.market_type);
+
+        thread::spawn(move || {
+            if topic.starts_with("orderbook") {
+                let book = match serde_json::from_value::<BookData>(data) {
+                    Ok(book) => book,
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        return;
+                    }
+                };
+
+                // Levels sent with a snapshot seed the book, so they're all updates
+                let snapshot = kind == "snapshot" || book.u == 1;
+                let mut deltas: Vec<orderbook::Delta> = Vec::with_capacity(book.a.len() + book.b.len());
+
+                // Begin sequence counting at 1 in order to reconstruct a proper sequence count
+                let mut seq = 1;
+
+                for (levels, side) in vec![(book.a, orderbook::ASK), (book.b, orderbook::BID)] {
+                    for level in levels {
+                        let (price, size) = match (level[0].parse::<f32>(), level[1].parse::<f32>()) {
+                            (Ok(price), Ok(size)) => (price, size),
+                            _ => continue,
+                        };
+
+                        deltas.push(orderbook::Delta {
+                            symbol: book.s.clone(),
+                            price,
+                            size,
+                            seq,
+                            event: side ^ if size == 0.0 && !snapshot {
+                                orderbook::REMOVE
+                            } else {
+                                orderbook::UPDATE
+                            },
+                            ts,
+                            received_ts: None,
+                        });
+
+                        seq += 1;
+                    }
+                }
+
+                if deltas.is_empty() {
+                    return;
+                }
+
+                exchange::record_symbol_messages(&health, deltas.iter().map(|delta| delta.symbol.as_str()));
+                // Lock the connection until we are able to aquire it
+                exchange::publish_deltas(&*redis_ref.lock().unwrap(), &channel, &deltas, &health);
+
+            } else if topic.starts_with("orderBook") {
+                let deltas = match legacy_book_deltas(data, kind == "snapshot", ts) {
+                    Ok(deltas) => deltas,
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        return;
+                    }
+                };
+
+                if deltas.is_empty() {
+                    return;
+                }
+
+                exchange::record_symbol_messages(&health, deltas.iter().map(|delta| delta.symbol.as_str()));
+                exchange::publish_deltas(&*redis_ref.lock().unwrap(), &channel, &deltas, &health);
+
+            } else if topic.starts_with("trade.") {
+                let trades: Vec<orderbook::Trade> = match serde_json::from_value::<Vec<LegacyTradeData>>(data) {
+                    Ok(trades) => trades.into_iter()
+                        .map(|trade| orderbook::Trade {
+                            price: trade.price,
+                            size: trade.size,
+                            side: if trade.side == "Buy" {
+                                orderbook::TradeSide::Buy
+                            } else {
+                                orderbook::TradeSide::Sell
+                            },
+                            symbol: trade.symbol,
+                            ts: trade.trade_time_ms as f64 * 0.001f64,
+                            exchange: Exchange::Bybit,
+                            trade_id: Some(trade.trade_id),
+                            received_ts: None,
+                        })
+                        .collect(),
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        return;
+                    }
+                };
+
+                exchange::record_symbol_messages(&health, trades.iter().map(|trade| trade.symbol.as_str()));
+                exchange::publish_trades(&*redis_ref.lock().unwrap(), &format!("{}:trades", channel), &trades, &health);
+
+            } else if topic.starts_with("publicTrade") {
+                let trades: Vec<orderbook::Trade> = match serde_json::from_value::<Vec<TradeData>>(data) {
+                    Ok(trades) => trades.into_iter()
+                        .filter_map(|trade| Some(orderbook::Trade {
+                            price: trade.p.parse::<f64>().ok()?,
+                            size: trade.v.parse::<f64>().ok()?,
+                            side: if trade.side == "Buy" {
+                                orderbook::TradeSide::Buy
+                            } else {
+                                orderbook::TradeSide::Sell
+                            },
+                            symbol: trade.s,
+                            ts: trade.time as f64 * 0.001f64,
+                            exchange: Exchange::Bybit,
+                            trade_id: Some(trade.i),
+                            received_ts: None,
+                        }))
+                        .collect(),
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        return;
+                    }
+                };
+
+                exchange::record_symbol_messages(&health, trades.iter().map(|trade| trade.symbol.as_str()));
+                exchange::publish_trades(&*redis_ref.lock().unwrap(), &format!("{}:trades", channel), &trades, &health);
+            }
+        });
+
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> Result<Option<ws::Frame>, Error> {
+        self.health.on_frame(frame)
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut *self.storage);
+            return;
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Bybit Socket is closing. Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            market_type: self.market_type,
+            single_channels: self.single_channels.clone(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+    }
+
+    fn on_timeout(&mut self, event: Token) -> Result<(), ws::Error> {
+        if self.health.is_stopped() {
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
+        if event == PING {
+            let ping = SubscribeMessage {
+                op: "ping".into(),
+                args: vec![],
+            };
+
+            self.out.send(serde_json::to_string(&ping).unwrap())?;
+            return self.out.timeout(PING_INTERVAL_MS, PING);
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Bybit Socket timed out (5s of inactivity). Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            market_type: self.market_type,
+            single_channels: self.single_channels.clone(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+
+        Ok(())
+    }
+}
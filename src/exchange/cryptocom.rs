@@ -0,0 +1,572 @@
+use std::collections::HashMap;
+use std::thread;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use redis;
+use serde_json;
+use ws;
+use ws::util::Token;
+use ws::{Error, Handler, Handshake, Message, Sender};
+
+use config::{Config, ConfigError};
+use exchange::{self, Asset, AssetExchange, ConnectionHealth, Exchange, ExchangeError, MarketType};
+use exchange::huobi::diff_levels;
+use orderbook;
+use storage::{StorageBackend, TectonicBackend};
+
+const EXPIRE: Token = Token(1);
+
+/// Exchange related metadata. The fields are used to establish
+/// a successful connection with the exchange via websockets.
+#[derive(Clone)]
+pub struct WSExchange {
+    /// Full URL to connect to. Example: `wss://stream.crypto.com/v2/market`
+    pub host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    pub snapshot_received: bool,
+
+    /// Collection metadata
+    pub metadata: MetaData,
+    /// Type of market we're collecting. Deltas are published to the `<exchange>:<market type>` Redis channel
+    pub market_type: MarketType,
+
+    /// Channels we subscribe to for every instrument (i.e. `book.{}.150`, `trade.{}`).
+    /// `{}` is replaced with the instrument name.
+    pub single_channels: Vec<String>,
+
+    /// Connection health, shared with the running websocket handler
+    pub health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    pub storage: Box<dyn StorageBackend>,
+
+    /// Redis URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub redis_url: String,
+    /// Redis client (before connection). Opened from `redis_url` when connecting
+    pub r: redis::Client,
+    /// Redis password: If this is present, it replaces the password in `redis_url` (if any) when connecting
+    pub r_password: Option<String>,
+}
+
+/// Create two identical structs and transfer the data over when we start the websocket.
+pub struct WSExchangeSender {
+    /// Full URL to connect to. Example: `wss://stream.crypto.com/v2/market`
+    host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    snapshot_received: bool,
+
+    /// Collection metadata
+    metadata: MetaData,
+
+    /// Channels we subscribe to for every instrument
+    single_channels: Vec<String>,
+    /// Last orderbook we've received for every instrument (i.e. `BTC_USDT`). Crypto.com sends
+    /// the full depth on every update, so we keep the previous one around to diff against.
+    books: HashMap<String, LocalBook>,
+
+    /// Connection health
+    health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    storage: Box<dyn StorageBackend>,
+    /// Redis client (used to send deltas as PUBSUB)
+    r: Arc<Mutex<redis::Connection>>,
+
+    /// Websocket sender
+    out: Sender,
+}
+
+/// Meta data for our data source. This is useful for data warehousing and accessing the data.
+/// All types contained within are considered optional. This may be expanded in the future.
+#[derive(Clone)]
+pub struct MetaData {
+    /// Exchange name. We will warehouse the data under this name
+    pub exchange: Arc<String>,
+
+    /// Vector of asset pairs we're going to warehouse
+    pub asset_pair: Option<Vec<[exchange::Asset; 2]>>,
+
+    /// Type of market we're warehousing. Set from `WSExchange::market_type` when connecting
+    pub market_type: Option<MarketType>,
+
+    /// Starting datetime of our data collection
+    start_date: Option<DateTime<Utc>>,
+
+    /// Ending datetime of our data collection
+    end_date: Option<DateTime<Utc>>,
+}
+
+impl WSExchange {
+    /// Health of the connection to the exchange
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+}
+
+impl AssetExchange for WSExchange {
+    fn default_settings() -> Result<Box<Self>, String> {
+        Ok(Box::new(Self {
+            host: "wss://stream.crypto.com/v2/market".into(),
+
+            snapshot_received: false,
+
+            metadata: MetaData {
+                exchange: Arc::new("cryptocom".into()),
+                asset_pair: Some(vec![
+                    [Asset::BTC, Asset::USDT],]),
+                market_type: None,
+                start_date: None,
+                end_date: None,
+            },
+            market_type: MarketType::Spot,
+
+            single_channels: vec![
+                "book.{}.150".into(),
+                "trade.{}".into()],
+
+            health: ConnectionHealth::new(Exchange::CryptoCom),
+
+            storage: Box::new(TectonicBackend::new(None, None, "cryptocom").map_err(|e| format!("Unable to connect to TectonicDB: {}", e))?),
+            redis_url: exchange::DEFAULT_REDIS_URL.into(),
+            r: redis::Client::open(exchange::DEFAULT_REDIS_URL).unwrap(),
+            r_password: None,
+        }))
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        if let Some(pairs) = config.asset_pairs()? {
+            self.metadata.asset_pair = Some(pairs);
+        }
+        if let Some(ref channels) = config.channels.names {
+            self.single_channels = channels.clone();
+        }
+        if let Some(ref url) = config.redis.url {
+            self.redis_url = url.clone();
+        }
+        if config.redis.password.is_some() {
+            self.r_password = config.redis.password.clone();
+        }
+        if let Some(storage) = config.storage("cryptocom")? {
+            self.storage = storage;
+        }
+
+        Ok(())
+    }
+
+    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
+        // The password is sent as part of the URL, so the client authenticates on its own
+        self.r = exchange::redis_client(&self.redis_url, self.r_password.as_ref().map(|password| password.as_str()))?;
+
+        self.r.get_connection()
+    }
+
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    fn start(&self) -> Result<(), ExchangeError> {
+        WSExchange::run_with_settings(Some(self))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
+        // Try to use the settings the user passes before resorting to default settings.
+        let mut settings = match settings {
+            Some(settings) => settings.clone(),
+            None => *WSExchange::default_settings().map_err(ConfigError::Settings)?,
+        };
+
+        let r = Arc::new(Mutex::new(settings.init_redis()?));
+
+        ws::connect(settings.host.clone(), |out| WSExchangeSender {
+            host: settings.host.clone(),
+
+            snapshot_received: settings.snapshot_received.clone(),
+            metadata: MetaData { market_type: Some(settings.market_type), ..settings.metadata.clone() },
+
+            single_channels: settings.single_channels.clone(),
+            books: HashMap::new(),
+
+            health: settings.health.clone(),
+            storage: settings.storage.clone(),
+            r: r.clone(),
+
+            out,
+        })?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct SubscribeMessage {
+    id: u64,
+    method: String,
+    params: SubscribeParams,
+    /// Current time in milliseconds
+    nonce: i64,
+}
+
+#[derive(Serialize)]
+struct SubscribeParams {
+    channels: Vec<String>,
+}
+
+/// Reply to a `public/heartbeat` request. The `id` has to match the request's
+#[derive(Serialize)]
+struct HeartbeatResponse {
+    id: u64,
+    method: String,
+}
+
+/// Every message sent by Crypto.com. Heartbeats and subscription acknowledgements
+/// come without a `result`.
+#[derive(Deserialize)]
+struct EventMessage {
+    id: Option<u64>,
+    /// i.e. `public/heartbeat`, `subscribe`
+    method: String,
+    code: Option<i64>,
+    result: Option<EventResult>,
+}
+
+#[derive(Deserialize)]
+struct EventResult {
+    /// Instrument the data applies to (i.e. `BTC_USDT`)
+    instrument_name: String,
+    /// `book` or `trade`
+    channel: String,
+    data: serde_json::Value,
+}
+
+/// Full depth orderbook, as sent on the `book.{instrument}.{depth}` channel
+#[derive(Clone, Debug, Deserialize)]
+pub struct BookData {
+    /// Bid levels as `[price, size, order count]`
+    pub bids: Vec<Vec<serde_json::Value>>,
+    /// Ask levels as `[price, size, order count]`
+    pub asks: Vec<Vec<serde_json::Value>>,
+    /// Timestamp in milliseconds
+    pub t: u64,
+}
+
+#[derive(Deserialize)]
+struct TradeData {
+    /// Trade ID
+    d: serde_json::Value,
+    /// Taker side (`BUY` or `SELL`)
+    s: String,
+    /// Price
+    p: serde_json::Value,
+    /// Quantity
+    q: serde_json::Value,
+    /// Trade time in milliseconds
+    t: u64,
+}
+
+/// Reads a price or size, which Crypto.com sends either as a number or as a string
+pub fn parse_number(value: &serde_json::Value) -> Option<f64> {
+    match value {
+        serde_json::Value::Number(number) => number.as_f64(),
+        serde_json::Value::String(string) => string.parse::<f64>().ok(),
+        _ => None,
+    }
+}
+
+/// Converts `[price, size, order count]` levels to `[price, size]`, skipping any level we can't read
+pub fn parse_levels(levels: &[Vec<serde_json::Value>]) -> Vec<[f64; 2]> {
+    levels.iter()
+        .filter_map(|level| Some([
+            parse_number(level.get(0)?)?,
+            parse_number(level.get(1)?)?,
+        ]))
+        .collect()
+}
+
+/// The last full orderbook received for an instrument, as `[price, size]` levels
+#[derive(Clone, Debug, Default)]
+pub struct LocalBook {
+    /// Bid levels
+    pub bids: Vec<[f64; 2]>,
+    /// Ask levels
+    pub asks: Vec<[f64; 2]>,
+}
+
+impl LocalBook {
+    /// Replaces the book with a new snapshot and returns the levels that changed as
+    /// `(asks, bids)`, where a size of zero marks a level that has left the book.
+    pub fn update(&mut self, book: &BookData) -> (Vec<(f64, f64)>, Vec<(f64, f64)>) {
+        let asks = parse_levels(&book.asks);
+        let bids = parse_levels(&book.bids);
+
+        let changes = (diff_levels(&self.asks, &asks), diff_levels(&self.bids, &bids));
+
+        self.asks = asks;
+        self.bids = bids;
+
+        changes
+    }
+}
+
+impl WSExchangeSender {
+    /// Diffs an orderbook snapshot against the previous one and publishes the changed levels
+    fn on_book(&mut self, symbol: String, book: BookData) {
+        let (asks, bids) = self.books.entry(symbol.clone())
+            .or_insert_with(LocalBook::default)
+            .update(&book);
+
+        self.snapshot_received = true;
+
+        let ts = book.t as f64 * 0.001f64;
+        let mut deltas: Vec<orderbook::Delta> = Vec::with_capacity(asks.len() + bids.len());
+
+        // Begin sequence counting at 1 in order to reconstruct a proper sequence count
+        let mut seq = 1;
+
+        for (levels, side) in vec![(asks, orderbook::ASK), (bids, orderbook::BID)] {
+            for (price, size) in levels {
+                let (price, size) = (price as f32, size as f32);
+
+                deltas.push(orderbook::Delta {
+                    symbol: symbol.clone(),
+                    price,
+                    size,
+                    seq,
+                    event: side ^ if size == 0.0 {
+                        orderbook::REMOVE
+                    } else {
+                        orderbook::UPDATE
+                    },
+                    ts,
+                    received_ts: None,
+                });
+
+                seq += 1;
+            }
+        }
+
+        if deltas.is_empty() {
+            return;
+        }
+
+        // Lock the connection until we are able to aquire it. The listener inserts what we publish into TectonicDB
+        exchange::record_symbol_messages(&self.health, deltas.iter().map(|delta| delta.symbol.as_str()));
+        exchange::publish_deltas(&*self.r.lock().unwrap(), &exchange::market_channel(&self.metadata.exchange, self.metadata.market_type), &deltas, &self.health);
+    }
+
+    /// Answers a heartbeat. Crypto.com drops the connection if we don't reply within a few seconds
+    fn respond_heartbeat(&self, id: u64) -> Result<(), Error> {
+        let msg = HeartbeatResponse {
+            id,
+            method: "public/respond-heartbeat".into(),
+        };
+
+        self.out.send(serde_json::to_string(&msg).unwrap())
+    }
+}
+
+impl Handler for WSExchangeSender {
+    fn on_open(&mut self, _: Handshake) -> Result<(), Error> {
+        self.health.track_connection(&self.out);
+
+        // Measure the round-trip time of the new connection
+        self.health.ping_latency(&self.out)?;
+
+        // Set a timeout for 5 seconds of inactivity
+        // Issue: currently, this reruns every five seconds. Comment out while we fix.
+        //self.out.timeout(5_000, EXPIRE).unwrap();
+
+        let mut channels = vec![];
+
+        for pair in self.metadata.asset_pair.as_ref().expect("No asset pairs passed to Crypto.com structure") {
+            let normalized_pair = match exchange::get_asset_pair(pair, Exchange::CryptoCom) {
+                Ok(normalized_pair) => normalized_pair,
+                Err(e) => {
+                    println!("Skipping Crypto.com subscription: {}", e);
+                    continue;
+                }
+            };
+            let db_name = format!("{}_{}", self.metadata.exchange.deref(), normalized_pair);
+
+            // Create the database if it doesn't exist yet. This avoids many issues
+            // relating to inserting to a non-existant database.
+            self.storage.create(&db_name)?;
+
+            for channel in &self.single_channels {
+                channels.push(channel.replace("{}", &normalized_pair));
+            }
+        }
+
+        let msg = SubscribeMessage {
+            id: 1,
+            method: "subscribe".into(),
+            params: SubscribeParams {
+                channels,
+            },
+            nonce: Utc::now().timestamp_millis(),
+        };
+
+        println!("Sending message {}", serde_json::to_string(&msg).unwrap());
+        self.out.send(serde_json::to_string(&msg).unwrap())
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<(), Error> {
+        self.health.record_message();
+
+        let message = match serde_json::from_slice::<EventMessage>(&msg.into_data()) {
+            Ok(message) => message,
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            }
+        };
+
+        if message.method == "public/heartbeat" {
+            return match message.id {
+                Some(id) => self.respond_heartbeat(id),
+                None => Ok(()),
+            };
+        }
+
+        if let Some(code) = message.code {
+            if code != 0 {
+                println!("Crypto.com error code {} on {}", code, message.method);
+                return Ok(());
+            }
+        }
+
+        let result = match message.result {
+            Some(result) => result,
+            None => return Ok(()),
+        };
+
+        if result.channel == "book" {
+            // Books are diffed on the socket thread, since every snapshot is compared to the previous one
+            let books = match serde_json::from_value::<Vec<BookData>>(result.data) {
+                Ok(books) => books,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return Ok(());
+                }
+            };
+
+            for book in books {
+                self.on_book(result.instrument_name.clone(), book);
+            }
+
+            return Ok(());
+        }
+
+        if result.channel != "trade" {
+            return Ok(());
+        }
+
+        let redis_ref = self.r.clone();
+        let health = self.health.clone();
+        let channel = exchange::market_channel(&self.metadata.exchange, self.metadata.market_type);
+
+        thread::spawn(move || {
+            let data = match serde_json::from_value::<Vec<TradeData>>(result.data) {
+                Ok(data) => data,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return;
+                }
+            };
+
+            let trades: Vec<orderbook::Trade> = data.iter()
+                .filter_map(|trade| Some(orderbook::Trade {
+                    symbol: result.instrument_name.clone(),
+                    price: parse_number(&trade.p)?,
+                    size: parse_number(&trade.q)?,
+                    side: if trade.s == "BUY" {
+                        orderbook::TradeSide::Buy
+                    } else {
+                        orderbook::TradeSide::Sell
+                    },
+                    ts: trade.t as f64 * 0.001f64,
+                    exchange: Exchange::CryptoCom,
+                    trade_id: match trade.d {
+                        serde_json::Value::String(ref id) => Some(id.clone()),
+                        serde_json::Value::Number(ref id) => Some(id.to_string()),
+                        _ => None,
+                    },
+                    received_ts: None,
+                }))
+                .collect();
+
+            if trades.is_empty() {
+                return;
+            }
+
+            exchange::record_symbol_messages(&health, trades.iter().map(|trade| trade.symbol.as_str()));
+            exchange::publish_trades(&*redis_ref.lock().unwrap(), &format!("{}:trades", channel), &trades, &health);
+        });
+
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> Result<Option<ws::Frame>, Error> {
+        self.health.on_frame(frame)
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut *self.storage);
+            return;
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Crypto.com Socket is closing. Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            single_channels: self.single_channels.clone(),
+            books: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+    }
+
+    fn on_timeout(&mut self, _: Token) -> Result<(), ws::Error> {
+        if self.health.is_stopped() {
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Crypto.com Socket timed out (5s of inactivity). Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            single_channels: self.single_channels.clone(),
+            books: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,564 @@
+use std::collections::HashMap;
+use std::thread;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use redis;
+use reqwest;
+use serde_json::{self, Value};
+use ws;
+use ws::util::Token;
+use ws::{Error, Handler, Handshake, Message, Sender};
+
+use config::{Config, ConfigError};
+use exchange::{self, AssetExchange, ConnectionHealth, Exchange, ExchangeError, MarketType, OptionsAsset};
+use orderbook;
+use storage::{StorageBackend, TectonicBackend};
+
+const EXPIRE: Token = Token(1);
+
+/// Exchange related metadata. The fields are used to establish
+/// a successful connection with the exchange via websockets.
+#[derive(Clone)]
+pub struct WSExchange {
+    /// Full URL to connect to. Example: `wss://www.deribit.com/ws/api/v2`
+    pub host: String,
+    /// REST API base URL. Used to fetch the option chain. Example: `https://www.deribit.com/api/v2`
+    pub rest_host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    pub snapshot_received: bool,
+
+    /// Collection metadata
+    pub metadata: MetaData,
+    /// Type of market we're collecting. Deltas are published to the `<exchange>:<market type>` Redis channel
+    pub market_type: MarketType,
+
+    /// Channel names we subscribe to for every instrument (i.e. `book`, `trades`)
+    pub single_channels: Vec<String>,
+
+    /// Connection health, shared with the running websocket handler
+    pub health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    pub storage: Box<dyn StorageBackend>,
+
+    /// Redis URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub redis_url: String,
+    /// Redis client (before connection). Opened from `redis_url` when connecting
+    pub r: redis::Client,
+    /// Redis password: If this is present, it replaces the password in `redis_url` (if any) when connecting
+    pub r_password: Option<String>,
+}
+
+/// Create two identical structs and transfer the data over when we start the websocket.
+pub struct WSExchangeSender {
+    /// Full URL to connect to. Example: `wss://www.deribit.com/ws/api/v2`
+    host: String,
+    /// REST API base URL
+    rest_host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    snapshot_received: bool,
+
+    /// Collection metadata
+    metadata: MetaData,
+
+    /// Channel names we subscribe to for every instrument
+    single_channels: Vec<String>,
+    /// `change_id` continuity tracking for every instrument's book
+    change_ids: ChangeIds,
+
+    /// Connection health
+    health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    storage: Box<dyn StorageBackend>,
+    /// Redis client (used to send deltas as PUBSUB)
+    r: Arc<Mutex<redis::Connection>>,
+
+    /// Websocket sender
+    out: Sender,
+}
+
+/// Meta data for our data source. This is useful for data warehousing and accessing the data.
+/// All types contained within are considered optional. This may be expanded in the future.
+#[derive(Clone)]
+pub struct MetaData {
+    /// Exchange name. We will warehouse the data under this name
+    pub exchange: Arc<String>,
+
+    /// Instruments we're going to warehouse (i.e. `BTC-PERPETUAL`)
+    pub instruments: Vec<String>,
+
+    /// Currencies whose entire (non-expired) option chain we're going to warehouse
+    pub options: Vec<OptionsAsset>,
+
+    /// Type of market we're warehousing. Set from `WSExchange::market_type` when connecting
+    pub market_type: Option<MarketType>,
+
+    /// Starting datetime of our data collection
+    start_date: Option<DateTime<Utc>>,
+
+    /// Ending datetime of our data collection
+    end_date: Option<DateTime<Utc>>,
+}
+
+impl WSExchange {
+    /// Health of the connection to the exchange
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+}
+
+impl AssetExchange for WSExchange {
+    fn default_settings() -> Result<Box<Self>, String> {
+        Ok(Box::new(Self {
+            host: "wss://www.deribit.com/ws/api/v2".into(),
+            rest_host: "https://www.deribit.com/api/v2".into(),
+
+            snapshot_received: false,
+
+            metadata: MetaData {
+                exchange: Arc::new("deribit".into()),
+                instruments: vec![
+                    "BTC-PERPETUAL".into()],
+                options: vec![
+                    OptionsAsset::BTC],
+                market_type: None,
+                start_date: None,
+                end_date: None,
+            },
+            market_type: MarketType::InversePerpetual,
+
+            single_channels: vec![
+                "book".into(),
+                "trades".into()],
+
+            health: ConnectionHealth::new(Exchange::Deribit),
+
+            storage: Box::new(TectonicBackend::new(None, None, "deribit").map_err(|e| format!("Unable to connect to TectonicDB: {}", e))?),
+            redis_url: exchange::DEFAULT_REDIS_URL.into(),
+            r: redis::Client::open(exchange::DEFAULT_REDIS_URL).unwrap(),
+            r_password: None,
+        }))
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        // Deribit collects instruments rather than asset pairs, so there's nothing to take from `[assets]`
+        if let Some(ref channels) = config.channels.names {
+            self.single_channels = channels.clone();
+        }
+        if let Some(ref url) = config.redis.url {
+            self.redis_url = url.clone();
+        }
+        if config.redis.password.is_some() {
+            self.r_password = config.redis.password.clone();
+        }
+        if let Some(storage) = config.storage("deribit")? {
+            self.storage = storage;
+        }
+
+        Ok(())
+    }
+
+    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
+        // The password is sent as part of the URL, so the client authenticates on its own
+        self.r = exchange::redis_client(&self.redis_url, self.r_password.as_ref().map(|password| password.as_str()))?;
+
+        self.r.get_connection()
+    }
+
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    fn start(&self) -> Result<(), ExchangeError> {
+        WSExchange::run_with_settings(Some(self))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
+        // Try to use the settings the user passes before resorting to default settings.
+        let mut settings = match settings {
+            Some(settings) => settings.clone(),
+            None => *WSExchange::default_settings().map_err(ConfigError::Settings)?,
+        };
+
+        let r = Arc::new(Mutex::new(settings.init_redis()?));
+
+        ws::connect(settings.host.clone(), |out| WSExchangeSender {
+            host: settings.host.clone(),
+            rest_host: settings.rest_host.clone(),
+
+            snapshot_received: settings.snapshot_received.clone(),
+            metadata: MetaData { market_type: Some(settings.market_type), ..settings.metadata.clone() },
+
+            single_channels: settings.single_channels.clone(),
+            change_ids: ChangeIds::default(),
+
+            health: settings.health.clone(),
+            storage: settings.storage.clone(),
+            r: r.clone(),
+
+            out,
+        })?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RequestMessage {
+    jsonrpc: String,
+    id: u64,
+    method: String,
+    params: SubscribeParams,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SubscribeParams {
+    channels: Vec<String>,
+}
+
+/// Subscription notification. Responses to our own requests carry `result` or `error` instead.
+#[derive(Deserialize)]
+struct NotificationMessage {
+    method: Option<String>,
+    params: Option<NotificationParams>,
+    error: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct NotificationParams {
+    /// Channel the data belongs to (i.e. `book.BTC-PERPETUAL.raw`)
+    channel: String,
+    data: Value,
+}
+
+/// Raw book channel data
+#[derive(Deserialize)]
+struct BookData {
+    /// `snapshot` or `change`
+    #[serde(rename = "type")]
+    kind: String,
+    /// Timestamp in milliseconds
+    timestamp: u64,
+    instrument_name: String,
+    change_id: u64,
+    /// Not present on snapshots
+    prev_change_id: Option<u64>,
+    /// Bid levels as `[action, price, amount]`, where `action` is `new`, `change`, or `delete`
+    bids: Vec<(String, f64, f64)>,
+    /// Ask levels as `[action, price, amount]`
+    asks: Vec<(String, f64, f64)>,
+}
+
+/// Raw trades channel data
+#[derive(Deserialize)]
+struct TradeData {
+    trade_id: String,
+    instrument_name: String,
+    price: f64,
+    amount: f64,
+    /// Taker side
+    direction: String,
+    /// Timestamp in milliseconds
+    timestamp: u64,
+}
+
+/// Response from `/public/get_instruments`
+#[derive(Deserialize)]
+struct InstrumentsResponse {
+    result: Vec<InstrumentData>,
+}
+
+#[derive(Deserialize)]
+struct InstrumentData {
+    instrument_name: String,
+}
+
+/// Tracks the last `change_id` of every instrument's book. Every change notification
+/// carries the `change_id` of the notification before it as `prev_change_id`, so a
+/// mismatch means that we've missed an update.
+#[derive(Default)]
+pub struct ChangeIds {
+    last: HashMap<String, u64>,
+}
+
+impl ChangeIds {
+    /// Records a snapshot, which restarts the continuity check for the instrument
+    pub fn snapshot(&mut self, instrument: &str, change_id: u64) {
+        self.last.insert(instrument.into(), change_id);
+    }
+
+    /// Records a change, returning `false` if it doesn't follow the last change we've seen.
+    /// The instrument is forgotten on a gap, so changes are rejected until the next snapshot.
+    pub fn change(&mut self, instrument: &str, change_id: u64, prev_change_id: u64) -> bool {
+        match self.last.get(instrument) {
+            Some(&last) if last == prev_change_id => {
+                self.last.insert(instrument.into(), change_id);
+                true
+            },
+            _ => {
+                self.last.remove(instrument);
+                false
+            }
+        }
+    }
+}
+
+/// Deribit's currency name for an options asset
+fn currency(asset: &OptionsAsset) -> &'static str {
+    match asset {
+        OptionsAsset::BTC => "BTC",
+        OptionsAsset::ETH => "ETH",
+    }
+}
+
+/// Maps the action of a book level (`new`, `change` or `delete`) to its delta event on the given side.
+/// Deribit tells us when a level is new, so it's recorded as an insertion rather than an update.
+pub fn level_event(side: u8, action: &str) -> u8 {
+    side ^ match action {
+        "new" => orderbook::INSERT,
+        "delete" => orderbook::REMOVE,
+        _ => orderbook::UPDATE,
+    }
+}
+
+impl WSExchangeSender {
+    /// The configured instruments, followed by the option chains we were asked for
+    fn instruments(&self) -> Vec<String> {
+        let mut instruments = self.metadata.instruments.clone();
+
+        for asset in &self.metadata.options {
+            let url = format!("{}/public/get_instruments?currency={}&kind=option&expired=false", self.rest_host, currency(asset));
+
+            match reqwest::get(&url).and_then(|mut response| response.json::<InstrumentsResponse>()) {
+                Ok(response) => instruments.extend(response.result.into_iter().map(|instrument| instrument.instrument_name)),
+                Err(e) => println!("Failed to fetch Deribit {} option chain: {}", currency(asset), e),
+            }
+        }
+
+        instruments
+    }
+
+    /// Sends a JSON-RPC request to (un)subscribe to the given channels
+    fn send_request(&mut self, method: &str, channels: Vec<String>) -> Result<(), Error> {
+        let msg = RequestMessage {
+            jsonrpc: "2.0".into(),
+            id: 1,
+            method: method.into(),
+            params: SubscribeParams { channels },
+        };
+
+        self.out.send(serde_json::to_string(&msg).unwrap())
+    }
+
+    /// Applies a book notification and publishes its deltas. Forces a resubscribe if we've missed an update.
+    fn on_book(&mut self, book: BookData) -> Result<(), Error> {
+        if book.kind == "snapshot" {
+            self.change_ids.snapshot(&book.instrument_name, book.change_id);
+            self.snapshot_received = true;
+        } else if !self.change_ids.change(&book.instrument_name, book.change_id, book.prev_change_id.unwrap_or(0)) {
+            println!("Deribit book for {} is missing updates (change_id gap). Resubscribing...", book.instrument_name);
+
+            // Deribit sends a fresh snapshot once we've resubscribed
+            let channel = format!("book.{}.raw", book.instrument_name);
+            self.send_request("public/unsubscribe", vec![channel.clone()])?;
+            return self.send_request("public/subscribe", vec![channel]);
+        }
+
+        let ts = book.timestamp as f64 * 0.001f64;
+        let mut deltas: Vec<orderbook::Delta> = Vec::with_capacity(book.asks.len() + book.bids.len());
+
+        // Begin sequence counting at 1 in order to reconstruct a proper sequence count
+        let mut seq = 1;
+
+        for (levels, side) in vec![(&book.asks, orderbook::ASK), (&book.bids, orderbook::BID)] {
+            for &(ref action, price, amount) in levels {
+                let event = level_event(side, action);
+
+                deltas.push(orderbook::Delta {
+                    symbol: book.instrument_name.clone(),
+                    price: price as f32,
+                    size: if event & orderbook::REMOVE != 0 { 0.0 } else { amount as f32 },
+                    seq,
+                    event,
+                    ts,
+                    received_ts: None,
+                });
+
+                seq += 1;
+            }
+        }
+
+        // Lock the connection until we are able to aquire it
+        if !deltas.is_empty() {
+            exchange::record_symbol_messages(&self.health, deltas.iter().map(|delta| delta.symbol.as_str()));
+            exchange::publish_deltas(&*self.r.lock().unwrap(), &exchange::market_channel(&self.metadata.exchange, self.metadata.market_type), &deltas, &self.health);
+        }
+
+        Ok(())
+    }
+}
+
+impl Handler for WSExchangeSender {
+    fn on_open(&mut self, _: Handshake) -> Result<(), Error> {
+        self.health.track_connection(&self.out);
+
+        // Measure the round-trip time of the new connection
+        self.health.ping_latency(&self.out)?;
+
+        // Set a timeout for 5 seconds of inactivity
+        // Issue: currently, this reruns every five seconds. Comment out while we fix.
+        //self.out.timeout(5_000, EXPIRE).unwrap();
+
+        let mut channels = vec![];
+
+        for instrument in self.instruments() {
+            let db_name = format!("{}_{}", self.metadata.exchange.deref(), instrument);
+
+            // Create the database if it doesn't exist yet. This avoids many issues
+            // relating to inserting to a non-existant database.
+            self.storage.create(&db_name)?;
+
+            for channel in &self.single_channels {
+                channels.push(format!("{}.{}.raw", channel, instrument));
+            }
+        }
+
+        println!("Subscribing to {} Deribit channels", channels.len());
+        self.send_request("public/subscribe", channels)
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<(), Error> {
+        self.health.record_message();
+
+        let params = match serde_json::from_slice::<NotificationMessage>(&msg.into_data()) {
+            Ok(NotificationMessage { method: Some(ref method), params: Some(params), .. }) if method == "subscription" => params,
+            Ok(NotificationMessage { error: Some(error), .. }) => {
+                println!("Deribit error: {}", error);
+                return Ok(());
+            },
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            }
+        };
+
+        if params.channel.starts_with("book.") {
+            // Book notifications are handled on the socket thread, since the `change_id`
+            // continuity check depends on the order in which they arrive.
+            return match serde_json::from_value::<BookData>(params.data) {
+                Ok(book) => self.on_book(book),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    Ok(())
+                }
+            };
+        }
+
+        if !params.channel.starts_with("trades.") {
+            return Ok(());
+        }
+
+        let redis_ref = self.r.clone();
+        let health = self.health.clone();
+        let channel = exchange::market_channel(&self.metadata.exchange, self.metadata.market_type);
+
+        thread::spawn(move || {
+            let trades: Vec<orderbook::Trade> = match serde_json::from_value::<Vec<TradeData>>(params.data) {
+                Ok(trades) => trades.into_iter()
+                    .map(|trade| orderbook::Trade {
+                        symbol: trade.instrument_name,
+                        price: trade.price,
+                        size: trade.amount,
+                        side: if trade.direction == "buy" {
+                            orderbook::TradeSide::Buy
+                        } else {
+                            orderbook::TradeSide::Sell
+                        },
+                        ts: trade.timestamp as f64 * 0.001f64,
+                        exchange: Exchange::Deribit,
+                        trade_id: Some(trade.trade_id),
+                        received_ts: None,
+                    })
+                    .collect(),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return;
+                }
+            };
+
+            exchange::record_symbol_messages(&health, trades.iter().map(|trade| trade.symbol.as_str()));
+            exchange::publish_trades(&*redis_ref.lock().unwrap(), &format!("{}:trades", channel), &trades, &health);
+        });
+
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> Result<Option<ws::Frame>, Error> {
+        self.health.on_frame(frame)
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut *self.storage);
+            return;
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Deribit Socket is closing. Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            rest_host: self.rest_host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            single_channels: self.single_channels.clone(),
+            change_ids: ChangeIds::default(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+    }
+
+    fn on_timeout(&mut self, _: Token) -> Result<(), ws::Error> {
+        if self.health.is_stopped() {
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Deribit Socket timed out (5s of inactivity). Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            rest_host: self.rest_host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            single_channels: self.single_channels.clone(),
+            change_ids: ChangeIds::default(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+
+        Ok(())
+    }
+}
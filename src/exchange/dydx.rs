@@ -0,0 +1,602 @@
+use std::collections::HashMap;
+use std::thread;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use redis;
+use serde_json;
+use ws;
+use ws::util::Token;
+use ws::{Error, Handler, Handshake, Message, Sender};
+
+use config::{Config, ConfigError};
+use exchange::{self, Asset, AssetExchange, ConnectionHealth, Exchange, ExchangeError, MarketType};
+use orderbook;
+use storage::{StorageBackend, TectonicBackend};
+
+const EXPIRE: Token = Token(1);
+
+/// Exchange related metadata. The fields are used to establish
+/// a successful connection with the exchange via websockets.
+#[derive(Clone)]
+pub struct WSExchange {
+    /// Full URL to connect to. Example: `wss://api.dydx.exchange/v3/ws`
+    pub host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    pub snapshot_received: bool,
+
+    /// Collection metadata
+    pub metadata: MetaData,
+    /// Type of market we're collecting. Deltas are published to the `<exchange>:<market type>` Redis channel
+    pub market_type: MarketType,
+
+    /// Channels we subscribe to for every market (i.e. `v3_orderbook`, `v3_trades`)
+    pub single_channels: Vec<String>,
+
+    /// Connection health, shared with the running websocket handler
+    pub health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    pub storage: Box<dyn StorageBackend>,
+
+    /// Redis URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub redis_url: String,
+    /// Redis client (before connection). Opened from `redis_url` when connecting
+    pub r: redis::Client,
+    /// Redis password: If this is present, it replaces the password in `redis_url` (if any) when connecting
+    pub r_password: Option<String>,
+}
+
+/// Create two identical structs and transfer the data over when we start the websocket.
+pub struct WSExchangeSender {
+    /// Full URL to connect to. Example: `wss://api.dydx.exchange/v3/ws`
+    host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    snapshot_received: bool,
+
+    /// Collection metadata
+    metadata: MetaData,
+
+    /// Channels we subscribe to for every market
+    single_channels: Vec<String>,
+    /// Offset of every level of every market's book (i.e. `BTC-USD`)
+    offsets: HashMap<String, LevelOffsets>,
+
+    /// Connection health
+    health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    storage: Box<dyn StorageBackend>,
+    /// Redis client (used to send deltas as PUBSUB)
+    r: Arc<Mutex<redis::Connection>>,
+
+    /// Websocket sender
+    out: Sender,
+}
+
+/// Meta data for our data source. This is useful for data warehousing and accessing the data.
+/// All types contained within are considered optional. This may be expanded in the future.
+#[derive(Clone)]
+pub struct MetaData {
+    /// Exchange name. We will warehouse the data under this name
+    pub exchange: Arc<String>,
+
+    /// Vector of asset pairs we're going to warehouse
+    pub asset_pair: Option<Vec<[exchange::Asset; 2]>>,
+
+    /// Type of market we're warehousing. Set from `WSExchange::market_type` when connecting
+    pub market_type: Option<MarketType>,
+
+    /// Starting datetime of our data collection
+    start_date: Option<DateTime<Utc>>,
+
+    /// Ending datetime of our data collection
+    end_date: Option<DateTime<Utc>>,
+}
+
+impl WSExchange {
+    /// Health of the connection to the exchange
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+}
+
+impl AssetExchange for WSExchange {
+    fn default_settings() -> Result<Box<Self>, String> {
+        Ok(Box::new(Self {
+            host: "wss://api.dydx.exchange/v3/ws".into(),
+
+            snapshot_received: false,
+
+            metadata: MetaData {
+                exchange: Arc::new("dydx".into()),
+                asset_pair: Some(vec![
+                    [Asset::BTC, Asset::USD],]),
+                market_type: None,
+                start_date: None,
+                end_date: None,
+            },
+            market_type: MarketType::LinearPerpetual,
+
+            single_channels: vec![
+                "v3_orderbook".into(),
+                "v3_trades".into()],
+
+            health: ConnectionHealth::new(Exchange::DyDx),
+
+            storage: Box::new(TectonicBackend::new(None, None, "dydx").map_err(|e| format!("Unable to connect to TectonicDB: {}", e))?),
+            redis_url: exchange::DEFAULT_REDIS_URL.into(),
+            r: redis::Client::open(exchange::DEFAULT_REDIS_URL).unwrap(),
+            r_password: None,
+        }))
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        if let Some(pairs) = config.asset_pairs()? {
+            self.metadata.asset_pair = Some(pairs);
+        }
+        if let Some(ref channels) = config.channels.names {
+            self.single_channels = channels.clone();
+        }
+        if let Some(ref url) = config.redis.url {
+            self.redis_url = url.clone();
+        }
+        if config.redis.password.is_some() {
+            self.r_password = config.redis.password.clone();
+        }
+        if let Some(storage) = config.storage("dydx")? {
+            self.storage = storage;
+        }
+
+        Ok(())
+    }
+
+    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
+        // The password is sent as part of the URL, so the client authenticates on its own
+        self.r = exchange::redis_client(&self.redis_url, self.r_password.as_ref().map(|password| password.as_str()))?;
+
+        self.r.get_connection()
+    }
+
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    fn start(&self) -> Result<(), ExchangeError> {
+        WSExchange::run_with_settings(Some(self))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
+        // Try to use the settings the user passes before resorting to default settings.
+        let mut settings = match settings {
+            Some(settings) => settings.clone(),
+            None => *WSExchange::default_settings().map_err(ConfigError::Settings)?,
+        };
+
+        let r = Arc::new(Mutex::new(settings.init_redis()?));
+
+        ws::connect(settings.host.clone(), |out| WSExchangeSender {
+            host: settings.host.clone(),
+
+            snapshot_received: settings.snapshot_received.clone(),
+            metadata: MetaData { market_type: Some(settings.market_type), ..settings.metadata.clone() },
+
+            single_channels: settings.single_channels.clone(),
+            offsets: HashMap::new(),
+
+            health: settings.health.clone(),
+            storage: settings.storage.clone(),
+            r: r.clone(),
+
+            out,
+        })?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct SubscribeMessage {
+    #[serde(rename = "type")]
+    type_: String,
+    channel: String,
+    /// Market (i.e. `BTC-USD`)
+    id: String,
+    /// Asks for the offset of every level of the initial book
+    #[serde(rename = "includeOffsets")]
+    include_offsets: bool,
+}
+
+/// Every message sent by dYdX
+#[derive(Deserialize)]
+struct EventMessage {
+    /// `connected`, `subscribed`, `channel_data` or `error`
+    #[serde(rename = "type")]
+    type_: String,
+    channel: Option<String>,
+    /// Market the message applies to (i.e. `BTC-USD`)
+    id: Option<String>,
+    contents: Option<serde_json::Value>,
+    message: Option<String>,
+}
+
+/// Level of the initial book, as sent in the `subscribed` message
+#[derive(Clone, Debug, Deserialize)]
+pub struct OffsetLevel {
+    /// Level price
+    pub price: String,
+    /// Level size
+    pub size: String,
+    /// Offset of the last update to the level
+    pub offset: String,
+}
+
+/// Initial book, as sent in the `subscribed` message of the `v3_orderbook` channel
+#[derive(Clone, Debug, Deserialize)]
+pub struct BookSnapshot {
+    /// Bid levels
+    pub bids: Vec<OffsetLevel>,
+    /// Ask levels
+    pub asks: Vec<OffsetLevel>,
+}
+
+/// Book update, as sent in the `channel_data` messages of the `v3_orderbook` channel.
+/// Every level changed by the update shares its offset.
+#[derive(Clone, Debug, Deserialize)]
+pub struct BookUpdate {
+    /// Offset of the update
+    pub offset: String,
+    /// Bid levels as `[price, size]`
+    #[serde(default)]
+    pub bids: Vec<[String; 2]>,
+    /// Ask levels as `[price, size]`
+    #[serde(default)]
+    pub asks: Vec<[String; 2]>,
+}
+
+#[derive(Deserialize)]
+struct TradeContents {
+    trades: Vec<TradeData>,
+}
+
+#[derive(Deserialize)]
+struct TradeData {
+    /// Taker side (`BUY` or `SELL`)
+    side: String,
+    size: String,
+    price: String,
+    /// ISO 8601 timestamp (i.e. `2021-01-05T18:33:25.000Z`)
+    #[serde(rename = "createdAt")]
+    created_at: String,
+}
+
+/// Offset of the last update applied to every level of a book. dYdX may deliver updates to a level
+/// out of order, so an update only applies if its offset is greater than the level's. Offsets of levels
+/// that have left the book are kept, so that a stale update can't bring the level back.
+#[derive(Clone, Debug, Default)]
+pub struct LevelOffsets {
+    /// Offsets of the bid levels, keyed by price
+    pub bids: HashMap<String, u64>,
+    /// Offsets of the ask levels, keyed by price
+    pub asks: HashMap<String, u64>,
+}
+
+impl LevelOffsets {
+    /// Replaces the offsets with those of an initial book
+    pub fn seed(&mut self, snapshot: &BookSnapshot) {
+        self.bids.clear();
+        self.asks.clear();
+
+        for (levels, side) in vec![(&snapshot.bids, orderbook::BID), (&snapshot.asks, orderbook::ASK)] {
+            for level in levels {
+                if let Ok(offset) = level.offset.parse::<u64>() {
+                    self.apply(side, &level.price, offset);
+                }
+            }
+        }
+    }
+
+    /// Records the offset of an update to a level. Returns `false` if the update is stale,
+    /// meaning the level has already been updated at this offset or a later one.
+    pub fn apply(&mut self, side: u8, price: &str, offset: u64) -> bool {
+        let offsets = if side == orderbook::BID {
+            &mut self.bids
+        } else {
+            &mut self.asks
+        };
+
+        match offsets.get(price) {
+            Some(&last) if last >= offset => false,
+            _ => {
+                offsets.insert(price.to_string(), offset);
+                true
+            }
+        }
+    }
+
+    /// Filters an update down to the levels that aren't stale, as `(asks, bids)` with `[price, size]` levels
+    pub fn update(&mut self, update: &BookUpdate) -> (Vec<[String; 2]>, Vec<[String; 2]>) {
+        let offset = match update.offset.parse::<u64>() {
+            Ok(offset) => offset,
+            Err(_) => return (vec![], vec![]),
+        };
+
+        let asks = update.asks.iter()
+            .filter(|level| self.apply(orderbook::ASK, &level[0], offset))
+            .cloned()
+            .collect();
+        let bids = update.bids.iter()
+            .filter(|level| self.apply(orderbook::BID, &level[0], offset))
+            .cloned()
+            .collect();
+
+        (asks, bids)
+    }
+}
+
+/// Converts `[price, size]` levels into deltas. A size of zero removes the level.
+fn levels_to_deltas(symbol: &str, levels: &[[String; 2]], side: u8, ts: f64, seq: &mut u32) -> Vec<orderbook::Delta> {
+    levels.iter()
+        .filter_map(|level| {
+            let price = level[0].parse::<f32>().ok()?;
+            let size = level[1].parse::<f32>().ok()?;
+            *seq += 1;
+
+            Some(orderbook::Delta {
+                symbol: symbol.to_string(),
+                price,
+                size,
+                seq: *seq,
+                event: side ^ if size == 0.0 {
+                    orderbook::REMOVE
+                } else {
+                    orderbook::UPDATE
+                },
+                ts,
+                received_ts: None,
+            })
+        })
+        .collect()
+}
+
+impl WSExchangeSender {
+    /// Publishes orderbook deltas to redis
+    fn publish(&self, deltas: &Vec<orderbook::Delta>) {
+        if deltas.is_empty() {
+            return;
+        }
+
+        exchange::record_symbol_messages(&self.health, deltas.iter().map(|delta| delta.symbol.as_str()));
+        // Lock the connection until we are able to aquire it
+        exchange::publish_deltas(&*self.r.lock().unwrap(), &exchange::market_channel(&self.metadata.exchange, self.metadata.market_type), deltas, &self.health);
+    }
+
+    /// Seeds the market's offsets with the initial book and publishes it in full
+    fn on_snapshot(&mut self, symbol: String, snapshot: BookSnapshot) {
+        self.offsets.entry(symbol.clone())
+            .or_insert_with(LevelOffsets::default)
+            .seed(&snapshot);
+
+        self.snapshot_received = true;
+
+        let to_levels = |levels: &Vec<OffsetLevel>| -> Vec<[String; 2]> {
+            levels.iter().map(|level| [level.price.clone(), level.size.clone()]).collect()
+        };
+
+        let ts = Utc::now().timestamp_millis() as f64 * 0.001f64;
+        let mut seq = 0;
+        let mut deltas = levels_to_deltas(&symbol, &to_levels(&snapshot.asks), orderbook::ASK, ts, &mut seq);
+        deltas.extend(levels_to_deltas(&symbol, &to_levels(&snapshot.bids), orderbook::BID, ts, &mut seq));
+
+        self.publish(&deltas);
+    }
+
+    /// Publishes the levels of an update that aren't stale
+    fn on_update(&mut self, symbol: String, update: BookUpdate) {
+        // Updates received before the initial book can't be applied to anything
+        let (asks, bids) = match self.offsets.get_mut(&symbol) {
+            Some(offsets) => offsets.update(&update),
+            None => return,
+        };
+
+        let ts = Utc::now().timestamp_millis() as f64 * 0.001f64;
+        let mut seq = 0;
+        let mut deltas = levels_to_deltas(&symbol, &asks, orderbook::ASK, ts, &mut seq);
+        deltas.extend(levels_to_deltas(&symbol, &bids, orderbook::BID, ts, &mut seq));
+
+        self.publish(&deltas);
+    }
+}
+
+impl Handler for WSExchangeSender {
+    fn on_open(&mut self, _: Handshake) -> Result<(), Error> {
+        self.health.track_connection(&self.out);
+
+        // Measure the round-trip time of the new connection
+        self.health.ping_latency(&self.out)?;
+
+        // Set a timeout for 5 seconds of inactivity
+        // Issue: currently, this reruns every five seconds. Comment out while we fix.
+        //self.out.timeout(5_000, EXPIRE).unwrap();
+
+        for pair in self.metadata.asset_pair.as_ref().expect("No asset pairs passed to dYdX structure") {
+            let normalized_pair = match exchange::get_asset_pair(pair, Exchange::DyDx) {
+                Ok(normalized_pair) => normalized_pair,
+                Err(e) => {
+                    println!("Skipping dYdX subscription: {}", e);
+                    continue;
+                }
+            };
+            let db_name = format!("{}_{}", self.metadata.exchange.deref(), normalized_pair);
+
+            // Create the database if it doesn't exist yet. This avoids many issues
+            // relating to inserting to a non-existant database.
+            self.storage.create(&db_name)?;
+
+            // dYdX only takes a single market per subscription
+            for channel in &self.single_channels {
+                let msg = SubscribeMessage {
+                    type_: "subscribe".into(),
+                    channel: channel.to_string(),
+                    id: normalized_pair.clone(),
+                    include_offsets: true,
+                };
+
+                println!("Sending message {}", serde_json::to_string(&msg).unwrap());
+                self.out.send(serde_json::to_string(&msg).unwrap())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<(), Error> {
+        self.health.record_message();
+
+        let message = match serde_json::from_slice::<EventMessage>(&msg.into_data()) {
+            Ok(message) => message,
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            }
+        };
+
+        if message.type_ == "error" {
+            println!("dYdX error: {}", message.message.unwrap_or_default());
+            return Ok(());
+        }
+
+        let (channel, symbol, contents) = match (message.channel, message.id, message.contents) {
+            (Some(channel), Some(symbol), Some(contents)) => (channel, symbol, contents),
+            _ => return Ok(()),
+        };
+
+        if channel == "v3_orderbook" {
+            // Offsets are checked on the socket thread, since every update has to be compared to the ones before it
+            if message.type_ == "subscribed" {
+                match serde_json::from_value::<BookSnapshot>(contents) {
+                    Ok(snapshot) => self.on_snapshot(symbol, snapshot),
+                    Err(e) => println!("Error: {}", e),
+                }
+            } else if message.type_ == "channel_data" {
+                match serde_json::from_value::<BookUpdate>(contents) {
+                    Ok(update) => self.on_update(symbol, update),
+                    Err(e) => println!("Error: {}", e),
+                }
+            }
+
+            return Ok(());
+        }
+
+        // The initial trades message only contains historical trades
+        if channel != "v3_trades" || message.type_ != "channel_data" {
+            return Ok(());
+        }
+
+        let redis_ref = self.r.clone();
+        let health = self.health.clone();
+        let channel = exchange::market_channel(&self.metadata.exchange, self.metadata.market_type);
+
+        thread::spawn(move || {
+            let contents = match serde_json::from_value::<TradeContents>(contents) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return;
+                }
+            };
+
+            let trades: Vec<orderbook::Trade> = contents.trades.iter()
+                .filter_map(|trade| Some(orderbook::Trade {
+                    symbol: symbol.clone(),
+                    price: trade.price.parse::<f64>().ok()?,
+                    size: trade.size.parse::<f64>().ok()?,
+                    side: if trade.side == "BUY" {
+                        orderbook::TradeSide::Buy
+                    } else {
+                        orderbook::TradeSide::Sell
+                    },
+                    ts: DateTime::parse_from_rfc3339(&trade.created_at)
+                        .map(|ts| ts.timestamp_millis() as f64 * 0.001f64)
+                        .unwrap_or(Utc::now().timestamp_millis() as f64 * 0.001f64),
+                    exchange: Exchange::DyDx,
+                    trade_id: None,
+                    received_ts: None,
+                }))
+                .collect();
+
+            if trades.is_empty() {
+                return;
+            }
+
+            exchange::record_symbol_messages(&health, trades.iter().map(|trade| trade.symbol.as_str()));
+            exchange::publish_trades(&*redis_ref.lock().unwrap(), &format!("{}:trades", channel), &trades, &health);
+        });
+
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> Result<Option<ws::Frame>, Error> {
+        self.health.on_frame(frame)
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut *self.storage);
+            return;
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("dYdX Socket is closing. Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            single_channels: self.single_channels.clone(),
+            offsets: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+    }
+
+    fn on_timeout(&mut self, _: Token) -> Result<(), ws::Error> {
+        if self.health.is_stopped() {
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("dYdX Socket timed out (5s of inactivity). Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            single_channels: self.single_channels.clone(),
+            offsets: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+
+        Ok(())
+    }
+}
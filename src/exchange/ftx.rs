@@ -0,0 +1,592 @@
+use std::collections::{BTreeMap, HashMap};
+use std::thread;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use crc32fast;
+use redis;
+use serde_json::{self, Value};
+use ws;
+use ws::util::Token;
+use ws::{Error, Handler, Handshake, Message, Sender};
+
+use config::{Config, ConfigError};
+use exchange::{self, Asset, AssetExchange, ConnectionHealth, Exchange, ExchangeError, MarketType};
+use orderbook;
+use storage::{StorageBackend, TectonicBackend};
+
+const EXPIRE: Token = Token(1);
+
+/// Number of levels per side included in FTX's orderbook checksum
+const CHECKSUM_DEPTH: usize = 100;
+
+/// Exchange related metadata. The fields are used to establish
+/// a successful connection with the exchange via websockets.
+#[derive(Clone)]
+pub struct WSExchange {
+    /// Full URL to connect to. Example: `wss://ftx.com/ws/`, or `wss://ftx.us/ws/` for FTX US
+    pub host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    pub snapshot_received: bool,
+
+    /// Collection metadata
+    pub metadata: MetaData,
+    /// Type of market we're collecting. Deltas are published to the `<exchange>:<market type>` Redis channel
+    pub market_type: MarketType,
+
+    /// Channel names we subscribe to for every market (i.e. `orderbook`, `trades`)
+    pub single_channels: Vec<String>,
+    /// Futures markets we subscribe to in addition to the spot asset pairs (i.e. `BTC-PERP`, `BTC-1227`)
+    pub futures_markets: Vec<String>,
+
+    /// Connection health, shared with the running websocket handler
+    pub health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    pub storage: Box<dyn StorageBackend>,
+
+    /// Redis URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub redis_url: String,
+    /// Redis client (before connection). Opened from `redis_url` when connecting
+    pub r: redis::Client,
+    /// Redis password: If this is present, it replaces the password in `redis_url` (if any) when connecting
+    pub r_password: Option<String>,
+}
+
+/// Create two identical structs and transfer the data over when we start the websocket.
+pub struct WSExchangeSender {
+    /// Full URL to connect to. Example: `wss://ftx.com/ws/`
+    host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    snapshot_received: bool,
+
+    /// Collection metadata
+    metadata: MetaData,
+
+    /// Channel names we subscribe to for every market
+    single_channels: Vec<String>,
+    /// Futures markets we subscribe to in addition to the spot asset pairs
+    futures_markets: Vec<String>,
+    /// Local copy of every orderbook, keyed by market. A market is only present once
+    /// we've received its `partial` snapshot.
+    books: HashMap<String, LocalBook>,
+
+    /// Connection health
+    health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    storage: Box<dyn StorageBackend>,
+    /// Redis client (used to send deltas as PUBSUB)
+    r: Arc<Mutex<redis::Connection>>,
+
+    /// Websocket sender
+    out: Sender,
+}
+
+/// Meta data for our data source. This is useful for data warehousing and accessing the data.
+/// All types contained within are considered optional. This may be expanded in the future.
+#[derive(Clone)]
+pub struct MetaData {
+    /// Exchange name. We will warehouse the data under this name
+    pub exchange: Arc<String>,
+
+    /// Vector of asset pairs we're going to warehouse
+    pub asset_pair: Option<Vec<[exchange::Asset; 2]>>,
+
+    /// Type of market we're warehousing. Set from `WSExchange::market_type` when connecting
+    pub market_type: Option<MarketType>,
+
+    /// Starting datetime of our data collection
+    start_date: Option<DateTime<Utc>>,
+
+    /// Ending datetime of our data collection
+    end_date: Option<DateTime<Utc>>,
+}
+
+impl WSExchange {
+    /// Health of the connection to the exchange
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+}
+
+impl AssetExchange for WSExchange {
+    fn default_settings() -> Result<Box<Self>, String> {
+        Ok(Box::new(Self {
+            host: "wss://ftx.com/ws/".into(),
+
+            snapshot_received: false,
+
+            metadata: MetaData {
+                exchange: Arc::new("ftx".into()),
+                asset_pair: Some(vec![
+                    [Asset::BTC, Asset::USD],]),
+                market_type: None,
+                start_date: None,
+                end_date: None,
+            },
+            market_type: MarketType::Spot,
+
+            single_channels: vec![
+                "orderbook".into(),
+                "trades".into()],
+            futures_markets: vec![
+                "BTC-PERP".into()],
+
+            health: ConnectionHealth::new(Exchange::FTX),
+
+            storage: Box::new(TectonicBackend::new(None, None, "ftx").map_err(|e| format!("Unable to connect to TectonicDB: {}", e))?),
+            redis_url: exchange::DEFAULT_REDIS_URL.into(),
+            r: redis::Client::open(exchange::DEFAULT_REDIS_URL).unwrap(),
+            r_password: None,
+        }))
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        if let Some(pairs) = config.asset_pairs()? {
+            self.metadata.asset_pair = Some(pairs);
+        }
+        if let Some(ref channels) = config.channels.names {
+            self.single_channels = channels.clone();
+        }
+        if let Some(ref url) = config.redis.url {
+            self.redis_url = url.clone();
+        }
+        if config.redis.password.is_some() {
+            self.r_password = config.redis.password.clone();
+        }
+        if let Some(storage) = config.storage("ftx")? {
+            self.storage = storage;
+        }
+
+        Ok(())
+    }
+
+    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
+        // The password is sent as part of the URL, so the client authenticates on its own
+        self.r = exchange::redis_client(&self.redis_url, self.r_password.as_ref().map(|password| password.as_str()))?;
+
+        self.r.get_connection()
+    }
+
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    fn start(&self) -> Result<(), ExchangeError> {
+        WSExchange::run_with_settings(Some(self))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
+        // Try to use the settings the user passes before resorting to default settings.
+        let mut settings = match settings {
+            Some(settings) => settings.clone(),
+            None => *WSExchange::default_settings().map_err(ConfigError::Settings)?,
+        };
+
+        let r = Arc::new(Mutex::new(settings.init_redis()?));
+
+        ws::connect(settings.host.clone(), |out| WSExchangeSender {
+            host: settings.host.clone(),
+
+            snapshot_received: settings.snapshot_received.clone(),
+            metadata: MetaData { market_type: Some(settings.market_type), ..settings.metadata.clone() },
+
+            single_channels: settings.single_channels.clone(),
+            futures_markets: settings.futures_markets.clone(),
+            books: HashMap::new(),
+
+            health: settings.health.clone(),
+            storage: settings.storage.clone(),
+            r: r.clone(),
+
+            out,
+        })?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SubscribeMessage {
+    op: String,
+    channel: String,
+    market: String,
+}
+
+/// Every message pushed by FTX has this form. `type` is one of `subscribed`, `unsubscribed`,
+/// `partial`, `update`, `error`, or `info`.
+#[derive(Deserialize)]
+struct EventMessage {
+    channel: Option<String>,
+    market: Option<String>,
+    #[serde(rename = "type")]
+    kind: String,
+    /// Error and info messages
+    msg: Option<String>,
+    data: Option<Value>,
+}
+
+/// Orderbook channel data
+#[derive(Deserialize)]
+struct BookData {
+    /// Timestamp in seconds
+    time: f64,
+    /// CRC32 of the top 100 levels
+    checksum: u32,
+    /// Bid levels as `[price, size]`
+    bids: Vec<[f64; 2]>,
+    /// Ask levels as `[price, size]`
+    asks: Vec<[f64; 2]>,
+}
+
+/// Trades channel data
+#[derive(Deserialize)]
+struct TradeData {
+    id: Option<u64>,
+    price: f64,
+    size: f64,
+    /// Taker side
+    side: String,
+    /// RFC3339 timestamp
+    time: String,
+}
+
+/// Local copy of a single FTX orderbook, used to verify checksums.
+#[derive(Default)]
+pub struct LocalBook {
+    /// Asks as `(price, size)`, keyed by the bits of the price. The bit pattern of a positive
+    /// float sorts the same way as the float itself.
+    asks: BTreeMap<u64, (f64, f64)>,
+    /// Bids as `(price, size)`, keyed by the bits of the price
+    bids: BTreeMap<u64, (f64, f64)>,
+}
+
+impl LocalBook {
+    /// Inserts or replaces a level on the given side (`orderbook::ASK` or `orderbook::BID`).
+    /// A size of zero removes the level from the book.
+    pub fn apply(&mut self, side: u8, price: f64, size: f64) {
+        let levels = if side == orderbook::BID { &mut self.bids } else { &mut self.asks };
+
+        if size == 0.0 {
+            levels.remove(&price.to_bits());
+        } else {
+            levels.insert(price.to_bits(), (price, size));
+        }
+    }
+
+    /// Calculates the CRC32 checksum of the top 100 levels. Bids and asks are interleaved
+    /// as `bid_price:bid_size:ask_price:ask_size:...`, in the same manner as FTX.
+    pub fn checksum(&self) -> u32 {
+        let mut bids = self.bids.values().rev();
+        let mut asks = self.asks.values();
+        let mut fields: Vec<String> = Vec::with_capacity(CHECKSUM_DEPTH * 4);
+
+        for _ in 0..CHECKSUM_DEPTH {
+            for level in vec![bids.next(), asks.next()] {
+                if let Some(&(price, size)) = level {
+                    fields.push(checksum_float(price));
+                    fields.push(checksum_float(size));
+                }
+            }
+        }
+
+        crc32fast::hash(fields.join(":").as_bytes())
+    }
+}
+
+/// FTX formats the floats in its checksum the way Python does: integers keep a trailing
+/// `.0`, and very small or very large numbers use scientific notation with at least two
+/// exponent digits (i.e. `1e-05`).
+pub fn checksum_float(value: f64) -> String {
+    if value != 0.0 && (value.abs() < 1e-4 || value.abs() >= 1e16) {
+        let formatted = format!("{:e}", value);
+        let mut parts = formatted.split('e');
+        let mantissa = parts.next().unwrap_or("");
+        let exponent = parts.next().and_then(|exponent| exponent.parse::<i32>().ok()).unwrap_or(0);
+
+        return format!("{}e{}{:02}", mantissa, if exponent < 0 { "-" } else { "+" }, exponent.abs());
+    }
+
+    if value.fract() == 0.0 {
+        format!("{:.1}", value)
+    } else {
+        format!("{}", value)
+    }
+}
+
+/// FTX sends spot markets as `BTC/USD`. We strip the separator so that the market can be used
+/// as part of a TectonicDB database name (i.e. `ftx_BTCUSD`). Futures markets are left as is.
+fn db_symbol(market: &str) -> String {
+    market.replace("/", "")
+}
+
+impl WSExchangeSender {
+    /// Every market we subscribe to: spot asset pairs first, then the futures markets
+    fn markets(&self) -> Vec<String> {
+        let mut markets = vec![];
+
+        for pair in self.metadata.asset_pair.as_ref().expect("No asset pairs passed to FTX structure") {
+            match exchange::get_asset_pair(pair, Exchange::FTX) {
+                Ok(market) => markets.push(market),
+                Err(e) => println!("Skipping FTX subscription: {}", e),
+            }
+        }
+
+        markets.extend(self.futures_markets.iter().cloned());
+        markets
+    }
+
+    /// Drops and re-requests the orderbook for a single market. FTX sends a fresh `partial`
+    /// once the new subscription is active, which reseeds our local book.
+    fn resubscribe_book(&mut self, market: String) -> Result<(), Error> {
+        self.books.remove(&market);
+
+        for op in &["unsubscribe", "subscribe"] {
+            let msg = SubscribeMessage {
+                op: op.to_string(),
+                channel: "orderbook".into(),
+                market: market.clone(),
+            };
+
+            self.out.send(serde_json::to_string(&msg).unwrap())?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies an orderbook message to our local book, verifies its checksum, and publishes the deltas
+    fn on_book(&mut self, market: String, kind: &str, data: BookData) -> Result<(), Error> {
+        if kind == "partial" {
+            self.books.insert(market.clone(), LocalBook::default());
+            self.snapshot_received = true;
+        }
+
+        let symbol = db_symbol(&market);
+        let mut deltas: Vec<orderbook::Delta> = Vec::with_capacity(data.bids.len() + data.asks.len());
+
+        {
+            // Discard updates that arrive before the snapshot
+            let book = match self.books.get_mut(&market) {
+                Some(book) => book,
+                None => return Ok(()),
+            };
+
+            // Begin sequence counting at 1 in order to reconstruct a proper sequence count
+            let mut seq = 1;
+
+            for (levels, side) in vec![(&data.asks, orderbook::ASK), (&data.bids, orderbook::BID)] {
+                for level in levels {
+                    let (price, size) = (level[0], level[1]);
+                    book.apply(side, price, size);
+
+                    deltas.push(orderbook::Delta {
+                        symbol: symbol.clone(),
+                        price: price as f32,
+                        size: size as f32,
+                        seq,
+                        event: side ^ if size == 0.0 {
+                            orderbook::REMOVE
+                        } else {
+                            orderbook::UPDATE
+                        },
+                        ts: data.time,
+                        received_ts: None,
+                    });
+
+                    seq += 1;
+                }
+            }
+
+            if book.checksum() != data.checksum {
+                println!("FTX checksum mismatch for {}. Resubscribing to the orderbook...", market);
+                return self.resubscribe_book(market);
+            }
+        }
+
+        // Lock the connection until we are able to aquire it
+        if !deltas.is_empty() {
+            exchange::record_symbol_messages(&self.health, deltas.iter().map(|delta| delta.symbol.as_str()));
+            exchange::publish_deltas(&*self.r.lock().unwrap(), &exchange::market_channel(&self.metadata.exchange, self.metadata.market_type), &deltas, &self.health);
+        }
+
+        Ok(())
+    }
+}
+
+impl Handler for WSExchangeSender {
+    fn on_open(&mut self, _: Handshake) -> Result<(), Error> {
+        self.health.track_connection(&self.out);
+
+        // Measure the round-trip time of the new connection
+        self.health.ping_latency(&self.out)?;
+
+        // Set a timeout for 5 seconds of inactivity
+        // Issue: currently, this reruns every five seconds. Comment out while we fix.
+        //self.out.timeout(5_000, EXPIRE).unwrap();
+
+        for market in self.markets() {
+            let db_name = format!("{}_{}", self.metadata.exchange.deref(), db_symbol(&market));
+
+            // Create the database if it doesn't exist yet. This avoids many issues
+            // relating to inserting to a non-existant database.
+            self.storage.create(&db_name)?;
+
+            // FTX only accepts a single channel per subscription message
+            for channel in &self.single_channels {
+                let msg = SubscribeMessage {
+                    op: "subscribe".into(),
+                    channel: channel.to_string(),
+                    market: market.clone(),
+                };
+
+                println!("Sending message {}", serde_json::to_string(&msg).unwrap());
+                self.out.send(serde_json::to_string(&msg).unwrap())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<(), Error> {
+        self.health.record_message();
+
+        let message = match serde_json::from_slice::<EventMessage>(&msg.into_data()) {
+            Ok(message) => message,
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            }
+        };
+
+        let (channel, market, data) = match message {
+            EventMessage { channel: Some(channel), market: Some(market), data: Some(data), .. } => (channel, market, data),
+            EventMessage { kind, msg, .. } => {
+                if kind == "error" {
+                    println!("FTX error: {}", msg.unwrap_or_default());
+                }
+                return Ok(());
+            }
+        };
+
+        if channel == "orderbook" {
+            // Orderbook messages are handled on the socket thread so that our local copy of the
+            // book sees updates in the same order that FTX calculated its checksums in.
+            let kind = data["action"].as_str().unwrap_or("").to_string();
+
+            return match serde_json::from_value::<BookData>(data) {
+                Ok(book) => self.on_book(market, &kind, book),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    Ok(())
+                }
+            };
+        }
+
+        if channel != "trades" {
+            return Ok(());
+        }
+
+        let redis_ref = self.r.clone();
+        let health = self.health.clone();
+        let channel = exchange::market_channel(&self.metadata.exchange, self.metadata.market_type);
+
+        thread::spawn(move || {
+            let trades: Vec<orderbook::Trade> = match serde_json::from_value::<Vec<TradeData>>(data) {
+                Ok(trades) => trades.into_iter()
+                    .map(|trade| orderbook::Trade {
+                        symbol: db_symbol(&market),
+                        price: trade.price,
+                        size: trade.size,
+                        side: if trade.side == "buy" {
+                            orderbook::TradeSide::Buy
+                        } else {
+                            orderbook::TradeSide::Sell
+                        },
+                        ts: DateTime::parse_from_rfc3339(&trade.time)
+                            .map(|time| time.timestamp_millis() as f64 * 0.001f64)
+                            .unwrap_or(Utc::now().timestamp_millis() as f64 * 0.001f64),
+                        exchange: Exchange::FTX,
+                        trade_id: trade.id.map(|id| id.to_string()),
+                        received_ts: None,
+                    })
+                    .collect(),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return;
+                }
+            };
+
+            exchange::record_symbol_messages(&health, trades.iter().map(|trade| trade.symbol.as_str()));
+            exchange::publish_trades(&*redis_ref.lock().unwrap(), &format!("{}:trades", channel), &trades, &health);
+        });
+
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> Result<Option<ws::Frame>, Error> {
+        self.health.on_frame(frame)
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut *self.storage);
+            return;
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("FTX Socket is closing. Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            single_channels: self.single_channels.clone(),
+            futures_markets: self.futures_markets.clone(),
+            books: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+    }
+
+    fn on_timeout(&mut self, _: Token) -> Result<(), ws::Error> {
+        if self.health.is_stopped() {
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("FTX Socket timed out (5s of inactivity). Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            single_channels: self.single_channels.clone(),
+            futures_markets: self.futures_markets.clone(),
+            books: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,641 @@
+use std::collections::HashMap;
+use std::thread;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use redis;
+use reqwest;
+use serde_json;
+use ws;
+use ws::util::Token;
+use ws::{Error, Handler, Handshake, Message, Sender};
+
+use config::{Config, ConfigError};
+use exchange::{self, Asset, AssetExchange, ConnectionHealth, Exchange, ExchangeError, MarketType};
+use orderbook;
+use storage::{StorageBackend, TectonicBackend};
+
+const EXPIRE: Token = Token(1);
+
+/// Exchange related metadata. The fields are used to establish
+/// a successful connection with the exchange via websockets.
+#[derive(Clone)]
+pub struct WSExchange {
+    /// Full URL to connect to. Example: `wss://api.gateio.ws/ws/v4/`
+    pub host: String,
+    /// REST API base URL. Used to fetch orderbook snapshots. Example: `https://api.gateio.ws/api/v4`
+    pub rest_host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    pub snapshot_received: bool,
+
+    /// Collection metadata
+    pub metadata: MetaData,
+    /// Type of market we're collecting. Deltas are published to the `<exchange>:<market type>` Redis channel
+    pub market_type: MarketType,
+
+    /// Interval at which `spot.order_book_update` pushes updates (i.e. `100ms`)
+    pub update_interval: String,
+    /// Number of levels to request from the REST orderbook snapshot
+    pub snapshot_depth: u32,
+
+    /// Connection health, shared with the running websocket handler
+    pub health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    pub storage: Box<dyn StorageBackend>,
+
+    /// Redis URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub redis_url: String,
+    /// Redis client (before connection). Opened from `redis_url` when connecting
+    pub r: redis::Client,
+    /// Redis password: If this is present, it replaces the password in `redis_url` (if any) when connecting
+    pub r_password: Option<String>,
+}
+
+/// Create two identical structs and transfer the data over when we start the websocket.
+pub struct WSExchangeSender {
+    /// Full URL to connect to. Example: `wss://api.gateio.ws/ws/v4/`
+    host: String,
+    /// REST API base URL
+    rest_host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    snapshot_received: bool,
+
+    /// Collection metadata
+    metadata: MetaData,
+
+    /// Interval at which `spot.order_book_update` pushes updates
+    update_interval: String,
+    /// Number of levels to request from the REST orderbook snapshot
+    snapshot_depth: u32,
+    /// Stitches every pair's updates onto its snapshot, keyed by pair (i.e. `BTC_USDT`)
+    books: HashMap<String, BookStitcher>,
+
+    /// Connection health
+    health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    storage: Box<dyn StorageBackend>,
+    /// Redis client (used to send deltas as PUBSUB)
+    r: Arc<Mutex<redis::Connection>>,
+
+    /// Websocket sender
+    out: Sender,
+}
+
+/// Meta data for our data source. This is useful for data warehousing and accessing the data.
+/// All types contained within are considered optional. This may be expanded in the future.
+#[derive(Clone)]
+pub struct MetaData {
+    /// Exchange name. We will warehouse the data under this name
+    pub exchange: Arc<String>,
+
+    /// Vector of asset pairs we're going to warehouse
+    pub asset_pair: Option<Vec<[exchange::Asset; 2]>>,
+
+    /// Type of market we're warehousing. Set from `WSExchange::market_type` when connecting
+    pub market_type: Option<MarketType>,
+
+    /// Starting datetime of our data collection
+    start_date: Option<DateTime<Utc>>,
+
+    /// Ending datetime of our data collection
+    end_date: Option<DateTime<Utc>>,
+}
+
+impl WSExchange {
+    /// Health of the connection to the exchange
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+}
+
+impl AssetExchange for WSExchange {
+    fn default_settings() -> Result<Box<Self>, String> {
+        Ok(Box::new(Self {
+            host: "wss://api.gateio.ws/ws/v4/".into(),
+            rest_host: "https://api.gateio.ws/api/v4".into(),
+
+            snapshot_received: false,
+
+            metadata: MetaData {
+                exchange: Arc::new("gateio".into()),
+                asset_pair: Some(vec![
+                    [Asset::BTC, Asset::USDT],]),
+                market_type: None,
+                start_date: None,
+                end_date: None,
+            },
+            market_type: MarketType::Spot,
+
+            update_interval: "100ms".into(),
+            snapshot_depth: 100,
+
+            health: ConnectionHealth::new(Exchange::GateIO),
+
+            storage: Box::new(TectonicBackend::new(None, None, "gateio").map_err(|e| format!("Unable to connect to TectonicDB: {}", e))?),
+            redis_url: exchange::DEFAULT_REDIS_URL.into(),
+            r: redis::Client::open(exchange::DEFAULT_REDIS_URL).unwrap(),
+            r_password: None,
+        }))
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        if let Some(pairs) = config.asset_pairs()? {
+            self.metadata.asset_pair = Some(pairs);
+        }
+        if let Some(ref url) = config.redis.url {
+            self.redis_url = url.clone();
+        }
+        if config.redis.password.is_some() {
+            self.r_password = config.redis.password.clone();
+        }
+        if let Some(storage) = config.storage("gateio")? {
+            self.storage = storage;
+        }
+
+        Ok(())
+    }
+
+    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
+        // The password is sent as part of the URL, so the client authenticates on its own
+        self.r = exchange::redis_client(&self.redis_url, self.r_password.as_ref().map(|password| password.as_str()))?;
+
+        self.r.get_connection()
+    }
+
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    fn start(&self) -> Result<(), ExchangeError> {
+        WSExchange::run_with_settings(Some(self))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
+        // Try to use the settings the user passes before resorting to default settings.
+        let mut settings = match settings {
+            Some(settings) => settings.clone(),
+            None => *WSExchange::default_settings().map_err(ConfigError::Settings)?,
+        };
+
+        let r = Arc::new(Mutex::new(settings.init_redis()?));
+
+        ws::connect(settings.host.clone(), |out| WSExchangeSender {
+            host: settings.host.clone(),
+            rest_host: settings.rest_host.clone(),
+
+            snapshot_received: settings.snapshot_received.clone(),
+            metadata: MetaData { market_type: Some(settings.market_type), ..settings.metadata.clone() },
+
+            update_interval: settings.update_interval.clone(),
+            snapshot_depth: settings.snapshot_depth,
+            books: HashMap::new(),
+
+            health: settings.health.clone(),
+            storage: settings.storage.clone(),
+            r: r.clone(),
+
+            out,
+        })?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct SubscribeMessage {
+    /// Request time in seconds
+    time: i64,
+    channel: String,
+    event: String,
+    payload: Vec<String>,
+}
+
+/// Every message pushed by Gate.io
+#[derive(Deserialize)]
+struct EventMessage {
+    /// i.e. `spot.order_book_update`
+    channel: String,
+    /// `subscribe` for subscription responses, `update` for data
+    event: String,
+    error: Option<serde_json::Value>,
+    result: Option<serde_json::Value>,
+}
+
+/// `spot.order_book_update` event
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct BookUpdate {
+    /// Update time in milliseconds
+    pub t: u64,
+    /// Pair (i.e. `BTC_USDT`)
+    pub s: String,
+    /// First update ID in the event
+    #[serde(rename = "U")]
+    pub first_update_id: u64,
+    /// Last update ID in the event
+    #[serde(rename = "u")]
+    pub last_update_id: u64,
+    /// Bids as `[price, size]`
+    #[serde(default)]
+    pub b: Vec<[String; 2]>,
+    /// Asks as `[price, size]`
+    #[serde(default)]
+    pub a: Vec<[String; 2]>,
+}
+
+/// `spot.trades` event
+#[derive(Deserialize)]
+struct TradeEvent {
+    id: u64,
+    /// Trade time in milliseconds, with a fractional part (i.e. `"1606292218213.4578"`)
+    create_time_ms: String,
+    /// Taker side (`buy` or `sell`)
+    side: String,
+    currency_pair: String,
+    amount: String,
+    price: String,
+}
+
+/// REST orderbook snapshot (`GET /spot/order_book?with_id=true`)
+#[derive(Deserialize)]
+struct BookSnapshot {
+    id: u64,
+    asks: Vec<[String; 2]>,
+    bids: Vec<[String; 2]>,
+}
+
+/// We've missed at least one update. The book must be reseeded from a new snapshot
+#[derive(Debug, PartialEq)]
+pub struct StitchGap;
+
+/// Stitches `spot.order_book_update` events onto a REST snapshot. Events are buffered until the snapshot's
+/// ID is known, after which the first event to apply must satisfy `U <= id + 1 <= u`, and every event
+/// after it must begin right where the previous one left off (`U == previous u + 1`).
+#[derive(Clone, Debug, Default)]
+pub struct BookStitcher {
+    /// Last update ID applied to the book, or the ID of the snapshot. `None` until seeded
+    pub last_update_id: Option<u64>,
+    /// Set once we've applied the first event following the snapshot
+    pub synced: bool,
+    /// Events received before the snapshot
+    buffer: Vec<BookUpdate>,
+}
+
+impl BookStitcher {
+    /// Creates a stitcher that buffers events until it's seeded
+    pub fn new() -> Self {
+        BookStitcher::default()
+    }
+
+    /// Whether or not we have a snapshot to apply events to
+    pub fn is_seeded(&self) -> bool {
+        self.last_update_id.is_some()
+    }
+
+    /// Seeds the book with the ID of a REST snapshot, and returns the buffered events that apply on top of it
+    pub fn seed(&mut self, snapshot_id: u64) -> Result<Vec<BookUpdate>, StitchGap> {
+        self.last_update_id = Some(snapshot_id);
+        self.synced = false;
+
+        let buffer: Vec<BookUpdate> = self.buffer.drain(..).collect();
+        let mut updates = Vec::with_capacity(buffer.len());
+
+        for update in buffer {
+            updates.extend(self.push(update)?);
+        }
+
+        Ok(updates)
+    }
+
+    /// Buffers the event if we haven't been seeded yet. Otherwise, returns the event if it applies
+    /// to the book, nothing if it's older than the book, or an error if we've missed an event.
+    pub fn push(&mut self, update: BookUpdate) -> Result<Vec<BookUpdate>, StitchGap> {
+        let last_update_id = match self.last_update_id {
+            Some(last_update_id) => last_update_id,
+            None => {
+                self.buffer.push(update);
+                return Ok(vec![]);
+            }
+        };
+
+        if update.last_update_id <= last_update_id {
+            return Ok(vec![]);
+        }
+
+        let expected = last_update_id + 1;
+        let in_sequence = if self.synced {
+            update.first_update_id == expected
+        } else {
+            update.first_update_id <= expected
+        };
+
+        if !in_sequence {
+            return Err(StitchGap);
+        }
+
+        self.last_update_id = Some(update.last_update_id);
+        self.synced = true;
+
+        Ok(vec![update])
+    }
+}
+
+/// Converts `[price, size]` levels into deltas. A size of zero removes the level.
+fn levels_to_deltas(symbol: &str, levels: &[[String; 2]], side: u8, ts: f64, seq: &mut u32) -> Vec<orderbook::Delta> {
+    levels.iter()
+        .filter_map(|level| {
+            let price = level[0].parse::<f32>().ok()?;
+            let size = level[1].parse::<f32>().ok()?;
+            *seq += 1;
+
+            Some(orderbook::Delta {
+                symbol: symbol.to_string(),
+                price,
+                size,
+                seq: *seq,
+                event: side ^ if size == 0.0 {
+                    orderbook::REMOVE
+                } else {
+                    orderbook::UPDATE
+                },
+                ts,
+                received_ts: None,
+            })
+        })
+        .collect()
+}
+
+impl WSExchangeSender {
+    /// Publishes orderbook deltas to redis
+    fn publish(&self, deltas: &Vec<orderbook::Delta>) {
+        if deltas.is_empty() {
+            return;
+        }
+
+        exchange::record_symbol_messages(&self.health, deltas.iter().map(|delta| delta.symbol.as_str()));
+        // Lock the connection until we are able to aquire it
+        exchange::publish_deltas(&*self.r.lock().unwrap(), &exchange::market_channel(&self.metadata.exchange, self.metadata.market_type), deltas, &self.health);
+    }
+
+    /// Fetches an orderbook snapshot over REST and publishes its levels. Returns the snapshot's ID
+    fn fetch_snapshot(&mut self, symbol: &str) -> Option<u64> {
+        let url = format!("{}/spot/order_book?currency_pair={}&limit={}&with_id=true", self.rest_host, symbol, self.snapshot_depth);
+
+        let snapshot: BookSnapshot = match reqwest::get(&url).and_then(|mut response| response.json()) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                println!("Failed to fetch Gate.io orderbook snapshot for {}: {}", symbol, e);
+                return None;
+            }
+        };
+
+        let ts = Utc::now().timestamp_millis() as f64 * 0.001f64;
+        let mut seq = 0;
+        let mut deltas = levels_to_deltas(symbol, &snapshot.asks, orderbook::ASK, ts, &mut seq);
+        deltas.extend(levels_to_deltas(symbol, &snapshot.bids, orderbook::BID, ts, &mut seq));
+
+        self.publish(&deltas);
+        self.snapshot_received = true;
+
+        Some(snapshot.id)
+    }
+
+    /// Stitches an update onto the pair's book, seeding it from a REST snapshot first if necessary
+    fn on_book_update(&mut self, update: BookUpdate) {
+        let symbol = update.s.clone();
+
+        let mut result = self.books.entry(symbol.clone())
+            .or_insert_with(BookStitcher::new)
+            .push(update);
+
+        if !self.books[&symbol].is_seeded() {
+            result = match self.fetch_snapshot(&symbol) {
+                Some(snapshot_id) => self.books.get_mut(&symbol).unwrap().seed(snapshot_id),
+                None => Ok(vec![]),
+            };
+        }
+
+        let updates = match result {
+            Ok(updates) => updates,
+            Err(StitchGap) => {
+                // Forget the book so that the next update reseeds it
+                println!("Gate.io orderbook for {} is out of sequence. Reseeding the book...", symbol);
+                self.books.remove(&symbol);
+                return;
+            }
+        };
+
+        for update in updates {
+            let ts = update.t as f64 * 0.001f64;
+            let mut seq = 0;
+            let mut deltas = levels_to_deltas(&update.s, &update.a, orderbook::ASK, ts, &mut seq);
+            deltas.extend(levels_to_deltas(&update.s, &update.b, orderbook::BID, ts, &mut seq));
+
+            self.publish(&deltas);
+        }
+    }
+
+    /// Sends a subscription request
+    fn subscribe(&self, channel: &str, payload: Vec<String>) -> Result<(), Error> {
+        let msg = SubscribeMessage {
+            time: Utc::now().timestamp(),
+            channel: channel.into(),
+            event: "subscribe".into(),
+            payload,
+        };
+
+        println!("Sending message {}", serde_json::to_string(&msg).unwrap());
+        self.out.send(serde_json::to_string(&msg).unwrap())
+    }
+}
+
+impl Handler for WSExchangeSender {
+    fn on_open(&mut self, _: Handshake) -> Result<(), Error> {
+        self.health.track_connection(&self.out);
+
+        // Measure the round-trip time of the new connection
+        self.health.ping_latency(&self.out)?;
+
+        // Set a timeout for 5 seconds of inactivity
+        // Issue: currently, this reruns every five seconds. Comment out while we fix.
+        //self.out.timeout(5_000, EXPIRE).unwrap();
+
+        let mut symbols = vec![];
+
+        for pair in self.metadata.asset_pair.as_ref().expect("No asset pairs passed to Gate.io structure") {
+            let normalized_pair = match exchange::get_asset_pair(pair, Exchange::GateIO) {
+                Ok(normalized_pair) => normalized_pair,
+                Err(e) => {
+                    println!("Skipping Gate.io subscription: {}", e);
+                    continue;
+                }
+            };
+            let db_name = format!("{}_{}", self.metadata.exchange.deref(), normalized_pair);
+
+            // Create the database if it doesn't exist yet. This avoids many issues
+            // relating to inserting to a non-existant database.
+            self.storage.create(&db_name)?;
+
+            symbols.push(normalized_pair);
+        }
+
+        // Orderbook updates are subscribed to one pair at a time
+        for symbol in &symbols {
+            self.subscribe("spot.order_book_update", vec![symbol.clone(), self.update_interval.clone()])?;
+        }
+
+        self.subscribe("spot.trades", symbols)
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<(), Error> {
+        self.health.record_message();
+
+        let message = match serde_json::from_slice::<EventMessage>(&msg.into_data()) {
+            Ok(message) => message,
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            }
+        };
+
+        if let Some(error) = message.error {
+            println!("Gate.io error on {}: {}", message.channel, error);
+            return Ok(());
+        }
+
+        let result = match message.result {
+            Some(result) => result,
+            None => return Ok(()),
+        };
+
+        // Subscription responses
+        if message.event != "update" {
+            return Ok(());
+        }
+
+        if message.channel == "spot.order_book_update" {
+            // Updates are stitched on the socket thread, since the update IDs
+            // have to be checked in the order the updates arrive.
+            match serde_json::from_value::<BookUpdate>(result) {
+                Ok(update) => self.on_book_update(update),
+                Err(e) => println!("Error: {}", e),
+            }
+
+            return Ok(());
+        }
+
+        if message.channel != "spot.trades" {
+            return Ok(());
+        }
+
+        let redis_ref = self.r.clone();
+        let health = self.health.clone();
+        let channel = exchange::market_channel(&self.metadata.exchange, self.metadata.market_type);
+
+        thread::spawn(move || {
+            let trade = match serde_json::from_value::<TradeEvent>(result) {
+                Ok(trade) => trade,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return;
+                }
+            };
+
+            let (price, size) = match (trade.price.parse::<f64>(), trade.amount.parse::<f64>()) {
+                (Ok(price), Ok(size)) => (price, size),
+                _ => return,
+            };
+
+            let trades = vec![orderbook::Trade {
+                symbol: trade.currency_pair,
+                price,
+                size,
+                side: if trade.side == "buy" {
+                    orderbook::TradeSide::Buy
+                } else {
+                    orderbook::TradeSide::Sell
+                },
+                ts: trade.create_time_ms.parse::<f64>()
+                    .map(|ts| ts * 0.001f64)
+                    .unwrap_or(Utc::now().timestamp_millis() as f64 * 0.001f64),
+                exchange: Exchange::GateIO,
+                trade_id: Some(trade.id.to_string()),
+                received_ts: None,
+            }];
+
+            exchange::record_symbol_messages(&health, trades.iter().map(|trade| trade.symbol.as_str()));
+            exchange::publish_trades(&*redis_ref.lock().unwrap(), &format!("{}:trades", channel), &trades, &health);
+        });
+
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> Result<Option<ws::Frame>, Error> {
+        self.health.on_frame(frame)
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut *self.storage);
+            return;
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Gate.io Socket is closing. Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            rest_host: self.rest_host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            update_interval: self.update_interval.clone(),
+            snapshot_depth: self.snapshot_depth,
+            books: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+    }
+
+    fn on_timeout(&mut self, _: Token) -> Result<(), ws::Error> {
+        if self.health.is_stopped() {
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Gate.io Socket timed out (5s of inactivity). Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            rest_host: self.rest_host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            update_interval: self.update_interval.clone(),
+            snapshot_depth: self.snapshot_depth,
+            books: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+
+        Ok(())
+    }
+}
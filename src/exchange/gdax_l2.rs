@@ -1,16 +1,19 @@
+use std::collections::HashMap;
 use std::thread;
 use std::ops::Deref;
 use std::sync::{Arc, Mutex};
 
 use chrono::prelude::*;
-use redis::{self, Commands};
+use redis;
 use serde_json;
 use ws;
 use ws::util::Token;
 use ws::{Error, Handler, Handshake, Message, Sender};
 
-use exchange::{self, Asset, AssetExchange, Exchange};
+use config::{Config, ConfigError};
+use exchange::{self, Asset, AssetExchange, ConnectionHealth, Exchange, ExchangeError, MarketType};
 use orderbook;
+use storage::{StorageBackend, TectonicBackend};
 
 const EXPIRE: Token = Token(1);
 
@@ -26,16 +29,23 @@ pub struct WSExchange {
 
     /// Collection metadata
     pub metadata: MetaData,
+    /// Type of market we're collecting. Deltas are published to the `<exchange>:<market type>` Redis channel
+    pub market_type: MarketType,
 
     /// Channel name with no argument we want to subscribe to
     pub single_channels: Vec<String>,
 
-    /// TectonicDB connection
-    pub tectonic: orderbook::tectonic::TectonicConnection,
+    /// Connection health, shared with the running websocket handler
+    pub health: ConnectionHealth,
 
-    /// Redis client (before connection)
+    /// Storage backend the collected deltas are warehoused in
+    pub storage: Box<dyn StorageBackend>,
+
+    /// Redis URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub redis_url: String,
+    /// Redis client (before connection). Opened from `redis_url` when connecting
     pub r: redis::Client,
-    /// Redis password: If this is present, we will send an AUTH message to the server on connect
+    /// Redis password: If this is present, it replaces the password in `redis_url` (if any) when connecting
     pub r_password: Option<String>,
 }
 
@@ -53,11 +63,18 @@ pub struct WSExchangeSender {
     /// Channel name with no argument we want to subscribe to
     single_channels: Vec<String>,
 
-    /// TectonicDB connection
-    tectonic: orderbook::tectonic::TectonicConnection,
+    /// Connection health
+    health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    storage: Box<dyn StorageBackend>,
     /// Redis client (used to send deltas as PUBSUB)
     r: Arc<Mutex<redis::Connection>>,
 
+    /// Last level2 sequence number of every product. Updates must follow it, otherwise we've missed one.
+    /// Messages the feed doesn't number (i.e. the original `level2` channel) aren't checked.
+    sequences: HashMap<String, u64>,
+
     /// Websocket sender
     out: Sender,
 }
@@ -72,6 +89,9 @@ pub struct MetaData {
     /// Vector of asset pairs we're going to warehouse
     pub asset_pair: Option<Vec<[exchange::Asset; 2]>>,
 
+    /// Type of market we're warehousing. Set from `WSExchange::market_type` when connecting
+    pub market_type: Option<MarketType>,
+
     /// Starting datetime of our data collection
     start_date: Option<DateTime<Utc>>,
 
@@ -79,6 +99,13 @@ pub struct MetaData {
     end_date: Option<DateTime<Utc>>,
 }
 
+impl WSExchange {
+    /// Health of the connection to the exchange
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+}
+
 impl AssetExchange for WSExchange {
     fn default_settings() -> Result<Box<Self>, String> {
         Ok(Box::new(Self {
@@ -90,54 +117,87 @@ impl AssetExchange for WSExchange {
                 exchange: Arc::new("gdax".into()),
                 asset_pair: Some(vec![
                     [Asset::BTC, Asset::USD],]),
+                market_type: None,
                 start_date: None,
                 end_date: None,
             },
+            market_type: MarketType::Spot,
 
             single_channels: vec![
                 "level2".into(), 
                 "matches".into()],
 
-            tectonic: orderbook::tectonic::TectonicConnection::new(None, None).expect("Unable to connect to TectonicDB"),
-            r: redis::Client::open("redis://localhost").unwrap(),
+            health: ConnectionHealth::new(Exchange::GDAX),
+
+            storage: Box::new(TectonicBackend::new(None, None, "gdax").map_err(|e| format!("Unable to connect to TectonicDB: {}", e))?),
+            redis_url: exchange::DEFAULT_REDIS_URL.into(),
+            r: redis::Client::open(exchange::DEFAULT_REDIS_URL).unwrap(),
             r_password: None,
         }))
     }
 
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        if let Some(pairs) = config.asset_pairs()? {
+            self.metadata.asset_pair = Some(pairs);
+        }
+        if let Some(ref channels) = config.channels.names {
+            self.single_channels = channels.clone();
+        }
+        if let Some(ref url) = config.redis.url {
+            self.redis_url = url.clone();
+        }
+        if config.redis.password.is_some() {
+            self.r_password = config.redis.password.clone();
+        }
+        if let Some(storage) = config.storage("gdax")? {
+            self.storage = storage;
+        }
+
+        Ok(())
+    }
+
     fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
-        let redis_connection = self.r.clone()
-            .get_connection()
-            .unwrap();
-
-        // Send an auth message if we have a password
-        match &self.r_password {
-            Some(password) => {
-                redis::cmd("AUTH").arg(password)
-                    .execute(&redis_connection);
-            },
-            None => (),
-        };
+        // The password is sent as part of the URL, so the client authenticates on its own
+        self.r = exchange::redis_client(&self.redis_url, self.r_password.as_ref().map(|password| password.as_str()))?;
 
-        Ok(redis_connection)
+        self.r.get_connection()
     }
 
-    fn run(settings: Option<&Self>) {
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    fn start(&self) -> Result<(), ExchangeError> {
+        WSExchange::run_with_settings(Some(self))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
         // Try to use the settings the user passes before resorting to default settings.
-        let mut settings = settings.cloned().unwrap_or(*WSExchange::default_settings().unwrap());
+        let mut settings = match settings {
+            Some(settings) => settings.clone(),
+            None => *WSExchange::default_settings().map_err(ConfigError::Settings)?,
+        };
+
+        let r = Arc::new(Mutex::new(settings.init_redis()?));
 
         ws::connect(settings.host.clone(), |out| WSExchangeSender {
             host: settings.host.clone(),
 
             snapshot_received: settings.snapshot_received.clone(),
-            metadata: settings.metadata.clone(),
+            metadata: MetaData { market_type: Some(settings.market_type), ..settings.metadata.clone() },
 
             single_channels: settings.single_channels.clone(),
             
-            tectonic: settings.tectonic.clone(),
-            r: Arc::new(Mutex::new(settings.init_redis().expect("Failed to connect to Redis server."))),
+            health: settings.health.clone(),
+            storage: settings.storage.clone(),
+            r: r.clone(),
+
+            sequences: HashMap::new(),
 
             out,
-        }).unwrap();
+        })?;
+
+        Ok(())
     }
 }
 
@@ -192,29 +252,67 @@ struct EventMessage {
     side: Option<String>,
 }
 
+/// Drops and re-requests the level2 channel for a single product. GDAX sends a fresh snapshot
+/// once the new subscription is active, which reseeds any book built from our deltas.
+fn resubscribe_level2(out: &Sender, product_id: &str) -> Result<(), Error> {
+    for type_ in &["unsubscribe", "subscribe"] {
+        let msg = SubscribeMessage {
+            type_: type_.to_string(),
+            product_ids: vec![product_id.to_string()],
+            channels: vec!["level2".into()],
+        };
+
+        out.send(serde_json::to_string(&msg).unwrap())?;
+    }
+
+    Ok(())
+}
+
+/// Checks a level2 update's sequence number against the last one we've seen for its product, and records it.
+/// On a gap, the product is forgotten so that the next snapshot starts its sequence over.
+pub fn is_level2_gap(sequences: &mut HashMap<String, u64>, product_id: &str, sequence: u64) -> bool {
+    if let Some(&last) = sequences.get(product_id) {
+        if exchange::is_sequence_gap(last, sequence) {
+            sequences.remove(product_id);
+            return true;
+        }
+    }
+
+    sequences.insert(product_id.to_string(), sequence);
+    false
+}
+
 impl Handler for WSExchangeSender {
     fn on_open(&mut self, _: Handshake) -> Result<(), Error> {
+        self.health.track_connection(&self.out);
+
+        // Measure the round-trip time of the new connection
+        self.health.ping_latency(&self.out)?;
+
         // Set a timeout for 5 seconds of inactivity
         // Issue: currently, this reruns every five seconds. Comment out while we fix.
         //self.out.timeout(5_000, EXPIRE).unwrap();
 
-        for pair in self.metadata.asset_pair.as_ref().expect("No asset pairs passed to GDAX structure") {
-            let db_name = format!("{}_{}", self.metadata.exchange.deref(), exchange::get_asset_pair(pair, Exchange::GDAX));
-
-            if !self.tectonic.exists(db_name.clone())? {
-                let _ = self.tectonic.create(db_name);
-            }
-        }
-
         let mut msg = SubscribeMessage {
             type_: "subscribe".into(),
             product_ids: vec![],
             channels: vec![],
         };
 
-        for pair in self.metadata.asset_pair.as_ref().expect("No asset pair provided to GDAX struct") {
-            // Formats the asset pairs into the exchange's asset pair notation
-            let normalized_pair = exchange::get_asset_pair(pair, Exchange::GDAX);
+        for pair in self.metadata.asset_pair.as_ref().expect("No asset pairs passed to GDAX structure") {
+            // Formats the asset pairs into the exchange's asset pair notation. Pairs GDAX
+            // doesn't list are skipped rather than taking down the whole handler.
+            let normalized_pair = match exchange::get_asset_pair(pair, Exchange::GDAX) {
+                Ok(normalized_pair) => normalized_pair,
+                Err(e) => {
+                    println!("Skipping GDAX subscription: {}", e);
+                    continue;
+                }
+            };
+            let db_name = format!("{}_{}", self.metadata.exchange.deref(), normalized_pair);
+
+            self.storage.create(&db_name)?;
+
             msg.product_ids.push(normalized_pair);
         }
 
@@ -233,88 +331,125 @@ impl Handler for WSExchangeSender {
     }
 
     fn on_message(&mut self, msg: Message) -> Result<(), Error> {
+        self.health.record_message();
+
+        let message = match serde_json::from_slice::<EventMessage>(&msg.into_data()) {
+            Ok(message) => message,
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            }
+        };
+
+        // Coinbase's level2 channel carries no checksum to run `Book::verify_checksum` against, so its sequence
+        // numbers are what tells us the book missed an update. They're checked before handing the message off,
+        // since the threads below don't publish in the order the messages were received in.
+        if let Some(sequence) = message.sequence {
+            if message.type_ == "snapshot" {
+                self.sequences.insert(message.product_id.clone(), sequence as u64);
+            } else if message.changes.is_some() && is_level2_gap(&mut self.sequences, &message.product_id, sequence as u64) {
+                println!("GDAX level2 sequence gap for {}. Requesting a new snapshot...", message.product_id);
+                return resubscribe_level2(&self.out, &message.product_id);
+            }
+        }
+
         let redis_ref = self.r.clone();
-        let exchange = self.metadata.exchange.clone();
+        let health = self.health.clone();
+        let channel = exchange::market_channel(&self.metadata.exchange, self.metadata.market_type);
+        let out = self.out.clone();
 
         thread::spawn(move || {
-            match serde_json::from_slice::<EventMessage>(&msg.into_data()) {
-                Ok(message) => {
-                    // Begin sequence counting at 1 in order to reconstruct a proper sequence count 
-                    if message.changes.is_some() {
-                        let mut seq = 1;
-                        let mut deltas: Vec<orderbook::Delta> = Vec::with_capacity(32);
-
-                        for update in message.changes.unwrap() {
-                            deltas.push(orderbook::Delta {
-                                // TODO: See if there's a way to avoid using clone
-                                symbol: message.product_id.clone(),
-                                price: update.1.parse::<f32>().unwrap(),
-                                size: update.2.parse::<f32>().unwrap(),
-                                seq: seq,
-                                event: if update.0 == "buy" {
-                                        orderbook::BID
-                                    } else { 
-                                        orderbook::ASK 
-                                    } ^ if update.2.parse::<f32>().unwrap() == 0.0 {
-                                        orderbook::REMOVE
-                                    } else {
-                                        orderbook::UPDATE
-                                    },
-                                ts: Utc.datetime_from_str(&message.time, "%Y-%m-%dT%H:%M:%S.%3fZ")
-                                    .unwrap()
-                                    .timestamp_millis() as f64 * 0.001f64
-                            });
-
-                            seq += 1;
+            // Begin sequence counting at 1 in order to reconstruct a proper sequence count 
+            if message.changes.is_some() {
+                let mut seq = 1;
+                let mut deltas: Vec<orderbook::Delta> = Vec::with_capacity(32);
+
+                for update in message.changes.unwrap() {
+                    let (price, size) = match (update.1.parse::<f32>(), update.2.parse::<f32>()) {
+                        (Ok(price), Ok(size)) => (price, size),
+                        _ => {
+                            // A corrupt update leaves any book built from our deltas in an unknown
+                            // state, so we request a new snapshot instead of publishing the rest.
+                            println!("Invalid GDAX level2 update for {}. Requesting a new snapshot...", message.product_id);
+                            let _ = resubscribe_level2(&out, &message.product_id);
+                            return;
                         }
-
-                        // Lock the connection until we are able to aquire it
-                        let _ = redis_ref.as_ref()
-                            .lock()
+                    };
+
+                    deltas.push(orderbook::Delta {
+                        // TODO: See if there's a way to avoid using clone
+                        symbol: message.product_id.clone(),
+                        price,
+                        size,
+                        seq: seq,
+                        event: if update.0 == "buy" {
+                                orderbook::BID
+                            } else { 
+                                orderbook::ASK 
+                            } ^ if size == 0.0 {
+                                orderbook::REMOVE
+                            } else {
+                                orderbook::UPDATE
+                            },
+                        ts: Utc.datetime_from_str(&message.time, "%Y-%m-%dT%H:%M:%S.%3fZ")
                             .unwrap()
-                            .publish::<&str, &str, u8>(exchange.deref(), &serde_json::to_string(&deltas).unwrap())
-                            .expect("Failed to publish message to redis PUBSUB");
-
-                    } else if message.type_ == "match" || message.type_ == "last_match" {
-                        let _ = redis_ref.as_ref()
-                            .lock()
-                            .unwrap()
-                            .publish::<&str, &str, u8>(
-                                exchange.deref(), 
-                                &serde_json::to_string(&[orderbook::Delta{
-                                    symbol: message.product_id,
-                                    price: message.price.unwrap().parse::<f32>().unwrap(),
-                                    size: message.size.unwrap().parse::<f32>().unwrap(),
-                                    seq: message.sequence.unwrap() as u32,
-                                    event: if message.side.unwrap() == "buy" {
-                                        orderbook::BID
-                                    } else { 
-                                        orderbook::ASK 
-                                    } ^ orderbook::TRADE,
-
-                                    ts: Utc.datetime_from_str(&message.time, "%Y-%m-%dT%H:%M:%S.%6fZ")
-                                        .expect("Failed to parse DateTime from string")
-                                        .timestamp_millis() as f64 * 0.001f64 
-                                }])
-                                .unwrap())
-                            .expect("Failed to publish GDAX 'match' to Redis");
+                            .timestamp_millis() as f64 * 0.001f64,
+                        received_ts: None,
+                    });
+
+                    seq += 1;
+                }
+
+                exchange::record_symbol_messages(&health, deltas.iter().map(|delta| delta.symbol.as_str()));
+                // Lock the connection until we are able to aquire it
+                exchange::publish_deltas(&*redis_ref.lock().unwrap(), &channel, &deltas, &health);
+
+            } else if message.type_ == "match" || message.type_ == "last_match" {
+                let trade = orderbook::Trade {
+                    symbol: message.product_id,
+                    price: message.price.unwrap().parse::<f64>().unwrap(),
+                    size: message.size.unwrap().parse::<f64>().unwrap(),
+                    // GDAX reports the side of the maker order, so the taker is on the other side
+                    side: if message.side.unwrap() == "buy" {
+                        orderbook::TradeSide::Sell
                     } else {
-                        // Message is snapshot. Save to disk and upload to s3 or google cloud 
+                        orderbook::TradeSide::Buy
+                    },
+                    ts: Utc.datetime_from_str(&message.time, "%Y-%m-%dT%H:%M:%S.%6fZ")
+                        .expect("Failed to parse DateTime from string")
+                        .timestamp_millis() as f64 * 0.001f64,
+                    exchange: Exchange::GDAX,
+                    trade_id: message.trade_id.map(|id| id.to_string()),
+                    received_ts: None,
+                };
+
+                health.record_symbol_message(&trade.symbol);
+                exchange::publish_trades(&*redis_ref.lock().unwrap(), &format!("{}:trades", channel), &[trade], &health);
+            } else {
+                // Message is snapshot. Save to disk and upload to s3 or google cloud 
 
-                    }
-                },
-                Err(e) => println!("Error: {}", e),
-            };
+            }
         });
 
         Ok(())
     }
 
+    fn on_frame(&mut self, frame: ws::Frame) -> Result<Option<ws::Frame>, Error> {
+        self.health.on_frame(frame)
+    }
+
     fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut *self.storage);
+            return;
+        }
+
         // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
         // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
         println!("GDAX Socket is closing. Opening a new connection...");
 
+        self.health.record_reconnect();
+
         ws::connect(self.host.clone(), |out| WSExchangeSender{
             host: self.host.clone(),
             snapshot_received: false,
@@ -322,18 +457,27 @@ impl Handler for WSExchangeSender {
 
             single_channels: self.single_channels.clone(),
 
-            tectonic: self.tectonic.clone(),
+            health: self.health.clone(),
+            storage: self.storage.clone(),
             r: self.r.clone(),
 
+            sequences: HashMap::new(),
+
             out,
         }).unwrap();
     }
 
     fn on_timeout(&mut self, _: Token) -> Result<(), ws::Error> {
+        if self.health.is_stopped() {
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
         // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
         // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
         println!("GDAX Socket timed out (5s of inactivity). Opening a new connection...");
 
+        self.health.record_reconnect();
+
         ws::connect(self.host.clone(), |out| WSExchangeSender{
             host: self.host.clone(),
             snapshot_received: false,
@@ -341,9 +485,12 @@ impl Handler for WSExchangeSender {
 
             single_channels: self.single_channels.clone(),
 
-            tectonic: self.tectonic.clone(),
+            health: self.health.clone(),
+            storage: self.storage.clone(),
             r: self.r.clone(),
 
+            sequences: HashMap::new(),
+
             out,
         }).unwrap();
 
@@ -0,0 +1,442 @@
+use std::thread;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use redis;
+use serde_json;
+use ws;
+use ws::util::Token;
+use ws::{Error, Handler, Handshake, Message, Sender};
+
+use config::{Config, ConfigError};
+use exchange::{self, Asset, AssetExchange, ConnectionHealth, Exchange, ExchangeError, MarketType};
+use orderbook;
+use storage::{StorageBackend, TectonicBackend};
+
+const EXPIRE: Token = Token(1);
+
+/// Exchange related metadata. The fields are used to establish
+/// a successful connection with the exchange via websockets.
+#[derive(Clone)]
+pub struct WSExchange {
+    /// Full URL to connect to. Example: `wss://api.gemini.com/v2/marketdata`
+    pub host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    pub snapshot_received: bool,
+
+    /// Collection metadata
+    pub metadata: MetaData,
+    /// Type of market we're collecting. Deltas are published to the `<exchange>:<market type>` Redis channel
+    pub market_type: MarketType,
+
+    /// Subscription names we subscribe to for every asset pair (i.e. `l2`). Trades are included in `l2`
+    pub subscriptions: Vec<String>,
+
+    /// Connection health, shared with the running websocket handler
+    pub health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    pub storage: Box<dyn StorageBackend>,
+
+    /// Redis URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub redis_url: String,
+    /// Redis client (before connection). Opened from `redis_url` when connecting
+    pub r: redis::Client,
+    /// Redis password: If this is present, it replaces the password in `redis_url` (if any) when connecting
+    pub r_password: Option<String>,
+}
+
+/// Create two identical structs and transfer the data over when we start the websocket.
+pub struct WSExchangeSender {
+    /// Full URL to connect to. Example: `wss://api.gemini.com/v2/marketdata`
+    host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    snapshot_received: bool,
+
+    /// Collection metadata
+    metadata: MetaData,
+
+    /// Subscription names we subscribe to for every asset pair
+    subscriptions: Vec<String>,
+
+    /// Connection health
+    health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    storage: Box<dyn StorageBackend>,
+    /// Redis client (used to send deltas as PUBSUB)
+    r: Arc<Mutex<redis::Connection>>,
+
+    /// Websocket sender
+    out: Sender,
+}
+
+/// Meta data for our data source. This is useful for data warehousing and accessing the data.
+/// All types contained within are considered optional. This may be expanded in the future.
+#[derive(Clone)]
+pub struct MetaData {
+    /// Exchange name. We will warehouse the data under this name
+    pub exchange: Arc<String>,
+
+    /// Vector of asset pairs we're going to warehouse
+    pub asset_pair: Option<Vec<[exchange::Asset; 2]>>,
+
+    /// Type of market we're warehousing. Set from `WSExchange::market_type` when connecting
+    pub market_type: Option<MarketType>,
+
+    /// Starting datetime of our data collection
+    start_date: Option<DateTime<Utc>>,
+
+    /// Ending datetime of our data collection
+    end_date: Option<DateTime<Utc>>,
+}
+
+impl WSExchange {
+    /// Health of the connection to the exchange
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+}
+
+impl AssetExchange for WSExchange {
+    fn default_settings() -> Result<Box<Self>, String> {
+        Ok(Box::new(Self {
+            host: "wss://api.gemini.com/v2/marketdata".into(),
+
+            snapshot_received: false,
+
+            metadata: MetaData {
+                exchange: Arc::new("gemini".into()),
+                asset_pair: Some(vec![
+                    [Asset::BTC, Asset::USD],]),
+                market_type: None,
+                start_date: None,
+                end_date: None,
+            },
+            market_type: MarketType::Spot,
+
+            subscriptions: vec![
+                "l2".into()],
+
+            health: ConnectionHealth::new(Exchange::Gemini),
+
+            storage: Box::new(TectonicBackend::new(None, None, "gemini").map_err(|e| format!("Unable to connect to TectonicDB: {}", e))?),
+            redis_url: exchange::DEFAULT_REDIS_URL.into(),
+            r: redis::Client::open(exchange::DEFAULT_REDIS_URL).unwrap(),
+            r_password: None,
+        }))
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        if let Some(pairs) = config.asset_pairs()? {
+            self.metadata.asset_pair = Some(pairs);
+        }
+        if let Some(ref url) = config.redis.url {
+            self.redis_url = url.clone();
+        }
+        if config.redis.password.is_some() {
+            self.r_password = config.redis.password.clone();
+        }
+        if let Some(storage) = config.storage("gemini")? {
+            self.storage = storage;
+        }
+
+        Ok(())
+    }
+
+    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
+        // The password is sent as part of the URL, so the client authenticates on its own
+        self.r = exchange::redis_client(&self.redis_url, self.r_password.as_ref().map(|password| password.as_str()))?;
+
+        self.r.get_connection()
+    }
+
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    fn start(&self) -> Result<(), ExchangeError> {
+        WSExchange::run_with_settings(Some(self))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
+        // Try to use the settings the user passes before resorting to default settings.
+        let mut settings = match settings {
+            Some(settings) => settings.clone(),
+            None => *WSExchange::default_settings().map_err(ConfigError::Settings)?,
+        };
+
+        let r = Arc::new(Mutex::new(settings.init_redis()?));
+
+        ws::connect(settings.host.clone(), |out| WSExchangeSender {
+            host: settings.host.clone(),
+
+            snapshot_received: settings.snapshot_received.clone(),
+            metadata: MetaData { market_type: Some(settings.market_type), ..settings.metadata.clone() },
+
+            subscriptions: settings.subscriptions.clone(),
+
+            health: settings.health.clone(),
+            storage: settings.storage.clone(),
+            r: r.clone(),
+
+            out,
+        })?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SubscribeMessage {
+    #[serde(rename = "type")]
+    type_: String,
+    subscriptions: Vec<Subscription>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Subscription {
+    name: String,
+    symbols: Vec<String>,
+}
+
+/// Every message pushed by Gemini has this form. `l2_updates` carry `changes` (and the most recent
+/// trades on the first message), whereas `trade` messages carry the trade fields directly.
+#[derive(Deserialize)]
+struct EventMessage {
+    /// `l2_updates`, `trade`, or `heartbeat`
+    #[serde(rename = "type")]
+    type_: String,
+    /// Asset pair the message applies to (i.e. `BTCUSD`)
+    #[serde(default)]
+    symbol: String,
+
+    /// Orderbook changes as `[side, price, quantity]`
+    changes: Option<Vec<[String; 3]>>,
+
+    /// Timestamp in milliseconds. Only present on trades
+    timestamp: Option<u64>,
+    /// Trade ID
+    event_id: Option<u64>,
+    price: Option<String>,
+    quantity: Option<String>,
+    /// Taker side (`buy` or `sell`)
+    side: Option<String>,
+}
+
+impl Handler for WSExchangeSender {
+    fn on_open(&mut self, _: Handshake) -> Result<(), Error> {
+        self.health.track_connection(&self.out);
+
+        // Measure the round-trip time of the new connection
+        self.health.ping_latency(&self.out)?;
+
+        // Set a timeout for 5 seconds of inactivity
+        // Issue: currently, this reruns every five seconds. Comment out while we fix.
+        //self.out.timeout(5_000, EXPIRE).unwrap();
+
+        let mut symbols = vec![];
+
+        for pair in self.metadata.asset_pair.as_ref().expect("No asset pairs passed to Gemini structure") {
+            let normalized_pair = match exchange::get_asset_pair(pair, Exchange::Gemini) {
+                Ok(normalized_pair) => normalized_pair,
+                Err(e) => {
+                    println!("Skipping Gemini subscription: {}", e);
+                    continue;
+                }
+            };
+            let db_name = format!("{}_{}", self.metadata.exchange.deref(), normalized_pair);
+
+            // Create the database if it doesn't exist yet. This avoids many issues
+            // relating to inserting to a non-existant database.
+            self.storage.create(&db_name)?;
+
+            symbols.push(normalized_pair);
+        }
+
+        let msg = SubscribeMessage {
+            type_: "subscribe".into(),
+            subscriptions: self.subscriptions.iter()
+                .map(|name| Subscription {
+                    name: name.to_string(),
+                    symbols: symbols.clone(),
+                })
+                .collect(),
+        };
+
+        println!("Sending message {}", serde_json::to_string(&msg).unwrap());
+        self.out.send(serde_json::to_string(&msg).unwrap())
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<(), Error> {
+        self.health.record_message();
+
+        let message = match serde_json::from_slice::<EventMessage>(&msg.into_data()) {
+            Ok(message) => message,
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            }
+        };
+
+        // The first `l2_updates` message of a subscription is the snapshot of the book
+        if message.type_ == "l2_updates" {
+            self.snapshot_received = true;
+        }
+
+        let redis_ref = self.r.clone();
+        let health = self.health.clone();
+        let channel = exchange::market_channel(&self.metadata.exchange, self.metadata.market_type);
+
+        thread::spawn(move || {
+            if message.type_ == "l2_updates" {
+                // Gemini doesn't timestamp book updates, so we use the time we received them
+                let ts = Utc::now().timestamp_millis() as f64 * 0.001f64;
+                let changes = message.changes.unwrap_or_default();
+                let mut deltas: Vec<orderbook::Delta> = Vec::with_capacity(changes.len());
+
+                // Begin sequence counting at 1 in order to reconstruct a proper sequence count
+                let mut seq = 1;
+
+                for change in changes {
+                    let (price, size) = match (change[1].parse::<f32>(), change[2].parse::<f32>()) {
+                        (Ok(price), Ok(size)) => (price, size),
+                        _ => continue,
+                    };
+
+                    deltas.push(orderbook::Delta {
+                        symbol: message.symbol.clone(),
+                        price,
+                        size,
+                        seq,
+                        event: if change[0] == "buy" {
+                                orderbook::BID
+                            } else {
+                                orderbook::ASK
+                            } ^ if size == 0.0 {
+                                orderbook::REMOVE
+                            } else {
+                                orderbook::UPDATE
+                            },
+                        ts,
+                        received_ts: None,
+                    });
+
+                    seq += 1;
+                }
+
+                if deltas.is_empty() {
+                    return;
+                }
+
+                exchange::record_symbol_messages(&health, deltas.iter().map(|delta| delta.symbol.as_str()));
+                // Lock the connection until we are able to aquire it
+                exchange::publish_deltas(&*redis_ref.lock().unwrap(), &channel, &deltas, &health);
+
+            } else if message.type_ == "trade" {
+                let (price, size) = match (
+                    message.price.and_then(|price| price.parse::<f64>().ok()),
+                    message.quantity.and_then(|quantity| quantity.parse::<f64>().ok())) {
+
+                    (Some(price), Some(size)) => (price, size),
+                    _ => return,
+                };
+
+                let buy = message.side.map(|side| side == "buy").unwrap_or(false);
+                let ts = message.timestamp
+                    .map(|ts| ts as f64 * 0.001f64)
+                    .unwrap_or(Utc::now().timestamp_millis() as f64 * 0.001f64);
+
+                let trade = orderbook::Trade {
+                    symbol: message.symbol,
+                    price,
+                    size,
+                    side: if buy {
+                        orderbook::TradeSide::Buy
+                    } else {
+                        orderbook::TradeSide::Sell
+                    },
+                    ts,
+                    exchange: Exchange::Gemini,
+                    trade_id: message.event_id.map(|id| id.to_string()),
+                    received_ts: None,
+                };
+
+                // Trades are stored alongside the orderbook deltas, and are also published on their own channel
+                let delta = orderbook::Delta::from(&trade);
+
+                let r = redis_ref.as_ref()
+                    .lock()
+                    .unwrap();
+
+                health.record_symbol_message(&trade.symbol);
+                exchange::publish_deltas(&*r, &channel, &[delta], &health);
+                exchange::publish_trades(&*r, &format!("{}:trades", channel), &[trade], &health);
+            }
+        });
+
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> Result<Option<ws::Frame>, Error> {
+        self.health.on_frame(frame)
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut *self.storage);
+            return;
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Gemini Socket is closing. Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            subscriptions: self.subscriptions.clone(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+    }
+
+    fn on_timeout(&mut self, _: Token) -> Result<(), ws::Error> {
+        if self.health.is_stopped() {
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Gemini Socket timed out (5s of inactivity). Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            subscriptions: self.subscriptions.clone(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,559 @@
+use std::collections::HashMap;
+use std::thread;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use redis;
+use serde_json;
+use ws;
+use ws::util::Token;
+use ws::{Error, Handler, Handshake, Message, Sender};
+
+use config::{Config, ConfigError};
+use exchange::{self, Asset, AssetExchange, ConnectionHealth, Exchange, ExchangeError, MarketType};
+use orderbook;
+use storage::{StorageBackend, TectonicBackend};
+
+const EXPIRE: Token = Token(1);
+
+/// Exchange related metadata. The fields are used to establish
+/// a successful connection with the exchange via websockets.
+#[derive(Clone)]
+pub struct WSExchange {
+    /// Full URL to connect to. Example: `wss://api.hitbtc.com/api/2/ws`
+    pub host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    pub snapshot_received: bool,
+
+    /// Collection metadata
+    pub metadata: MetaData,
+    /// Type of market we're collecting. Deltas are published to the `<exchange>:<market type>` Redis channel
+    pub market_type: MarketType,
+
+    /// Connection health, shared with the running websocket handler
+    pub health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    pub storage: Box<dyn StorageBackend>,
+
+    /// Redis URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub redis_url: String,
+    /// Redis client (before connection). Opened from `redis_url` when connecting
+    pub r: redis::Client,
+    /// Redis password: If this is present, it replaces the password in `redis_url` (if any) when connecting
+    pub r_password: Option<String>,
+}
+
+/// Create two identical structs and transfer the data over when we start the websocket.
+pub struct WSExchangeSender {
+    /// Full URL to connect to. Example: `wss://api.hitbtc.com/api/2/ws`
+    host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    snapshot_received: bool,
+
+    /// Collection metadata
+    metadata: MetaData,
+
+    /// Sequence of the last book notification applied for every symbol (i.e. `BTCUSD`)
+    sequences: HashMap<String, u64>,
+    /// ID of the next JSON-RPC request we send
+    request_id: u64,
+
+    /// Connection health
+    health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    storage: Box<dyn StorageBackend>,
+    /// Redis client (used to send deltas as PUBSUB)
+    r: Arc<Mutex<redis::Connection>>,
+
+    /// Websocket sender
+    out: Sender,
+}
+
+/// Meta data for our data source. This is useful for data warehousing and accessing the data.
+/// All types contained within are considered optional. This may be expanded in the future.
+#[derive(Clone)]
+pub struct MetaData {
+    /// Exchange name. We will warehouse the data under this name
+    pub exchange: Arc<String>,
+
+    /// Vector of asset pairs we're going to warehouse
+    pub asset_pair: Option<Vec<[exchange::Asset; 2]>>,
+
+    /// Type of market we're warehousing. Set from `WSExchange::market_type` when connecting
+    pub market_type: Option<MarketType>,
+
+    /// Starting datetime of our data collection
+    start_date: Option<DateTime<Utc>>,
+
+    /// Ending datetime of our data collection
+    end_date: Option<DateTime<Utc>>,
+}
+
+impl WSExchange {
+    /// Health of the connection to the exchange
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+}
+
+impl AssetExchange for WSExchange {
+    fn default_settings() -> Result<Box<Self>, String> {
+        Ok(Box::new(Self {
+            host: "wss://api.hitbtc.com/api/2/ws".into(),
+
+            snapshot_received: false,
+
+            metadata: MetaData {
+                exchange: Arc::new("hitbtc".into()),
+                asset_pair: Some(vec![
+                    [Asset::BTC, Asset::USD],]),
+                market_type: None,
+                start_date: None,
+                end_date: None,
+            },
+            market_type: MarketType::Spot,
+
+            health: ConnectionHealth::new(Exchange::HitBTC),
+
+            storage: Box::new(TectonicBackend::new(None, None, "hitbtc").map_err(|e| format!("Unable to connect to TectonicDB: {}", e))?),
+            redis_url: exchange::DEFAULT_REDIS_URL.into(),
+            r: redis::Client::open(exchange::DEFAULT_REDIS_URL).unwrap(),
+            r_password: None,
+        }))
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        if let Some(pairs) = config.asset_pairs()? {
+            self.metadata.asset_pair = Some(pairs);
+        }
+        if let Some(ref url) = config.redis.url {
+            self.redis_url = url.clone();
+        }
+        if config.redis.password.is_some() {
+            self.r_password = config.redis.password.clone();
+        }
+        if let Some(storage) = config.storage("hitbtc")? {
+            self.storage = storage;
+        }
+
+        Ok(())
+    }
+
+    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
+        // The password is sent as part of the URL, so the client authenticates on its own
+        self.r = exchange::redis_client(&self.redis_url, self.r_password.as_ref().map(|password| password.as_str()))?;
+
+        self.r.get_connection()
+    }
+
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    fn start(&self) -> Result<(), ExchangeError> {
+        WSExchange::run_with_settings(Some(self))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
+        // Try to use the settings the user passes before resorting to default settings.
+        let mut settings = match settings {
+            Some(settings) => settings.clone(),
+            None => *WSExchange::default_settings().map_err(ConfigError::Settings)?,
+        };
+
+        let r = Arc::new(Mutex::new(settings.init_redis()?));
+
+        ws::connect(settings.host.clone(), |out| WSExchangeSender {
+            host: settings.host.clone(),
+
+            snapshot_received: settings.snapshot_received.clone(),
+            metadata: MetaData { market_type: Some(settings.market_type), ..settings.metadata.clone() },
+
+            sequences: HashMap::new(),
+            request_id: 1,
+
+            health: settings.health.clone(),
+            storage: settings.storage.clone(),
+            r: r.clone(),
+
+            out,
+        })?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct RequestMessage {
+    method: String,
+    params: RequestParams,
+    id: u64,
+}
+
+#[derive(Serialize)]
+struct RequestParams {
+    symbol: String,
+}
+
+/// JSON-RPC notification. Responses to our requests carry a `result` instead of a `method`, and are ignored.
+#[derive(Deserialize)]
+struct Notification {
+    /// `snapshotOrderbook`, `updateOrderbook`, `snapshotTrades`, or `updateTrades`
+    method: Option<String>,
+    params: Option<serde_json::Value>,
+    error: Option<serde_json::Value>,
+}
+
+/// Orderbook snapshot or update. Updates only carry the levels that changed
+#[derive(Deserialize)]
+struct BookParams {
+    ask: Vec<Level>,
+    bid: Vec<Level>,
+    /// Symbol (i.e. `BTCUSD`)
+    symbol: String,
+    /// Increases by one with every notification of the symbol's book
+    sequence: u64,
+    /// ISO 8601 timestamp (i.e. `2018-11-19T05:00:28.193Z`)
+    timestamp: Option<String>,
+}
+
+/// Price level with string-encoded decimals. A size of zero removes the level
+#[derive(Deserialize)]
+struct Level {
+    price: String,
+    size: String,
+}
+
+#[derive(Deserialize)]
+struct TradeParams {
+    data: Vec<TradeData>,
+    symbol: String,
+}
+
+#[derive(Deserialize)]
+struct TradeData {
+    id: u64,
+    price: String,
+    quantity: String,
+    /// Taker side (`buy` or `sell`)
+    side: String,
+    timestamp: String,
+}
+
+/// Outcome of checking a book notification against the sequence we've already applied
+#[derive(Debug, PartialEq)]
+pub enum SequenceCheck {
+    /// The notification continues the book and should be applied
+    Apply,
+    /// The notification is older than what we've already applied and should be discarded
+    Drop,
+    /// We've missed at least one notification. The book must be reseeded from a new snapshot
+    Gap,
+}
+
+/// Checks the `sequence` of an `updateOrderbook` notification against the last one applied,
+/// which must be exactly one behind it.
+pub fn check_sequence(last: u64, sequence: u64) -> SequenceCheck {
+    if sequence <= last {
+        SequenceCheck::Drop
+    } else if sequence == last + 1 {
+        SequenceCheck::Apply
+    } else {
+        SequenceCheck::Gap
+    }
+}
+
+/// Parses a string-encoded decimal (i.e. `"0.054588"`). HitBTC sends decimals as strings so that
+/// they don't lose precision in transit. Anything that isn't a finite number is rejected instead of
+/// being written to the book.
+pub fn parse_decimal(value: &str) -> Option<f32> {
+    value.trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|value| value.is_finite())
+}
+
+/// Parses an ISO 8601 timestamp into UNIX epoch time in seconds
+fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(|ts| ts.timestamp_millis() as f64 * 0.001f64)
+}
+
+impl WSExchangeSender {
+    /// Sends a JSON-RPC request for a single symbol
+    fn request(&mut self, method: &str, symbol: &str) -> Result<(), Error> {
+        let msg = RequestMessage {
+            method: method.into(),
+            params: RequestParams {
+                symbol: symbol.to_string(),
+            },
+            id: self.request_id,
+        };
+        self.request_id += 1;
+
+        println!("Sending message {}", serde_json::to_string(&msg).unwrap());
+        self.out.send(serde_json::to_string(&msg).unwrap())
+    }
+
+    /// Resubscribes to a symbol's book, so that HitBTC sends us a fresh snapshot
+    fn resubscribe(&mut self, symbol: &str) -> Result<(), Error> {
+        self.sequences.remove(symbol);
+
+        self.request("unsubscribeOrderbook", symbol)?;
+        self.request("subscribeOrderbook", symbol)
+    }
+
+    /// Applies a book snapshot or update. Snapshots reset the sequence, whereas updates
+    /// must follow the last notification we've applied.
+    fn on_book(&mut self, book: BookParams, snapshot: bool) -> Result<(), Error> {
+        if snapshot {
+            self.snapshot_received = true;
+        } else {
+            let check = match self.sequences.get(&book.symbol) {
+                Some(last) => check_sequence(*last, book.sequence),
+                // We're still waiting on the snapshot
+                None => SequenceCheck::Drop,
+            };
+
+            match check {
+                SequenceCheck::Apply => (),
+                SequenceCheck::Drop => return Ok(()),
+                SequenceCheck::Gap => {
+                    println!("HitBTC book for {} is out of sequence. Resubscribing...", book.symbol);
+                    return self.resubscribe(&book.symbol);
+                }
+            }
+        }
+
+        self.sequences.insert(book.symbol.clone(), book.sequence);
+
+        let ts = book.timestamp.as_ref()
+            .and_then(|ts| parse_timestamp(ts))
+            .unwrap_or(Utc::now().timestamp_millis() as f64 * 0.001f64);
+
+        let mut deltas: Vec<orderbook::Delta> = Vec::with_capacity(book.ask.len() + book.bid.len());
+
+        // Begin sequence counting at 1 in order to reconstruct a proper sequence count
+        let mut seq = 1;
+
+        for (levels, side) in vec![(&book.ask, orderbook::ASK), (&book.bid, orderbook::BID)] {
+            for level in levels {
+                let (price, size) = match (parse_decimal(&level.price), parse_decimal(&level.size)) {
+                    (Some(price), Some(size)) => (price, size),
+                    _ => {
+                        println!("Skipping invalid HitBTC level {} @ {}", level.size, level.price);
+                        continue;
+                    }
+                };
+
+                deltas.push(orderbook::Delta {
+                    symbol: book.symbol.clone(),
+                    price,
+                    size,
+                    seq,
+                    event: side ^ if size == 0.0 {
+                        orderbook::REMOVE
+                    } else {
+                        orderbook::UPDATE
+                    },
+                    ts,
+                    received_ts: None,
+                });
+
+                seq += 1;
+            }
+        }
+
+        if deltas.is_empty() {
+            return Ok(());
+        }
+
+        exchange::record_symbol_messages(&self.health, deltas.iter().map(|delta| delta.symbol.as_str()));
+        // Lock the connection until we are able to aquire it
+        exchange::publish_deltas(&*self.r.lock().unwrap(), &exchange::market_channel(&self.metadata.exchange, self.metadata.market_type), &deltas, &self.health);
+
+        Ok(())
+    }
+}
+
+impl Handler for WSExchangeSender {
+    fn on_open(&mut self, _: Handshake) -> Result<(), Error> {
+        self.health.track_connection(&self.out);
+
+        // Measure the round-trip time of the new connection
+        self.health.ping_latency(&self.out)?;
+
+        // Set a timeout for 5 seconds of inactivity
+        // Issue: currently, this reruns every five seconds. Comment out while we fix.
+        //self.out.timeout(5_000, EXPIRE).unwrap();
+
+        let pairs = self.metadata.asset_pair.clone().expect("No asset pairs passed to HitBTC structure");
+
+        for pair in &pairs {
+            let symbol = match exchange::get_asset_pair(pair, Exchange::HitBTC) {
+                Ok(symbol) => symbol,
+                Err(e) => {
+                    println!("Skipping HitBTC subscription: {}", e);
+                    continue;
+                }
+            };
+            let db_name = format!("{}_{}", self.metadata.exchange.deref(), symbol);
+
+            // Create the database if it doesn't exist yet. This avoids many issues
+            // relating to inserting to a non-existant database.
+            self.storage.create(&db_name)?;
+
+            self.request("subscribeOrderbook", &symbol)?;
+            self.request("subscribeTrades", &symbol)?;
+        }
+
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<(), Error> {
+        self.health.record_message();
+
+        let message = match serde_json::from_slice::<Notification>(&msg.into_data()) {
+            Ok(message) => message,
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            }
+        };
+
+        if let Some(error) = message.error {
+            println!("HitBTC error: {}", error);
+            return Ok(());
+        }
+
+        let (method, params) = match (message.method, message.params) {
+            (Some(method), Some(params)) => (method, params),
+            // Responses to our subscription requests
+            _ => return Ok(()),
+        };
+
+        match method.as_str() {
+            // Book notifications are applied on the socket thread, since the sequence
+            // checks depend on the order in which they arrive.
+            "snapshotOrderbook" | "updateOrderbook" => {
+                match serde_json::from_value::<BookParams>(params) {
+                    Ok(book) => return self.on_book(book, method == "snapshotOrderbook"),
+                    Err(e) => println!("Error: {}", e),
+                }
+
+                return Ok(());
+            },
+            // The trades snapshot replays the most recent trades, which we may have already stored
+            "updateTrades" => (),
+            _ => return Ok(()),
+        }
+
+        let redis_ref = self.r.clone();
+        let health = self.health.clone();
+        let channel = exchange::market_channel(&self.metadata.exchange, self.metadata.market_type);
+
+        thread::spawn(move || {
+            let params = match serde_json::from_value::<TradeParams>(params) {
+                Ok(params) => params,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return;
+                }
+            };
+
+            let trades: Vec<orderbook::Trade> = params.data.into_iter()
+                .filter_map(|trade| Some(orderbook::Trade {
+                    symbol: params.symbol.clone(),
+                    price: parse_decimal(&trade.price)? as f64,
+                    size: parse_decimal(&trade.quantity)? as f64,
+                    side: if trade.side == "buy" {
+                        orderbook::TradeSide::Buy
+                    } else {
+                        orderbook::TradeSide::Sell
+                    },
+                    ts: parse_timestamp(&trade.timestamp)
+                        .unwrap_or(Utc::now().timestamp_millis() as f64 * 0.001f64),
+                    exchange: Exchange::HitBTC,
+                    trade_id: Some(trade.id.to_string()),
+                    received_ts: None,
+                }))
+                .collect();
+
+            if trades.is_empty() {
+                return;
+            }
+
+            exchange::record_symbol_messages(&health, trades.iter().map(|trade| trade.symbol.as_str()));
+            exchange::publish_trades(&*redis_ref.lock().unwrap(), &format!("{}:trades", channel), &trades, &health);
+        });
+
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> Result<Option<ws::Frame>, Error> {
+        self.health.on_frame(frame)
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut *self.storage);
+            return;
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("HitBTC Socket is closing. Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            sequences: HashMap::new(),
+            request_id: 1,
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+    }
+
+    fn on_timeout(&mut self, _: Token) -> Result<(), ws::Error> {
+        if self.health.is_stopped() {
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("HitBTC Socket timed out (5s of inactivity). Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            sequences: HashMap::new(),
+            request_id: 1,
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+
+        Ok(())
+    }
+}
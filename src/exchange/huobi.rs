@@ -0,0 +1,688 @@
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::thread;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use flate2::read::GzDecoder;
+use redis;
+use serde_json::{self, Value};
+use ws;
+use ws::util::Token;
+use ws::{Error, Handler, Handshake, Message, Sender};
+
+use config::{Config, ConfigError};
+use exchange::{self, Asset, AssetExchange, ConnectionHealth, Exchange, ExchangeError, MarketType};
+use orderbook;
+use storage::{StorageBackend, TectonicBackend};
+
+const EXPIRE: Token = Token(1);
+
+/// Exchange related metadata. The fields are used to establish
+/// a successful connection with the exchange via websockets.
+#[derive(Clone)]
+pub struct WSExchange {
+    /// Full URL to connect to. Example: `wss://api.huobi.pro/ws`
+    pub host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    pub snapshot_received: bool,
+
+    /// Collection metadata
+    pub metadata: MetaData,
+    /// Type of market we're collecting. Deltas are published to the `<exchange>:<market type>` Redis channel
+    pub market_type: MarketType,
+
+    /// Orderbook channel we subscribe to for every asset pair
+    pub book_channel: BookChannel,
+
+    /// Connection health, shared with the running websocket handler
+    pub health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    pub storage: Box<dyn StorageBackend>,
+
+    /// Redis URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub redis_url: String,
+    /// Redis client (before connection). Opened from `redis_url` when connecting
+    pub r: redis::Client,
+    /// Redis password: If this is present, it replaces the password in `redis_url` (if any) when connecting
+    pub r_password: Option<String>,
+}
+
+/// Create two identical structs and transfer the data over when we start the websocket.
+pub struct WSExchangeSender {
+    /// Full URL to connect to. Example: `wss://api.huobi.pro/ws`
+    host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    snapshot_received: bool,
+
+    /// Collection metadata
+    metadata: MetaData,
+
+    /// Orderbook channel we subscribe to for every asset pair
+    book_channel: BookChannel,
+    /// Sequence tracking for every symbol's MBP book, keyed by symbol (i.e. `btcusdt`)
+    sequences: HashMap<String, MbpSequence>,
+    /// Last full book received for every symbol on the `depth` channel, as `(bids, asks)`
+    depth_books: HashMap<String, (Vec<[f64; 2]>, Vec<[f64; 2]>)>,
+
+    /// Connection health
+    health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    storage: Box<dyn StorageBackend>,
+    /// Redis client (used to send deltas as PUBSUB)
+    r: Arc<Mutex<redis::Connection>>,
+
+    /// Websocket sender
+    out: Sender,
+}
+
+/// Meta data for our data source. This is useful for data warehousing and accessing the data.
+/// All types contained within are considered optional. This may be expanded in the future.
+#[derive(Clone)]
+pub struct MetaData {
+    /// Exchange name. We will warehouse the data under this name
+    pub exchange: Arc<String>,
+
+    /// Vector of asset pairs we're going to warehouse
+    pub asset_pair: Option<Vec<[exchange::Asset; 2]>>,
+
+    /// Type of market we're warehousing. Set from `WSExchange::market_type` when connecting
+    pub market_type: Option<MarketType>,
+
+    /// Starting datetime of our data collection
+    start_date: Option<DateTime<Utc>>,
+
+    /// Ending datetime of our data collection
+    end_date: Option<DateTime<Utc>>,
+}
+
+impl WSExchange {
+    /// Health of the connection to the exchange
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+}
+
+impl AssetExchange for WSExchange {
+    fn default_settings() -> Result<Box<Self>, String> {
+        Ok(Box::new(Self {
+            host: "wss://api.huobi.pro/ws".into(),
+
+            snapshot_received: false,
+
+            metadata: MetaData {
+                exchange: Arc::new("huobi".into()),
+                asset_pair: Some(vec![
+                    [Asset::BTC, Asset::USDT],]),
+                market_type: None,
+                start_date: None,
+                end_date: None,
+            },
+            market_type: MarketType::Spot,
+
+            book_channel: BookChannel::Mbp(150),
+
+            health: ConnectionHealth::new(Exchange::Huobi),
+
+            storage: Box::new(TectonicBackend::new(None, None, "huobi").map_err(|e| format!("Unable to connect to TectonicDB: {}", e))?),
+            redis_url: exchange::DEFAULT_REDIS_URL.into(),
+            r: redis::Client::open(exchange::DEFAULT_REDIS_URL).unwrap(),
+            r_password: None,
+        }))
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        if let Some(pairs) = config.asset_pairs()? {
+            self.metadata.asset_pair = Some(pairs);
+        }
+        if let Some(ref url) = config.redis.url {
+            self.redis_url = url.clone();
+        }
+        if config.redis.password.is_some() {
+            self.r_password = config.redis.password.clone();
+        }
+        if let Some(storage) = config.storage("huobi")? {
+            self.storage = storage;
+        }
+
+        Ok(())
+    }
+
+    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
+        // The password is sent as part of the URL, so the client authenticates on its own
+        self.r = exchange::redis_client(&self.redis_url, self.r_password.as_ref().map(|password| password.as_str()))?;
+
+        self.r.get_connection()
+    }
+
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    fn start(&self) -> Result<(), ExchangeError> {
+        WSExchange::run_with_settings(Some(self))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
+        // Try to use the settings the user passes before resorting to default settings.
+        let mut settings = match settings {
+            Some(settings) => settings.clone(),
+            None => *WSExchange::default_settings().map_err(ConfigError::Settings)?,
+        };
+
+        let r = Arc::new(Mutex::new(settings.init_redis()?));
+
+        ws::connect(settings.host.clone(), |out| WSExchangeSender {
+            host: settings.host.clone(),
+
+            snapshot_received: settings.snapshot_received.clone(),
+            metadata: MetaData { market_type: Some(settings.market_type), ..settings.metadata.clone() },
+
+            book_channel: settings.book_channel.clone(),
+            sequences: HashMap::new(),
+            depth_books: HashMap::new(),
+
+            health: settings.health.clone(),
+            storage: settings.storage.clone(),
+            r: r.clone(),
+
+            out,
+        })?;
+
+        Ok(())
+    }
+}
+
+/// Orderbook channels offered by Huobi
+#[derive(Clone, Debug, PartialEq)]
+pub enum BookChannel {
+    /// Incremental market by price (MBP) updates with the given number of levels. Huobi accepts 5, 20, and 150
+    Mbp(u32),
+    /// Full book of up to 150 levels, pushed about once a second. The value is the price
+    /// aggregation step, where 0 is no aggregation (i.e. `market.btcusdt.depth.step0`)
+    Depth(u8),
+}
+
+impl BookChannel {
+    /// Channel name for the given symbol (i.e. `market.btcusdt.mbp.150`)
+    pub fn channel(&self, symbol: &str) -> String {
+        match self {
+            BookChannel::Mbp(levels) => format!("market.{}.mbp.{}", symbol, levels),
+            BookChannel::Depth(step) => format!("market.{}.depth.step{}", symbol, step),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SubscribeMessage {
+    sub: String,
+    id: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RequestMessage {
+    req: String,
+    id: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PongMessage {
+    pong: u64,
+}
+
+/// Every message pushed by Huobi has this form. Pushes carry `ch` and `tick`, whereas replies
+/// to our requests carry `rep` and `data`.
+#[derive(Deserialize)]
+struct EventMessage {
+    /// Keepalive. Must be answered with a pong carrying the same value
+    ping: Option<u64>,
+
+    /// Channel the push belongs to (i.e. `market.btcusdt.mbp.150`)
+    ch: Option<String>,
+    /// Timestamp in milliseconds
+    ts: Option<u64>,
+    tick: Option<Value>,
+
+    /// Channel the reply belongs to
+    rep: Option<String>,
+    data: Option<Value>,
+
+    status: Option<String>,
+    #[serde(rename = "err-msg")]
+    err_msg: Option<String>,
+}
+
+/// Market by price book. Incremental updates carry `prevSeqNum`, snapshots don't.
+#[derive(Deserialize)]
+struct BookData {
+    #[serde(rename = "seqNum")]
+    seq_num: u64,
+    #[serde(rename = "prevSeqNum")]
+    prev_seq_num: Option<u64>,
+    /// Bid levels as `[price, size]`. Missing if no bids changed
+    #[serde(default)]
+    bids: Vec<[f64; 2]>,
+    /// Ask levels as `[price, size]`. Missing if no asks changed
+    #[serde(default)]
+    asks: Vec<[f64; 2]>,
+}
+
+/// Full book pushed on the `depth` channel
+#[derive(Deserialize)]
+struct DepthData {
+    #[serde(default)]
+    bids: Vec<[f64; 2]>,
+    #[serde(default)]
+    asks: Vec<[f64; 2]>,
+}
+
+/// Trade detail channel data
+#[derive(Deserialize)]
+struct TradeTick {
+    data: Vec<TradeData>,
+}
+
+#[derive(Deserialize)]
+struct TradeData {
+    #[serde(rename = "tradeId")]
+    trade_id: u64,
+    price: f64,
+    amount: f64,
+    /// Taker side
+    direction: String,
+    /// Timestamp in milliseconds
+    ts: u64,
+}
+
+/// Outcome of checking an incremental MBP update against the last `seqNum` we've applied
+#[derive(Debug, PartialEq)]
+pub enum MbpCheck {
+    /// The update continues the book and should be applied
+    Apply,
+    /// We don't have a snapshot yet, or the update is older than it
+    Drop,
+    /// We've missed at least one update. A new snapshot is needed
+    Gap,
+}
+
+/// Tracks the `seqNum` of a single symbol's MBP book. Every incremental update carries the
+/// `seqNum` of the update before it as `prevSeqNum`.
+#[derive(Default)]
+pub struct MbpSequence {
+    /// `seqNum` of the last snapshot or update applied to the book
+    last_seq_num: Option<u64>,
+}
+
+impl MbpSequence {
+    /// Records a snapshot, which restarts the continuity check
+    pub fn snapshot(&mut self, seq_num: u64) {
+        self.last_seq_num = Some(seq_num);
+    }
+
+    /// Checks an incremental update, advancing the sequence if it applies. The book is
+    /// forgotten on a gap, so updates are dropped until the next snapshot.
+    pub fn check(&mut self, prev_seq_num: u64, seq_num: u64) -> MbpCheck {
+        let last_seq_num = match self.last_seq_num {
+            Some(last_seq_num) => last_seq_num,
+            None => return MbpCheck::Drop,
+        };
+
+        if seq_num <= last_seq_num {
+            return MbpCheck::Drop;
+        }
+        if prev_seq_num != last_seq_num {
+            self.last_seq_num = None;
+            return MbpCheck::Gap;
+        }
+
+        self.last_seq_num = Some(seq_num);
+        MbpCheck::Apply
+    }
+}
+
+/// Compares two full books of a single side and returns the levels that changed as `(price, size)`.
+/// Levels missing from `current` are returned with a size of zero, which marks them as removed.
+pub fn diff_levels(previous: &[[f64; 2]], current: &[[f64; 2]]) -> Vec<(f64, f64)> {
+    let mut changes: Vec<(f64, f64)> = current.iter()
+        .filter(|level| !previous.iter().any(|prev| prev[0] == level[0] && prev[1] == level[1]))
+        .map(|level| (level[0], level[1]))
+        .collect();
+
+    changes.extend(previous.iter()
+        .filter(|prev| !current.iter().any(|level| level[0] == prev[0]))
+        .map(|prev| (prev[0], 0.0)));
+
+    changes
+}
+
+/// Huobi compresses every frame with gzip
+fn decompress(msg: Message) -> Result<Vec<u8>, io::Error> {
+    match msg {
+        Message::Text(text) => Ok(text.into_bytes()),
+        Message::Binary(data) => {
+            let mut buf = vec![];
+            GzDecoder::new(&data[..]).read_to_end(&mut buf)?;
+
+            Ok(buf)
+        }
+    }
+}
+
+/// Extracts the symbol from a channel name (i.e. `market.btcusdt.mbp.150` to `btcusdt`)
+fn channel_symbol(channel: &str) -> String {
+    channel.split('.').nth(1).unwrap_or("").to_string()
+}
+
+impl WSExchangeSender {
+    /// Requests a full snapshot of a symbol's MBP book. Huobi's REST depth endpoint doesn't
+    /// carry a `seqNum`, so snapshots are requested over the websocket instead.
+    fn request_snapshot(&mut self, symbol: &str) -> Result<(), Error> {
+        let msg = RequestMessage {
+            req: self.book_channel.channel(symbol),
+            id: symbol.to_string(),
+        };
+
+        self.out.send(serde_json::to_string(&msg).unwrap())
+    }
+
+    /// Applies a snapshot or incremental update and publishes its deltas
+    fn on_book(&mut self, symbol: String, snapshot: bool, book: BookData, ts: f64) -> Result<(), Error> {
+        if snapshot {
+            self.sequences.entry(symbol.clone()).or_insert_with(MbpSequence::default).snapshot(book.seq_num);
+            self.snapshot_received = true;
+        } else {
+            let check = self.sequences.entry(symbol.clone())
+                .or_insert_with(MbpSequence::default)
+                .check(book.prev_seq_num.unwrap_or(0), book.seq_num);
+
+            match check {
+                MbpCheck::Apply => (),
+                MbpCheck::Drop => return Ok(()),
+                MbpCheck::Gap => {
+                    println!("Huobi book for {} is out of sequence. Requesting a new snapshot...", symbol);
+                    return self.request_snapshot(&symbol);
+                }
+            }
+        }
+
+        let asks: Vec<(f64, f64)> = book.asks.iter().map(|level| (level[0], level[1])).collect();
+        let bids: Vec<(f64, f64)> = book.bids.iter().map(|level| (level[0], level[1])).collect();
+
+        self.publish_levels(symbol, asks, bids, ts);
+
+        Ok(())
+    }
+
+    /// Diffs a full book from the `depth` channel against the previous one, and publishes the changes
+    fn on_depth(&mut self, symbol: String, depth: DepthData, ts: f64) {
+        let (asks, bids) = {
+            let previous = self.depth_books.entry(symbol.clone()).or_insert_with(|| (vec![], vec![]));
+            let changes = (diff_levels(&previous.1, &depth.asks), diff_levels(&previous.0, &depth.bids));
+
+            *previous = (depth.bids, depth.asks);
+            changes
+        };
+
+        self.snapshot_received = true;
+        self.publish_levels(symbol, asks, bids, ts);
+    }
+
+    /// Publishes ask and bid levels as `(price, size)` to Redis, where a size of zero removes the level
+    fn publish_levels(&self, symbol: String, asks: Vec<(f64, f64)>, bids: Vec<(f64, f64)>, ts: f64) {
+        let mut deltas: Vec<orderbook::Delta> = Vec::with_capacity(asks.len() + bids.len());
+
+        // Begin sequence counting at 1 in order to reconstruct a proper sequence count
+        let mut seq = 1;
+
+        for (levels, side) in vec![(asks, orderbook::ASK), (bids, orderbook::BID)] {
+            for (price, size) in levels {
+                let (price, size) = (price as f32, size as f32);
+
+                deltas.push(orderbook::Delta {
+                    symbol: symbol.clone(),
+                    price,
+                    size,
+                    seq,
+                    event: side ^ if size == 0.0 {
+                        orderbook::REMOVE
+                    } else {
+                        orderbook::UPDATE
+                    },
+                    ts,
+                    received_ts: None,
+                });
+
+                seq += 1;
+            }
+        }
+
+        // Lock the connection until we are able to aquire it
+        if !deltas.is_empty() {
+            exchange::record_symbol_messages(&self.health, deltas.iter().map(|delta| delta.symbol.as_str()));
+            exchange::publish_deltas(&*self.r.lock().unwrap(), &exchange::market_channel(&self.metadata.exchange, self.metadata.market_type), &deltas, &self.health);
+        }
+    }
+}
+
+impl Handler for WSExchangeSender {
+    fn on_open(&mut self, _: Handshake) -> Result<(), Error> {
+        self.health.track_connection(&self.out);
+
+        // Measure the round-trip time of the new connection
+        self.health.ping_latency(&self.out)?;
+
+        // Set a timeout for 5 seconds of inactivity
+        // Issue: currently, this reruns every five seconds. Comment out while we fix.
+        //self.out.timeout(5_000, EXPIRE).unwrap();
+
+        for pair in self.metadata.asset_pair.clone().expect("No asset pairs passed to Huobi structure") {
+            let symbol = match exchange::get_asset_pair(&pair, Exchange::Huobi) {
+                Ok(symbol) => symbol,
+                Err(e) => {
+                    println!("Skipping Huobi subscription: {}", e);
+                    continue;
+                }
+            };
+            let db_name = format!("{}_{}", self.metadata.exchange.deref(), symbol);
+
+            // Create the database if it doesn't exist yet. This avoids many issues
+            // relating to inserting to a non-existant database.
+            self.storage.create(&db_name)?;
+
+            for channel in vec![self.book_channel.channel(&symbol), format!("market.{}.trade.detail", symbol)] {
+                let msg = SubscribeMessage {
+                    sub: channel.clone(),
+                    id: channel,
+                };
+
+                println!("Sending message {}", serde_json::to_string(&msg).unwrap());
+                self.out.send(serde_json::to_string(&msg).unwrap())?;
+            }
+
+            // Incremental updates are dropped until we've received the snapshot they build on.
+            // The depth channel pushes full books, so it doesn't need one.
+            if let BookChannel::Mbp(_) = self.book_channel {
+                self.request_snapshot(&symbol)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<(), Error> {
+        self.health.record_message();
+
+        let data = match decompress(msg) {
+            Ok(data) => data,
+            Err(e) => {
+                println!("Failed to decompress Huobi message: {}", e);
+                return Ok(());
+            }
+        };
+
+        let message = match serde_json::from_slice::<EventMessage>(&data) {
+            Ok(message) => message,
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            }
+        };
+
+        // Huobi closes the connection if we don't answer its pings within a few seconds
+        if let Some(ping) = message.ping {
+            return self.out.send(serde_json::to_string(&PongMessage { pong: ping }).unwrap());
+        }
+
+        if message.status.as_ref().map(|status| status == "error").unwrap_or(false) {
+            println!("Huobi error: {}", message.err_msg.unwrap_or_default());
+            return Ok(());
+        }
+
+        let ts = message.ts.map(|ts| ts as f64 * 0.001f64)
+            .unwrap_or(Utc::now().timestamp_millis() as f64 * 0.001f64);
+
+        // Snapshot reply
+        if let (Some(rep), Some(data)) = (message.rep, message.data) {
+            return match serde_json::from_value::<BookData>(data) {
+                Ok(book) => self.on_book(channel_symbol(&rep), true, book, ts),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    Ok(())
+                }
+            };
+        }
+
+        let (channel, tick) = match (message.ch, message.tick) {
+            (Some(channel), Some(tick)) => (channel, tick),
+            _ => return Ok(()),
+        };
+
+        if channel.contains(".mbp.") {
+            // Book updates are handled on the socket thread, since the sequence
+            // checks depend on the order in which they arrive.
+            return match serde_json::from_value::<BookData>(tick) {
+                Ok(book) => self.on_book(channel_symbol(&channel), false, book, ts),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    Ok(())
+                }
+            };
+        }
+
+        if channel.contains(".depth.") {
+            match serde_json::from_value::<DepthData>(tick) {
+                Ok(depth) => self.on_depth(channel_symbol(&channel), depth, ts),
+                Err(e) => println!("Error: {}", e),
+            };
+
+            return Ok(());
+        }
+
+        if !channel.ends_with(".trade.detail") {
+            return Ok(());
+        }
+
+        let redis_ref = self.r.clone();
+        let health = self.health.clone();
+        let channel = exchange::market_channel(&self.metadata.exchange, self.metadata.market_type);
+
+        thread::spawn(move || {
+            let symbol = channel_symbol(&channel);
+            let trades: Vec<orderbook::Trade> = match serde_json::from_value::<TradeTick>(tick) {
+                Ok(tick) => tick.data.into_iter()
+                    .map(|trade| orderbook::Trade {
+                        symbol: symbol.clone(),
+                        price: trade.price,
+                        size: trade.amount,
+                        side: if trade.direction == "buy" {
+                            orderbook::TradeSide::Buy
+                        } else {
+                            orderbook::TradeSide::Sell
+                        },
+                        ts: trade.ts as f64 * 0.001f64,
+                        exchange: Exchange::Huobi,
+                        trade_id: Some(trade.trade_id.to_string()),
+                        received_ts: None,
+                    })
+                    .collect(),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return;
+                }
+            };
+
+            exchange::record_symbol_messages(&health, trades.iter().map(|trade| trade.symbol.as_str()));
+            exchange::publish_trades(&*redis_ref.lock().unwrap(), &format!("{}:trades", channel), &trades, &health);
+        });
+
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> Result<Option<ws::Frame>, Error> {
+        self.health.on_frame(frame)
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut *self.storage);
+            return;
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Huobi Socket is closing. Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            book_channel: self.book_channel.clone(),
+            sequences: HashMap::new(),
+            depth_books: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+    }
+
+    fn on_timeout(&mut self, _: Token) -> Result<(), ws::Error> {
+        if self.health.is_stopped() {
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Huobi Socket timed out (5s of inactivity). Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            book_channel: self.book_channel.clone(),
+            sequences: HashMap::new(),
+            depth_books: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+
+        Ok(())
+    }
+}
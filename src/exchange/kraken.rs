@@ -0,0 +1,577 @@
+use std::collections::{BTreeMap, HashMap};
+use std::thread;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use crc32fast;
+use redis;
+use serde_json::{self, Value};
+use ws;
+use ws::util::Token;
+use ws::{Error, Handler, Handshake, Message, Sender};
+
+use config::{Config, ConfigError};
+use exchange::{self, Asset, AssetExchange, ConnectionHealth, Exchange, ExchangeError, MarketType};
+use orderbook;
+use storage::{StorageBackend, TectonicBackend};
+
+const EXPIRE: Token = Token(1);
+
+/// Exchange related metadata. The fields are used to establish
+/// a successful connection with the exchange via websockets.
+#[derive(Clone)]
+pub struct WSExchange {
+    /// Full URL to connect to. Example: `wss://ws.kraken.com`
+    pub host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    pub snapshot_received: bool,
+
+    /// Collection metadata
+    pub metadata: MetaData,
+    /// Type of market we're collecting. Deltas are published to the `<exchange>:<market type>` Redis channel
+    pub market_type: MarketType,
+
+    /// Channel names we subscribe to for every asset pair (i.e. `book`, `trade`)
+    pub single_channels: Vec<String>,
+    /// Orderbook depth we request from the `book` channel. Kraken accepts 10, 25, 100, 500, and 1000
+    pub book_depth: u32,
+
+    /// Connection health, shared with the running websocket handler
+    pub health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    pub storage: Box<dyn StorageBackend>,
+
+    /// Redis URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub redis_url: String,
+    /// Redis client (before connection). Opened from `redis_url` when connecting
+    pub r: redis::Client,
+    /// Redis password: If this is present, it replaces the password in `redis_url` (if any) when connecting
+    pub r_password: Option<String>,
+}
+
+/// Create two identical structs and transfer the data over when we start the websocket.
+pub struct WSExchangeSender {
+    /// Full URL to connect to. Example: `wss://ws.kraken.com`
+    host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    snapshot_received: bool,
+
+    /// Collection metadata
+    metadata: MetaData,
+
+    /// Channel names we subscribe to for every asset pair
+    single_channels: Vec<String>,
+    /// Orderbook depth we request from the `book` channel
+    book_depth: u32,
+    /// Local copy of every book we're subscribed to, keyed by Kraken's pair name (i.e. `XBT/USD`).
+    /// Used to verify the checksums Kraken sends with each book update.
+    books: HashMap<String, LocalBook>,
+
+    /// Connection health
+    health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    storage: Box<dyn StorageBackend>,
+    /// Redis client (used to send deltas as PUBSUB)
+    r: Arc<Mutex<redis::Connection>>,
+
+    /// Websocket sender
+    out: Sender,
+}
+
+/// Meta data for our data source. This is useful for data warehousing and accessing the data.
+/// All types contained within are considered optional. This may be expanded in the future.
+#[derive(Clone)]
+pub struct MetaData {
+    /// Exchange name. We will warehouse the data under this name
+    pub exchange: Arc<String>,
+
+    /// Vector of asset pairs we're going to warehouse
+    pub asset_pair: Option<Vec<[exchange::Asset; 2]>>,
+
+    /// Type of market we're warehousing. Set from `WSExchange::market_type` when connecting
+    pub market_type: Option<MarketType>,
+
+    /// Starting datetime of our data collection
+    start_date: Option<DateTime<Utc>>,
+
+    /// Ending datetime of our data collection
+    end_date: Option<DateTime<Utc>>,
+}
+
+impl WSExchange {
+    /// Health of the connection to the exchange
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+}
+
+impl AssetExchange for WSExchange {
+    fn default_settings() -> Result<Box<Self>, String> {
+        Ok(Box::new(Self {
+            host: "wss://ws.kraken.com".into(),
+
+            snapshot_received: false,
+
+            metadata: MetaData {
+                exchange: Arc::new("kraken".into()),
+                asset_pair: Some(vec![
+                    [Asset::BTC, Asset::USD],]),
+                market_type: None,
+                start_date: None,
+                end_date: None,
+            },
+            market_type: MarketType::Spot,
+
+            single_channels: vec![
+                "book".into(),
+                "trade".into()],
+            book_depth: 10,
+
+            health: ConnectionHealth::new(Exchange::Kraken),
+
+            storage: Box::new(TectonicBackend::new(None, None, "kraken").map_err(|e| format!("Unable to connect to TectonicDB: {}", e))?),
+            redis_url: exchange::DEFAULT_REDIS_URL.into(),
+            r: redis::Client::open(exchange::DEFAULT_REDIS_URL).unwrap(),
+            r_password: None,
+        }))
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        if let Some(pairs) = config.asset_pairs()? {
+            self.metadata.asset_pair = Some(pairs);
+        }
+        if let Some(ref channels) = config.channels.names {
+            self.single_channels = channels.clone();
+        }
+        if let Some(ref url) = config.redis.url {
+            self.redis_url = url.clone();
+        }
+        if config.redis.password.is_some() {
+            self.r_password = config.redis.password.clone();
+        }
+        if let Some(storage) = config.storage("kraken")? {
+            self.storage = storage;
+        }
+
+        Ok(())
+    }
+
+    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
+        // The password is sent as part of the URL, so the client authenticates on its own
+        self.r = exchange::redis_client(&self.redis_url, self.r_password.as_ref().map(|password| password.as_str()))?;
+
+        self.r.get_connection()
+    }
+
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    fn start(&self) -> Result<(), ExchangeError> {
+        WSExchange::run_with_settings(Some(self))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
+        // Try to use the settings the user passes before resorting to default settings.
+        let mut settings = match settings {
+            Some(settings) => settings.clone(),
+            None => *WSExchange::default_settings().map_err(ConfigError::Settings)?,
+        };
+
+        let r = Arc::new(Mutex::new(settings.init_redis()?));
+
+        ws::connect(settings.host.clone(), |out| WSExchangeSender {
+            host: settings.host.clone(),
+
+            snapshot_received: settings.snapshot_received.clone(),
+            metadata: MetaData { market_type: Some(settings.market_type), ..settings.metadata.clone() },
+
+            single_channels: settings.single_channels.clone(),
+            book_depth: settings.book_depth,
+            books: HashMap::new(),
+
+            health: settings.health.clone(),
+            storage: settings.storage.clone(),
+            r: r.clone(),
+
+            out,
+        })?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SubscribeMessage {
+    event: String,
+    pair: Vec<String>,
+    subscription: Subscription,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Subscription {
+    name: String,
+    /// Only valid for the `book` channel
+    #[serde(skip_serializing_if = "Option::is_none")]
+    depth: Option<u32>,
+}
+
+/// Kraken sends symbols as `XBT/USD`. We strip the separator so that the symbol can be used
+/// as part of a TectonicDB database name (i.e. `kraken_XBTUSD`).
+fn db_symbol(pair: &str) -> String {
+    pair.replace("/", "")
+}
+
+/// Parses a single `[price, volume, timestamp, ...]` level sent by Kraken. Both book levels
+/// and trades share this layout for their first three elements.
+fn parse_level(level: &Value) -> Option<(f32, f32, f64)> {
+    let level = level.as_array()?;
+
+    Some((
+        level.get(0)?.as_str()?.parse::<f32>().ok()?,
+        level.get(1)?.as_str()?.parse::<f32>().ok()?,
+        level.get(2)?.as_str()?.parse::<f64>().ok()?,
+    ))
+}
+
+/// Local copy of a single Kraken book. We keep the price and volume strings exactly as Kraken sent
+/// them, since the checksum is calculated over the strings and not over their float values.
+#[derive(Default)]
+pub struct LocalBook {
+    /// Asks, keyed by price with the decimal point removed
+    asks: BTreeMap<u64, (String, String)>,
+    /// Bids, keyed by price with the decimal point removed
+    bids: BTreeMap<u64, (String, String)>,
+    /// Set once we've received a snapshot. Checksums can't be verified before then.
+    synced: bool,
+}
+
+impl LocalBook {
+    /// Inserts or replaces a level on the given side (`orderbook::ASK` or `orderbook::BID`).
+    /// A volume of zero removes the level from the book.
+    pub fn apply(&mut self, side: u8, price: &str, volume: &str) {
+        let key = match price_key(price) {
+            Some(key) => key,
+            None => return,
+        };
+        let levels = if side == orderbook::BID { &mut self.bids } else { &mut self.asks };
+
+        if volume.parse::<f64>().map(|volume| volume == 0.0).unwrap_or(true) {
+            levels.remove(&key);
+        } else {
+            levels.insert(key, (price.to_string(), volume.to_string()));
+        }
+    }
+
+    /// Drops the levels that fall outside of the subscribed depth. Kraken does not send removals
+    /// for levels that are pushed out of the book, so we have to do this ourselves.
+    pub fn truncate(&mut self, depth: usize) {
+        while self.asks.len() > depth {
+            let worst = *self.asks.keys().next_back().unwrap();
+            self.asks.remove(&worst);
+        }
+        while self.bids.len() > depth {
+            let worst = *self.bids.keys().next().unwrap();
+            self.bids.remove(&worst);
+        }
+    }
+
+    /// Calculates the CRC32 checksum of the top ten asks (ascending) followed by the top ten
+    /// bids (descending), in the same manner as Kraken.
+    pub fn checksum(&self) -> u32 {
+        let mut payload = String::with_capacity(512);
+
+        for &(ref price, ref volume) in self.asks.values().take(10).chain(self.bids.values().rev().take(10)) {
+            payload.push_str(&checksum_field(price));
+            payload.push_str(&checksum_field(volume));
+        }
+
+        crc32fast::hash(payload.as_bytes())
+    }
+}
+
+/// Kraken sends prices with a fixed number of decimals for each pair, so the digits alone
+/// make an exact integer key without going through a float.
+fn price_key(price: &str) -> Option<u64> {
+    price.replace(".", "").parse::<u64>().ok()
+}
+
+/// Strips the decimal point and any leading zeros from a price or volume, as required by
+/// Kraken's checksum format.
+fn checksum_field(value: &str) -> String {
+    value.replace(".", "").trim_left_matches('0').to_string()
+}
+
+impl Handler for WSExchangeSender {
+    fn on_open(&mut self, _: Handshake) -> Result<(), Error> {
+        self.health.track_connection(&self.out);
+
+        // Measure the round-trip time of the new connection
+        self.health.ping_latency(&self.out)?;
+
+        // Set a timeout for 5 seconds of inactivity
+        // Issue: currently, this reruns every five seconds. Comment out while we fix.
+        //self.out.timeout(5_000, EXPIRE).unwrap();
+
+        let mut pairs = vec![];
+
+        for pair in self.metadata.asset_pair.as_ref().expect("No asset pairs passed to Kraken structure") {
+            let normalized_pair = match exchange::get_asset_pair(pair, Exchange::Kraken) {
+                Ok(normalized_pair) => normalized_pair,
+                Err(e) => {
+                    println!("Skipping Kraken subscription: {}", e);
+                    continue;
+                }
+            };
+            let db_name = format!("{}_{}", self.metadata.exchange.deref(), db_symbol(&normalized_pair));
+
+            // Create the database if it doesn't exist yet. This avoids many issues
+            // relating to inserting to a non-existant database.
+            self.storage.create(&db_name)?;
+
+            pairs.push(normalized_pair);
+        }
+
+        // Kraken only accepts a single channel per subscription message, so we send one for each
+        for channel in &self.single_channels {
+            let msg = SubscribeMessage {
+                event: "subscribe".into(),
+                pair: pairs.clone(),
+                subscription: Subscription {
+                    name: channel.to_string(),
+                    depth: if channel == "book" { Some(self.book_depth) } else { None },
+                },
+            };
+
+            println!("Sending message {}", serde_json::to_string(&msg).unwrap());
+            self.out.send(serde_json::to_string(&msg).unwrap())?;
+        }
+
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<(), Error> {
+        self.health.record_message();
+
+        let message = match serde_json::from_slice::<Value>(&msg.into_data()) {
+            // Data messages are sent as arrays in the form of `[channelID, data..., channelName, pair]`.
+            // Everything else (heartbeats, system status, subscription status) is sent as an object.
+            Ok(Value::Array(message)) => message,
+            Ok(event) => {
+                if event["event"] == "subscriptionStatus" && event["status"] == "error" {
+                    println!("Kraken subscription error: {}", event["errorMessage"]);
+                }
+                return Ok(());
+            },
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            },
+        };
+
+        if message.len() < 4 {
+            return Ok(());
+        }
+
+        let channel_name = message[message.len() - 2].as_str().unwrap_or("").to_string();
+        let pair = message[message.len() - 1].as_str().unwrap_or("").to_string();
+        let symbol = db_symbol(&pair);
+
+        if channel_name.starts_with("book") {
+            // Book messages are handled on the socket thread so that our local copy of the
+            // book sees updates in the same order that Kraken calculated its checksums in.
+            let mut deltas: Vec<orderbook::Delta> = Vec::with_capacity(32);
+            let mut checksum = None;
+
+            // Begin sequence counting at 1 in order to reconstruct a proper sequence count
+            let mut seq = 1;
+
+            let book = self.books.entry(pair.clone()).or_insert_with(LocalBook::default);
+
+            // Book updates may contain up to two objects: one for asks and one for bids.
+            // Snapshots use the `as`/`bs` keys, whereas updates use `a`/`b`.
+            for data in &message[1..message.len() - 2] {
+                if data.get("as").is_some() || data.get("bs").is_some() {
+                    *book = LocalBook::default();
+                    book.synced = true;
+                }
+
+                for (key, side) in &[("as", orderbook::ASK), ("bs", orderbook::BID),
+                                     ("a", orderbook::ASK), ("b", orderbook::BID)] {
+                    let levels = match data.get(key).and_then(|levels| levels.as_array()) {
+                        Some(levels) => levels,
+                        None => continue,
+                    };
+
+                    for level in levels {
+                        let (price, size, ts) = match parse_level(level) {
+                            Some(level) => level,
+                            None => continue,
+                        };
+
+                        // `parse_level` already validated both fields as strings
+                        book.apply(*side, level[0].as_str().unwrap(), level[1].as_str().unwrap());
+
+                        deltas.push(orderbook::Delta {
+                            symbol: symbol.clone(),
+                            price,
+                            size,
+                            seq,
+                            event: side ^ if size == 0.0 {
+                                orderbook::REMOVE
+                            } else {
+                                orderbook::UPDATE
+                            },
+                            ts,
+                            received_ts: None,
+                        });
+
+                        seq += 1;
+                    }
+                }
+
+                if let Some(c) = data.get("c").and_then(|c| c.as_str()).and_then(|c| c.parse::<u32>().ok()) {
+                    checksum = Some(c);
+                }
+            }
+
+            book.truncate(self.book_depth as usize);
+            let valid = !book.synced || checksum.map(|checksum| checksum == book.checksum()).unwrap_or(true);
+
+            exchange::record_symbol_messages(&self.health, deltas.iter().map(|delta| delta.symbol.as_str()));
+            exchange::publish_deltas(&*self.r.lock().unwrap(), &exchange::market_channel(&self.metadata.exchange, self.metadata.market_type), &deltas, &self.health);
+
+            if !valid {
+                println!("Kraken checksum mismatch for {}. Resubscribing to the book...", pair);
+                self.books.remove(&pair);
+                self.resubscribe_book(pair)?;
+            }
+
+            return Ok(());
+        }
+
+        if channel_name != "trade" {
+            return Ok(());
+        }
+
+        let redis_ref = self.r.clone();
+        let channel = exchange::market_channel(&self.metadata.exchange, self.metadata.market_type);
+        let health = self.health.clone();
+
+        thread::spawn(move || {
+            // Trades are published on their own channel, separate from the orderbook deltas
+            let trades: Vec<orderbook::Trade> = message[1].as_array()
+                .unwrap_or(&vec![])
+                .iter()
+                .filter_map(|trade| Some(orderbook::Trade {
+                    symbol: symbol.clone(),
+                    price: trade.get(0)?.as_str()?.parse::<f64>().ok()?,
+                    size: trade.get(1)?.as_str()?.parse::<f64>().ok()?,
+                    side: if trade.get(3)? == "b" {
+                        orderbook::TradeSide::Buy
+                    } else {
+                        orderbook::TradeSide::Sell
+                    },
+                    ts: trade.get(2)?.as_str()?.parse::<f64>().ok()?,
+                    exchange: Exchange::Kraken,
+                    trade_id: None,
+                    received_ts: None,
+                }))
+                .collect();
+
+            exchange::record_symbol_messages(&health, trades.iter().map(|trade| trade.symbol.as_str()));
+            exchange::publish_trades(&*redis_ref.lock().unwrap(), &format!("{}:trades", channel), &trades, &health);
+        });
+
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> Result<Option<ws::Frame>, Error> {
+        self.health.on_frame(frame)
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut *self.storage);
+            return;
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Kraken Socket is closing. Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            single_channels: self.single_channels.clone(),
+            book_depth: self.book_depth,
+            books: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+    }
+
+    fn on_timeout(&mut self, _: Token) -> Result<(), ws::Error> {
+        if self.health.is_stopped() {
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Kraken Socket timed out (5s of inactivity). Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            single_channels: self.single_channels.clone(),
+            book_depth: self.book_depth,
+            books: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+
+        Ok(())
+    }
+}
+
+impl WSExchangeSender {
+    /// Drops and re-requests the book for a single pair. Kraken sends a fresh snapshot
+    /// once the new subscription is active, which reseeds our local book.
+    fn resubscribe_book(&mut self, pair: String) -> Result<(), Error> {
+        for event in &["unsubscribe", "subscribe"] {
+            let msg = SubscribeMessage {
+                event: event.to_string(),
+                pair: vec![pair.clone()],
+                subscription: Subscription {
+                    name: "book".into(),
+                    depth: Some(self.book_depth),
+                },
+            };
+
+            self.out.send(serde_json::to_string(&msg).unwrap())?;
+        }
+
+        Ok(())
+    }
+}
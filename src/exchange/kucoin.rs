@@ -0,0 +1,732 @@
+use std::collections::HashMap;
+use std::thread;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use redis;
+use reqwest;
+use serde_json;
+use ws;
+use ws::util::Token;
+use ws::{Error, Handler, Handshake, Message, Sender};
+
+use config::{Config, ConfigError};
+use exchange::{self, Asset, AssetExchange, ConnectionHealth, Exchange, ExchangeError, MarketType};
+use orderbook;
+use storage::{StorageBackend, TectonicBackend};
+
+const EXPIRE: Token = Token(1);
+const PING: Token = Token(2);
+
+/// Used until the bullet handshake tells us how often the server expects a ping
+const DEFAULT_PING_INTERVAL_MS: u64 = 18_000;
+
+/// Exchange related metadata. The fields are used to establish
+/// a successful connection with the exchange via websockets.
+#[derive(Clone)]
+pub struct WSExchange {
+    /// REST API base URL. KuCoin hands out the websocket endpoint and a connection token
+    /// from this host, and serves level2 snapshots from it. Example: `https://api.kucoin.com`
+    pub rest_host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    pub snapshot_received: bool,
+
+    /// Collection metadata
+    pub metadata: MetaData,
+    /// Type of market we're collecting. Deltas are published to the `<exchange>:<market type>` Redis channel
+    pub market_type: MarketType,
+
+    /// Topics we subscribe to for every asset pair (i.e. `/market/level2`, `/market/match`)
+    pub single_channels: Vec<String>,
+
+    /// Connection health, shared with the running websocket handler
+    pub health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    pub storage: Box<dyn StorageBackend>,
+
+    /// Redis URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub redis_url: String,
+    /// Redis client (before connection). Opened from `redis_url` when connecting
+    pub r: redis::Client,
+    /// Redis password: If this is present, it replaces the password in `redis_url` (if any) when connecting
+    pub r_password: Option<String>,
+}
+
+/// Create two identical structs and transfer the data over when we start the websocket.
+pub struct WSExchangeSender {
+    /// REST API base URL
+    rest_host: String,
+    /// Interval, in milliseconds, the server expects us to ping it at
+    ping_interval: u64,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    snapshot_received: bool,
+
+    /// Collection metadata
+    metadata: MetaData,
+
+    /// Topics we subscribe to for every asset pair
+    single_channels: Vec<String>,
+    /// Sequence tracking for every symbol's level2 stream, keyed by symbol (i.e. `BTC-USDT`)
+    sequences: HashMap<String, Level2Sequence>,
+
+    /// Connection health
+    health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    storage: Box<dyn StorageBackend>,
+    /// Redis client (used to send deltas as PUBSUB)
+    r: Arc<Mutex<redis::Connection>>,
+
+    /// Websocket sender
+    out: Sender,
+}
+
+/// Meta data for our data source. This is useful for data warehousing and accessing the data.
+/// All types contained within are considered optional. This may be expanded in the future.
+#[derive(Clone)]
+pub struct MetaData {
+    /// Exchange name. We will warehouse the data under this name
+    pub exchange: Arc<String>,
+
+    /// Vector of asset pairs we're going to warehouse
+    pub asset_pair: Option<Vec<[exchange::Asset; 2]>>,
+
+    /// Type of market we're warehousing. Set from `WSExchange::market_type` when connecting
+    pub market_type: Option<MarketType>,
+
+    /// Starting datetime of our data collection
+    start_date: Option<DateTime<Utc>>,
+
+    /// Ending datetime of our data collection
+    end_date: Option<DateTime<Utc>>,
+}
+
+impl WSExchange {
+    /// Health of the connection to the exchange
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+}
+
+impl AssetExchange for WSExchange {
+    fn default_settings() -> Result<Box<Self>, String> {
+        Ok(Box::new(Self {
+            rest_host: "https://api.kucoin.com".into(),
+
+            snapshot_received: false,
+
+            metadata: MetaData {
+                exchange: Arc::new("kucoin".into()),
+                asset_pair: Some(vec![
+                    [Asset::BTC, Asset::USDT],]),
+                market_type: None,
+                start_date: None,
+                end_date: None,
+            },
+            market_type: MarketType::Spot,
+
+            single_channels: vec![
+                "/market/level2".into(),
+                "/market/match".into()],
+
+            health: ConnectionHealth::new(Exchange::KuCoin),
+
+            storage: Box::new(TectonicBackend::new(None, None, "kucoin").map_err(|e| format!("Unable to connect to TectonicDB: {}", e))?),
+            redis_url: exchange::DEFAULT_REDIS_URL.into(),
+            r: redis::Client::open(exchange::DEFAULT_REDIS_URL).unwrap(),
+            r_password: None,
+        }))
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        if let Some(pairs) = config.asset_pairs()? {
+            self.metadata.asset_pair = Some(pairs);
+        }
+        if let Some(ref channels) = config.channels.names {
+            self.single_channels = channels.clone();
+        }
+        if let Some(ref url) = config.redis.url {
+            self.redis_url = url.clone();
+        }
+        if config.redis.password.is_some() {
+            self.r_password = config.redis.password.clone();
+        }
+        if let Some(storage) = config.storage("kucoin")? {
+            self.storage = storage;
+        }
+
+        Ok(())
+    }
+
+    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
+        // The password is sent as part of the URL, so the client authenticates on its own
+        self.r = exchange::redis_client(&self.redis_url, self.r_password.as_ref().map(|password| password.as_str()))?;
+
+        self.r.get_connection()
+    }
+
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    fn start(&self) -> Result<(), ExchangeError> {
+        WSExchange::run_with_settings(Some(self))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
+        // Try to use the settings the user passes before resorting to default settings.
+        let mut settings = match settings {
+            Some(settings) => settings.clone(),
+            None => *WSExchange::default_settings().map_err(ConfigError::Settings)?,
+        };
+
+        // KuCoin doesn't have a static websocket host. We have to ask for one, along with a token.
+        let (host, ping_interval) = bullet(&settings.rest_host)?;
+
+        let r = Arc::new(Mutex::new(settings.init_redis()?));
+
+        ws::connect(host, |out| WSExchangeSender {
+            rest_host: settings.rest_host.clone(),
+            ping_interval,
+
+            snapshot_received: settings.snapshot_received.clone(),
+            metadata: MetaData { market_type: Some(settings.market_type), ..settings.metadata.clone() },
+
+            single_channels: settings.single_channels.clone(),
+            sequences: HashMap::new(),
+
+            health: settings.health.clone(),
+            storage: settings.storage.clone(),
+            r: r.clone(),
+
+            out,
+        })?;
+
+        Ok(())
+    }
+}
+
+/// Response to `POST /api/v1/bullet-public`
+#[derive(Deserialize)]
+struct BulletResponse {
+    data: BulletData,
+}
+
+#[derive(Deserialize)]
+struct BulletData {
+    /// Token we must pass along when connecting to any of the instance servers
+    token: String,
+    #[serde(rename = "instanceServers")]
+    instance_servers: Vec<InstanceServer>,
+}
+
+#[derive(Deserialize)]
+struct InstanceServer {
+    /// Websocket endpoint. Example: `wss://ws-api-spot.kucoin.com/`
+    endpoint: String,
+    /// Interval, in milliseconds, the server expects us to ping it at
+    #[serde(rename = "pingInterval")]
+    ping_interval: Option<u64>,
+}
+
+/// Performs the bullet handshake, returning the full URL of the websocket endpoint (token included)
+/// and the ping interval the server expects us to respect.
+fn bullet(rest_host: &str) -> Result<(String, u64), reqwest::Error> {
+    let response: BulletResponse = reqwest::Client::new()
+        .post(&format!("{}/api/v1/bullet-public", rest_host))
+        .send()
+        .and_then(|mut response| response.json())?;
+
+    let server = match response.data.instance_servers.into_iter().next() {
+        Some(server) => server,
+        None => {
+            println!("KuCoin bullet response didn't include any instance servers. Using the default endpoint");
+            InstanceServer {
+                endpoint: "wss://ws-api-spot.kucoin.com/".into(),
+                ping_interval: None,
+            }
+        }
+    };
+
+    let connect_id = Utc::now().timestamp_millis();
+
+    Ok((
+        connect_url(&server.endpoint, &response.data.token, connect_id),
+        server.ping_interval.unwrap_or(DEFAULT_PING_INTERVAL_MS),
+    ))
+}
+
+/// Builds the URL we connect to from the endpoint and token handed out by the bullet handshake
+pub fn connect_url(endpoint: &str, token: &str, connect_id: i64) -> String {
+    format!("{}?token={}&connectId={}", endpoint.trim_right_matches('/'), token, connect_id)
+}
+
+#[derive(Serialize, Deserialize)]
+struct SubscribeMessage {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    topic: String,
+    #[serde(rename = "privateChannel")]
+    private_channel: bool,
+    response: bool,
+}
+
+#[derive(Serialize)]
+struct PingMessage {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+/// Every message KuCoin sends us. `topic`, `subject` and `data` are only present on `message` types
+#[derive(Deserialize)]
+struct KuCoinMessage {
+    /// One of `welcome`, `ack`, `pong`, `message` or `error`
+    #[serde(rename = "type")]
+    kind: String,
+    topic: Option<String>,
+    data: Option<serde_json::Value>,
+}
+
+/// Level2 market data event
+#[derive(Deserialize)]
+struct Level2Update {
+    #[serde(rename = "sequenceStart")]
+    sequence_start: u64,
+    #[serde(rename = "sequenceEnd")]
+    sequence_end: u64,
+    /// Symbol (i.e. `BTC-USDT`)
+    symbol: String,
+    changes: Level2Changes,
+    /// Event time in milliseconds
+    time: Option<u64>,
+}
+
+/// Changes as `[price, size, sequence]`
+#[derive(Deserialize)]
+struct Level2Changes {
+    asks: Vec<[String; 3]>,
+    bids: Vec<[String; 3]>,
+}
+
+/// Match (trade) event
+#[derive(Deserialize)]
+struct MatchEvent {
+    symbol: String,
+    /// Taker side. One of `buy` or `sell`
+    side: String,
+    price: String,
+    size: String,
+    #[serde(rename = "tradeId")]
+    trade_id: String,
+    /// Trade time in nanoseconds
+    time: String,
+}
+
+/// REST level2 snapshot (`GET /api/v1/market/orderbook/level2_100`)
+#[derive(Deserialize)]
+struct SnapshotResponse {
+    data: Level2Snapshot,
+}
+
+#[derive(Deserialize)]
+struct Level2Snapshot {
+    sequence: String,
+    bids: Vec<[String; 2]>,
+    asks: Vec<[String; 2]>,
+}
+
+/// Outcome of checking a level2 event against the sequence numbers we've already applied
+#[derive(Debug, PartialEq)]
+pub enum SequenceCheck {
+    /// The event continues the book and should be applied
+    Apply,
+    /// The event is older than our snapshot and should be discarded
+    Drop,
+    /// We've missed at least one event. The book must be reseeded from a new snapshot
+    Gap,
+}
+
+/// Tracks the sequence numbers of a single symbol's level2 stream. Every change carries its own
+/// sequence number, and every event covers the range `sequenceStart..=sequenceEnd`. The first event
+/// applied after a snapshot may overlap it, but every event after that must begin right where the
+/// previous one left off.
+#[derive(Clone, Debug)]
+pub struct Level2Sequence {
+    /// Last sequence number applied to the book, or the `sequence` of the snapshot
+    pub sequence: u64,
+}
+
+impl Level2Sequence {
+    /// Starts tracking from the `sequence` of a REST level2 snapshot
+    pub fn new(sequence: u64) -> Self {
+        Level2Sequence {
+            sequence,
+        }
+    }
+
+    /// Checks an event's `sequenceStart` and `sequenceEnd` against the sequence. This doesn't advance
+    /// the sequence, since changes overlapping the snapshot need to be filtered with `applies` first.
+    pub fn check(&self, sequence_start: u64, sequence_end: u64) -> SequenceCheck {
+        if sequence_end <= self.sequence {
+            return SequenceCheck::Drop;
+        }
+
+        if sequence_start > self.sequence + 1 {
+            return SequenceCheck::Gap;
+        }
+
+        SequenceCheck::Apply
+    }
+
+    /// Whether or not a single change is newer than what the book already reflects
+    pub fn applies(&self, change_sequence: u64) -> bool {
+        change_sequence > self.sequence
+    }
+
+    /// Advances the sequence past an applied event
+    pub fn advance(&mut self, sequence_end: u64) {
+        self.sequence = sequence_end;
+    }
+}
+
+/// Converts `[price, size, sequence]` changes into deltas, skipping the ones the book already reflects.
+/// A size of zero removes the level.
+fn changes_to_deltas(symbol: &str, changes: &[[String; 3]], side: u8, ts: f64, sequence: &Level2Sequence, seq: &mut u32) -> Vec<orderbook::Delta> {
+    changes.iter()
+        .filter(|change| change[2].parse::<u64>().map(|s| sequence.applies(s)).unwrap_or(false))
+        .filter_map(|change| level_to_delta(symbol, &change[0], &change[1], side, ts, seq))
+        .collect()
+}
+
+/// Converts `[price, size]` snapshot levels into deltas
+fn levels_to_deltas(symbol: &str, levels: &[[String; 2]], side: u8, ts: f64, seq: &mut u32) -> Vec<orderbook::Delta> {
+    levels.iter()
+        .filter_map(|level| level_to_delta(symbol, &level[0], &level[1], side, ts, seq))
+        .collect()
+}
+
+fn level_to_delta(symbol: &str, price: &str, size: &str, side: u8, ts: f64, seq: &mut u32) -> Option<orderbook::Delta> {
+    let price = price.parse::<f32>().ok()?;
+    let size = size.parse::<f32>().ok()?;
+    *seq += 1;
+
+    Some(orderbook::Delta {
+        symbol: symbol.to_string(),
+        price,
+        size,
+        seq: *seq,
+        event: side ^ if size == 0.0 {
+            orderbook::REMOVE
+        } else {
+            orderbook::UPDATE
+        },
+        ts,
+        received_ts: None,
+    })
+}
+
+impl WSExchangeSender {
+    /// Publishes orderbook deltas to redis
+    fn publish(&self, deltas: &Vec<orderbook::Delta>) {
+        if deltas.is_empty() {
+            return;
+        }
+
+        exchange::record_symbol_messages(&self.health, deltas.iter().map(|delta| delta.symbol.as_str()));
+        // Lock the connection until we are able to aquire it
+        exchange::publish_deltas(&*self.r.lock().unwrap(), &exchange::market_channel(&self.metadata.exchange, self.metadata.market_type), deltas, &self.health);
+    }
+
+    /// Fetches a level2 snapshot over REST and publishes its levels so that the book is seeded
+    /// before any level2 events are applied.
+    fn seed_book(&mut self, symbol: &str) -> Option<Level2Sequence> {
+        let url = format!("{}/api/v1/market/orderbook/level2_100?symbol={}", self.rest_host, symbol);
+
+        let snapshot: SnapshotResponse = match reqwest::get(&url).and_then(|mut response| response.json()) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                println!("Failed to fetch KuCoin level2 snapshot for {}: {}", symbol, e);
+                return None;
+            }
+        };
+
+        let sequence = match snapshot.data.sequence.parse::<u64>() {
+            Ok(sequence) => sequence,
+            Err(e) => {
+                println!("Invalid KuCoin snapshot sequence for {}: {}", symbol, e);
+                return None;
+            }
+        };
+
+        let ts = Utc::now().timestamp_millis() as f64 * 0.001f64;
+        let mut seq = 0;
+        let mut deltas = levels_to_deltas(symbol, &snapshot.data.asks, orderbook::ASK, ts, &mut seq);
+        deltas.extend(levels_to_deltas(symbol, &snapshot.data.bids, orderbook::BID, ts, &mut seq));
+
+        self.publish(&deltas);
+        self.snapshot_received = true;
+
+        Some(Level2Sequence::new(sequence))
+    }
+
+    /// Applies a level2 event, seeding the book from a REST snapshot first if necessary
+    fn on_level2(&mut self, update: Level2Update) {
+        if !self.sequences.contains_key(&update.symbol) {
+            match self.seed_book(&update.symbol) {
+                Some(sequence) => { self.sequences.insert(update.symbol.clone(), sequence); },
+                None => return,
+            }
+        }
+
+        let check = self.sequences[&update.symbol].check(update.sequence_start, update.sequence_end);
+
+        match check {
+            SequenceCheck::Apply => (),
+            SequenceCheck::Drop => return,
+            SequenceCheck::Gap => {
+                // Forget the sequence so that the next event reseeds the book
+                println!("KuCoin level2 stream for {} is out of sequence. Reseeding the book...", update.symbol);
+                self.sequences.remove(&update.symbol);
+                return;
+            }
+        }
+
+        let ts = update.time
+            .map(|time| time as f64 * 0.001f64)
+            .unwrap_or(Utc::now().timestamp_millis() as f64 * 0.001f64);
+        let mut seq = 0;
+
+        let deltas = {
+            let sequence = &self.sequences[&update.symbol];
+            let mut deltas = changes_to_deltas(&update.symbol, &update.changes.asks, orderbook::ASK, ts, sequence, &mut seq);
+            deltas.extend(changes_to_deltas(&update.symbol, &update.changes.bids, orderbook::BID, ts, sequence, &mut seq));
+            deltas
+        };
+
+        self.sequences.get_mut(&update.symbol)
+            .unwrap()
+            .advance(update.sequence_end);
+
+        self.publish(&deltas);
+    }
+
+    /// Performs a new bullet handshake and reconnects. Tokens aren't guaranteed to outlive
+    /// the connection they were issued for, so we never reuse the previous one.
+    fn reconnect(&mut self) {
+        self.health.record_reconnect();
+
+        let (host, ping_interval) = match bullet(&self.rest_host) {
+            Ok(endpoint) => endpoint,
+            Err(e) => {
+                println!("Failed to obtain a KuCoin websocket token: {}", e);
+                return;
+            }
+        };
+
+        ws::connect(host, |out| WSExchangeSender{
+            rest_host: self.rest_host.clone(),
+            ping_interval,
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            single_channels: self.single_channels.clone(),
+            sequences: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+    }
+}
+
+impl Handler for WSExchangeSender {
+    fn on_open(&mut self, _: Handshake) -> Result<(), Error> {
+        self.health.track_connection(&self.out);
+
+        // Measure the round-trip time of the new connection
+        self.health.ping_latency(&self.out)?;
+
+        // KuCoin closes connections that haven't pinged within the interval it handed out
+        self.out.timeout(self.ping_interval, PING)?;
+
+        // Set a timeout for 5 seconds of inactivity
+        // Issue: currently, this reruns every five seconds. Comment out while we fix.
+        //self.out.timeout(5_000, EXPIRE).unwrap();
+
+        let pairs = self.metadata.asset_pair.clone().expect("No asset pairs passed to KuCoin structure");
+
+        for pair in &pairs {
+            let normalized_pair = match exchange::get_asset_pair(pair, Exchange::KuCoin) {
+                Ok(normalized_pair) => normalized_pair,
+                Err(_) => continue,
+            };
+            let db_name = format!("{}_{}", self.metadata.exchange.deref(), normalized_pair);
+
+            // Create the database if it doesn't exist yet. This avoids many issues
+            // relating to inserting to a non-existant database.
+            self.storage.create(&db_name)?;
+        }
+
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<(), Error> {
+        self.health.record_message();
+
+        let message = match serde_json::from_slice::<KuCoinMessage>(&msg.into_data()) {
+            Ok(message) => message,
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            }
+        };
+
+        match message.kind.as_str() {
+            // We can only subscribe once the server has welcomed us
+            "welcome" => {
+                let pairs = self.metadata.asset_pair.clone().expect("No asset pairs passed to KuCoin structure");
+
+                for (id, channel) in self.single_channels.iter().enumerate() {
+                    let msg = SubscribeMessage {
+                        id: (id + 1).to_string(),
+                        kind: "subscribe".into(),
+                        topic: topic(&pairs, channel),
+                        private_channel: false,
+                        response: true,
+                    };
+
+                    println!("Sending message {}", serde_json::to_string(&msg).unwrap());
+                    self.out.send(serde_json::to_string(&msg).unwrap())?;
+                }
+
+                return Ok(());
+            },
+            "message" => (),
+            "error" => {
+                println!("KuCoin error: {:?}", message.data);
+                return Ok(());
+            },
+            // Subscription acks and pongs
+            _ => return Ok(()),
+        }
+
+        let (topic, data) = match (message.topic, message.data) {
+            (Some(topic), Some(data)) => (topic, data),
+            _ => return Ok(()),
+        };
+
+        if topic.starts_with("/market/level2:") {
+            // Level2 events are applied on the socket thread, since the sequence checks
+            // depend on the order in which they arrive.
+            match serde_json::from_value::<Level2Update>(data) {
+                Ok(update) => self.on_level2(update),
+                Err(e) => println!("Error: {}", e),
+            }
+
+            return Ok(());
+        }
+
+        if !topic.starts_with("/market/match:") {
+            return Ok(());
+        }
+
+        let redis_ref = self.r.clone();
+        let health = self.health.clone();
+        let channel = exchange::market_channel(&self.metadata.exchange, self.metadata.market_type);
+
+        thread::spawn(move || {
+            let trade = match serde_json::from_value::<MatchEvent>(data) {
+                Ok(trade) => trade,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return;
+                }
+            };
+
+            let trades = vec![orderbook::Trade {
+                symbol: trade.symbol,
+                price: trade.price.parse::<f64>().unwrap(),
+                size: trade.size.parse::<f64>().unwrap(),
+                side: if trade.side == "buy" {
+                    orderbook::TradeSide::Buy
+                } else {
+                    orderbook::TradeSide::Sell
+                },
+                ts: trade.time.parse::<f64>().unwrap_or(0.0) * 0.000000001f64,
+                exchange: Exchange::KuCoin,
+                trade_id: Some(trade.trade_id),
+                received_ts: None,
+            }];
+
+            exchange::record_symbol_messages(&health, trades.iter().map(|trade| trade.symbol.as_str()));
+            exchange::publish_trades(&*redis_ref.lock().unwrap(), &format!("{}:trades", channel), &trades, &health);
+        });
+
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> Result<Option<ws::Frame>, Error> {
+        self.health.on_frame(frame)
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut *self.storage);
+            return;
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("KuCoin Socket is closing. Opening a new connection...");
+
+        self.reconnect();
+    }
+
+    fn on_timeout(&mut self, event: Token) -> Result<(), ws::Error> {
+        if self.health.is_stopped() {
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
+        if event == PING {
+            let ping = PingMessage {
+                id: Utc::now().timestamp_millis().to_string(),
+                kind: "ping".into(),
+            };
+
+            self.out.send(serde_json::to_string(&ping).unwrap())?;
+            return self.out.timeout(self.ping_interval, PING);
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("KuCoin Socket timed out (5s of inactivity). Opening a new connection...");
+
+        self.reconnect();
+
+        Ok(())
+    }
+}
+
+/// Builds a topic covering every asset pair (i.e. `/market/level2:BTC-USDT,ETH-USDT`).
+/// Pairs that aren't listed on KuCoin are skipped.
+pub fn topic(pairs: &Vec<[Asset; 2]>, channel: &str) -> String {
+    let symbols: Vec<String> = pairs.iter()
+        .filter_map(|pair| match exchange::get_asset_pair(pair, Exchange::KuCoin) {
+            Ok(symbol) => Some(symbol),
+            Err(e) => {
+                println!("Skipping KuCoin topic {}: {}", channel, e);
+                None
+            }
+        })
+        .collect();
+
+    format!("{}:{}", channel, symbols.join(","))
+}
@@ -1,23 +1,91 @@
 /// Binance exchange
 pub mod binance;
+/// Binance USDT-margined futures exchange module
+pub mod binance_futures;
+/// Bitfinex exchange module
+pub mod bitfinex;
 /// BitMEX exchange module
 pub mod bitmex;
+/// Bitstamp exchange module
+pub mod bitstamp;
+/// Bittrex exchange module
+pub mod bittrex;
+/// Bybit exchange module
+pub mod bybit;
+/// Crypto.com exchange module
+pub mod cryptocom;
+/// Deribit exchange module
+pub mod deribit;
+/// dYdX exchange module
+pub mod dydx;
+/// FTX exchange module
+pub mod ftx;
+/// Gate.io exchange module
+pub mod gateio;
+/// Gemini exchange module
+pub mod gemini;
 /// GDAX managed by level 2 orderbook
 pub mod gdax_l2;
+/// HitBTC exchange module
+pub mod hitbtc;
+/// Huobi Global exchange module
+pub mod huobi;
+/// Kraken exchange module
+pub mod kraken;
+/// KuCoin exchange module
+pub mod kucoin;
+/// OKX exchange module
+pub mod okx;
+/// Phemex exchange module
+pub mod phemex;
+/// Poloniex exchange module
+pub mod poloniex;
+/// Upbit exchange module
+pub mod upbit;
 
-use redis;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-/// Returns the list of supported exchanges as a vector of strings
+use config::{Config, ConfigError};
+use orderbook;
+use orderbook::tectonic::TectonicError;
+use redis::{self, Commands, ConnectionLike};
+use reqwest;
+use serde::Serialize;
+use serde_json;
+use storage::StorageBackend;
+use url::Url;
+use ws;
+use strum::{AsStaticRef, IntoEnumIterator};
+
+/// Redis server exchanges publish to, unless configured otherwise
+pub const DEFAULT_REDIS_URL: &str = "redis://localhost";
+
+/// Returns the list of supported exchanges as a vector of strings. The list is derived from
+/// the [`Exchange`] enum, so adding a variant there is all it takes to add it here.
+///
+/// Not every [`Asset`] is listed on every exchange (i.e. the DeFi tokens are only mapped for GDAX).
+/// [`Exchange::normalize_asset`] returns [`AssetError::Unsupported`] for the ones that aren't.
 pub fn get_supported_exchanges() -> Vec<String> {
-    vec![
-        String::from("poloniex"),
-        String::from("gdax"),
-        String::from("bitmex"),
-    ]
+    Exchange::all_variants()
+        .iter()
+        .map(|exchange| exchange.to_string())
+        .collect()
 }
 
 /// Complete list of all the exchanges we support as an enum. This is also used as a unique
 /// identifier to differentiate where the data originated. Is used in the `orderbook` module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, EnumIter, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Exchange {
     /// Poloniex exchange
     Poloniex,
@@ -25,9 +93,52 @@ pub enum Exchange {
     GDAX,
     /// BitMEX exchange
     BitMEX,
+    /// Kraken exchange
+    Kraken,
+    /// Binance exchange
+    Binance,
+    /// OKX exchange (formerly OKEx)
+    OKX,
+    /// Bitfinex exchange
+    Bitfinex,
+    /// FTX exchange
+    FTX,
+    /// Deribit exchange
+    Deribit,
+    /// Bitstamp exchange
+    Bitstamp,
+    /// Bybit exchange
+    Bybit,
+    /// Huobi Global exchange
+    Huobi,
+    /// Gemini exchange
+    Gemini,
+    /// KuCoin exchange
+    KuCoin,
+    /// Upbit exchange
+    Upbit,
+    /// HitBTC exchange
+    HitBTC,
+    /// Gate.io exchange
+    GateIO,
+    /// Crypto.com Exchange
+    CryptoCom,
+    /// dYdX decentralized exchange
+    DyDx,
+    /// Phemex derivatives exchange
+    Phemex,
+    /// Bittrex exchange
+    Bittrex,
+    /// Binance USDT-margined futures
+    #[serde(rename = "binance_futures")]
+    BinanceFutures,
 }
 
 impl Exchange {
+    /// Every exchange we support, in declaration order
+    pub fn all_variants() -> Vec<Exchange> {
+        Exchange::iter().collect()
+    }
     /// Useful method to identify how exactly the market/asset pair is constructed.
     /// Some exchanges place the market first (i.e. USD-BTC) whereas others don't (BTC-USD).
     pub fn market_first(&self) -> bool {
@@ -35,6 +146,25 @@ impl Exchange {
             Exchange::Poloniex => true,
             Exchange::GDAX => false,
             Exchange::BitMEX => false,
+            Exchange::Kraken => false,
+            Exchange::Binance => false,
+            Exchange::OKX => false,
+            Exchange::Bitfinex => false,
+            Exchange::FTX => false,
+            Exchange::Deribit => false,
+            Exchange::Bitstamp => false,
+            Exchange::Bybit => false,
+            Exchange::Huobi => false,
+            Exchange::Gemini => false,
+            Exchange::KuCoin => false,
+            Exchange::Upbit => true,
+            Exchange::HitBTC => false,
+            Exchange::GateIO => false,
+            Exchange::CryptoCom => false,
+            Exchange::DyDx => false,
+            Exchange::Phemex => false,
+            Exchange::Bittrex => false,
+            Exchange::BinanceFutures => false,
         }
     }
     /// Returns the separator present in the market/asset pair. Some exchanges don't include
@@ -44,13 +174,33 @@ impl Exchange {
             Exchange::Poloniex => "-".into(),
             Exchange::GDAX => "-".into(),
             Exchange::BitMEX => "".into(),
+            Exchange::Kraken => "/".into(),
+            Exchange::Binance => "".into(),
+            Exchange::OKX => "-".into(),
+            Exchange::Bitfinex => "".into(),
+            Exchange::FTX => "/".into(),
+            Exchange::Deribit => "-".into(),
+            Exchange::Bitstamp => "".into(),
+            Exchange::Bybit => "".into(),
+            Exchange::Huobi => "".into(),
+            Exchange::Gemini => "".into(),
+            Exchange::KuCoin => "-".into(),
+            Exchange::Upbit => "-".into(),
+            Exchange::HitBTC => "".into(),
+            Exchange::GateIO => "_".into(),
+            Exchange::CryptoCom => "_".into(),
+            Exchange::DyDx => "-".into(),
+            Exchange::Phemex => "".into(),
+            Exchange::Bittrex => "-".into(),
+            Exchange::BinanceFutures => "".into(),
         }
     }
 
     /// This function takes the asset, and converts it to its representation on an exchange.
     /// Example: Bitcoin is annotated as `BTC` on Poloniex, but appears as `XBT` in BitMEX.
-    pub fn normalize_asset(&self, asset: &Asset) -> Option<String> {
-        match self {
+    /// Returns [`AssetError::Unsupported`] if the exchange doesn't list the asset.
+    pub fn normalize_asset(&self, asset: &Asset) -> Result<String, AssetError> {
+        let normalized: Option<String> = match self {
             Exchange::Poloniex => match asset {
                 Asset::BTC => Some("BTC".into()),
                 Asset::ETH => Some("ETH".into()),
@@ -64,6 +214,21 @@ impl Exchange {
                 Asset::ETH => Some("ETH".into()),
                 Asset::LTC => Some("LTC".into()),
 
+                Asset::UNI => Some("UNI".into()),
+                Asset::AAVE => Some("AAVE".into()),
+                Asset::COMP => Some("COMP".into()),
+                Asset::SUSHI => Some("SUSHI".into()),
+                Asset::CRV => Some("CRV".into()),
+                Asset::SNX => Some("SNX".into()),
+                Asset::MKR => Some("MKR".into()),
+                Asset::YFI => Some("YFI".into()),
+
+                Asset::SOL => Some("SOL".into()),
+                Asset::AVAX => Some("AVAX".into()),
+                Asset::DOT => Some("DOT".into()),
+                Asset::ADA => Some("ADA".into()),
+                Asset::LINK => Some("LINK".into()),
+
                 Asset::USD => Some("USD".into()),
                 Asset::USDC => Some("USDC".into()),
                 _ => None
@@ -75,7 +240,233 @@ impl Exchange {
 
                 Asset::USD => Some("USD".into()),
                 _ => None
+            },
+            Exchange::Kraken => match asset {
+                Asset::BTC => Some("XBT".into()),
+                Asset::ETH => Some("ETH".into()),
+                Asset::LTC => Some("LTC".into()),
+                Asset::USDT => Some("USDT".into()),
+                Asset::USDC => Some("USDC".into()),
+
+                Asset::USD => Some("USD".into()),
+                Asset::JPY => Some("JPY".into()),
+                Asset::EUR => Some("EUR".into()),
+                Asset::GBP => Some("GBP".into()),
+                Asset::CAD => Some("CAD".into()),
+                _ => None
+            },
+            Exchange::Binance => match asset {
+                Asset::BTC => Some("BTC".into()),
+                Asset::ETH => Some("ETH".into()),
+                Asset::LTC => Some("LTC".into()),
+
+                Asset::SOL => Some("SOL".into()),
+                Asset::AVAX => Some("AVAX".into()),
+                Asset::DOT => Some("DOT".into()),
+                Asset::ADA => Some("ADA".into()),
+                Asset::LINK => Some("LINK".into()),
+
+                Asset::USDT => Some("USDT".into()),
+                Asset::USDC => Some("USDC".into()),
+                Asset::BUSD => Some("BUSD".into()),
+                _ => None
+            },
+            Exchange::OKX => match asset {
+                Asset::BTC => Some("BTC".into()),
+                Asset::ETH => Some("ETH".into()),
+                Asset::LTC => Some("LTC".into()),
+
+                Asset::USDT => Some("USDT".into()),
+                Asset::USDC => Some("USDC".into()),
+                _ => None
+            },
+            Exchange::Bitfinex => match asset {
+                Asset::BTC => Some("BTC".into()),
+                Asset::ETH => Some("ETH".into()),
+                Asset::LTC => Some("LTC".into()),
+                Asset::USDT => Some("UST".into()),
+
+                Asset::USD => Some("USD".into()),
+                Asset::JPY => Some("JPY".into()),
+                Asset::EUR => Some("EUR".into()),
+                Asset::GBP => Some("GBP".into()),
+                _ => None
+            },
+            Exchange::FTX => match asset {
+                Asset::BTC => Some("BTC".into()),
+                Asset::ETH => Some("ETH".into()),
+                Asset::LTC => Some("LTC".into()),
+                Asset::USDT => Some("USDT".into()),
+
+                Asset::USD => Some("USD".into()),
+                _ => None
+            },
+            Exchange::Deribit => match asset {
+                Asset::BTC => Some("BTC".into()),
+                Asset::ETH => Some("ETH".into()),
+
+                Asset::USD => Some("USD".into()),
+                _ => None
+            },
+            Exchange::Bitstamp => match asset {
+                Asset::BTC => Some("btc".into()),
+                Asset::ETH => Some("eth".into()),
+                Asset::LTC => Some("ltc".into()),
+                Asset::USDT => Some("usdt".into()),
+                Asset::USDC => Some("usdc".into()),
+
+                Asset::USD => Some("usd".into()),
+                Asset::EUR => Some("eur".into()),
+                Asset::GBP => Some("gbp".into()),
+                _ => None
+            },
+            Exchange::Bybit => match asset {
+                Asset::BTC => Some("BTC".into()),
+                Asset::ETH => Some("ETH".into()),
+                Asset::LTC => Some("LTC".into()),
+                Asset::USDT => Some("USDT".into()),
+                Asset::USDC => Some("USDC".into()),
+
+                Asset::USD => Some("USD".into()),
+                _ => None
+            },
+            Exchange::Huobi => match asset {
+                Asset::BTC => Some("btc".into()),
+                Asset::ETH => Some("eth".into()),
+                Asset::LTC => Some("ltc".into()),
+
+                Asset::USDT => Some("usdt".into()),
+                Asset::USDC => Some("usdc".into()),
+                _ => None
+            },
+            Exchange::Gemini => match asset {
+                Asset::BTC => Some("BTC".into()),
+                Asset::ETH => Some("ETH".into()),
+                Asset::LTC => Some("LTC".into()),
+                Asset::USDT => Some("USDT".into()),
+
+                Asset::USD => Some("USD".into()),
+                Asset::EUR => Some("EUR".into()),
+                Asset::GBP => Some("GBP".into()),
+                _ => None
+            },
+            Exchange::KuCoin => match asset {
+                Asset::BTC => Some("BTC".into()),
+                Asset::ETH => Some("ETH".into()),
+                Asset::LTC => Some("LTC".into()),
+                Asset::USDT => Some("USDT".into()),
+                Asset::USDC => Some("USDC".into()),
+                _ => None
+            },
+            Exchange::Upbit => match asset {
+                Asset::BTC => Some("BTC".into()),
+                Asset::ETH => Some("ETH".into()),
+                Asset::LTC => Some("LTC".into()),
+
+                Asset::USDT => Some("USDT".into()),
+
+                Asset::KRW => Some("KRW".into()),
+                _ => None
+            },
+            Exchange::HitBTC => match asset {
+                Asset::BTC => Some("BTC".into()),
+                Asset::ETH => Some("ETH".into()),
+                Asset::LTC => Some("LTC".into()),
+
+                Asset::USD => Some("USD".into()),
+                _ => None
+            },
+            Exchange::GateIO => match asset {
+                Asset::BTC => Some("BTC".into()),
+                Asset::ETH => Some("ETH".into()),
+                Asset::LTC => Some("LTC".into()),
+                Asset::USDT => Some("USDT".into()),
+                Asset::USDC => Some("USDC".into()),
+                _ => None
+            },
+            Exchange::CryptoCom => match asset {
+                Asset::BTC => Some("BTC".into()),
+                Asset::ETH => Some("ETH".into()),
+                Asset::LTC => Some("LTC".into()),
+                Asset::USDT => Some("USDT".into()),
+                Asset::USDC => Some("USDC".into()),
+                _ => None
+            },
+            Exchange::DyDx => match asset {
+                Asset::BTC => Some("BTC".into()),
+                Asset::ETH => Some("ETH".into()),
+                Asset::LTC => Some("LTC".into()),
+                Asset::USD => Some("USD".into()),
+                _ => None
+            },
+            Exchange::Phemex => match asset {
+                Asset::BTC => Some("BTC".into()),
+                Asset::ETH => Some("ETH".into()),
+                Asset::LTC => Some("LTC".into()),
+
+                Asset::USD => Some("USD".into()),
+                _ => None
+            },
+            Exchange::Bittrex => match asset {
+                Asset::BTC => Some("BTC".into()),
+                Asset::ETH => Some("ETH".into()),
+                Asset::LTC => Some("LTC".into()),
+
+                Asset::USDT => Some("USDT".into()),
+                Asset::USD => Some("USD".into()),
+                Asset::EUR => Some("EUR".into()),
+                _ => None
+            },
+            Exchange::BinanceFutures => match asset {
+                Asset::BTC => Some("BTC".into()),
+                Asset::ETH => Some("ETH".into()),
+                Asset::LTC => Some("LTC".into()),
+
+                Asset::USDT => Some("USDT".into()),
+                _ => None
             }
+        };
+
+        normalized.ok_or_else(|| AssetError::Unsupported(asset.clone(), *self))
+    }
+    /// Reverse of `normalize_asset`. Takes the exchange's representation of an asset and
+    /// converts it back into an [`Asset`]. Example: `XBT` on BitMEX becomes `Asset::BTC`.
+    pub fn denormalize_asset(&self, symbol: &str) -> Option<Asset> {
+        Asset::iter().find(|asset| match self.normalize_asset(asset) {
+            Ok(normalized) => normalized == symbol,
+            Err(_) => false,
+        })
+    }
+    /// Parses an exchange symbol (i.e. `XBTUSD`, `BTC-USD`, `USDT-BTC`) back into a `MARKET, ASSET`
+    /// pair, in the same order `get_asset_pair` expects it.
+    ///
+    /// When the exchange doesn't use a separator (or the symbol doesn't contain it), we match the longest
+    /// known asset prefix whose remainder is also a known asset, so that `XBTUSD` resolves to `[BTC, USD]`.
+    pub fn parse_asset_pair(&self, pair: &str) -> Option<[Asset; 2]> {
+        let separator = self.asset_separator();
+
+        let (first, second) = if !separator.is_empty() && pair.contains(separator.as_str()) {
+            let mut split = pair.splitn(2, separator.as_str());
+
+            (self.denormalize_asset(split.next()?)?, self.denormalize_asset(split.next()?)?)
+        } else {
+            let mut prefixes: Vec<(String, Asset)> = Asset::iter()
+                .filter_map(|asset| self.normalize_asset(&asset).ok().map(|normalized| (normalized, asset)))
+                .filter(|(normalized, _)| pair.starts_with(normalized.as_str()))
+                .collect();
+
+            // Longest match first
+            prefixes.sort_by_key(|(normalized, _)| Reverse(normalized.len()));
+
+            prefixes.into_iter()
+                .filter_map(|(normalized, asset)| self.denormalize_asset(&pair[normalized.len()..])
+                    .map(|remainder| (asset, remainder)))
+                .next()?
+        };
+
+        match self.market_first() {
+            true => Some([second, first]),
+            false => Some([first, second]),
         }
     }
     /// Indicates whether or not the exchange supports standard buyer/seller transactions without any sort of contracts.
@@ -85,6 +476,25 @@ impl Exchange {
             Exchange::BitMEX => false,
             Exchange::GDAX => true,
             Exchange::Poloniex => true,
+            Exchange::Kraken => true,
+            Exchange::Binance => true,
+            Exchange::OKX => true,
+            Exchange::Bitfinex => true,
+            Exchange::FTX => true,
+            Exchange::Deribit => false,
+            Exchange::Bitstamp => true,
+            Exchange::Bybit => true,
+            Exchange::Huobi => true,
+            Exchange::Gemini => true,
+            Exchange::KuCoin => true,
+            Exchange::Upbit => true,
+            Exchange::HitBTC => true,
+            Exchange::GateIO => true,
+            Exchange::CryptoCom => true,
+            Exchange::DyDx => false,
+            Exchange::Phemex => false,
+            Exchange::Bittrex => true,
+            Exchange::BinanceFutures => false,
         }
     }
     /// Exchanges that support options
@@ -93,6 +503,53 @@ impl Exchange {
             Exchange::BitMEX => true,
             Exchange::GDAX => false,
             Exchange::Poloniex => false,
+            Exchange::Kraken => false,
+            Exchange::Binance => false,
+            Exchange::OKX => true,
+            Exchange::Bitfinex => false,
+            Exchange::FTX => false,
+            Exchange::Deribit => true,
+            Exchange::Bitstamp => false,
+            Exchange::Bybit => false,
+            Exchange::Huobi => false,
+            Exchange::Gemini => false,
+            Exchange::KuCoin => false,
+            Exchange::Upbit => false,
+            Exchange::HitBTC => false,
+            Exchange::GateIO => false,
+            Exchange::CryptoCom => false,
+            Exchange::DyDx => false,
+            Exchange::Phemex => false,
+            Exchange::Bittrex => false,
+            Exchange::BinanceFutures => false,
+        }
+    }
+    /// Market type the exchange's collector collects unless configured otherwise (i.e. inverse perpetuals on BitMEX).
+    /// Its deltas are published to the channel of that market type (see [`market_channel`]).
+    pub fn default_market_type(&self) -> MarketType {
+        match self {
+            Exchange::BitMEX => MarketType::InversePerpetual,
+            Exchange::GDAX => MarketType::Spot,
+            Exchange::Poloniex => MarketType::Spot,
+            Exchange::Kraken => MarketType::Spot,
+            Exchange::Binance => MarketType::Spot,
+            Exchange::OKX => MarketType::Spot,
+            Exchange::Bitfinex => MarketType::Spot,
+            Exchange::FTX => MarketType::Spot,
+            Exchange::Deribit => MarketType::InversePerpetual,
+            Exchange::Bitstamp => MarketType::Spot,
+            Exchange::Bybit => MarketType::LinearPerpetual,
+            Exchange::Huobi => MarketType::Spot,
+            Exchange::Gemini => MarketType::Spot,
+            Exchange::KuCoin => MarketType::Spot,
+            Exchange::Upbit => MarketType::Spot,
+            Exchange::HitBTC => MarketType::Spot,
+            Exchange::GateIO => MarketType::Spot,
+            Exchange::CryptoCom => MarketType::Spot,
+            Exchange::DyDx => MarketType::LinearPerpetual,
+            Exchange::Phemex => MarketType::InversePerpetual,
+            Exchange::Bittrex => MarketType::Spot,
+            Exchange::BinanceFutures => MarketType::LinearPerpetual,
         }
     }
     /// Exchanges that support futures
@@ -101,24 +558,753 @@ impl Exchange {
             Exchange::BitMEX => true,
             Exchange::GDAX => false,
             Exchange::Poloniex => false,
+            Exchange::Kraken => false,
+            Exchange::Binance => false,
+            Exchange::OKX => true,
+            Exchange::Bitfinex => false,
+            Exchange::FTX => true,
+            Exchange::Deribit => true,
+            Exchange::Bitstamp => false,
+            Exchange::Bybit => true,
+            Exchange::Huobi => false,
+            Exchange::Gemini => false,
+            Exchange::KuCoin => false,
+            Exchange::Upbit => false,
+            Exchange::HitBTC => false,
+            Exchange::GateIO => false,
+            Exchange::CryptoCom => false,
+            Exchange::DyDx => true,
+            Exchange::Phemex => true,
+            Exchange::Bittrex => false,
+            Exchange::BinanceFutures => true,
+        }
+    }
+    /// Exchanges we collect perpetual funding rates from
+    pub fn supports_funding_rates(&self) -> bool {
+        match self {
+            Exchange::BitMEX => true,
+            Exchange::GDAX => false,
+            Exchange::Poloniex => false,
+            Exchange::Kraken => false,
+            Exchange::Binance => false,
+            Exchange::OKX => false,
+            Exchange::Bitfinex => false,
+            Exchange::FTX => false,
+            Exchange::Deribit => false,
+            Exchange::Bitstamp => false,
+            Exchange::Bybit => false,
+            Exchange::Huobi => false,
+            Exchange::Gemini => false,
+            Exchange::KuCoin => false,
+            Exchange::Upbit => false,
+            Exchange::HitBTC => false,
+            Exchange::GateIO => false,
+            Exchange::CryptoCom => false,
+            Exchange::DyDx => false,
+            Exchange::Phemex => false,
+            Exchange::Bittrex => false,
+            Exchange::BinanceFutures => false,
+        }
+    }
+
+    /// Number of decimal places the exchange quotes prices with for the given asset pair. Useful
+    /// for rounding `Delta.price` without floating point artifacts (i.e. `6500.1` instead of `6500.10009765625`).
+    /// Returns `None` if the pair isn't listed or the precision isn't known. Exchanges that expose
+    /// precision dynamically (i.e. BitMEX through its instrument endpoint) always return `None`,
+    /// and the live value should be used instead.
+    pub fn price_precision(&self, pair: &[Asset; 2]) -> Option<u8> {
+        get_asset_pair(pair, *self).ok()?;
+
+        match self {
+            Exchange::Poloniex => Some(8),
+            // Newer listings (i.e. DeFi tokens) are quoted with anywhere between 2 and 4 decimals, depending on the token
+            Exchange::GDAX if ![Asset::BTC, Asset::ETH, Asset::LTC].contains(&pair[0]) => None,
+            Exchange::GDAX => match pair[1] {
+                Asset::BTC => Some(5),
+                Asset::USD | Asset::USDC | Asset::EUR | Asset::GBP => Some(2),
+                _ => None
+            },
+            Exchange::BitMEX => None,
+            Exchange::Kraken => match (&pair[0], &pair[1]) {
+                (Asset::BTC, Asset::JPY) => Some(0),
+                (Asset::BTC, _) => Some(1),
+                (Asset::ETH, Asset::BTC) => Some(5),
+                (Asset::LTC, Asset::BTC) => Some(6),
+                (Asset::ETH, _) | (Asset::LTC, _) => Some(2),
+                _ => None
+            },
+            Exchange::Binance => None,
+            Exchange::OKX => None,
+            // Bitfinex uses five significant digits rather than a fixed number of decimals
+            Exchange::Bitfinex => None,
+            Exchange::FTX => None,
+            Exchange::Deribit => None,
+            Exchange::Bitstamp => None,
+            Exchange::Bybit => None,
+            Exchange::Huobi => None,
+            Exchange::Gemini => None,
+            Exchange::KuCoin => None,
+            Exchange::Upbit => None,
+            Exchange::HitBTC => None,
+            Exchange::GateIO => None,
+            Exchange::CryptoCom => None,
+            Exchange::DyDx => None,
+            Exchange::Phemex => None,
+            Exchange::Bittrex => None,
+            Exchange::BinanceFutures => None,
+        }
+    }
+    /// Number of decimal places the exchange quotes order sizes with for the given asset pair.
+    /// Same caveats as [`Exchange::price_precision`] apply.
+    pub fn quantity_precision(&self, pair: &[Asset; 2]) -> Option<u8> {
+        get_asset_pair(pair, *self).ok()?;
+
+        match self {
+            Exchange::Poloniex => Some(8),
+            Exchange::GDAX => Some(8),
+            Exchange::BitMEX => None,
+            Exchange::Kraken => Some(8),
+            Exchange::Binance => None,
+            Exchange::OKX => None,
+            Exchange::Bitfinex => Some(8),
+            Exchange::FTX => None,
+            Exchange::Deribit => None,
+            Exchange::Bitstamp => None,
+            Exchange::Bybit => None,
+            Exchange::Huobi => None,
+            Exchange::Gemini => None,
+            Exchange::KuCoin => None,
+            Exchange::Upbit => None,
+            Exchange::HitBTC => None,
+            Exchange::GateIO => None,
+            Exchange::CryptoCom => None,
+            Exchange::DyDx => None,
+            Exchange::Phemex => None,
+            Exchange::Bittrex => None,
+            Exchange::BinanceFutures => None,
+        }
+    }
+
+    /// Smallest order size the exchange accepts for the given asset pair, in units of the asset
+    /// (or contracts, for BitMEX). Returns `None` if the pair isn't listed or the minimum isn't known.
+    pub fn min_order_size(&self, pair: &[Asset; 2]) -> Option<f32> {
+        get_asset_pair(pair, *self).ok()?;
+
+        match self {
+            // Poloniex limits the total of an order (0.0001 BTC) rather than its size
+            Exchange::Poloniex => None,
+            Exchange::GDAX => match pair[0] {
+                Asset::BTC => Some(0.001),
+                Asset::ETH => Some(0.01),
+                Asset::LTC => Some(0.1),
+                _ => None
+            },
+            Exchange::BitMEX => Some(1.0),
+            Exchange::Kraken => match pair[0] {
+                Asset::BTC => Some(0.002),
+                Asset::ETH => Some(0.02),
+                Asset::LTC => Some(0.1),
+                _ => None
+            },
+            Exchange::Binance => None,
+            Exchange::OKX => None,
+            Exchange::Bitfinex => None,
+            Exchange::FTX => None,
+            Exchange::Deribit => None,
+            Exchange::Bitstamp => None,
+            Exchange::Bybit => None,
+            Exchange::Huobi => None,
+            Exchange::Gemini => None,
+            Exchange::KuCoin => None,
+            Exchange::Upbit => None,
+            Exchange::HitBTC => None,
+            Exchange::GateIO => None,
+            Exchange::CryptoCom => None,
+            Exchange::DyDx => None,
+            Exchange::Phemex => None,
+            Exchange::Bittrex => None,
+            Exchange::BinanceFutures => None,
+        }
+    }
+    /// Base tier `(maker, taker)` fees as fractions of the order value (i.e. `0.001` is 0.1%).
+    /// A negative maker fee is a rebate.
+    pub fn fees(&self) -> (f32, f32) {
+        match self {
+            Exchange::Poloniex => (0.001, 0.002),
+            Exchange::GDAX => (0.0, 0.003),
+            Exchange::BitMEX => (-0.00025, 0.00075),
+            Exchange::Kraken => (0.0016, 0.0026),
+            Exchange::Binance => (0.001, 0.001),
+            Exchange::OKX => (0.0008, 0.001),
+            Exchange::Bitfinex => (0.001, 0.002),
+            Exchange::FTX => (0.0002, 0.0007),
+            Exchange::Deribit => (0.0, 0.0005),
+            Exchange::Bitstamp => (0.0025, 0.0025),
+            Exchange::Bybit => (0.0001, 0.0006),
+            Exchange::Huobi => (0.002, 0.002),
+            Exchange::Gemini => (0.002, 0.004),
+            Exchange::KuCoin => (0.001, 0.001),
+            Exchange::Upbit => (0.0005, 0.0005),
+            Exchange::HitBTC => (0.001, 0.0025),
+            Exchange::GateIO => (0.002, 0.002),
+            Exchange::CryptoCom => (0.001, 0.0016),
+            Exchange::DyDx => (0.0002, 0.0005),
+            Exchange::Phemex => (-0.00025, 0.00075),
+            Exchange::Bittrex => (0.0035, 0.0035),
+            Exchange::BinanceFutures => (0.0002, 0.0004),
+        }
+    }
+}
+
+/// Controls how long we wait before reconnecting to an exchange after the websocket drops.
+/// The delay doubles with every consecutive attempt, starting at `base_delay_ms` and capped
+/// at `max_delay_ms`, so that we don't hammer an exchange during an outage.
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    /// Delay before the first reconnection attempt
+    pub base_delay_ms: u64,
+    /// Upper bound of the delay between two reconnection attempts
+    pub max_delay_ms: u64,
+    /// Randomizes the delay between half and all of its value to avoid reconnecting in lockstep
+    pub jitter: bool,
+    /// Number of consecutive attempts we make before giving up. `None` retries forever
+    pub max_attempts: Option<u32>,
+    /// How long a connection must stay up before its drop is no longer counted as a consecutive attempt
+    pub reset_after_ms: u64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            base_delay_ms: 500,
+            max_delay_ms: 60_000,
+            jitter: true,
+            max_attempts: None,
+            reset_after_ms: 30_000,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Computes how long we should wait before making reconnection attempt number `attempt` (starting at 0)
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay_ms = self.base_delay_ms
+            .saturating_mul(1u64.checked_shl(attempt).unwrap_or(u64::max_value()))
+            .min(self.max_delay_ms);
+
+        if !self.jitter {
+            return Duration::from_millis(delay_ms)
+        }
+
+        // We don't need a cryptographically secure source of randomness here, the clock will do.
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.subsec_nanos() as u64)
+            .unwrap_or(0);
+
+        Duration::from_millis(delay_ms / 2 + nanos % (delay_ms / 2 + 1))
+    }
+
+    /// Returns true once a connection has stayed up long enough for the backoff to start over
+    pub fn is_stable(&self, uptime: Duration) -> bool {
+        uptime >= Duration::from_millis(self.reset_after_ms)
+    }
+
+    /// Returns true once we've used up all of our reconnection attempts
+    pub fn exhausted(&self, attempts: u32) -> bool {
+        match self.max_attempts {
+            Some(max_attempts) => attempts >= max_attempts,
+            None => false,
+        }
+    }
+}
+
+/// Token bucket limiting how often we call an exchange's REST API. The bucket holds up to `burst` tokens
+/// and refills at `requests_per_second`. Share it (i.e. behind an `Arc<Mutex<_>>`) between everything
+/// calling the same API, reconnections included, so that they all count against the same limit.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    /// Rate at which the bucket refills
+    requests_per_second: f64,
+    /// Maximum number of tokens the bucket holds
+    burst: u32,
+    /// Tokens available. Negative once requests are waiting for tokens that haven't been refilled yet
+    tokens: f64,
+    /// When the bucket was last refilled
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a full bucket
+    pub fn new(requests_per_second: f64, burst: u32) -> Self {
+        RateLimiter {
+            requests_per_second,
+            burst,
+            tokens: burst as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes a token, returning how long the caller must sleep before making its request (zero if a token
+    /// was available). The token is reserved right away, so the lock can be released before sleeping.
+    pub fn acquire(&mut self) -> Duration {
+        self.acquire_at(Instant::now())
+    }
+
+    /// Same as [`acquire`](#method.acquire), as of `now`
+    pub fn acquire_at(&mut self, now: Instant) -> Duration {
+        if now > self.last_refill {
+            let elapsed = now - self.last_refill;
+            let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+
+            self.tokens = (self.tokens + elapsed * self.requests_per_second).min(self.burst as f64);
+            self.last_refill = now;
+        }
+
+        self.tokens -= 1.0;
+
+        if self.tokens >= 0.0 {
+            return Duration::from_secs(0);
+        }
+
+        let wait = -self.tokens / self.requests_per_second;
+        Duration::new(wait.trunc() as u64, (wait.fract() * 1e9) as u32)
+    }
+}
+
+/// Returns true if `seq` doesn't directly follow `last_seq`, meaning that at least one message was missed.
+/// Messages that are older than (or repeat) `last_seq` aren't gaps.
+pub fn is_sequence_gap(last_seq: u64, seq: u64) -> bool {
+    last_seq.checked_add(1).map_or(false, |next| seq > next)
+}
+
+/// Tracks the health of a websocket connection so that operators can tell whether it has
+/// silently stalled, and how often it drops. Clones share the same underlying counters, so
+/// the copy held by `WSExchange` reflects what its running `WSExchangeSender` records.
+#[derive(Clone, Debug)]
+pub struct ConnectionHealth {
+    /// Exchange the connection belongs to
+    pub exchange: Exchange,
+    /// When we last received a frame from the exchange
+    pub last_message_ts: Arc<Mutex<Option<Instant>>>,
+    /// Number of times we've reconnected
+    pub reconnect_count: Arc<AtomicUsize>,
+    /// Round-trip time of the last ping we've sent
+    pub latency_ms: Arc<Mutex<Option<f64>>>,
+    /// Number of replayed deltas we've dropped instead of publishing
+    pub duplicates_skipped: Arc<AtomicU64>,
+    /// Number of deltas a [`DeltaFilter`] dropped instead of publishing
+    pub filtered_count: Arc<AtomicU64>,
+    /// Messages received and deltas published, by symbol (see [`publish_deltas`])
+    pub symbols: Arc<Mutex<HashMap<String, SymbolCounts>>>,
+    /// Number of times publishing to Redis failed
+    pub redis_publish_errors: Arc<AtomicU64>,
+    /// Set by [`ConnectionHealth::stop`]. Once set, the connection isn't reopened when it closes
+    pub shutdown: Arc<AtomicBool>,
+    /// Sender of the connection currently open, closed by [`ConnectionHealth::stop`]
+    connection: Arc<Mutex<Option<ws::Sender>>>,
+}
+
+impl ConnectionHealth {
+    /// Creates a blank health record for a connection to the exchange
+    pub fn new(exchange: Exchange) -> Self {
+        ConnectionHealth {
+            exchange,
+            last_message_ts: Arc::new(Mutex::new(None)),
+            reconnect_count: Arc::new(AtomicUsize::new(0)),
+            latency_ms: Arc::new(Mutex::new(None)),
+            duplicates_skipped: Arc::new(AtomicU64::new(0)),
+            filtered_count: Arc::new(AtomicU64::new(0)),
+            symbols: Arc::new(Mutex::new(HashMap::new())),
+            redis_publish_errors: Arc::new(AtomicU64::new(0)),
+            shutdown: Arc::new(AtomicBool::new(false)),
+            connection: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Records the sender of a connection that just opened, so that [`ConnectionHealth::stop`] can close it
+    pub fn track_connection(&self, out: &ws::Sender) {
+        *self.connection.lock().unwrap() = Some(out.clone());
+    }
+
+    /// Stops the collector: its websocket is closed cleanly, its pending deltas are flushed, and
+    /// `run` returns instead of reconnecting. Can be called from any thread.
+    pub fn stop(&self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+
+        if let Some(ref out) = *self.connection.lock().unwrap() {
+            // The connection may already be gone, in which case there's nothing left to close
+            let _ = out.close(ws::CloseCode::Normal);
+        }
+    }
+
+    /// Returns true once [`ConnectionHealth::stop`] has been called
+    pub fn is_stopped(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Records that we've just received a message
+    pub fn record_message(&self) {
+        *self.last_message_ts.lock().unwrap() = Some(Instant::now());
+    }
+
+    /// Records that the connection dropped and we're reconnecting
+    pub fn record_reconnect(&self) {
+        self.reconnect_count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Records that we've dropped a delta the exchange sent us twice
+    pub fn record_duplicate(&self) {
+        self.duplicates_skipped.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Records that a [`DeltaFilter`] dropped a delta
+    pub fn record_filtered(&self) {
+        self.filtered_count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Records that we've received a message carrying updates of the symbol
+    pub fn record_symbol_message(&self, symbol: &str) {
+        self.symbols.lock().unwrap().entry(symbol.to_string()).or_insert_with(SymbolCounts::default).messages += 1;
+    }
+
+    /// Records that we've published deltas of the symbol to Redis
+    pub fn record_published(&self, symbol: &str, deltas: u64) {
+        self.symbols.lock().unwrap().entry(symbol.to_string()).or_insert_with(SymbolCounts::default).deltas_published += deltas;
+    }
+
+    /// Records that publishing to Redis failed
+    pub fn record_publish_error(&self) {
+        self.redis_publish_errors.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Time elapsed since the last message. `None` if we haven't received anything yet
+    pub fn since_last_message(&self) -> Option<Duration> {
+        self.last_message_ts.lock()
+            .unwrap()
+            .map(|last_message_ts| last_message_ts.elapsed())
+    }
+
+    /// Sends a ping frame to measure the round-trip time of the connection. The time the ping was
+    /// sent is carried in its payload, which the exchange echoes back in the pong.
+    pub fn ping_latency(&self, out: &ws::Sender) -> ws::Result<()> {
+        let sent_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_secs() * 1_000 + since_epoch.subsec_millis() as u64)
+            .unwrap_or(0);
+
+        out.ping(sent_ms.to_string().into_bytes())
+    }
+
+    /// Records the round-trip time of a pong sent in response to [`ConnectionHealth::ping_latency`].
+    /// Pongs we didn't ask for (i.e. unsolicited keepalives) are ignored.
+    pub fn record_pong(&self, payload: &[u8]) {
+        let sent_ms = match String::from_utf8_lossy(payload).parse::<u64>() {
+            Ok(sent_ms) => sent_ms,
+            Err(_) => return,
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::from_secs(0));
+        let now_ms = now.as_secs() as f64 * 1_000f64 + now.subsec_nanos() as f64 * 1e-6;
+
+        *self.latency_ms.lock().unwrap() = Some((now_ms - sent_ms as f64).max(0.0));
+    }
+
+    /// Frame handler shared by every exchange. The `ws` handler has no dedicated pong callback,
+    /// so we look for pongs here before applying the default reserved bits check.
+    pub fn on_frame(&self, frame: ws::Frame) -> ws::Result<Option<ws::Frame>> {
+        if frame.opcode() == ws::OpCode::Pong {
+            self.record_pong(frame.payload());
+        }
+
+        if frame.has_rsv1() || frame.has_rsv2() || frame.has_rsv3() {
+            return Err(ws::Error::new(ws::ErrorKind::Protocol, "Encountered frame with reserved bits set."));
+        }
+
+        Ok(Some(frame))
+    }
+}
+
+/// Messages received and deltas published for a single symbol of a connection
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SymbolCounts {
+    /// Number of messages carrying updates of the symbol
+    pub messages: u64,
+    /// Number of the symbol's deltas published to Redis
+    pub deltas_published: u64,
+}
+
+/// Writes the deltas a stopped collector still has queued, since nothing else will flush them
+pub fn flush_storage(exchange: Exchange, storage: &mut dyn StorageBackend) {
+    if let Err(e) = storage.flush() {
+        error!("Failed to flush the {} deltas on shutdown: {}", exchange, e);
+    }
+}
+
+/// Default number of deltas a [`DeduplicationWindow`] remembers
+pub const DEFAULT_DEDUP_CAPACITY: usize = 1000;
+
+/// Remembers the most recent deltas we've published, so that updates the exchange replays
+/// (i.e. after a reconnect) aren't published or stored twice. Once full, the oldest delta is forgotten.
+///
+/// A delta is identified by its content (see [`DeduplicationWindow::key`]), so two genuine updates that are
+/// identical within the window can't be told apart unless the exchange timestamped them.
+#[derive(Clone, Debug)]
+pub struct DeduplicationWindow {
+    /// Maximum number of deltas remembered
+    capacity: usize,
+    /// Keys of the remembered deltas
+    seen: HashSet<u64>,
+    /// Keys of the remembered deltas, oldest first
+    order: VecDeque<u64>,
+}
+
+impl Default for DeduplicationWindow {
+    fn default() -> Self {
+        DeduplicationWindow::new(DEFAULT_DEDUP_CAPACITY)
+    }
+}
+
+impl DeduplicationWindow {
+    /// Creates a window remembering up to `capacity` deltas
+    pub fn new(capacity: usize) -> Self {
+        DeduplicationWindow {
+            capacity,
+            seen: HashSet::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Hashes a delta's `(seq, symbol)` along with its content: price, size, event, and the exchange's
+    /// timestamp when it has one. The sequence alone doesn't identify a delta: most exchanges number deltas
+    /// within a single message, and BitMEX leaves it at 0. The time we received the delta is left out, since
+    /// a replayed update is received at another time than its first copy.
+    pub fn key(delta: &orderbook::Delta) -> u64 {
+        let mut hasher = DefaultHasher::new();
+
+        (delta.seq, &delta.symbol).hash(&mut hasher);
+        (delta.price.to_bits(), delta.size.to_bits(), delta.event).hash(&mut hasher);
+        delta.exchange_ts().map(f64::to_bits).hash(&mut hasher);
+
+        hasher.finish()
+    }
+
+    /// Returns true if the delta is in the window. Otherwise, it's added to the window
+    pub fn is_duplicate(&mut self, delta: &orderbook::Delta) -> bool {
+        let key = DeduplicationWindow::key(delta);
+
+        if !self.seen.insert(key) {
+            return true;
+        }
+
+        self.order.push_back(key);
+        while self.order.len() > self.capacity.max(1) {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+
+        false
+    }
+
+    /// Number of deltas remembered
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+}
+
+/// Drops the deltas consumers aren't interested in (i.e. tiny orders) before they're stored or published.
+/// The default filter lets every delta through.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeltaFilter {
+    /// Deltas smaller than this are dropped. Removals are kept whatever their size, since
+    /// dropping them would leave stale levels in the consumers' books.
+    pub min_size: f64,
+    /// Deltas priced below this are dropped
+    pub min_price: f64,
+    /// Symbols we keep the deltas of. `None` keeps every symbol
+    pub allowed_symbols: Option<HashSet<String>>,
+}
+
+impl DeltaFilter {
+    /// Returns true if the delta passes the filter
+    pub fn allows(&self, delta: &orderbook::Delta) -> bool {
+        if let Some(ref allowed_symbols) = self.allowed_symbols {
+            if !allowed_symbols.contains(&delta.symbol) {
+                return false;
+            }
         }
+
+        let is_removal = delta.event & orderbook::REMOVE == orderbook::REMOVE;
+
+        (is_removal || delta.size as f64 >= self.min_size) && delta.price as f64 >= self.min_price
+    }
+
+    /// Drops the deltas that don't pass the filter, counting them in `health`
+    pub fn apply(&self, deltas: &mut Vec<orderbook::Delta>, health: &ConnectionHealth) {
+        deltas.retain(|delta| if self.allows(delta) {
+            true
+        } else {
+            health.record_filtered();
+            false
+        });
+    }
+}
+
+impl fmt::Display for Exchange {
+    /// Canonical lowercase name of the exchange. This is the name we use for Redis channels
+    /// and TectonicDB database prefixes.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Exchange::Poloniex => "poloniex",
+            Exchange::GDAX => "gdax",
+            Exchange::BitMEX => "bitmex",
+            Exchange::Kraken => "kraken",
+            Exchange::Binance => "binance",
+            Exchange::OKX => "okx",
+            Exchange::Bitfinex => "bitfinex",
+            Exchange::FTX => "ftx",
+            Exchange::Deribit => "deribit",
+            Exchange::Bitstamp => "bitstamp",
+            Exchange::Bybit => "bybit",
+            Exchange::Huobi => "huobi",
+            Exchange::Gemini => "gemini",
+            Exchange::KuCoin => "kucoin",
+            Exchange::Upbit => "upbit",
+            Exchange::HitBTC => "hitbtc",
+            Exchange::GateIO => "gateio",
+            Exchange::CryptoCom => "cryptocom",
+            Exchange::DyDx => "dydx",
+            Exchange::Phemex => "phemex",
+            Exchange::Bittrex => "bittrex",
+            Exchange::BinanceFutures => "binance_futures",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Exchange {
+    type Err = ExchangeParseError;
+
+    /// Parses an exchange from its name. Matching is case-insensitive, and `coinbase` is
+    /// accepted as an alias for GDAX.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "poloniex" => Ok(Exchange::Poloniex),
+            "gdax" | "coinbase" => Ok(Exchange::GDAX),
+            "bitmex" => Ok(Exchange::BitMEX),
+            "kraken" => Ok(Exchange::Kraken),
+            "binance" => Ok(Exchange::Binance),
+            "okx" | "okex" => Ok(Exchange::OKX),
+            "bitfinex" => Ok(Exchange::Bitfinex),
+            "ftx" => Ok(Exchange::FTX),
+            "deribit" => Ok(Exchange::Deribit),
+            "bitstamp" => Ok(Exchange::Bitstamp),
+            "bybit" => Ok(Exchange::Bybit),
+            "huobi" | "htx" => Ok(Exchange::Huobi),
+            "gemini" => Ok(Exchange::Gemini),
+            "kucoin" => Ok(Exchange::KuCoin),
+            "upbit" => Ok(Exchange::Upbit),
+            "hitbtc" => Ok(Exchange::HitBTC),
+            "gateio" | "gate.io" => Ok(Exchange::GateIO),
+            "cryptocom" | "crypto.com" => Ok(Exchange::CryptoCom),
+            "dydx" | "dy/dx" => Ok(Exchange::DyDx),
+            "phemex" => Ok(Exchange::Phemex),
+            "bittrex" => Ok(Exchange::Bittrex),
+            "binance_futures" | "binancefutures" => Ok(Exchange::BinanceFutures),
+            _ => Err(ExchangeParseError(name.into())),
+        }
+    }
+}
+
+/// Error returned when parsing an exchange name we don't recognize
+#[derive(Debug, PartialEq)]
+pub struct ExchangeParseError(pub String);
+
+impl fmt::Display for ExchangeParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown exchange \"{}\". Supported exchanges are: {}", self.0, get_supported_exchanges().join(", "))
+    }
+}
+
+impl error::Error for ExchangeParseError {
+    fn description(&self) -> &str {
+        "Unknown exchange"
+    }
+}
+
+/// Adds a password to a Redis URL (i.e. `redis://127.0.0.1:6379/0` to `redis://:hunter2@127.0.0.1:6379/0`),
+/// replacing the URL's own password if it has one. The URL is returned as is if there's no password.
+pub fn redis_connection_url(redis_url: &str, password: Option<&str>) -> Result<String, redis::RedisError> {
+    let mut url = Url::parse(redis_url)
+        .map_err(|_| redis::RedisError::from((redis::ErrorKind::InvalidClientConfig, "Redis URL did not parse")))?;
+
+    if password.is_some() {
+        url.set_password(password)
+            .map_err(|_| redis::RedisError::from((redis::ErrorKind::InvalidClientConfig, "Redis URL can't hold a password")))?;
     }
+
+    Ok(url.into_string())
+}
+
+/// Opens a Redis client for the URL, authenticating with the password if there is one
+pub fn redis_client(redis_url: &str, password: Option<&str>) -> Result<redis::Client, redis::RedisError> {
+    redis::Client::open(redis_connection_url(redis_url, password)?.as_str())
 }
 
 /// Skeleton methods that we expect all exchanges to implement
 pub trait AssetExchange {
     /// Require that each asset exchange we define have defaults
-    fn default_settings() -> Result<Box<Self>, String>;
+    fn default_settings() -> Result<Box<Self>, String> where Self: Sized;
+    /// Loads settings from a TOML configuration file. Anything the file leaves out
+    /// keeps its value from [`AssetExchange::default_settings`]
+    fn from_config(path: &Path) -> Result<Box<Self>, ConfigError> where Self: Sized {
+        let config = Config::from_file(path)?;
+        let mut settings = Self::default_settings().map_err(ConfigError::Settings)?;
+
+        settings.configure(&config)?;
+
+        Ok(settings)
+    }
+    /// Applies the `[redis]`, `[tectonic]`, `[channels]` and `[assets]` sections of a configuration to the settings
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError>;
     /// Initializes the redis connection
     fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError>;
-    /// Start and run the websocket data collection
-    fn run(settings: Option<&Self>);
+    /// Enables the collection of funding rates, alongside the orderbook. Returns `false` if
+    /// we don't collect funding rates from the exchange (see [`Exchange::supports_funding_rates`]).
+    fn subscribe_funding(&mut self) -> bool {
+        false
+    }
+    /// Start and run the websocket data collection, loading settings from the TOML configuration
+    /// file if one is given. Falls back to [`AssetExchange::default_settings`] otherwise.
+    /// Returns once the collection stops, with the error that stopped it if it didn't stop on its own.
+    fn run(config: Option<&Path>) -> Result<(), ExchangeError> where Self: Sized {
+        let settings = match config {
+            Some(path) => Self::from_config(path)?,
+            None => Self::default_settings().map_err(ConfigError::Settings)?,
+        };
+
+        Self::run_with_settings(Some(&settings))
+    }
+    /// Start and run the websocket data collection with the given settings, or the default settings if `None`
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> where Self: Sized;
+    /// Health of the connection to the exchange
+    fn health(&self) -> &ConnectionHealth;
+    /// Start and run the websocket data collection with these settings. Unlike [`AssetExchange::run_with_settings`],
+    /// this can be called on trait objects (i.e. the connections of a [`ConnectionManager`](../manager/struct.ConnectionManager.html))
+    fn start(&self) -> Result<(), ExchangeError>;
 }
 
 /// Assets that are currently supported. We plan on standardizing all token names across multiple exchanges,
 /// so having an enum of supported assets is quite... the asset ᕕ( ᐛ )ᕗ. We've included fiat as well in here,
 /// as they are considered a valid market on many websites
-#[derive(AsStaticStr, Clone)]
+///
+/// Discriminants can be used as stable indexes (i.e. in TectonicDB), so new assets are appended rather than
+/// inserted next to the ones they're related to.
+#[derive(AsStaticStr, Clone, Debug, PartialEq, EnumIter, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
 pub enum Asset {
     /// Bitcoin
     BTC = 0,
@@ -148,12 +1334,112 @@ pub enum Asset {
     /// Canadian Dollar
     CAD,
     /// Australian Dollar
-    AUD
+    AUD,
+
+    // DeFi
+    //
+    /// Uniswap
+    UNI,
+    /// Aave
+    AAVE,
+    /// Compound
+    COMP,
+    /// SushiSwap
+    SUSHI,
+    /// Curve DAO Token
+    CRV,
+    /// Synthetix Network Token
+    SNX,
+    /// Maker
+    MKR,
+    /// yearn.finance
+    YFI,
+
+    // Layer 1s and major altcoins
+    //
+    /// Solana
+    SOL,
+    /// Avalanche
+    AVAX,
+    /// Polkadot
+    DOT,
+    /// Cardano
+    ADA,
+    /// Chainlink
+    LINK,
+
+    /// Binance USD stablecoin
+    BUSD,
+}
+
+impl fmt::Display for Asset {
+    /// Canonical ticker of the asset (i.e. `BTC`). This is exchange-neutral, use `Exchange::normalize_asset`
+    /// to get the ticker an exchange uses (i.e. `XBT` on BitMEX).
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_static())
+    }
+}
+
+impl Asset {
+    /// Whether the asset is a DeFi governance token (i.e. UNI)
+    pub fn is_defi(&self) -> bool {
+        match self {
+            Asset::UNI | Asset::AAVE | Asset::COMP | Asset::SUSHI |
+            Asset::CRV | Asset::SNX | Asset::MKR | Asset::YFI => true,
+            _ => false,
+        }
+    }
+
+    /// Other tickers the asset commonly goes by (i.e. `XBT` for Bitcoin). Accepted when parsing, never displayed.
+    pub fn aliases(&self) -> &'static [&'static str] {
+        match self {
+            Asset::BTC => &["XBT", "XXBT"],
+            Asset::ETH => &["XETH"],
+            Asset::LTC => &["XLTC"],
+            Asset::USDT => &["UST"],
+            Asset::USD => &["ZUSD"],
+            Asset::JPY => &["ZJPY"],
+            Asset::EUR => &["ZEUR"],
+            Asset::GBP => &["ZGBP"],
+            Asset::CAD => &["ZCAD"],
+            _ => &[],
+        }
+    }
+}
+
+impl FromStr for Asset {
+    type Err = AssetParseError;
+
+    /// Parses an asset from its canonical ticker or one of its aliases. Matching is case-insensitive.
+    fn from_str(ticker: &str) -> Result<Self, Self::Err> {
+        Asset::iter()
+            .find(|asset| asset.as_static().eq_ignore_ascii_case(ticker))
+            .or_else(|| Asset::iter().find(|asset| asset.aliases().iter().any(|alias| alias.eq_ignore_ascii_case(ticker))))
+            .ok_or_else(|| AssetParseError(ticker.into()))
+    }
+}
+
+/// Error returned when parsing a ticker that doesn't match any [`Asset`]
+#[derive(Debug, PartialEq)]
+pub struct AssetParseError(pub String);
+
+impl fmt::Display for AssetParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown asset \"{}\"", self.0)
+    }
+}
+
+impl error::Error for AssetParseError {
+    fn description(&self) -> &str {
+        "Unknown asset"
+    }
 }
 
 /// Options are by nature much more different from other assets. For one, very few assets
 /// will have options support, so it would make sense to separate the asset classes into two 
 /// distinct groups, which is what we've done here.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
 pub enum OptionsAsset {
     /// Bitcoin options
     BTC = 0,
@@ -162,57 +1448,311 @@ pub enum OptionsAsset {
 }
 
 /// Same reasoning as options. The exclusivity of futures warrants its own group of assets.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
 pub enum FuturesAsset {
     /// Bitcoin Futures
     BTC = 0,
     /// Ethereum options
     ETH,
+    /// Litecoin futures
+    LTC,
+}
+
+impl FuturesAsset {
+    /// Asset the contract tracks
+    pub fn underlying(&self) -> Asset {
+        match self {
+            FuturesAsset::BTC => Asset::BTC,
+            FuturesAsset::ETH => Asset::ETH,
+            FuturesAsset::LTC => Asset::LTC,
+        }
+    }
+
+    /// Asset pair of the USDT-margined perpetual contract (i.e. `BTCUSDT` on Binance)
+    pub fn usdt_perpetual(&self) -> [Asset; 2] {
+        [self.underlying(), Asset::USDT]
+    }
+}
+
+/// Type of market a websocket subscription covers. Every type is published on its own Redis
+/// channel (see [`market_channel`]), so consumers can subscribe to the instruments they care about.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarketType {
+    /// Spot markets (i.e. BTC/USD on GDAX)
+    Spot,
+    /// Dated futures margined in the quote asset
+    LinearFutures,
+    /// Dated futures margined in the base asset (i.e. XBTZ18 on BitMEX)
+    InverseFutures,
+    /// Perpetual swaps margined in the quote asset (i.e. BTCUSDT on Binance Futures)
+    LinearPerpetual,
+    /// Perpetual swaps margined in the base asset (i.e. XBTUSD on BitMEX)
+    InversePerpetual,
+    /// Options
+    Option,
+}
+
+impl MarketType {
+    /// Name of the market type as used in Redis channels (i.e. `inverse_perpetual`)
+    pub fn name(&self) -> &'static str {
+        match self {
+            MarketType::Spot => "spot",
+            MarketType::LinearFutures => "linear_futures",
+            MarketType::InverseFutures => "inverse_futures",
+            MarketType::LinearPerpetual => "linear_perpetual",
+            MarketType::InversePerpetual => "inverse_perpetual",
+            MarketType::Option => "option",
+        }
+    }
+}
+
+/// Redis channel deltas of an exchange's markets are published to (i.e. `bitmex:inverse_perpetual`).
+/// Trades are published on the same channel, suffixed with `:trades`. Without a market type, the
+/// exchange name is used as is.
+pub fn market_channel(exchange: &str, market_type: Option<MarketType>) -> String {
+    match market_type {
+        Some(market_type) => format!("{}:{}", exchange, market_type.name()),
+        None => exchange.to_string(),
+    }
+}
+
+/// Expands the `{symbol}` placeholder of a Redis channel (i.e. `bitmex:{symbol}` to `bitmex:XBTUSD`)
+pub fn redis_channel_name(channel: &str, symbol: &str) -> String {
+    channel.replace("{symbol}", symbol)
+}
+
+/// Publishes items as JSON arrays, one message per channel the items expand to. A channel without
+/// a `{symbol}` placeholder publishes every item in a single message.
+pub fn publish<C, T, F>(r: &C, channel: &str, items: &[T], symbol: F) -> redis::RedisResult<()>
+    where C: ConnectionLike, T: Serialize, F: Fn(&T) -> &str
+{
+    let mut messages: Vec<(String, Vec<&T>)> = vec![];
+
+    for item in items {
+        let name = redis_channel_name(channel, symbol(item));
+
+        match messages.iter().position(|(existing, _)| *existing == name) {
+            Some(i) => messages[i].1.push(item),
+            None => messages.push((name, vec![item])),
+        }
+    }
+
+    for (name, items) in messages {
+        let _ = r.publish::<&str, &str, u8>(&name, &serde_json::to_string(&items).unwrap())?;
+    }
+
+    Ok(())
+}
+
+/// Publishes deltas like [`publish`], counting them in `health` by symbol. A failed publish is counted and
+/// logged rather than taking the connection down. Every collector publishes its deltas through here, so that
+/// the metrics served on `/metrics` cover every exchange.
+pub fn publish_deltas<C: ConnectionLike>(r: &C, channel: &str, deltas: &[orderbook::Delta], health: &ConnectionHealth) {
+    if deltas.is_empty() {
+        return;
+    }
+
+    if let Err(e) = publish(r, channel, deltas, |delta| delta.symbol.as_str()) {
+        health.record_publish_error();
+        error!("Failed to publish {:?} deltas to redis PUBSUB: {}", health.exchange, e);
+        return;
+    }
+
+    let mut published: HashMap<&str, u64> = HashMap::new();
+    for delta in deltas {
+        *published.entry(delta.symbol.as_str()).or_insert(0) += 1;
+    }
+
+    for (symbol, count) in published {
+        health.record_published(symbol, count);
+    }
+}
+
+/// Publishes trades like [`publish`]. A failed publish is counted in `health` and logged rather than taking
+/// the connection down.
+pub fn publish_trades<C: ConnectionLike>(r: &C, channel: &str, trades: &[orderbook::Trade], health: &ConnectionHealth) {
+    if trades.is_empty() {
+        return;
+    }
+
+    if let Err(e) = publish(r, channel, trades, |trade| trade.symbol.as_str()) {
+        health.record_publish_error();
+        error!("Failed to publish {:?} trades to redis PUBSUB: {}", health.exchange, e);
+    }
+}
+
+/// Counts a message towards every symbol it carries updates of
+pub fn record_symbol_messages<'a, I: Iterator<Item = &'a str>>(health: &ConnectionHealth, symbols: I) {
+    let mut symbols: Vec<&str> = symbols.collect();
+    symbols.sort();
+    symbols.dedup();
+
+    for symbol in symbols {
+        health.record_symbol_message(symbol);
+    }
+}
+
+/// Errors that can occur when converting an [`Asset`] to its representation on an exchange.
+#[derive(Debug)]
+pub enum AssetError {
+    /// Asset has no representation on the exchange (i.e. JPY on GDAX)
+    Unsupported(Asset, Exchange),
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssetError::Unsupported(asset, exchange) =>
+                write!(f, "Asset {:?} is not supported on exchange {:?}", asset, exchange),
+        }
+    }
+}
+
+impl error::Error for AssetError {
+    fn description(&self) -> &str {
+        match self {
+            AssetError::Unsupported(_, _) => "Asset is not supported on exchange",
+        }
+    }
+}
+
+/// Errors that stop an exchange's data collection (see [`AssetExchange::run`])
+#[derive(Debug)]
+pub enum ExchangeError {
+    /// Failed to connect to the exchange, or the connection failed
+    Websocket(ws::Error),
+    /// Failed to connect to Redis
+    Redis(redis::RedisError),
+    /// TectonicDB command failed, or the connection couldn't be reestablished
+    Tectonic(TectonicError),
+    /// Request to the exchange's REST API failed (i.e. while negotiating the websocket endpoint)
+    Http(reqwest::Error),
+    /// Failed to load the exchange's settings
+    Config(ConfigError),
+}
+
+impl fmt::Display for ExchangeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExchangeError::Websocket(e) => write!(f, "Websocket error: {}", e),
+            ExchangeError::Redis(e) => write!(f, "Redis error: {}", e),
+            ExchangeError::Tectonic(e) => write!(f, "{}", e),
+            ExchangeError::Http(e) => write!(f, "HTTP request failed: {}", e),
+            ExchangeError::Config(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for ExchangeError {
+    fn description(&self) -> &str {
+        match self {
+            ExchangeError::Websocket(_) => "Websocket error",
+            ExchangeError::Redis(_) => "Redis error",
+            ExchangeError::Tectonic(_) => "TectonicDB error",
+            ExchangeError::Http(_) => "HTTP request failed",
+            ExchangeError::Config(_) => "Failed to load settings",
+        }
+    }
+}
+
+impl From<ws::Error> for ExchangeError {
+    fn from(e: ws::Error) -> Self {
+        ExchangeError::Websocket(e)
+    }
+}
+
+impl From<redis::RedisError> for ExchangeError {
+    fn from(e: redis::RedisError) -> Self {
+        ExchangeError::Redis(e)
+    }
+}
+
+impl From<TectonicError> for ExchangeError {
+    fn from(e: TectonicError) -> Self {
+        ExchangeError::Tectonic(e)
+    }
+}
+
+impl From<reqwest::Error> for ExchangeError {
+    fn from(e: reqwest::Error) -> Self {
+        ExchangeError::Http(e)
+    }
+}
+
+impl From<ConfigError> for ExchangeError {
+    fn from(e: ConfigError) -> Self {
+        ExchangeError::Config(e)
+    }
 }
 
 /// Helper function that takes in the assets you want to trade as a `MARKET, ASSET` vector pair.
 /// Depending on the exchange and whether the exchange chooses to flip around these values, we
-/// format it according to the exchange's configuration
-pub fn get_asset_pair(assets: &[Asset; 2], exch: Exchange) -> String {
-    match exch.market_first() {
-        true => {
-            let mut pair = String::with_capacity(16);
-            pair.push_str(&exch.normalize_asset(&assets[1]).expect("Asset 1 not found"));
-            pair.push_str(exch.asset_separator().as_str());
-            pair.push_str(&exch.normalize_asset(&assets[0]).expect("Asset 0 not found"));
-
-            pair
-        },
-        false => {
-            let mut pair = String::with_capacity(16);
-            pair.push_str(&exch.normalize_asset(&assets[0]).expect("Asset 0 not found"));
-            pair.push_str(exch.asset_separator().as_str());
-            pair.push_str(&exch.normalize_asset(&assets[1]).expect("Asset 1 not found"));
-
-            pair
-        }
-    }
-}
-
-/// Same as function `get_asset_pair`, but with the added benefit of batch processing.
-pub fn get_batch_asset_pairs(assets: &Vec<[Asset; 2]>, exch: Exchange) -> Vec<String> {
-    assets.into_iter().map(|asset_pair| {
-        match exch.market_first() {
-            true => {
-                let mut pair = String::with_capacity(16);
-                pair.push_str(&exch.normalize_asset(&asset_pair[1]).unwrap());
-                pair.push_str(exch.asset_separator().as_str());
-                pair.push_str(&exch.normalize_asset(&asset_pair[0]).unwrap());
-
-                pair
-            },
-            false => {
-                let mut pair = String::with_capacity(16);
-                pair.push_str(&exch.normalize_asset(&asset_pair[0]).unwrap());
-                pair.push_str(exch.asset_separator().as_str());
-                pair.push_str(&exch.normalize_asset(&asset_pair[1]).unwrap());
-
-                pair
-            }
+/// format it according to the exchange's configuration. Returns an [`AssetError`] if either asset
+/// isn't available on the exchange.
+pub fn try_get_asset_pair(assets: &[Asset; 2], exch: Exchange) -> Result<String, AssetError> {
+    let (first, second) = match exch.market_first() {
+        true => (&assets[1], &assets[0]),
+        false => (&assets[0], &assets[1]),
+    };
+
+    let mut pair = String::with_capacity(16);
+    pair.push_str(&exch.normalize_asset(first)?);
+    pair.push_str(exch.asset_separator().as_str());
+    pair.push_str(&exch.normalize_asset(second)?);
+
+    Ok(pair)
+}
+
+/// Same as [`try_get_asset_pair`], kept for the existing callers
+pub fn get_asset_pair(assets: &[Asset; 2], exch: Exchange) -> Result<String, AssetError> {
+    try_get_asset_pair(assets, exch)
+}
+
+/// Parses a TectonicDB database name (i.e. `bitmex_XBTUSD`) into the exchange the data came from
+/// and the asset pair it contains. Useful for labeling datasets read back from the database.
+pub fn parse_db_name(db_name: &str) -> Option<(Exchange, [Asset; 2])> {
+    let (exch, symbol) = split_db_name(db_name)?;
+    let pair = exch.parse_asset_pair(symbol)?;
+
+    Some((exch, pair))
+}
+
+/// Splits a TectonicDB database name into the exchange and the symbol as the exchange lists it
+/// (i.e. `bitmex_testnet_XBTUSD` into BitMEX and `XBTUSD`). Exchange names can contain underscores
+/// themselves (i.e. `binance_futures`), so the longest name the database starts with wins.
+pub fn split_db_name(db_name: &str) -> Option<(Exchange, &str)> {
+    let mut exchanges: Vec<Exchange> = Exchange::iter().collect();
+    exchanges.sort_by_key(|exch| Reverse(exch.to_string().len()));
+
+    for exch in exchanges {
+        let name = exch.to_string();
+
+        if !db_name.starts_with(&name) || !db_name[name.len()..].starts_with('_') {
+            continue;
         }
-    }).collect::<Vec<_>>()
-}
\ No newline at end of file
+
+        // Testnet data is stored apart from production's (see `bitmex::Environment::suffix`)
+        let symbol = &db_name[name.len() + 1..];
+        let symbol = if symbol.starts_with("testnet_") { &symbol["testnet_".len()..] } else { symbol };
+
+        return if symbol.is_empty() { None } else { Some((exch, symbol)) };
+    }
+
+    None
+}
+
+/// Same as function [`try_get_asset_pair`], but with the added benefit of batch processing. Every pair
+/// gets its own result, so that the pairs available on the exchange can be used even if others aren't.
+pub fn try_get_batch_asset_pairs(assets: &[[Asset; 2]], exch: Exchange) -> Vec<Result<String, AssetError>> {
+    assets.iter()
+        .map(|asset_pair| try_get_asset_pair(asset_pair, exch))
+        .collect()
+}
+
+/// Same as [`try_get_batch_asset_pairs`], but fails on the first pair that isn't available on the
+/// exchange, with the [`AssetError`] naming the asset that couldn't be normalized.
+pub fn get_batch_asset_pairs(assets: &Vec<[Asset; 2]>, exch: Exchange) -> Result<Vec<String>, AssetError> {
+    try_get_batch_asset_pairs(assets, exch).into_iter().collect()
+}
@@ -0,0 +1,640 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read};
+use std::thread;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use crc32fast;
+use flate2::read::DeflateDecoder;
+use redis;
+use serde_json::{self, Value};
+use ws;
+use ws::util::Token;
+use ws::{Error, Handler, Handshake, Message, Sender};
+
+use config::{Config, ConfigError};
+use exchange::{self, Asset, AssetExchange, ConnectionHealth, Exchange, ExchangeError, MarketType};
+use orderbook;
+use storage::{StorageBackend, TectonicBackend};
+
+const EXPIRE: Token = Token(1);
+/// Token for the keepalive ping
+const PING: Token = Token(2);
+
+/// OKX closes connections that haven't sent anything in 30 seconds. We ping a bit more often than that.
+const PING_INTERVAL_MS: u64 = 25_000;
+/// Number of levels per side included in the books checksum
+const CHECKSUM_DEPTH: usize = 25;
+
+/// Exchange related metadata. The fields are used to establish
+/// a successful connection with the exchange via websockets.
+#[derive(Clone)]
+pub struct WSExchange {
+    /// Full URL to connect to. Example: `wss://ws.okx.com:8443/ws/v5/public`
+    pub host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    pub snapshot_received: bool,
+
+    /// Collection metadata
+    pub metadata: MetaData,
+    /// Type of market we're collecting. Deltas are published to the `<exchange>:<market type>` Redis channel
+    pub market_type: MarketType,
+
+    /// Channel names we subscribe to for every instrument (i.e. `books`, `trades`)
+    pub single_channels: Vec<String>,
+
+    /// Connection health, shared with the running websocket handler
+    pub health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    pub storage: Box<dyn StorageBackend>,
+
+    /// Redis URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub redis_url: String,
+    /// Redis client (before connection). Opened from `redis_url` when connecting
+    pub r: redis::Client,
+    /// Redis password: If this is present, it replaces the password in `redis_url` (if any) when connecting
+    pub r_password: Option<String>,
+}
+
+/// Create two identical structs and transfer the data over when we start the websocket.
+pub struct WSExchangeSender {
+    /// Full URL to connect to. Example: `wss://ws.okx.com:8443/ws/v5/public`
+    host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    snapshot_received: bool,
+
+    /// Collection metadata
+    metadata: MetaData,
+
+    /// Channel names we subscribe to for every instrument
+    single_channels: Vec<String>,
+    /// Local copy of every book, keyed by instrument. An instrument is only present once
+    /// we've received its snapshot.
+    books: HashMap<String, LocalBook>,
+
+    /// Connection health
+    health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    storage: Box<dyn StorageBackend>,
+    /// Redis client (used to send deltas as PUBSUB)
+    r: Arc<Mutex<redis::Connection>>,
+
+    /// Websocket sender
+    out: Sender,
+}
+
+/// Meta data for our data source. This is useful for data warehousing and accessing the data.
+/// All types contained within are considered optional. This may be expanded in the future.
+#[derive(Clone)]
+pub struct MetaData {
+    /// Exchange name. We will warehouse the data under this name
+    pub exchange: Arc<String>,
+
+    /// Vector of asset pairs we're going to warehouse
+    pub asset_pair: Option<Vec<[exchange::Asset; 2]>>,
+
+    /// Type of market we're warehousing. Set from `WSExchange::market_type` when connecting
+    pub market_type: Option<MarketType>,
+
+    /// Starting datetime of our data collection
+    start_date: Option<DateTime<Utc>>,
+
+    /// Ending datetime of our data collection
+    end_date: Option<DateTime<Utc>>,
+}
+
+impl WSExchange {
+    /// Health of the connection to the exchange
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+}
+
+impl AssetExchange for WSExchange {
+    fn default_settings() -> Result<Box<Self>, String> {
+        Ok(Box::new(Self {
+            host: "wss://ws.okx.com:8443/ws/v5/public".into(),
+
+            snapshot_received: false,
+
+            metadata: MetaData {
+                exchange: Arc::new("okx".into()),
+                asset_pair: Some(vec![
+                    [Asset::BTC, Asset::USDT],]),
+                market_type: None,
+                start_date: None,
+                end_date: None,
+            },
+            market_type: MarketType::Spot,
+
+            single_channels: vec![
+                "books".into(),
+                "trades".into()],
+
+            health: ConnectionHealth::new(Exchange::OKX),
+
+            storage: Box::new(TectonicBackend::new(None, None, "okx").map_err(|e| format!("Unable to connect to TectonicDB: {}", e))?),
+            redis_url: exchange::DEFAULT_REDIS_URL.into(),
+            r: redis::Client::open(exchange::DEFAULT_REDIS_URL).unwrap(),
+            r_password: None,
+        }))
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        if let Some(pairs) = config.asset_pairs()? {
+            self.metadata.asset_pair = Some(pairs);
+        }
+        if let Some(ref channels) = config.channels.names {
+            self.single_channels = channels.clone();
+        }
+        if let Some(ref url) = config.redis.url {
+            self.redis_url = url.clone();
+        }
+        if config.redis.password.is_some() {
+            self.r_password = config.redis.password.clone();
+        }
+        if let Some(storage) = config.storage("okx")? {
+            self.storage = storage;
+        }
+
+        Ok(())
+    }
+
+    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
+        // The password is sent as part of the URL, so the client authenticates on its own
+        self.r = exchange::redis_client(&self.redis_url, self.r_password.as_ref().map(|password| password.as_str()))?;
+
+        self.r.get_connection()
+    }
+
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    fn start(&self) -> Result<(), ExchangeError> {
+        WSExchange::run_with_settings(Some(self))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
+        // Try to use the settings the user passes before resorting to default settings.
+        let mut settings = match settings {
+            Some(settings) => settings.clone(),
+            None => *WSExchange::default_settings().map_err(ConfigError::Settings)?,
+        };
+
+        let r = Arc::new(Mutex::new(settings.init_redis()?));
+
+        ws::connect(settings.host.clone(), |out| WSExchangeSender {
+            host: settings.host.clone(),
+
+            snapshot_received: settings.snapshot_received.clone(),
+            metadata: MetaData { market_type: Some(settings.market_type), ..settings.metadata.clone() },
+
+            single_channels: settings.single_channels.clone(),
+            books: HashMap::new(),
+
+            health: settings.health.clone(),
+            storage: settings.storage.clone(),
+            r: r.clone(),
+
+            out,
+        })?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SubscribeMessage {
+    op: String,
+    args: Vec<SubscribeArg>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SubscribeArg {
+    channel: String,
+    #[serde(rename = "instId")]
+    inst_id: String,
+}
+
+/// Every message pushed by OKX has this form. Event messages (subscription confirmations and errors)
+/// only carry `event`, whereas channel data carries `arg` and `data`.
+#[derive(Deserialize)]
+struct EventMessage {
+    /// Channel and instrument the data belongs to
+    arg: Option<SubscribeArg>,
+    /// `snapshot` or `update`. Only present on the books channel
+    action: Option<String>,
+    /// Channel data. Its layout depends on the channel
+    data: Option<Value>,
+
+    /// Event type (i.e. `subscribe`, `error`)
+    event: Option<String>,
+    /// Error message
+    msg: Option<String>,
+}
+
+/// Books channel data
+#[derive(Deserialize)]
+struct BookData {
+    /// Ask levels as `[price, size, liquidated orders, order count]`
+    asks: Vec<Vec<String>>,
+    /// Bid levels as `[price, size, liquidated orders, order count]`
+    bids: Vec<Vec<String>>,
+    /// Timestamp in milliseconds
+    ts: String,
+    /// Signed CRC32 of the top 25 levels
+    checksum: Option<i64>,
+    /// Sequence ID of this message
+    #[serde(rename = "seqId")]
+    seq_id: Option<i64>,
+    /// Sequence ID of the previous message. Snapshots carry `-1`
+    #[serde(rename = "prevSeqId")]
+    prev_seq_id: Option<i64>,
+}
+
+/// Trades channel data
+#[derive(Deserialize)]
+struct TradeData {
+    #[serde(rename = "instId")]
+    inst_id: String,
+    #[serde(rename = "tradeId")]
+    trade_id: String,
+    px: String,
+    sz: String,
+    /// Taker side
+    side: String,
+    /// Timestamp in milliseconds
+    ts: String,
+}
+
+/// OKX may compress frames with raw deflate. Compressed frames come in as binary messages,
+/// whereas plain frames are sent as text.
+fn decompress(msg: Message) -> Result<Vec<u8>, io::Error> {
+    match msg {
+        Message::Text(text) => Ok(text.into_bytes()),
+        Message::Binary(data) => {
+            let mut buf = vec![];
+            DeflateDecoder::new(&data[..]).read_to_end(&mut buf)?;
+
+            Ok(buf)
+        }
+    }
+}
+
+/// Converts millisecond timestamp strings sent by OKX to seconds
+fn parse_ts(ts: &str) -> f64 {
+    ts.parse::<f64>()
+        .map(|ts| ts * 0.001f64)
+        .unwrap_or(Utc::now().timestamp_millis() as f64 * 0.001f64)
+}
+
+/// Local copy of a single OKX book, kept with the original price and size strings so that
+/// checksums can be computed exactly as OKX does.
+#[derive(Default)]
+pub struct LocalBook {
+    /// Asks, keyed by the bits of the price. The bit pattern of a positive float sorts
+    /// the same way as the float itself.
+    asks: BTreeMap<u64, (String, String)>,
+    /// Bids, keyed by the bits of the price
+    bids: BTreeMap<u64, (String, String)>,
+    /// `seqId` of the last message applied to the book
+    pub seq_id: i64,
+}
+
+impl LocalBook {
+    /// Inserts or replaces a level on the given side (`orderbook::ASK` or `orderbook::BID`).
+    /// A size of zero removes the level from the book.
+    pub fn apply(&mut self, side: u8, price: &str, size: &str) {
+        let key = match price.parse::<f64>() {
+            Ok(price) => price.to_bits(),
+            Err(_) => return,
+        };
+        let levels = if side == orderbook::BID { &mut self.bids } else { &mut self.asks };
+
+        if size.parse::<f64>().map(|size| size == 0.0).unwrap_or(true) {
+            levels.remove(&key);
+        } else {
+            levels.insert(key, (price.to_string(), size.to_string()));
+        }
+    }
+
+    /// Calculates the checksum of the top 25 levels. Bids and asks are interleaved as
+    /// `bid_price:bid_size:ask_price:ask_size:...`, and the CRC32 is interpreted as a signed integer.
+    pub fn checksum(&self) -> i64 {
+        let mut bids = self.bids.values().rev();
+        let mut asks = self.asks.values();
+        let mut fields: Vec<&str> = Vec::with_capacity(CHECKSUM_DEPTH * 4);
+
+        for _ in 0..CHECKSUM_DEPTH {
+            for level in vec![bids.next(), asks.next()] {
+                if let Some(&(ref price, ref size)) = level {
+                    fields.push(price);
+                    fields.push(size);
+                }
+            }
+        }
+
+        crc32fast::hash(fields.join(":").as_bytes()) as i32 as i64
+    }
+}
+
+impl WSExchangeSender {
+    /// Drops and re-requests the book for a single instrument. OKX sends a fresh snapshot
+    /// once the new subscription is active, which reseeds our local book.
+    fn resubscribe_book(&mut self, channel: String, inst_id: String) -> Result<(), Error> {
+        self.books.remove(&inst_id);
+
+        for op in &["unsubscribe", "subscribe"] {
+            let msg = SubscribeMessage {
+                op: op.to_string(),
+                args: vec![SubscribeArg {
+                    channel: channel.clone(),
+                    inst_id: inst_id.clone(),
+                }],
+            };
+
+            self.out.send(serde_json::to_string(&msg).unwrap())?;
+        }
+
+        Ok(())
+    }
+
+    /// Applies a books message to our local book, checks its sequence and checksum, and publishes the deltas
+    fn on_book(&mut self, arg: SubscribeArg, action: Option<String>, book: BookData) -> Result<(), Error> {
+        let snapshot = action.as_ref().map(|action| action.as_str()) == Some("snapshot");
+
+        if snapshot {
+            self.books.insert(arg.inst_id.clone(), LocalBook::default());
+            self.snapshot_received = true;
+        }
+
+        let ts = parse_ts(&book.ts);
+        let mut deltas: Vec<orderbook::Delta> = Vec::with_capacity(book.asks.len() + book.bids.len());
+
+        {
+            // Discard updates that arrive before the snapshot
+            let local = match self.books.get_mut(&arg.inst_id) {
+                Some(local) => local,
+                None => return Ok(()),
+            };
+
+            // Every update must continue from the message before it
+            if !snapshot && book.prev_seq_id.map(|prev_seq_id| prev_seq_id != local.seq_id).unwrap_or(false) {
+                println!("OKX book for {} is out of sequence. Resubscribing...", arg.inst_id);
+                return self.resubscribe_book(arg.channel, arg.inst_id);
+            }
+
+            // Begin sequence counting at 1 in order to reconstruct a proper sequence count
+            let mut seq = 1;
+
+            for (levels, side) in vec![(&book.asks, orderbook::ASK), (&book.bids, orderbook::BID)] {
+                for level in levels {
+                    let (price, size) = match (level.get(0), level.get(1)) {
+                        (Some(price), Some(size)) => (price, size),
+                        _ => continue,
+                    };
+                    local.apply(side, price, size);
+
+                    let size = size.parse::<f32>().unwrap();
+
+                    deltas.push(orderbook::Delta {
+                        symbol: arg.inst_id.clone(),
+                        price: price.parse::<f32>().unwrap(),
+                        size,
+                        seq,
+                        // Snapshot levels are treated as updates so that they seed the book
+                        event: side ^ if size == 0.0 && !snapshot {
+                            orderbook::REMOVE
+                        } else {
+                            orderbook::UPDATE
+                        },
+                        ts,
+                        received_ts: None,
+                    });
+
+                    seq += 1;
+                }
+            }
+
+            if let Some(seq_id) = book.seq_id {
+                local.seq_id = seq_id;
+            }
+
+            if book.checksum.map(|checksum| checksum != local.checksum()).unwrap_or(false) {
+                println!("OKX checksum mismatch for {}. Resubscribing...", arg.inst_id);
+                return self.resubscribe_book(arg.channel, arg.inst_id);
+            }
+        }
+
+        // Lock the connection until we are able to aquire it
+        if !deltas.is_empty() {
+            exchange::record_symbol_messages(&self.health, deltas.iter().map(|delta| delta.symbol.as_str()));
+            exchange::publish_deltas(&*self.r.lock().unwrap(), &exchange::market_channel(&self.metadata.exchange, self.metadata.market_type), &deltas, &self.health);
+        }
+
+        Ok(())
+    }
+}
+
+impl Handler for WSExchangeSender {
+    fn on_open(&mut self, _: Handshake) -> Result<(), Error> {
+        self.health.track_connection(&self.out);
+
+        // Measure the round-trip time of the new connection
+        self.health.ping_latency(&self.out)?;
+
+        // Set a timeout for 5 seconds of inactivity
+        // Issue: currently, this reruns every five seconds. Comment out while we fix.
+        //self.out.timeout(5_000, EXPIRE).unwrap();
+        self.out.timeout(PING_INTERVAL_MS, PING)?;
+
+        let mut msg = SubscribeMessage {
+            op: "subscribe".into(),
+            args: vec![],
+        };
+
+        for pair in self.metadata.asset_pair.as_ref().expect("No asset pairs passed to OKX structure") {
+            let normalized_pair = match exchange::get_asset_pair(pair, Exchange::OKX) {
+                Ok(normalized_pair) => normalized_pair,
+                Err(e) => {
+                    println!("Skipping OKX subscription: {}", e);
+                    continue;
+                }
+            };
+            let db_name = format!("{}_{}", self.metadata.exchange.deref(), normalized_pair);
+
+            // Create the database if it doesn't exist yet. This avoids many issues
+            // relating to inserting to a non-existant database.
+            self.storage.create(&db_name)?;
+
+            for channel in &self.single_channels {
+                msg.args.push(SubscribeArg {
+                    channel: channel.to_string(),
+                    inst_id: normalized_pair.clone(),
+                });
+            }
+        }
+
+        println!("Sending message {}", serde_json::to_string(&msg).unwrap());
+        self.out.send(serde_json::to_string(&msg).unwrap())
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<(), Error> {
+        self.health.record_message();
+
+        let data = match decompress(msg) {
+            Ok(data) => data,
+            Err(e) => {
+                println!("Failed to decompress OKX message: {}", e);
+                return Ok(());
+            }
+        };
+
+        // Reply to our keepalive ping
+        if data == b"pong" {
+            return Ok(());
+        }
+
+        let (arg, action, data) = match serde_json::from_slice::<EventMessage>(&data) {
+            Ok(EventMessage { event: Some(event), msg, .. }) => {
+                if event == "error" {
+                    println!("OKX error: {}", msg.unwrap_or_default());
+                }
+                return Ok(());
+            },
+            Ok(EventMessage { arg: Some(arg), action, data: Some(data), .. }) => (arg, action, data),
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            }
+        };
+
+        if arg.channel.starts_with("books") {
+            // Book messages are handled on the socket thread, since both the sequence and
+            // checksum checks depend on the order in which they arrive.
+            let book = match serde_json::from_value::<Vec<BookData>>(data) {
+                Ok(mut books) => match books.pop() {
+                    Some(book) => book,
+                    None => return Ok(()),
+                },
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return Ok(());
+                }
+            };
+
+            return self.on_book(arg, action, book);
+        }
+
+        if arg.channel != "trades" {
+            return Ok(());
+        }
+
+        let redis_ref = self.r.clone();
+        let health = self.health.clone();
+        let channel = exchange::market_channel(&self.metadata.exchange, self.metadata.market_type);
+
+        thread::spawn(move || {
+            let trades: Vec<orderbook::Trade> = match serde_json::from_value::<Vec<TradeData>>(data) {
+                Ok(trades) => trades.into_iter()
+                    .map(|trade| orderbook::Trade {
+                        symbol: trade.inst_id,
+                        price: trade.px.parse::<f64>().unwrap(),
+                        size: trade.sz.parse::<f64>().unwrap(),
+                        side: if trade.side == "buy" {
+                            orderbook::TradeSide::Buy
+                        } else {
+                            orderbook::TradeSide::Sell
+                        },
+                        ts: parse_ts(&trade.ts),
+                        exchange: Exchange::OKX,
+                        trade_id: Some(trade.trade_id),
+                        received_ts: None,
+                    })
+                    .collect(),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return;
+                }
+            };
+
+            exchange::record_symbol_messages(&health, trades.iter().map(|trade| trade.symbol.as_str()));
+            exchange::publish_trades(&*redis_ref.lock().unwrap(), &format!("{}:trades", channel), &trades, &health);
+        });
+
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> Result<Option<ws::Frame>, Error> {
+        self.health.on_frame(frame)
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut *self.storage);
+            return;
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("OKX Socket is closing. Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            single_channels: self.single_channels.clone(),
+            books: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+    }
+
+    fn on_timeout(&mut self, event: Token) -> Result<(), ws::Error> {
+        if self.health.is_stopped() {
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
+        if event == PING {
+            self.out.send("ping")?;
+            return self.out.timeout(PING_INTERVAL_MS, PING);
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("OKX Socket timed out (5s of inactivity). Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            single_channels: self.single_channels.clone(),
+            books: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+
+        Ok(())
+    }
+}
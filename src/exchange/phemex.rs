@@ -0,0 +1,550 @@
+use std::collections::HashMap;
+use std::thread;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use redis;
+use serde_json;
+use ws;
+use ws::util::Token;
+use ws::{Error, Handler, Handshake, Message, Sender};
+
+use config::{Config, ConfigError};
+use exchange::{self, Asset, AssetExchange, ConnectionHealth, Exchange, ExchangeError, MarketType};
+use orderbook;
+use storage::{StorageBackend, TectonicBackend};
+
+const EXPIRE: Token = Token(1);
+const PING: Token = Token(2);
+
+/// Phemex drops connections that haven't sent a `server.ping` in 30 seconds
+const PING_INTERVAL_MS: u64 = 5_000;
+
+/// Phemex sends prices as scaled integers (`priceEp`), which are the price multiplied by this factor
+pub const PRICE_SCALE: f64 = 10_000.0;
+
+/// Exchange related metadata. The fields are used to establish
+/// a successful connection with the exchange via websockets.
+#[derive(Clone)]
+pub struct WSExchange {
+    /// Full URL to connect to. Example: `wss://phemex.com/ws`
+    pub host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    pub snapshot_received: bool,
+
+    /// Collection metadata
+    pub metadata: MetaData,
+    /// Type of market we're collecting. Deltas are published to the `<exchange>:<market type>` Redis channel
+    pub market_type: MarketType,
+
+    /// Subscription methods we call for every symbol (i.e. `orderbook.subscribe`, `trade.subscribe`)
+    pub single_channels: Vec<String>,
+
+    /// Connection health, shared with the running websocket handler
+    pub health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    pub storage: Box<dyn StorageBackend>,
+
+    /// Redis URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub redis_url: String,
+    /// Redis client (before connection). Opened from `redis_url` when connecting
+    pub r: redis::Client,
+    /// Redis password: If this is present, it replaces the password in `redis_url` (if any) when connecting
+    pub r_password: Option<String>,
+}
+
+/// Create two identical structs and transfer the data over when we start the websocket.
+pub struct WSExchangeSender {
+    /// Full URL to connect to. Example: `wss://phemex.com/ws`
+    host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    snapshot_received: bool,
+
+    /// Collection metadata
+    metadata: MetaData,
+
+    /// Subscription methods we call for every symbol
+    single_channels: Vec<String>,
+    /// Sequence of the last book message applied for every symbol (i.e. `BTCUSD`)
+    sequences: HashMap<String, u64>,
+    /// ID of the next request sent to Phemex
+    request_id: u64,
+
+    /// Connection health
+    health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    storage: Box<dyn StorageBackend>,
+    /// Redis client (used to send deltas as PUBSUB)
+    r: Arc<Mutex<redis::Connection>>,
+
+    /// Websocket sender
+    out: Sender,
+}
+
+/// Meta data for our data source. This is useful for data warehousing and accessing the data.
+/// All types contained within are considered optional. This may be expanded in the future.
+#[derive(Clone)]
+pub struct MetaData {
+    /// Exchange name. We will warehouse the data under this name
+    pub exchange: Arc<String>,
+
+    /// Vector of asset pairs we're going to warehouse
+    pub asset_pair: Option<Vec<[exchange::Asset; 2]>>,
+
+    /// Type of market we're warehousing. Set from `WSExchange::market_type` when connecting
+    pub market_type: Option<MarketType>,
+
+    /// Starting datetime of our data collection
+    start_date: Option<DateTime<Utc>>,
+
+    /// Ending datetime of our data collection
+    end_date: Option<DateTime<Utc>>,
+}
+
+impl WSExchange {
+    /// Health of the connection to the exchange
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+}
+
+impl AssetExchange for WSExchange {
+    fn default_settings() -> Result<Box<Self>, String> {
+        Ok(Box::new(Self {
+            host: "wss://phemex.com/ws".into(),
+
+            snapshot_received: false,
+
+            metadata: MetaData {
+                exchange: Arc::new("phemex".into()),
+                asset_pair: Some(vec![
+                    [Asset::BTC, Asset::USD],]),
+                market_type: None,
+                start_date: None,
+                end_date: None,
+            },
+            market_type: MarketType::InversePerpetual,
+
+            single_channels: vec![
+                "orderbook.subscribe".into(),
+                "trade.subscribe".into()],
+
+            health: ConnectionHealth::new(Exchange::Phemex),
+
+            storage: Box::new(TectonicBackend::new(None, None, "phemex").map_err(|e| format!("Unable to connect to TectonicDB: {}", e))?),
+            redis_url: exchange::DEFAULT_REDIS_URL.into(),
+            r: redis::Client::open(exchange::DEFAULT_REDIS_URL).unwrap(),
+            r_password: None,
+        }))
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        if let Some(pairs) = config.asset_pairs()? {
+            self.metadata.asset_pair = Some(pairs);
+        }
+        if let Some(ref channels) = config.channels.names {
+            self.single_channels = channels.clone();
+        }
+        if let Some(ref url) = config.redis.url {
+            self.redis_url = url.clone();
+        }
+        if config.redis.password.is_some() {
+            self.r_password = config.redis.password.clone();
+        }
+        if let Some(storage) = config.storage("phemex")? {
+            self.storage = storage;
+        }
+
+        Ok(())
+    }
+
+    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
+        // The password is sent as part of the URL, so the client authenticates on its own
+        self.r = exchange::redis_client(&self.redis_url, self.r_password.as_ref().map(|password| password.as_str()))?;
+
+        self.r.get_connection()
+    }
+
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    fn start(&self) -> Result<(), ExchangeError> {
+        WSExchange::run_with_settings(Some(self))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
+        // Try to use the settings the user passes before resorting to default settings.
+        let mut settings = match settings {
+            Some(settings) => settings.clone(),
+            None => *WSExchange::default_settings().map_err(ConfigError::Settings)?,
+        };
+
+        let r = Arc::new(Mutex::new(settings.init_redis()?));
+
+        ws::connect(settings.host.clone(), |out| WSExchangeSender {
+            host: settings.host.clone(),
+
+            snapshot_received: settings.snapshot_received.clone(),
+            metadata: MetaData { market_type: Some(settings.market_type), ..settings.metadata.clone() },
+
+            single_channels: settings.single_channels.clone(),
+            sequences: HashMap::new(),
+            request_id: 1,
+
+            health: settings.health.clone(),
+            storage: settings.storage.clone(),
+            r: r.clone(),
+
+            out,
+        })?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct RequestMessage {
+    id: u64,
+    method: String,
+    params: Vec<String>,
+}
+
+/// Response to one of our requests (i.e. `{"error": null, "id": 0, "result": "pong"}`)
+#[derive(Deserialize)]
+struct ResponseMessage {
+    error: Option<serde_json::Value>,
+}
+
+/// Book levels as `[priceEp, size]`
+#[derive(Clone, Debug, Deserialize)]
+pub struct BookLevels {
+    /// Ask levels
+    #[serde(default)]
+    pub asks: Vec<[i64; 2]>,
+    /// Bid levels
+    #[serde(default)]
+    pub bids: Vec<[i64; 2]>,
+}
+
+/// Book message pushed after calling `orderbook.subscribe`
+#[derive(Clone, Debug, Deserialize)]
+pub struct BookMessage {
+    /// Changed levels, or the whole book if this is a snapshot
+    pub book: BookLevels,
+    /// Sequence of the message. Incremental messages must follow the last one we've applied
+    pub sequence: u64,
+    /// Symbol (i.e. `BTCUSD`)
+    pub symbol: String,
+    /// Nanoseconds since the UNIX epoch
+    pub timestamp: u64,
+    /// `snapshot` or `incremental`
+    #[serde(rename = "type")]
+    pub type_: String,
+}
+
+/// Trade message pushed after calling `trade.subscribe`
+#[derive(Deserialize)]
+struct TradeMessage {
+    symbol: String,
+    /// Trades as `[timestamp (ns), side, priceEp, size]`
+    trades: Vec<(u64, String, i64, i64)>,
+    /// `snapshot` or `incremental`
+    #[serde(rename = "type")]
+    type_: String,
+}
+
+/// Converts a scaled integer price (`priceEp`) back to the price it represents
+pub fn unscale_price(price_ep: i64) -> f32 {
+    (price_ep as f64 / PRICE_SCALE) as f32
+}
+
+/// Converts a book message into deltas. A size of zero removes the level.
+pub fn book_to_deltas(book: &BookMessage) -> Vec<orderbook::Delta> {
+    let ts = book.timestamp as f64 * 0.000_000_001f64;
+    let mut deltas: Vec<orderbook::Delta> = Vec::with_capacity(book.book.asks.len() + book.book.bids.len());
+
+    // Begin sequence counting at 1 in order to reconstruct a proper sequence count
+    let mut seq = 1;
+
+    for (levels, side) in vec![(&book.book.asks, orderbook::ASK), (&book.book.bids, orderbook::BID)] {
+        for level in levels {
+            let size = level[1] as f32;
+
+            deltas.push(orderbook::Delta {
+                symbol: book.symbol.clone(),
+                price: unscale_price(level[0]),
+                size,
+                seq,
+                event: side ^ if size == 0.0 {
+                    orderbook::REMOVE
+                } else {
+                    orderbook::UPDATE
+                },
+                ts,
+                received_ts: None,
+            });
+
+            seq += 1;
+        }
+    }
+
+    deltas
+}
+
+impl WSExchangeSender {
+    /// Sends a JSON-RPC request
+    fn request(&mut self, method: &str, params: Vec<String>) -> Result<(), Error> {
+        let msg = RequestMessage {
+            id: self.request_id,
+            method: method.into(),
+            params,
+        };
+        self.request_id += 1;
+
+        self.out.send(serde_json::to_string(&msg).unwrap())
+    }
+
+    /// Calls every subscription method for every symbol we collect
+    fn subscribe(&mut self, methods: &[String]) -> Result<(), Error> {
+        let pairs = self.metadata.asset_pair.clone().expect("No asset pairs passed to Phemex structure");
+
+        for pair in &pairs {
+            let symbol = match exchange::get_asset_pair(pair, Exchange::Phemex) {
+                Ok(symbol) => symbol,
+                Err(e) => {
+                    println!("Skipping Phemex subscription: {}", e);
+                    continue;
+                }
+            };
+
+            // Phemex only takes a single symbol per subscription
+            for method in methods {
+                println!("Sending {} for {}", method, symbol);
+                self.request(method, vec![symbol.clone()])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resubscribes to every book, so that Phemex sends us fresh snapshots.
+    /// `orderbook.unsubscribe` can't target a single symbol, so all of them are resubscribed.
+    fn resubscribe_books(&mut self) -> Result<(), Error> {
+        self.sequences.clear();
+
+        self.request("orderbook.unsubscribe", vec![])?;
+        self.subscribe(&["orderbook.subscribe".to_string()])
+    }
+
+    /// Applies a book snapshot or update. Snapshots reset the sequence, whereas updates
+    /// must follow the last message we've applied.
+    fn on_book(&mut self, book: BookMessage) -> Result<(), Error> {
+        if book.type_ == "snapshot" {
+            self.snapshot_received = true;
+        } else {
+            match self.sequences.get(&book.symbol) {
+                Some(&last) if exchange::is_sequence_gap(last, book.sequence) => {
+                    println!("Phemex book for {} is out of sequence. Resubscribing...", book.symbol);
+                    return self.resubscribe_books();
+                },
+                // Stale or repeated message
+                Some(&last) if book.sequence <= last => return Ok(()),
+                Some(_) => (),
+                // We're still waiting on the snapshot
+                None => return Ok(()),
+            }
+        }
+
+        self.sequences.insert(book.symbol.clone(), book.sequence);
+
+        let deltas = book_to_deltas(&book);
+
+        if deltas.is_empty() {
+            return Ok(());
+        }
+
+        exchange::record_symbol_messages(&self.health, deltas.iter().map(|delta| delta.symbol.as_str()));
+        // Lock the connection until we are able to aquire it
+        exchange::publish_deltas(&*self.r.lock().unwrap(), &exchange::market_channel(&self.metadata.exchange, self.metadata.market_type), &deltas, &self.health);
+
+        Ok(())
+    }
+}
+
+impl Handler for WSExchangeSender {
+    fn on_open(&mut self, _: Handshake) -> Result<(), Error> {
+        self.health.track_connection(&self.out);
+
+        // Measure the round-trip time of the new connection
+        self.health.ping_latency(&self.out)?;
+
+        // Set a timeout for 5 seconds of inactivity
+        // Issue: currently, this reruns every five seconds. Comment out while we fix.
+        //self.out.timeout(5_000, EXPIRE).unwrap();
+        self.out.timeout(PING_INTERVAL_MS, PING)?;
+
+        for pair in self.metadata.asset_pair.clone().expect("No asset pairs passed to Phemex structure") {
+            if let Ok(symbol) = exchange::get_asset_pair(&pair, Exchange::Phemex) {
+                // Create the database if it doesn't exist yet. This avoids many issues
+                // relating to inserting to a non-existant database.
+                self.storage.create(&format!("{}_{}", self.metadata.exchange.deref(), symbol))?;
+            }
+        }
+
+        let methods = self.single_channels.clone();
+        self.subscribe(&methods)
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<(), Error> {
+        self.health.record_message();
+
+        let message = match serde_json::from_slice::<serde_json::Value>(&msg.into_data()) {
+            Ok(message) => message,
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            }
+        };
+
+        if message.get("book").is_some() {
+            // Sequences are checked on the socket thread, since every update has to follow the one before it
+            return match serde_json::from_value::<BookMessage>(message) {
+                Ok(book) => self.on_book(book),
+                Err(e) => {
+                    println!("Error: {}", e);
+                    Ok(())
+                }
+            };
+        }
+
+        if message.get("trades").is_none() {
+            // Responses to our requests, including the pongs
+            if let Ok(response) = serde_json::from_value::<ResponseMessage>(message) {
+                if let Some(error) = response.error.filter(|error| !error.is_null()) {
+                    println!("Phemex error: {}", error);
+                }
+            }
+
+            return Ok(());
+        }
+
+        let redis_ref = self.r.clone();
+        let health = self.health.clone();
+        let channel = exchange::market_channel(&self.metadata.exchange, self.metadata.market_type);
+
+        thread::spawn(move || {
+            let message = match serde_json::from_value::<TradeMessage>(message) {
+                Ok(message) => message,
+                Err(e) => {
+                    println!("Error: {}", e);
+                    return;
+                }
+            };
+
+            // The snapshot only contains historical trades
+            if message.type_ != "incremental" {
+                return;
+            }
+
+            let trades: Vec<orderbook::Trade> = message.trades.iter()
+                .map(|&(ts, ref side, price_ep, size)| orderbook::Trade {
+                    symbol: message.symbol.clone(),
+                    price: unscale_price(price_ep) as f64,
+                    size: size as f64,
+                    side: if side == "Buy" {
+                        orderbook::TradeSide::Buy
+                    } else {
+                        orderbook::TradeSide::Sell
+                    },
+                    ts: ts as f64 * 0.000_000_001f64,
+                    exchange: Exchange::Phemex,
+                    trade_id: None,
+                    received_ts: None,
+                })
+                .collect();
+
+            if trades.is_empty() {
+                return;
+            }
+
+            exchange::record_symbol_messages(&health, trades.iter().map(|trade| trade.symbol.as_str()));
+            exchange::publish_trades(&*redis_ref.lock().unwrap(), &format!("{}:trades", channel), &trades, &health);
+        });
+
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> Result<Option<ws::Frame>, Error> {
+        self.health.on_frame(frame)
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut *self.storage);
+            return;
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Phemex Socket is closing. Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            single_channels: self.single_channels.clone(),
+            sequences: HashMap::new(),
+            request_id: 1,
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+    }
+
+    fn on_timeout(&mut self, event: Token) -> Result<(), ws::Error> {
+        if self.health.is_stopped() {
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
+        if event == PING {
+            self.request("server.ping", vec![])?;
+            return self.out.timeout(PING_INTERVAL_MS, PING);
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Phemex Socket timed out (5s of inactivity). Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            single_channels: self.single_channels.clone(),
+            sequences: HashMap::new(),
+            request_id: 1,
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,520 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use redis;
+use serde_json::{self, Value};
+use ws;
+use ws::util::Token;
+use ws::{Error, Handler, Handshake, Message, Sender};
+
+use config::{Config, ConfigError};
+use exchange::{self, Asset, AssetExchange, ConnectionHealth, Exchange, ExchangeError, MarketType};
+use orderbook;
+use storage::{StorageBackend, TectonicBackend};
+
+const EXPIRE: Token = Token(1);
+
+/// Channel ID Poloniex uses for heartbeats
+const HEARTBEAT: u64 = 1010;
+
+/// Exchange related metadata. The fields are used to establish
+/// a successful connection with the exchange via websockets.
+#[derive(Clone)]
+pub struct WSExchange {
+    /// Full URL to connect to. Example: `wss://api2.poloniex.com`
+    pub host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    pub snapshot_received: bool,
+
+    /// Collection metadata
+    pub metadata: MetaData,
+    /// Type of market we're collecting. Deltas are published to the `<exchange>:<market type>` Redis channel
+    pub market_type: MarketType,
+
+    /// Resubscribe to a pair whenever we've missed one of its messages
+    pub gap_detection: bool,
+
+    /// Connection health, shared with the running websocket handler
+    pub health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    pub storage: Box<dyn StorageBackend>,
+
+    /// Redis URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub redis_url: String,
+    /// Redis client (before connection). Opened from `redis_url` when connecting
+    pub r: redis::Client,
+    /// Redis password: If this is present, it replaces the password in `redis_url` (if any) when connecting
+    pub r_password: Option<String>,
+}
+
+/// Create two identical structs and transfer the data over when we start the websocket.
+pub struct WSExchangeSender {
+    /// Full URL to connect to. Example: `wss://api2.poloniex.com`
+    host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    snapshot_received: bool,
+
+    /// Collection metadata
+    metadata: MetaData,
+
+    /// Poloniex multiplexes every pair over the same socket. Updates only carry a channel ID,
+    /// so we map the IDs to their symbols as the initial snapshots come in.
+    channel_symbols: HashMap<u64, String>,
+    /// Resubscribe to a pair whenever we've missed one of its messages
+    gap_detection: bool,
+    /// Sequence number of the last message received on every channel ID
+    last_seq: HashMap<u64, u64>,
+
+    /// Connection health
+    health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    storage: Box<dyn StorageBackend>,
+    /// Redis client (used to send deltas as PUBSUB)
+    r: Arc<Mutex<redis::Connection>>,
+
+    /// Websocket sender
+    out: Sender,
+}
+
+/// Meta data for our data source. This is useful for data warehousing and accessing the data.
+/// All types contained within are considered optional. This may be expanded in the future.
+#[derive(Clone)]
+pub struct MetaData {
+    /// Exchange name. We will warehouse the data under this name
+    pub exchange: Arc<String>,
+
+    /// Vector of asset pairs we're going to warehouse
+    pub asset_pair: Option<Vec<[exchange::Asset; 2]>>,
+
+    /// Type of market we're warehousing. Set from `WSExchange::market_type` when connecting
+    pub market_type: Option<MarketType>,
+
+    /// Starting datetime of our data collection
+    start_date: Option<DateTime<Utc>>,
+
+    /// Ending datetime of our data collection
+    end_date: Option<DateTime<Utc>>,
+}
+
+impl WSExchange {
+    /// Health of the connection to the exchange
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+}
+
+impl AssetExchange for WSExchange {
+    fn default_settings() -> Result<Box<Self>, String> {
+        Ok(Box::new(Self {
+            host: "wss://api2.poloniex.com".into(),
+
+            snapshot_received: false,
+
+            metadata: MetaData {
+                exchange: Arc::new("poloniex".into()),
+                asset_pair: Some(vec![
+                    [Asset::BTC, Asset::USDT],]),
+                market_type: None,
+                start_date: None,
+                end_date: None,
+            },
+            market_type: MarketType::Spot,
+
+            gap_detection: true,
+
+            health: ConnectionHealth::new(Exchange::Poloniex),
+
+            storage: Box::new(TectonicBackend::new(None, None, "poloniex").map_err(|e| format!("Unable to connect to TectonicDB: {}", e))?),
+            redis_url: exchange::DEFAULT_REDIS_URL.into(),
+            r: redis::Client::open(exchange::DEFAULT_REDIS_URL).unwrap(),
+            r_password: None,
+        }))
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        if let Some(pairs) = config.asset_pairs()? {
+            self.metadata.asset_pair = Some(pairs);
+        }
+        if let Some(ref url) = config.redis.url {
+            self.redis_url = url.clone();
+        }
+        if config.redis.password.is_some() {
+            self.r_password = config.redis.password.clone();
+        }
+        if let Some(storage) = config.storage("poloniex")? {
+            self.storage = storage;
+        }
+
+        Ok(())
+    }
+
+    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
+        // The password is sent as part of the URL, so the client authenticates on its own
+        self.r = exchange::redis_client(&self.redis_url, self.r_password.as_ref().map(|password| password.as_str()))?;
+
+        self.r.get_connection()
+    }
+
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    fn start(&self) -> Result<(), ExchangeError> {
+        WSExchange::run_with_settings(Some(self))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
+        // Try to use the settings the user passes before resorting to default settings.
+        let mut settings = match settings {
+            Some(settings) => settings.clone(),
+            None => *WSExchange::default_settings().map_err(ConfigError::Settings)?,
+        };
+
+        let r = Arc::new(Mutex::new(settings.init_redis()?));
+
+        ws::connect(settings.host.clone(), |out| WSExchangeSender {
+            host: settings.host.clone(),
+
+            snapshot_received: settings.snapshot_received.clone(),
+            metadata: MetaData { market_type: Some(settings.market_type), ..settings.metadata.clone() },
+
+            channel_symbols: HashMap::new(),
+            gap_detection: settings.gap_detection,
+            last_seq: HashMap::new(),
+
+            health: settings.health.clone(),
+            storage: settings.storage.clone(),
+            r: r.clone(),
+
+            out,
+        })?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SubscribeMessage {
+    command: String,
+    channel: String,
+}
+
+/// The websocket API names pairs with an underscore (i.e. `USDT_BTC`), whereas we store
+/// them with the separator returned by `asset_separator` (i.e. `USDT-BTC`).
+fn currency_pair(symbol: &str) -> String {
+    symbol.replace("-", "_")
+}
+
+/// Converts the levels of one side of an initial orderbook snapshot to deltas. Levels are sent
+/// as a `{"<price>": "<size>"}` map.
+fn snapshot_levels(symbol: &str, levels: &Value, side: u8, seq: u32, ts: f64) -> Vec<orderbook::Delta> {
+    levels.as_object()
+        .map(|levels| levels.iter()
+            .filter_map(|(price, size)| Some(orderbook::Delta {
+                symbol: symbol.into(),
+                price: price.parse::<f32>().ok()?,
+                size: size.as_str()?.parse::<f32>().ok()?,
+                seq,
+                event: side ^ orderbook::UPDATE,
+                ts,
+                received_ts: None,
+            }))
+            .collect())
+        .unwrap_or(vec![])
+}
+
+/// Parses the updates of a message received on `channel_id` into deltas and trades. Updates of a channel
+/// are skipped until its initial snapshot, which maps the channel to its symbol in `channel_symbols`.
+pub fn parse_updates(
+    updates: &[Value],
+    channel_id: u64,
+    seq: u32,
+    ts: f64,
+    channel_symbols: &mut HashMap<u64, String>,
+) -> (Vec<orderbook::Delta>, Vec<orderbook::Trade>) {
+    let mut deltas: Vec<orderbook::Delta> = Vec::with_capacity(updates.len());
+    let mut trades: Vec<orderbook::Trade> = vec![];
+
+    for update in updates {
+        match update.get(0).and_then(|kind| kind.as_str()) {
+            // Initial orderbook snapshot. The book is sent as `[asks, bids]`.
+            Some("i") => {
+                let symbol = match update[1]["currencyPair"].as_str() {
+                    Some(pair) => pair.replace("_", "-"),
+                    None => continue,
+                };
+
+                deltas.extend(snapshot_levels(&symbol, &update[1]["orderBook"][0], orderbook::ASK, seq, ts));
+                deltas.extend(snapshot_levels(&symbol, &update[1]["orderBook"][1], orderbook::BID, seq, ts));
+
+                channel_symbols.insert(channel_id, symbol);
+            },
+            // Orderbook update: `["o", <1 for bid, 0 for ask>, price, size]`
+            Some("o") => {
+                let symbol = match channel_symbols.get(&channel_id) {
+                    Some(symbol) => symbol.clone(),
+                    None => continue,
+                };
+                let size = update[3].as_str().and_then(|size| size.parse::<f32>().ok()).unwrap_or(0.0);
+
+                deltas.push(orderbook::Delta {
+                    symbol,
+                    price: match update[2].as_str().and_then(|price| price.parse::<f32>().ok()) {
+                        Some(price) => price,
+                        None => continue,
+                    },
+                    size,
+                    seq,
+                    event: if update[1] == 1 {
+                        orderbook::BID
+                    } else {
+                        orderbook::ASK
+                    } ^ if size == 0.0 {
+                        orderbook::REMOVE
+                    } else {
+                        orderbook::UPDATE
+                    },
+                    ts,
+                    received_ts: None,
+                });
+            },
+            // Trade: `["t", tradeID, <1 for buy, 0 for sell>, price, size, timestamp]`
+            Some("t") => {
+                let symbol = match channel_symbols.get(&channel_id) {
+                    Some(symbol) => symbol.clone(),
+                    None => continue,
+                };
+                let price = update[3].as_str().and_then(|price| price.parse::<f64>().ok());
+                let size = update[4].as_str().and_then(|size| size.parse::<f64>().ok());
+
+                if let (Some(price), Some(size)) = (price, size) {
+                    trades.push(orderbook::Trade {
+                        symbol,
+                        price,
+                        size,
+                        side: if update[2] == 1 {
+                            orderbook::TradeSide::Buy
+                        } else {
+                            orderbook::TradeSide::Sell
+                        },
+                        ts: update[5].as_f64().unwrap_or(ts),
+                        exchange: Exchange::Poloniex,
+                        trade_id: update[1].as_str().map(|id| id.into()),
+                        received_ts: None,
+                    });
+                }
+            },
+            _ => (),
+        }
+    }
+
+    (deltas, trades)
+}
+
+impl WSExchangeSender {
+    /// Unsubscribes from a pair and subscribes to it again, so that Poloniex sends a new snapshot
+    fn resubscribe(&mut self, channel_id: u64) -> Result<(), Error> {
+        self.last_seq.remove(&channel_id);
+
+        let symbol = match self.channel_symbols.remove(&channel_id) {
+            Some(symbol) => symbol,
+            None => return Ok(()),
+        };
+
+        for command in &["unsubscribe", "subscribe"] {
+            let msg = SubscribeMessage {
+                command: command.to_string(),
+                channel: currency_pair(&symbol),
+            };
+
+            println!("Sending message {}", serde_json::to_string(&msg).unwrap());
+            self.out.send(serde_json::to_string(&msg).unwrap())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Handler for WSExchangeSender {
+    fn on_open(&mut self, _: Handshake) -> Result<(), Error> {
+        self.health.track_connection(&self.out);
+
+        // Measure the round-trip time of the new connection
+        self.health.ping_latency(&self.out)?;
+
+        // Set a timeout for 5 seconds of inactivity
+        // Issue: currently, this reruns every five seconds. Comment out while we fix.
+        //self.out.timeout(5_000, EXPIRE).unwrap();
+
+        for pair in self.metadata.asset_pair.as_ref().expect("No asset pairs passed to Poloniex structure") {
+            let normalized_pair = match exchange::get_asset_pair(pair, Exchange::Poloniex) {
+                Ok(normalized_pair) => normalized_pair,
+                Err(e) => {
+                    println!("Skipping Poloniex subscription: {}", e);
+                    continue;
+                }
+            };
+            let db_name = format!("{}_{}", self.metadata.exchange.deref(), normalized_pair);
+
+            // Create the database if it doesn't exist yet. This avoids many issues
+            // relating to inserting to a non-existant database.
+            self.storage.create(&db_name)?;
+
+            // The price aggregated book channel also carries the trades for the pair
+            let msg = SubscribeMessage {
+                command: "subscribe".into(),
+                channel: currency_pair(&normalized_pair),
+            };
+
+            println!("Sending message {}", serde_json::to_string(&msg).unwrap());
+            self.out.send(serde_json::to_string(&msg).unwrap())?;
+        }
+
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<(), Error> {
+        self.health.record_message();
+
+        // Unlike other exchanges, we process Poloniex messages in order on the socket thread.
+        // The channel ID to symbol mapping is only sent with the initial snapshot, so every
+        // update that follows depends on the snapshot having been processed first.
+        let ts = Utc::now().timestamp_millis() as f64 * 0.001f64;
+
+        let message = match serde_json::from_slice::<Value>(&msg.into_data()) {
+            Ok(Value::Array(message)) => message,
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(())
+            }
+        };
+
+        // Messages come in the form of `[channelID, sequence, [updates...]]`
+        let channel_id = match message.get(0).and_then(|id| id.as_u64()) {
+            Some(HEARTBEAT) | None => return Ok(()),
+            Some(channel_id) => channel_id,
+        };
+        let seq = message.get(1).and_then(|seq| seq.as_u64()).unwrap_or(0);
+        let updates = match message.get(2).and_then(|updates| updates.as_array()) {
+            Some(updates) => updates,
+            // Subscription acknowledgements don't carry any updates
+            None => return Ok(()),
+        };
+
+        // Every channel numbers its messages. Missing one means the book is out of date,
+        // so we drop the message and start over from a new snapshot.
+        if self.gap_detection {
+            if let Some(last_seq) = self.last_seq.insert(channel_id, seq) {
+                if exchange::is_sequence_gap(last_seq, seq) {
+                    println!("Error: Poloniex channel {} skipped from sequence {} to {}. Resubscribing...", channel_id, last_seq, seq);
+                    self.snapshot_received = false;
+
+                    return self.resubscribe(channel_id);
+                }
+            }
+        }
+        let seq = seq as u32;
+
+        let (deltas, trades) = parse_updates(updates, channel_id, seq, ts, &mut self.channel_symbols);
+        if self.channel_symbols.contains_key(&channel_id) {
+            self.snapshot_received = true;
+        }
+
+        // Deltas and trades are stored as they come in, as well as published
+        if !deltas.is_empty() {
+            if let Err(e) = self.storage.insert(&deltas) {
+                println!("Error: Failed to store Poloniex deltas: {}", e);
+            }
+        }
+        if !trades.is_empty() {
+            let trade_deltas: Vec<orderbook::Delta> = trades.iter().map(orderbook::Delta::from).collect();
+
+            if let Err(e) = self.storage.insert(&trade_deltas) {
+                println!("Error: Failed to store Poloniex trades: {}", e);
+            }
+        }
+
+        // Lock the connection until we are able to aquire it
+        if !deltas.is_empty() {
+            exchange::record_symbol_messages(&self.health, deltas.iter().map(|delta| delta.symbol.as_str()));
+            exchange::publish_deltas(&*self.r.lock().unwrap(), &exchange::market_channel(&self.metadata.exchange, self.metadata.market_type), &deltas, &self.health);
+        }
+        if !trades.is_empty() {
+            exchange::record_symbol_messages(&self.health, trades.iter().map(|trade| trade.symbol.as_str()));
+            exchange::publish_trades(&*self.r.lock().unwrap(), &format!("{}:trades", exchange::market_channel(&self.metadata.exchange, self.metadata.market_type)), &trades, &self.health);
+        }
+
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> Result<Option<ws::Frame>, Error> {
+        self.health.on_frame(frame)
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut *self.storage);
+            return;
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Poloniex Socket is closing. Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            // Channel IDs are sent again with the new snapshots
+            channel_symbols: HashMap::new(),
+            gap_detection: self.gap_detection,
+            last_seq: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+    }
+
+    fn on_timeout(&mut self, _: Token) -> Result<(), ws::Error> {
+        if self.health.is_stopped() {
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Poloniex Socket timed out (5s of inactivity). Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            channel_symbols: HashMap::new(),
+            gap_detection: self.gap_detection,
+            last_seq: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,490 @@
+use std::collections::HashMap;
+use std::thread;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use redis;
+use serde_json;
+use ws;
+use ws::util::Token;
+use ws::{Error, Handler, Handshake, Message, Sender};
+
+use config::{Config, ConfigError};
+use exchange::{self, Asset, AssetExchange, ConnectionHealth, Exchange, ExchangeError, MarketType};
+use exchange::huobi::diff_levels;
+use orderbook;
+use storage::{StorageBackend, TectonicBackend};
+
+const EXPIRE: Token = Token(1);
+
+/// Exchange related metadata. The fields are used to establish
+/// a successful connection with the exchange via websockets.
+#[derive(Clone)]
+pub struct WSExchange {
+    /// Full URL to connect to. Example: `wss://api.upbit.com/websocket/v1`
+    pub host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    pub snapshot_received: bool,
+
+    /// Collection metadata
+    pub metadata: MetaData,
+    /// Type of market we're collecting. Deltas are published to the `<exchange>:<market type>` Redis channel
+    pub market_type: MarketType,
+
+    /// Data types we subscribe to for every asset pair (i.e. `orderbook`, `trade`)
+    pub single_channels: Vec<String>,
+
+    /// Connection health, shared with the running websocket handler
+    pub health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    pub storage: Box<dyn StorageBackend>,
+
+    /// Redis URL, including the port and database index (i.e. `redis://127.0.0.1:6379/0`)
+    pub redis_url: String,
+    /// Redis client (before connection). Opened from `redis_url` when connecting
+    pub r: redis::Client,
+    /// Redis password: If this is present, it replaces the password in `redis_url` (if any) when connecting
+    pub r_password: Option<String>,
+}
+
+/// Create two identical structs and transfer the data over when we start the websocket.
+pub struct WSExchangeSender {
+    /// Full URL to connect to. Example: `wss://api.upbit.com/websocket/v1`
+    host: String,
+
+    /// Indicate whether or not we've received the snapshot message yet
+    snapshot_received: bool,
+
+    /// Collection metadata
+    metadata: MetaData,
+
+    /// Data types we subscribe to for every asset pair
+    single_channels: Vec<String>,
+    /// Last orderbook we've received for every symbol (i.e. `KRW-BTC`). Upbit only sends full books,
+    /// so we keep the previous one around to diff against.
+    books: HashMap<String, LocalBook>,
+
+    /// Connection health
+    health: ConnectionHealth,
+
+    /// Storage backend the collected deltas are warehoused in
+    storage: Box<dyn StorageBackend>,
+    /// Redis client (used to send deltas as PUBSUB)
+    r: Arc<Mutex<redis::Connection>>,
+
+    /// Websocket sender
+    out: Sender,
+}
+
+/// Meta data for our data source. This is useful for data warehousing and accessing the data.
+/// All types contained within are considered optional. This may be expanded in the future.
+#[derive(Clone)]
+pub struct MetaData {
+    /// Exchange name. We will warehouse the data under this name
+    pub exchange: Arc<String>,
+
+    /// Vector of asset pairs we're going to warehouse
+    pub asset_pair: Option<Vec<[exchange::Asset; 2]>>,
+
+    /// Type of market we're warehousing. Set from `WSExchange::market_type` when connecting
+    pub market_type: Option<MarketType>,
+
+    /// Starting datetime of our data collection
+    start_date: Option<DateTime<Utc>>,
+
+    /// Ending datetime of our data collection
+    end_date: Option<DateTime<Utc>>,
+}
+
+impl WSExchange {
+    /// Health of the connection to the exchange
+    pub fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+}
+
+impl AssetExchange for WSExchange {
+    fn default_settings() -> Result<Box<Self>, String> {
+        Ok(Box::new(Self {
+            host: "wss://api.upbit.com/websocket/v1".into(),
+
+            snapshot_received: false,
+
+            metadata: MetaData {
+                exchange: Arc::new("upbit".into()),
+                asset_pair: Some(vec![
+                    [Asset::BTC, Asset::KRW],]),
+                market_type: None,
+                start_date: None,
+                end_date: None,
+            },
+            market_type: MarketType::Spot,
+
+            single_channels: vec![
+                "orderbook".into(),
+                "trade".into()],
+
+            health: ConnectionHealth::new(Exchange::Upbit),
+
+            storage: Box::new(TectonicBackend::new(None, None, "upbit").map_err(|e| format!("Unable to connect to TectonicDB: {}", e))?),
+            redis_url: exchange::DEFAULT_REDIS_URL.into(),
+            r: redis::Client::open(exchange::DEFAULT_REDIS_URL).unwrap(),
+            r_password: None,
+        }))
+    }
+
+    fn configure(&mut self, config: &Config) -> Result<(), ConfigError> {
+        if let Some(pairs) = config.asset_pairs()? {
+            self.metadata.asset_pair = Some(pairs);
+        }
+        if let Some(ref channels) = config.channels.names {
+            self.single_channels = channels.clone();
+        }
+        if let Some(ref url) = config.redis.url {
+            self.redis_url = url.clone();
+        }
+        if config.redis.password.is_some() {
+            self.r_password = config.redis.password.clone();
+        }
+        if let Some(storage) = config.storage("upbit")? {
+            self.storage = storage;
+        }
+
+        Ok(())
+    }
+
+    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
+        // The password is sent as part of the URL, so the client authenticates on its own
+        self.r = exchange::redis_client(&self.redis_url, self.r_password.as_ref().map(|password| password.as_str()))?;
+
+        self.r.get_connection()
+    }
+
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    fn start(&self) -> Result<(), ExchangeError> {
+        WSExchange::run_with_settings(Some(self))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
+        // Try to use the settings the user passes before resorting to default settings.
+        let mut settings = match settings {
+            Some(settings) => settings.clone(),
+            None => *WSExchange::default_settings().map_err(ConfigError::Settings)?,
+        };
+
+        let r = Arc::new(Mutex::new(settings.init_redis()?));
+
+        ws::connect(settings.host.clone(), |out| WSExchangeSender {
+            host: settings.host.clone(),
+
+            snapshot_received: settings.snapshot_received.clone(),
+            metadata: MetaData { market_type: Some(settings.market_type), ..settings.metadata.clone() },
+
+            single_channels: settings.single_channels.clone(),
+            books: HashMap::new(),
+
+            health: settings.health.clone(),
+            storage: settings.storage.clone(),
+            r: r.clone(),
+
+            out,
+        })?;
+
+        Ok(())
+    }
+}
+
+/// Upbit expects the subscription as a JSON array, starting with a ticket identifying the
+/// connection, followed by one entry for every data type.
+#[derive(Serialize)]
+struct Ticket {
+    ticket: String,
+}
+
+#[derive(Serialize)]
+struct Subscription {
+    #[serde(rename = "type")]
+    type_: String,
+    codes: Vec<String>,
+}
+
+/// Every message sent by Upbit. Orderbook messages carry `orderbook_units`, whereas trade messages
+/// carry the trade fields directly.
+#[derive(Deserialize)]
+struct EventMessage {
+    /// `orderbook` or `trade`
+    #[serde(rename = "type")]
+    type_: String,
+    /// Asset pair the message applies to (i.e. `KRW-BTC`)
+    code: String,
+    /// Timestamp in milliseconds
+    timestamp: u64,
+
+    /// Full orderbook, best level first
+    orderbook_units: Option<Vec<OrderbookUnit>>,
+
+    trade_price: Option<f64>,
+    trade_volume: Option<f64>,
+    /// Taker side. `BID` when the buyer was the taker, `ASK` otherwise
+    ask_bid: Option<String>,
+    /// Trade timestamp in milliseconds
+    trade_timestamp: Option<u64>,
+    /// Trade ID
+    sequential_id: Option<u64>,
+}
+
+/// A single level of an Upbit orderbook. Every unit holds both an ask and a bid.
+#[derive(Clone, Debug, Deserialize)]
+pub struct OrderbookUnit {
+    /// Ask price
+    pub ask_price: f64,
+    /// Bid price
+    pub bid_price: f64,
+    /// Ask size
+    pub ask_size: f64,
+    /// Bid size
+    pub bid_size: f64,
+}
+
+/// The last full orderbook received for a symbol, as `[price, size]` levels
+#[derive(Clone, Debug, Default)]
+pub struct LocalBook {
+    /// Bid levels
+    pub bids: Vec<[f64; 2]>,
+    /// Ask levels
+    pub asks: Vec<[f64; 2]>,
+}
+
+impl LocalBook {
+    /// Replaces the book with a new snapshot and returns the levels that changed as
+    /// `(asks, bids)`, where a size of zero marks a level that has left the book.
+    pub fn update(&mut self, units: &[OrderbookUnit]) -> (Vec<(f64, f64)>, Vec<(f64, f64)>) {
+        let asks: Vec<[f64; 2]> = units.iter().map(|unit| [unit.ask_price, unit.ask_size]).collect();
+        let bids: Vec<[f64; 2]> = units.iter().map(|unit| [unit.bid_price, unit.bid_size]).collect();
+
+        let changes = (diff_levels(&self.asks, &asks), diff_levels(&self.bids, &bids));
+
+        self.asks = asks;
+        self.bids = bids;
+
+        changes
+    }
+}
+
+impl WSExchangeSender {
+    /// Diffs an orderbook snapshot against the previous one and publishes the changed levels
+    fn on_orderbook(&mut self, symbol: String, units: Vec<OrderbookUnit>, ts: f64) {
+        let (asks, bids) = self.books.entry(symbol.clone())
+            .or_insert_with(LocalBook::default)
+            .update(&units);
+
+        self.snapshot_received = true;
+
+        let mut deltas: Vec<orderbook::Delta> = Vec::with_capacity(asks.len() + bids.len());
+
+        // Begin sequence counting at 1 in order to reconstruct a proper sequence count
+        let mut seq = 1;
+
+        for (levels, side) in vec![(asks, orderbook::ASK), (bids, orderbook::BID)] {
+            for (price, size) in levels {
+                let (price, size) = (price as f32, size as f32);
+
+                deltas.push(orderbook::Delta {
+                    symbol: symbol.clone(),
+                    price,
+                    size,
+                    seq,
+                    event: side ^ if size == 0.0 {
+                        orderbook::REMOVE
+                    } else {
+                        orderbook::UPDATE
+                    },
+                    ts,
+                    received_ts: None,
+                });
+
+                seq += 1;
+            }
+        }
+
+        // Identical snapshots are common, since Upbit resends the book whenever any level changes
+        if deltas.is_empty() {
+            return;
+        }
+
+        exchange::record_symbol_messages(&self.health, deltas.iter().map(|delta| delta.symbol.as_str()));
+        // Lock the connection until we are able to aquire it
+        exchange::publish_deltas(&*self.r.lock().unwrap(), &exchange::market_channel(&self.metadata.exchange, self.metadata.market_type), &deltas, &self.health);
+    }
+}
+
+impl Handler for WSExchangeSender {
+    fn on_open(&mut self, _: Handshake) -> Result<(), Error> {
+        self.health.track_connection(&self.out);
+
+        // Measure the round-trip time of the new connection
+        self.health.ping_latency(&self.out)?;
+
+        // Set a timeout for 5 seconds of inactivity
+        // Issue: currently, this reruns every five seconds. Comment out while we fix.
+        //self.out.timeout(5_000, EXPIRE).unwrap();
+
+        let mut codes = vec![];
+
+        for pair in self.metadata.asset_pair.as_ref().expect("No asset pairs passed to Upbit structure") {
+            let normalized_pair = match exchange::get_asset_pair(pair, Exchange::Upbit) {
+                Ok(normalized_pair) => normalized_pair,
+                Err(e) => {
+                    println!("Skipping Upbit subscription: {}", e);
+                    continue;
+                }
+            };
+            let db_name = format!("{}_{}", self.metadata.exchange.deref(), normalized_pair);
+
+            // Create the database if it doesn't exist yet. This avoids many issues
+            // relating to inserting to a non-existant database.
+            self.storage.create(&db_name)?;
+
+            codes.push(normalized_pair);
+        }
+
+        let mut msg = vec![serde_json::to_value(Ticket {
+            ticket: format!("rusty_road-{}", Utc::now().timestamp_millis()),
+        }).unwrap()];
+
+        for channel in &self.single_channels {
+            msg.push(serde_json::to_value(Subscription {
+                type_: channel.to_string(),
+                codes: codes.clone(),
+            }).unwrap());
+        }
+
+        println!("Sending message {}", serde_json::to_string(&msg).unwrap());
+        self.out.send(serde_json::to_string(&msg).unwrap())
+    }
+
+    fn on_message(&mut self, msg: Message) -> Result<(), Error> {
+        self.health.record_message();
+
+        // Upbit sends its JSON messages as binary frames
+        let message = match serde_json::from_slice::<EventMessage>(&msg.into_data()) {
+            Ok(message) => message,
+            Err(e) => {
+                println!("Error: {}", e);
+                return Ok(());
+            }
+        };
+
+        if message.type_ == "orderbook" {
+            // Books are diffed on the socket thread, since every snapshot is compared to the previous one
+            let ts = message.timestamp as f64 * 0.001f64;
+
+            if let Some(units) = message.orderbook_units {
+                self.on_orderbook(message.code, units, ts);
+            }
+
+            return Ok(());
+        }
+
+        if message.type_ != "trade" {
+            return Ok(());
+        }
+
+        let redis_ref = self.r.clone();
+        let health = self.health.clone();
+        let channel = exchange::market_channel(&self.metadata.exchange, self.metadata.market_type);
+
+        thread::spawn(move || {
+            let (price, size) = match (message.trade_price, message.trade_volume) {
+                (Some(price), Some(size)) => (price, size),
+                _ => return,
+            };
+
+            let trades = vec![orderbook::Trade {
+                symbol: message.code,
+                price,
+                size,
+                side: match message.ask_bid.as_ref().map(|side| side.as_str()) {
+                    Some("BID") => orderbook::TradeSide::Buy,
+                    _ => orderbook::TradeSide::Sell,
+                },
+                ts: message.trade_timestamp.unwrap_or(message.timestamp) as f64 * 0.001f64,
+                exchange: Exchange::Upbit,
+                trade_id: message.sequential_id.map(|id| id.to_string()),
+                received_ts: None,
+            }];
+
+            exchange::record_symbol_messages(&health, trades.iter().map(|trade| trade.symbol.as_str()));
+            exchange::publish_trades(&*redis_ref.lock().unwrap(), &format!("{}:trades", channel), &trades, &health);
+        });
+
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> Result<Option<ws::Frame>, Error> {
+        self.health.on_frame(frame)
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut *self.storage);
+            return;
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Upbit Socket is closing. Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            single_channels: self.single_channels.clone(),
+            books: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+    }
+
+    fn on_timeout(&mut self, _: Token) -> Result<(), ws::Error> {
+        if self.health.is_stopped() {
+            return self.out.close(ws::CloseCode::Normal);
+        }
+
+        // TODO: Have proper handling of disconnect events. We should be handling disconnects more gracefully
+        // instead of just reconnecting. We need to be prepared for them and handle data accordingly.
+        println!("Upbit Socket timed out (5s of inactivity). Opening a new connection...");
+
+        self.health.record_reconnect();
+
+        ws::connect(self.host.clone(), |out| WSExchangeSender{
+            host: self.host.clone(),
+            snapshot_received: false,
+            metadata: self.metadata.clone(),
+
+            single_channels: self.single_channels.clone(),
+            books: HashMap::new(),
+
+            health: self.health.clone(),
+            storage: self.storage.clone(),
+            r: self.r.clone(),
+
+            out,
+        }).unwrap();
+
+        Ok(())
+    }
+}
@@ -9,7 +9,7 @@
 //! plug it in to various exchanges. CCXT may be another option for this as well, as it already has built-in support for lots of crypto exchanges
 //!
 //! This project makes use of [TectonicDB](https://github.com/rickyhan/tectonicdb) to store orderbook data
-//! in a database efficiently. We also make use of LZMA2 to compress that data further to allow for more data storage.
+//! in a database efficiently (PostgreSQL is also supported, see the `storage` module). We also make use of LZMA2 to compress that data further to allow for more data storage.
 //!
 //! # Environment Variables
 //! `AWS_ACCESS_KEY_ID`: AWS Access Key
@@ -19,54 +19,99 @@
 //! `S3_STORAGE_CLASS`: Amazon S3 Storage class type. Defaults to "STANDARD_IA"
 //! `UPLOAD_PERIOD`: Sets the amount of time in seconds we should wait before dumping the
 //!     tectonicdb database and uploading it. Defaults to 86400 seconds (one day)
+//! `REDIS_URL`: Redis URL, including the port and database index. Defaults to `redis://127.0.0.1:6379/0`
 //! `REDIS_AUTH`: Redis password.
+//! `BITMEX_ENVIRONMENT`: BitMEX environment to collect from. "production" and "testnet" are valid values. Defaults to "production"
+//! `BITMEX_API_KEY`, `BITMEX_API_SECRET`: BitMEX API key and secret. When both are set, the account's `execution`, `order` and `position` tables are collected as well, and published to `bitmex_private_{table}`
+//! `METRICS_PORT`: Port the Prometheus metrics are served on, at `/metrics`. Defaults to 9898
+//! `RUST_LOG`: Log verbosity, i.e. `rusty_road=debug`. Only errors are logged by default
 //! `DTF_DB_PATH`: TectonicDB Database where files are written to. Defaults to `$HOME/tectonicdb/target/release/db`
 
 #![deny(missing_docs)]
 #![feature(custom_attribute)]
 #![feature(vec_remove_item)]
 #![feature(nll)]
+#![feature(integer_atomics)]
 
+extern crate base64;
 extern crate chrono;
+extern crate crc32fast;
+extern crate env_logger;
+extern crate flate2;
 extern crate futures;
+extern crate hex;
+extern crate hmac;
 extern crate ndarray;
+extern crate ordered_float;
+extern crate prometheus;
 extern crate rayon;
+#[cfg(feature = "kafka")]
+extern crate rdkafka;
 extern crate redis;
 extern crate reqwest;
 extern crate rusoto_core;
 extern crate rusoto_s3;
+extern crate serde;
 extern crate serde_json;
+extern crate sha2;
 extern crate strum;
 extern crate tar;
+extern crate tiny_http;
+extern crate toml;
 extern crate url;
 extern crate ws;
 extern crate xz2;
 
+#[cfg(feature = "postgres")]
+#[macro_use]
+extern crate diesel;
+#[macro_use]
+extern crate log;
 #[macro_use]
 extern crate serde_derive;
 #[macro_use]
 extern crate strum_macros;
 
+/// TOML configuration files for exchange settings
+pub mod config;
 /// Exchanges and exchange-related methods and modules
 pub mod exchange;
 /// Methods to listen on redis/ZeroMQ sockets.
 pub mod listener;
+/// Runs the connections to several exchanges at once, restarting the ones that fail
+pub mod manager;
+/// Prometheus metrics of the running connections, served over HTTP
+pub mod metrics;
 /// Handles uploading DTF compressed archives to the cloud
 pub mod uploader;
 /// Orderbook analytics and state management data structures
 pub mod orderbook;
+/// Replays deltas stored in TectonicDB to Redis, as if they were collected live
+pub mod replay;
+/// Aggregations computed from the collected trades (i.e. OHLCV candles)
+pub mod aggregation;
+/// Storage backends (TectonicDB, PostgreSQL) that collected deltas are warehoused in
+pub mod storage;
 /// Unit tests for various parts of this project
 pub mod tests;
 
 use std::env;
 use std::thread;
 
-use exchange::{Asset, AssetExchange, bitmex, gdax_l2};
+use exchange::{Asset, AssetExchange, ReconnectPolicy, bitmex, gdax_l2};
+use manager::ConnectionManager;
 use orderbook::tectonic;
 
 fn main() {
+    // Log verbosity is controlled with `RUST_LOG`, i.e. `RUST_LOG=rusty_road=debug`
+    env_logger::init();
+
     // Redis client is setup here so that we can provide it a host, password, and database
-    let r = redis::Client::open("redis://127.0.0.1:6379/0").unwrap();
+    let redis_url = match env::var_os("REDIS_URL") {
+        Some(url) => url.into_string().unwrap(),
+        None => "redis://127.0.0.1:6379/0".into(),
+    };
+    let r = redis::Client::open(redis_url.as_str()).unwrap();
     // TODO: Consider moving this to the `redis_init` function?
     let r_password = match env::var_os("REDIS_AUTH") {
         Some(password) => Some(password.into_string().unwrap()),
@@ -79,8 +124,19 @@ fn main() {
     let mut bitmex_settings = *bitmex::WSExchange::default_settings().unwrap();
     bitmex_settings.metadata.asset_pair = Some(vec![
         [Asset::BTC, Asset::USD],]);
-    bitmex_settings.r = r.clone();
+    bitmex_settings.redis_url = redis_url.clone();
     bitmex_settings.r_password = r_password.as_ref().cloned();
+    if let Some(environment) = env::var_os("BITMEX_ENVIRONMENT") {
+        bitmex_settings.environment = match environment.into_string().unwrap().as_str() {
+            "testnet" => bitmex::Environment::Testnet,
+            _ => bitmex::Environment::Production,
+        };
+    }
+    bitmex_settings.api_key = env::var("BITMEX_API_KEY").ok();
+    bitmex_settings.api_secret = env::var("BITMEX_API_SECRET").ok();
+    if bitmex_settings.api_key.is_some() && bitmex_settings.api_secret.is_some() {
+        bitmex_settings.single_channels.extend(vec!["execution".into(), "order".into(), "position".into()]);
+    }
 
     let mut gdax_settings = *gdax_l2::WSExchange::default_settings().unwrap();
     gdax_settings.metadata.asset_pair = Some(vec![
@@ -89,19 +145,25 @@ fn main() {
         [Asset::LTC, Asset::USD],
         [Asset::BTC, Asset::USDC],
     ]);
-    gdax_settings.r = r.clone();
+    gdax_settings.redis_url = redis_url.clone();
     gdax_settings.r_password = r_password.as_ref().cloned();
 
     // =====================================================
 
     let mut exchanges = vec![];
 
-    // Push exchange instance threads to vector
-    exchanges.push(thread::spawn(move ||
-        bitmex::WSExchange::run(Some(&bitmex_settings))));
+    // Exchange connections are restarted if they fail
+    let mut manager = ConnectionManager::new(vec![
+        Box::new(bitmex_settings),
+        Box::new(gdax_settings),
+    ]);
+    manager.restart_policy = Some(ReconnectPolicy::default());
+    manager.metrics_port = Some(match env::var("METRICS_PORT") {
+        Ok(port) => port.parse().expect("METRICS_PORT isn't a valid port"),
+        Err(_) => metrics::DEFAULT_METRICS_PORT,
+    });
 
-    exchanges.push(thread::spawn(move ||
-        gdax_l2::WSExchange::run(Some(&gdax_settings))));
+    exchanges.push(thread::spawn(move || manager.run_all()));
 
     // Start a listener to insert ticks into tectonicdb
     exchanges.push(thread::spawn(move ||
@@ -0,0 +1,163 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use exchange::{AssetExchange, ConnectionHealth, Exchange, ReconnectPolicy};
+use metrics::MetricsServer;
+
+/// Health of one of the connections run by a [`ConnectionManager`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectionStatus {
+    /// Exchange the connection belongs to
+    pub exchange: Exchange,
+    /// Time elapsed since the last message. `None` if we haven't received anything yet
+    pub since_last_message: Option<Duration>,
+    /// Number of times the connection has reconnected (or been restarted)
+    pub reconnect_count: usize,
+    /// Round-trip time of the last ping we've sent
+    pub latency_ms: Option<f64>,
+    /// Number of replayed deltas we've dropped instead of publishing
+    pub duplicates_skipped: u64,
+    /// Number of deltas the connection's `DeltaFilter` dropped instead of publishing
+    pub filtered_count: u64,
+}
+
+impl<'a> From<&'a ConnectionHealth> for ConnectionStatus {
+    fn from(health: &'a ConnectionHealth) -> ConnectionStatus {
+        ConnectionStatus {
+            exchange: health.exchange,
+            since_last_message: health.since_last_message(),
+            reconnect_count: health.reconnect_count.load(Ordering::SeqCst),
+            latency_ms: *health.latency_ms.lock().unwrap(),
+            duplicates_skipped: health.duplicates_skipped.load(Ordering::SeqCst),
+            filtered_count: health.filtered_count.load(Ordering::SeqCst),
+        }
+    }
+}
+
+/// Runs the connections to several exchanges at once, each on its own thread. A connection that fails
+/// (returns an error, or panics) is logged, and restarted following the `restart_policy` if there is one.
+///
+/// The manager can be shared (i.e. in an `Arc`) to check on its connections with [`ConnectionManager::status`]
+/// while [`ConnectionManager::run_all`] is blocking.
+pub struct ConnectionManager {
+    /// Connections that haven't been started yet. Emptied by `run_all`
+    connections: Mutex<Vec<Box<dyn AssetExchange + Send>>>,
+    /// Health of every connection, in the order they were given in
+    health: Vec<ConnectionHealth>,
+    /// Policy we follow when restarting failed connections. `None` leaves them stopped
+    pub restart_policy: Option<ReconnectPolicy>,
+    /// Port the Prometheus metrics of the connections are served on while they run. `None` doesn't serve them
+    pub metrics_port: Option<u16>,
+}
+
+impl ConnectionManager {
+    /// Creates a manager running the given connections. Failed connections aren't restarted, unless
+    /// a `restart_policy` is set.
+    pub fn new(connections: Vec<Box<dyn AssetExchange + Send>>) -> ConnectionManager {
+        let health = connections.iter()
+            .map(|connection| connection.health().clone())
+            .collect();
+
+        ConnectionManager {
+            connections: Mutex::new(connections),
+            health,
+            restart_policy: None,
+            metrics_port: None,
+        }
+    }
+
+    /// Health of every connection, in the order they were given in
+    pub fn status(&self) -> Vec<ConnectionStatus> {
+        self.health.iter().map(ConnectionStatus::from).collect()
+    }
+
+    /// Stops every connection (see [`ConnectionHealth::stop`]), which makes [`ConnectionManager::run_all`] return
+    pub fn stop_all(&self) {
+        for health in &self.health {
+            health.stop();
+        }
+    }
+
+    /// Starts every connection, and blocks until all of them have exited (and won't be restarted).
+    /// Connections are only run once: calling this again returns right away.
+    ///
+    /// If a `metrics_port` is set, the metrics are served for as long as the connections run. Failing to
+    /// serve them doesn't keep the connections from starting.
+    pub fn run_all(&self) {
+        let connections: Vec<Box<dyn AssetExchange + Send>> = self.connections.lock().unwrap().drain(..).collect();
+
+        let metrics = match self.metrics_port {
+            Some(port) if !connections.is_empty() => match MetricsServer::start(port, self.health.clone()) {
+                Ok(server) => Some(server),
+                Err(e) => {
+                    error!("Failed to serve metrics on port {}: {}", port, e);
+                    None
+                }
+            },
+            _ => None,
+        };
+
+        let handles: Vec<thread::JoinHandle<()>> = connections.into_iter()
+            .map(|connection| {
+                let restart_policy = self.restart_policy.clone();
+                thread::spawn(move || supervise(connection, restart_policy))
+            })
+            .collect();
+
+        for handle in handles {
+            let _ = handle.join();
+        }
+
+        if let Some(metrics) = metrics {
+            metrics.stop();
+        }
+    }
+}
+
+/// Runs a connection until it exits on its own, restarting it whenever it fails for as long as the policy allows
+fn supervise(connection: Box<dyn AssetExchange + Send>, restart_policy: Option<ReconnectPolicy>) {
+    let exchange = connection.health().exchange;
+    let mut attempts = 0;
+
+    loop {
+        let started = Instant::now();
+
+        match panic::catch_unwind(AssertUnwindSafe(|| connection.start())) {
+            Ok(Ok(())) => {
+                info!("{} connection exited", exchange);
+                return;
+            },
+            Ok(Err(e)) => error!("{} connection failed: {}", exchange, e),
+            Err(_) => error!("{} connection failed", exchange),
+        }
+
+        if connection.health().is_stopped() {
+            info!("{} connection stopped", exchange);
+            return;
+        }
+
+        let policy = match restart_policy {
+            Some(ref policy) => policy,
+            None => return,
+        };
+
+        if policy.is_stable(started.elapsed()) {
+            attempts = 0;
+        }
+
+        if policy.exhausted(attempts) {
+            error!("Giving up on the {} connection after {} restarts", exchange, attempts);
+            return;
+        }
+
+        let delay = policy.delay(attempts);
+        warn!("Restarting the {} connection in {}ms...", exchange, delay.as_secs() * 1000 + delay.subsec_millis() as u64);
+        thread::sleep(delay);
+
+        attempts += 1;
+        connection.health().record_reconnect();
+    }
+}
@@ -0,0 +1,183 @@
+use std::error;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use prometheus::{self, Encoder, GaugeVec, IntCounterVec, Opts, Registry, TextEncoder};
+use tiny_http::{Header, Request, Response, Server};
+
+use exchange::ConnectionHealth;
+
+/// Port the metrics are served on, unless configured otherwise
+pub const DEFAULT_METRICS_PORT: u16 = 9898;
+
+/// How often the server checks whether it was stopped
+const POLL_INTERVAL_MS: u64 = 100;
+
+/// Error serving the metrics
+#[derive(Debug)]
+pub enum MetricsError {
+    /// Failed to register or encode the metrics
+    Prometheus(prometheus::Error),
+    /// Failed to start the HTTP server (i.e. the port is taken)
+    Http(String),
+}
+
+impl fmt::Display for MetricsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MetricsError::Prometheus(e) => write!(f, "Prometheus error: {}", e),
+            MetricsError::Http(e) => write!(f, "HTTP server error: {}", e),
+        }
+    }
+}
+
+impl error::Error for MetricsError {
+    fn description(&self) -> &str {
+        match self {
+            MetricsError::Prometheus(_) => "Prometheus error",
+            MetricsError::Http(_) => "HTTP server error",
+        }
+    }
+}
+
+impl From<prometheus::Error> for MetricsError {
+    fn from(e: prometheus::Error) -> Self {
+        MetricsError::Prometheus(e)
+    }
+}
+
+/// Renders the metrics of the connections in the Prometheus text format. The metrics are built from scratch
+/// out of the connections' health every time, which keeps the counters there the single source of truth.
+pub fn render(health: &[ConnectionHealth]) -> Result<String, MetricsError> {
+    let registry = Registry::new();
+
+    let messages = IntCounterVec::new(
+        Opts::new("chocolate_road_messages_total", "Messages received, by symbol"),
+        &["exchange", "symbol"])?;
+    let reconnects = IntCounterVec::new(
+        Opts::new("chocolate_road_reconnects_total", "Number of times the connection reconnected"),
+        &["exchange"])?;
+    let deltas_published = IntCounterVec::new(
+        Opts::new("chocolate_road_deltas_published_total", "Deltas published to Redis, by symbol"),
+        &["exchange", "symbol"])?;
+    let latency_ms = GaugeVec::new(
+        Opts::new("chocolate_road_websocket_latency_ms", "Round-trip time of the last websocket ping"),
+        &["exchange"])?;
+    let redis_publish_errors = IntCounterVec::new(
+        Opts::new("chocolate_road_redis_publish_errors_total", "Number of times publishing to Redis failed"),
+        &["exchange"])?;
+
+    registry.register(Box::new(messages.clone()))?;
+    registry.register(Box::new(reconnects.clone()))?;
+    registry.register(Box::new(deltas_published.clone()))?;
+    registry.register(Box::new(latency_ms.clone()))?;
+    registry.register(Box::new(redis_publish_errors.clone()))?;
+
+    for health in health {
+        let exchange = health.exchange.to_string();
+
+        reconnects.with_label_values(&[&exchange])
+            .inc_by(health.reconnect_count.load(Ordering::SeqCst) as i64);
+        redis_publish_errors.with_label_values(&[&exchange])
+            .inc_by(health.redis_publish_errors.load(Ordering::SeqCst) as i64);
+
+        if let Some(latency) = *health.latency_ms.lock().unwrap() {
+            latency_ms.with_label_values(&[&exchange]).set(latency);
+        }
+
+        for (symbol, counts) in health.symbols.lock().unwrap().iter() {
+            messages.with_label_values(&[&exchange, symbol]).inc_by(counts.messages as i64);
+            deltas_published.with_label_values(&[&exchange, symbol]).inc_by(counts.deltas_published as i64);
+        }
+    }
+
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&registry.gather(), &mut buffer)?;
+
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
+
+/// Serves the metrics of the connections on `/metrics` from a thread of its own, until it's stopped (or dropped)
+pub struct MetricsServer {
+    /// Address the server listens on
+    addr: SocketAddr,
+    /// Set to stop the server
+    stopped: Arc<AtomicBool>,
+    /// Thread serving the requests
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    /// Starts serving the metrics on the port. Port 0 picks any free port (see [`MetricsServer::local_addr`])
+    pub fn start(port: u16, health: Vec<ConnectionHealth>) -> Result<MetricsServer, MetricsError> {
+        let server = Server::http(("0.0.0.0", port)).map_err(|e| MetricsError::Http(e.to_string()))?;
+        let addr = server.server_addr();
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let server_stopped = stopped.clone();
+        let thread = thread::spawn(move || {
+            while !server_stopped.load(Ordering::SeqCst) {
+                match server.recv_timeout(Duration::from_millis(POLL_INTERVAL_MS)) {
+                    Ok(Some(request)) => respond(request, &health),
+                    Ok(None) => (),
+                    Err(e) => {
+                        error!("Metrics server failed: {}", e);
+                        return;
+                    }
+                }
+            }
+        });
+
+        info!("Serving metrics on {}", addr);
+
+        Ok(MetricsServer {
+            addr,
+            stopped,
+            thread: Some(thread),
+        })
+    }
+
+    /// Address the server listens on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stops the server, waiting for its thread to exit
+    pub fn stop(self) {
+        // Dropping the server is what stops it
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Answers a scrape. Anything but `/metrics` is a 404
+fn respond(request: Request, health: &[ConnectionHealth]) {
+    let response = if request.url().split('?').next() != Some("/metrics") {
+        Response::from_string("Not found").with_status_code(404)
+    } else {
+        match render(health) {
+            Ok(metrics) => Response::from_string(metrics)
+                .with_header(Header::from_bytes(&b"Content-Type"[..], &b"text/plain; version=0.0.4"[..]).unwrap()),
+            Err(e) => {
+                error!("Failed to render metrics: {}", e);
+                Response::from_string(e.to_string()).with_status_code(500)
+            }
+        }
+    };
+
+    if let Err(e) = request.respond(response) {
+        warn!("Failed to answer a metrics scrape: {}", e);
+    }
+}
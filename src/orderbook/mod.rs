@@ -1,11 +1,18 @@
+use std::collections::BTreeMap;
+
 use chrono::prelude::*;
+use crc32fast;
 //use ndarray;
+use ordered_float::OrderedFloat;
 use rayon::prelude::*;
-use exchange::Asset;
+use exchange::{Asset, Exchange};
 
 /// TectonicDB client bindings
 pub mod tectonic;
 
+/// Number of levels per side that [`Book::checksum`] covers
+pub const CHECKSUM_DEPTH: usize = 10;
+
 /// Insertion event (i.e. new order)
 pub const INSERT: u8 = 1;
 /// Order cancelation
@@ -19,6 +26,80 @@ pub const ASK: u8 = 1 << 4;
 /// Bid side order
 pub const BID: u8 = 1 << 5;
 
+/// Side and kind of a [`Delta`], as an alternative to combining the flags above by hand. Converts
+/// to and from the flags, which remain the representation we send over the wire and store.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeltaEvent {
+    /// Bid level was inserted or changed size
+    BidUpdate,
+    /// Ask level was inserted or changed size
+    AskUpdate,
+    /// Bid level was removed
+    BidRemove,
+    /// Ask level was removed
+    AskRemove,
+    /// Trade against the bid side
+    BidTrade,
+    /// Trade against the ask side
+    AskTrade,
+}
+
+impl DeltaEvent {
+    /// Decodes the flags of [`Delta::event`]. Returns `None` for combinations that don't
+    /// describe a single side and kind (i.e. both `BID` and `ASK`, or no side at all).
+    pub fn from_bits(bits: u8) -> Option<DeltaEvent> {
+        let bid = match (bits & BID == BID, bits & ASK == ASK) {
+            (true, false) => true,
+            (false, true) => false,
+            _ => return None,
+        };
+
+        match (bits & !(BID | ASK), bid) {
+            (UPDATE, true) | (INSERT, true) => Some(DeltaEvent::BidUpdate),
+            (UPDATE, false) | (INSERT, false) => Some(DeltaEvent::AskUpdate),
+            (REMOVE, true) => Some(DeltaEvent::BidRemove),
+            (REMOVE, false) => Some(DeltaEvent::AskRemove),
+            (TRADE, true) => Some(DeltaEvent::BidTrade),
+            (TRADE, false) => Some(DeltaEvent::AskTrade),
+            _ => None,
+        }
+    }
+
+    /// Encodes the event as the flags expected in [`Delta::event`]
+    pub fn bits(&self) -> u8 {
+        match self {
+            DeltaEvent::BidUpdate => BID | UPDATE,
+            DeltaEvent::AskUpdate => ASK | UPDATE,
+            DeltaEvent::BidRemove => BID | REMOVE,
+            DeltaEvent::AskRemove => ASK | REMOVE,
+            DeltaEvent::BidTrade => BID | TRADE,
+            DeltaEvent::AskTrade => ASK | TRADE,
+        }
+    }
+
+    /// Whether the event belongs to the bid side of the book
+    pub fn is_bid(&self) -> bool {
+        match self {
+            DeltaEvent::BidUpdate | DeltaEvent::BidRemove | DeltaEvent::BidTrade => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the event is a trade
+    pub fn is_trade(&self) -> bool {
+        match self {
+            DeltaEvent::BidTrade | DeltaEvent::AskTrade => true,
+            _ => false,
+        }
+    }
+}
+
+impl From<DeltaEvent> for u8 {
+    fn from(event: DeltaEvent) -> u8 {
+        event.bits()
+    }
+}
+
 
 /// Contains all the necessary parts to reconstruct an orderbook. Deltas are the incremental changes
 /// that happen to the orderbook over time. Deltas are the primary way that orderbooks are updated.
@@ -34,9 +115,148 @@ pub struct Delta {
     pub seq: u32,
     /// Encodes two pieces of information using bitwise flags -- The order side (bid/ask), and the event that occured.
     pub event: u8,
-    /// Timestamp -- This is `u32` because `tectonicdb` expects `u32` for timestamp as UNIX epoch time
+    /// Timestamp -- This is `u32` because `tectonicdb` expects `u32` for timestamp as UNIX epoch time.
+    /// Set by the exchange when it timestamps its events, otherwise the time we received the delta.
     pub ts: f64,
+    /// Local time we received the delta, as UNIX epoch time in seconds. Compared to `ts`, this gives
+    /// the wire latency. Only set by exchanges that timestamp their events (and isn't stored in TectonicDB).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub received_ts: Option<f64>,
+}
+
+impl Delta {
+    /// Decodes [`Delta::event`]. See [`DeltaEvent::from_bits`]
+    pub fn event_kind(&self) -> Option<DeltaEvent> {
+        DeltaEvent::from_bits(self.event)
+    }
 
+    /// Time the exchange gave the delta. `None` when `ts` is only the time we received it (see [`Delta::received_ts`])
+    pub fn exchange_ts(&self) -> Option<f64> {
+        self.received_ts.map(|_| self.ts)
+    }
+}
+
+/// Side of the taker (aggressor) of a trade
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TradeSide {
+    /// Taker bought, lifting an ask
+    Buy,
+    /// Taker sold, hitting a bid
+    Sell,
+}
+
+/// A trade that was executed on an exchange. Trades are published separately from orderbook
+/// deltas (i.e. on `bitmex:trades` instead of `bitmex`) so that consumers can subscribe to one
+/// without having to filter out the other.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Trade {
+    /// Pair symbol (e.g. BTCUSD, XBTUSD, ETHUSD)
+    pub symbol: String,
+    /// Execution price
+    pub price: f64,
+    /// Execution size
+    pub size: f64,
+    /// Side of the taker
+    pub side: TradeSide,
+    /// Timestamp as UNIX epoch time in seconds
+    pub ts: f64,
+    /// Exchange the trade was executed on
+    pub exchange: Exchange,
+    /// Trade identifier assigned by the exchange, if it provides one
+    pub trade_id: Option<String>,
+    /// Local time we received the trade, as UNIX epoch time in seconds (see [`Delta::received_ts`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub received_ts: Option<f64>,
+}
+
+impl<'a> From<&'a Trade> for Delta {
+    /// Converts a trade into the delta format TectonicDB expects, with the `TRADE` flag set
+    fn from(trade: &'a Trade) -> Delta {
+        Delta {
+            symbol: trade.symbol.clone(),
+            price: trade.price as f32,
+            size: trade.size as f32,
+            seq: 0,
+            event: match trade.side {
+                TradeSide::Buy => DeltaEvent::BidTrade,
+                TradeSide::Sell => DeltaEvent::AskTrade,
+            }.into(),
+            ts: trade.ts,
+            received_ts: trade.received_ts,
+        }
+    }
+}
+
+/// Funding rate of a perpetual contract. Published on its own channel (i.e. `bitmex_funding`),
+/// separately from orderbook deltas and trades.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FundingRate {
+    /// Contract symbol (e.g. XBTUSD)
+    pub symbol: String,
+    /// Exchange the contract is listed on
+    pub exchange: Exchange,
+    /// Rate paid by longs to shorts (or by shorts to longs, if negative) over one funding interval
+    pub rate: f64,
+    /// Rate over a whole day, if the exchange sends it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_rate: Option<f64>,
+    /// Time of the next funding as UNIX epoch time in seconds
+    pub next_funding_ts: f64,
+    /// Time of the funding as UNIX epoch time in seconds
+    pub ts: f64,
+}
+
+impl FundingRate {
+    /// Encodes the funding as a delta, so that it can be stored alongside the books. The delta is stored under
+    /// the `funding_<symbol>` symbol (i.e. the `bitmex_funding_XBTUSD` TectonicDB database), with the rate as its
+    /// price and the daily rate (or 0) as its size. It has no side or event flags.
+    pub fn to_delta(&self) -> Delta {
+        Delta {
+            symbol: format!("funding_{}", self.symbol),
+            price: self.rate as f32,
+            size: self.daily_rate.unwrap_or(0.0) as f32,
+            seq: 0,
+            event: 0,
+            ts: self.ts,
+            received_ts: None,
+        }
+    }
+}
+
+/// Event in the lifecycle of a forced liquidation order. Published on its own channel (i.e. `bitmex_liquidation`).
+/// Orders are inserted, amended as they fill, and deleted once they're done: follow an order through its `order_id`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Liquidation {
+    /// ID of the liquidation order
+    pub order_id: String,
+    /// What happened to the order
+    pub action: LiquidationAction,
+    /// Contract symbol (e.g. XBTUSD)
+    pub symbol: String,
+    /// Side of the liquidation order (`Buy` when a short is liquidated, `Sell` when a long is).
+    /// Updates only carry the fields that changed, and deletes none of them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub side: Option<String>,
+    /// Price of the liquidation order
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<f64>,
+    /// Quantity left to be liquidated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leaves_qty: Option<f64>,
+    /// Time we received the event as UNIX epoch time in seconds. BitMEX doesn't timestamp liquidations
+    pub ts: f64,
+}
+
+/// What happened to a liquidation order
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LiquidationAction {
+    /// The order was placed (or was already in progress when we subscribed)
+    Insert,
+    /// The order was amended, i.e. partially filled
+    Update,
+    /// The order is gone, i.e. completely filled
+    Delete,
 }
 
 /// Before we can start applying deltas, we must have a snapshot to build off of. This is the initial state of the
@@ -88,8 +308,9 @@ pub struct Book {
     /// Indexes for ask-side pairs. Same as `bid_price_points`
     pub ask_price_points: Vec<u64>,
 
-    /// Stores an orderbook order as size pair with price stored as array index. Fast and efficient.
-    pub state: Vec<Option<f32>>,
+    /// Size of every price level, keyed by the price's index (see [`Book::price_index`]). Levels are stored sparsely,
+    /// since a dense array over every tick up to the price (i.e. BTC at a 0.0001 tick) doesn't fit in memory.
+    pub state: BTreeMap<u64, f32>,
 }
 
 impl Default for Book {
@@ -112,7 +333,7 @@ impl Default for Book {
             bid_price_points: Vec::new(),
             ask_price_points: Vec::new(),
 
-            state: Vec::new(),
+            state: BTreeMap::new(),
         }
     }
 }
@@ -123,12 +344,12 @@ impl Book {
     pub fn initialize(&mut self, snapshot: &Snapshot) {
         let mut bids: Vec<(u64, f32)> = snapshot.bids
             .iter()
-            .map(|bid| ((bid.0 / self.tick_size) as u64, bid.1))
+            .map(|bid| (self.price_index(bid.0), bid.1))
             .collect();
 
         let mut asks: Vec<(u64, f32)> = snapshot.asks
             .iter()
-            .map(|ask| ((ask.0 / self.tick_size) as u64, ask.1))
+            .map(|ask| (self.price_index(ask.0), ask.1))
             .collect();
 
         // Run these here because they return nothing.
@@ -136,13 +357,12 @@ impl Book {
         bids.sort_by_key(|bid| bid.0);
         asks.sort_by_key(|ask| ask.0);
 
-        // Initialize "empty" vector full of `None` values
-        self.state = vec![None; (1.0 / self.tick_size) as usize * 100_000];
+        self.state = BTreeMap::new();
 
         for (idx, (price, size)) in bids.iter().enumerate() {
             // Because we have already set the price to our "standardized format" above, we
             // don't need to perform arithmetic on the price variable.
-            self.state[*price as usize] = Some(*size);
+            self.state.insert(*price, *size);
             self.bid_price_points.push(*price);
 
             if idx == bids.len() - 1 {
@@ -152,7 +372,7 @@ impl Book {
             }
         }
         for (idx, (price, size)) in asks.iter().enumerate() {
-            self.state[*price as usize] = Some(*size);
+            self.state.insert(*price, *size);
             self.ask_price_points.push(*price);
             if idx == 0 {
                 self.best_ask = *price;
@@ -177,9 +397,6 @@ impl Book {
     /// TODO: also consider adding a vector to `Book` that contains price allocations present in the array.
     pub fn new_state(&mut self, updates: &Vec<(u64, f32, bool)>) {
         for (price, size, is_bid) in updates {
-            // Save our price as usize to avoid having to cast it everytime we want to access a vector element
-            let price_usize = *price as usize;
-
             if *is_bid {
                 // Limit Order: An order that is placed on the orderbook queue and does not affect
                 // the ask side of the orderbook (in most cases).
@@ -193,7 +410,7 @@ impl Book {
                         self.bid_price_points.sort();
 
                         let level_price = self.bid_price_points[self.bid_price_points.len() - 2];
-                        let bid_level_size = self.state[level_price as usize];
+                        let bid_level_size = self.state.get(&level_price).cloned();
 
                         self.best_bid = level_price;
                         self.best_bid_size = bid_level_size.unwrap();
@@ -202,11 +419,11 @@ impl Book {
                         self.bid_price_points.pop();
 
                         // Void the best level bid after having handled best-bid updates (if any)
-                        self.state[price_usize] = None;
+                        self.state.remove(price);
 
                     } else {
                         // Void the best level bid after having handled best-bid updates (if any)
-                        self.state[price_usize] = None;
+                        self.state.remove(price);
                         self.bid_price_points.remove_item(price);
                     }
 
@@ -214,7 +431,7 @@ impl Book {
                     // Updates limit order
 
                     let new_size = Some(*size);
-                    self.state[price_usize] = new_size;
+                    self.state.insert(*price, *size);
 
                     // Check for duplicates before adding anything to the vector
                     if !self.bid_price_points.iter().any(|p| *p == *price) {
@@ -244,17 +461,17 @@ impl Book {
                         let level_price = self.ask_price_points[1];
                         
                         self.best_ask = level_price;
-                        self.best_ask_size= self.state[level_price as usize].unwrap();
+                        self.best_ask_size= self.state[&level_price];
 
                         // TODO: This may be inefficient...
                         self.ask_price_points = self.ask_price_points[1..].to_vec();
 
                         // Void the best level bid after having handled best-bid updates (if any)
-                        self.state[price_usize] = None;
+                        self.state.remove(price);
 
                     } else {
                         // Void the best level bid after having handled best-bid updates (if any)
-                        self.state[price_usize] = None;
+                        self.state.remove(price);
                         self.ask_price_points.remove_item(price);
                     }
 
@@ -262,7 +479,7 @@ impl Book {
                     // Updates limit order. Make sure to handle `best_ask` case scenario.
                     let new_size = Some(*size);
 
-                    self.state[price_usize] = new_size;
+                    self.state.insert(*price, *size);
 
                     if !self.ask_price_points.iter().any(|p| *p == *price) {
                         self.ask_price_points.push(*price);
@@ -295,7 +512,7 @@ impl Book {
             bids: { let bids: Vec<(f32, f32)> = self.bid_price_points[..]
                 .par_iter()
                 .map(|level_price| {
-                    (*level_price as f32, self.state[*level_price as usize].unwrap_or(0.0))
+                    (*level_price as f32, self.state.get(level_price).cloned().unwrap_or(0.0))
                 })
                 .collect();
 
@@ -305,7 +522,7 @@ impl Book {
             asks: { let asks: Vec<(f32, f32)> = self.ask_price_points[..]
                 .par_iter()
                 .map(|level_price| {
-                    (*level_price as f32, self.state[*level_price as usize].unwrap_or(0.0))
+                    (*level_price as f32, self.state.get(level_price).cloned().unwrap_or(0.0))
                 })
                 .collect();
 
@@ -314,6 +531,121 @@ impl Book {
         }
     }
 
+    /// Folds a single delta into the book. A size of zero (or a removal event) removes the price level,
+    /// and anything else sets the level to the delta's size. Trades don't change the resting levels and
+    /// are ignored. Deltas can be applied to an empty book, or on top of [`Book::initialize`].
+    pub fn apply(&mut self, delta: &Delta) {
+        let event = match delta.event_kind() {
+            Some(event) => event,
+            None => return,
+        };
+
+        if event.is_trade() {
+            return;
+        }
+
+        let price = self.price_index(delta.price);
+        let remove = delta.size == 0.0 || event == DeltaEvent::BidRemove || event == DeltaEvent::AskRemove;
+
+        {
+            let price_points = if event.is_bid() { &mut self.bid_price_points } else { &mut self.ask_price_points };
+
+            if remove {
+                self.state.remove(&price);
+                price_points.retain(|p| *p != price);
+            } else {
+                self.state.insert(price, delta.size);
+
+                if !price_points.iter().any(|p| *p == price) {
+                    price_points.push(price);
+                }
+            }
+        }
+
+        // Recalculate the top of the book from the remaining levels. An empty side resets to zero.
+        if event.is_bid() {
+            self.best_bid = self.bid_price_points.iter().cloned().max().unwrap_or(0);
+            self.best_bid_size = self.state.get(&self.best_bid).cloned().unwrap_or(0.0);
+        } else {
+            self.best_ask = self.ask_price_points.iter().cloned().min().unwrap_or(0);
+            self.best_ask_size = self.state.get(&self.best_ask).cloned().unwrap_or(0.0);
+        }
+    }
+
+    /// Best bid as `(price, size)`, or `None` if there are no bids
+    pub fn best_bid(&self) -> Option<(f32, f32)> {
+        if self.bid_price_points.is_empty() {
+            return None;
+        }
+
+        Some((self.real_price(self.best_bid), self.best_bid_size))
+    }
+
+    /// Best ask as `(price, size)`, or `None` if there are no asks
+    pub fn best_ask(&self) -> Option<(f32, f32)> {
+        if self.ask_price_points.is_empty() {
+            return None;
+        }
+
+        Some((self.real_price(self.best_ask), self.best_ask_size))
+    }
+
+    /// Returns up to `levels` price levels as `(price, size)` for each side. Bids are sorted from the best
+    /// (highest) price down, and asks from the best (lowest) price up.
+    pub fn depth(&self, levels: usize) -> (Vec<(f32, f32)>, Vec<(f32, f32)>) {
+        let mut bid_prices = self.bid_price_points.clone();
+        let mut ask_prices = self.ask_price_points.clone();
+
+        bid_prices.sort_by(|a, b| b.cmp(a));
+        ask_prices.sort();
+
+        let bids = bid_prices.iter()
+            .take(levels)
+            .map(|price| (self.real_price(*price), self.state.get(price).cloned().unwrap_or(0.0)))
+            .collect();
+
+        let asks = ask_prices.iter()
+            .take(levels)
+            .map(|price| (self.real_price(*price), self.state.get(price).cloned().unwrap_or(0.0)))
+            .collect();
+
+        (bids, asks)
+    }
+
+    /// CRC32 of the top [`CHECKSUM_DEPTH`] levels of the book. Levels are interleaved from the top of the
+    /// book down as `bid_price:bid_size:ask_price:ask_size:...`, and a side that runs out of levels
+    /// is skipped. Prices and sizes are formatted without trailing zeros (i.e. `304` and `20.5`).
+    ///
+    /// Coinbase's level2 channel doesn't send a checksum to compare this against, so the GDAX collector
+    /// checks its books with the channel's sequence numbers instead (see `gdax_l2::is_level2_gap`).
+    pub fn checksum(&self) -> u32 {
+        let (bids, asks) = self.depth(CHECKSUM_DEPTH);
+        let mut fields: Vec<String> = Vec::with_capacity(CHECKSUM_DEPTH * 4);
+
+        for idx in 0..CHECKSUM_DEPTH {
+            for side in &[&bids, &asks] {
+                if let Some((price, size)) = side.get(idx) {
+                    fields.push(price.to_string());
+                    fields.push(size.to_string());
+                }
+            }
+        }
+
+        crc32fast::hash(fields.join(":").as_bytes())
+    }
+
+    /// Compares the checksum of the book with the one an exchange expects. A mismatch means
+    /// that the book is corrupt (i.e. we've dropped a message) and needs a new snapshot.
+    pub fn verify_checksum(&self, expected: u32) -> bool {
+        self.checksum() == expected
+    }
+
+    /// Converts a "real" price to its array index. Rounds to the nearest tick, since
+    /// dividing by fractional tick sizes isn't exact in floating point.
+    pub fn price_index(&self, price: f32) -> u64 {
+        (price / self.tick_size).round() as u64
+    }
+
     /// Return the "real" price of an asset instead of the array index (i.e. normalize price)
     pub fn real_price(&self, fake_price: u64) -> f32 {
         fake_price as f32 * self.tick_size
@@ -334,4 +666,121 @@ impl Book {
     pub fn ask_relative_price(&self, price: f32) -> f32 {
         price - self.real_price(self.best_ask)
     }
-}
\ No newline at end of file
+}
+/// Price level of a [`Level2Snapshot`] as `(price, size)`
+pub type PriceLevel = (f64, f64);
+
+/// Every level of a symbol's book at a point in time. Unlike deltas, a snapshot is enough on its own to
+/// rebuild the book, so consumers that start listening late don't need to have seen anything before it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Level2Snapshot {
+    /// Bid levels, from the best (highest) price down
+    pub bids: Vec<PriceLevel>,
+    /// Ask levels, from the best (lowest) price up
+    pub asks: Vec<PriceLevel>,
+    /// Pair symbol (e.g. BTCUSD, XBTUSD, ETHUSD)
+    pub symbol: String,
+    /// Exchange the book belongs to
+    pub exchange: Exchange,
+    /// Time of the snapshot as UNIX epoch time in seconds
+    pub ts: f64,
+}
+
+/// Live level 2 book for a single symbol, built by applying deltas as they arrive. Unlike [`Book`],
+/// prices aren't bucketed by tick size, so the book can be used without knowing the instrument's tick.
+#[derive(Clone, Debug)]
+pub struct Level2Orderbook {
+    /// Bid levels as price -> size
+    pub bids: BTreeMap<OrderedFloat<f64>, f64>,
+    /// Ask levels as price -> size
+    pub asks: BTreeMap<OrderedFloat<f64>, f64>,
+    /// Pair symbol (e.g. BTCUSD, XBTUSD, ETHUSD)
+    pub symbol: String,
+    /// Exchange the book belongs to
+    pub exchange: Exchange,
+}
+
+impl Level2Orderbook {
+    /// Creates an empty book
+    pub fn new(symbol: &str, exchange: Exchange) -> Self {
+        Level2Orderbook {
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            symbol: symbol.to_string(),
+            exchange,
+        }
+    }
+
+    /// Folds a single delta into the book. A size of zero (or the `REMOVE` flag) removes the price level,
+    /// and anything else sets the level to the delta's size. Trades don't change the book.
+    pub fn apply(&mut self, delta: &Delta) {
+        if delta.event & TRADE == TRADE {
+            return;
+        }
+
+        let side = match (delta.event & BID == BID, delta.event & ASK == ASK) {
+            (true, false) => &mut self.bids,
+            (false, true) => &mut self.asks,
+            _ => return,
+        };
+        let price = OrderedFloat(delta.price as f64);
+
+        if delta.size == 0.0 || delta.event & REMOVE == REMOVE {
+            side.remove(&price);
+        } else {
+            side.insert(price, delta.size as f64);
+        }
+    }
+
+    /// Best (highest) bid as `(price, size)`, or `None` if there are no bids
+    pub fn best_bid(&self) -> Option<(f64, f64)> {
+        self.bids.iter()
+            .next_back()
+            .map(|(price, size)| (price.into_inner(), *size))
+    }
+
+    /// Best (lowest) ask as `(price, size)`, or `None` if there are no asks
+    pub fn best_ask(&self) -> Option<(f64, f64)> {
+        self.asks.iter()
+            .next()
+            .map(|(price, size)| (price.into_inner(), *size))
+    }
+
+    /// Bid-ask spread (i.e. `best_ask - best_bid`). `None` unless both sides have levels
+    pub fn spread(&self) -> Option<f64> {
+        match (self.best_bid(), self.best_ask()) {
+            (Some((bid, _)), Some((ask, _))) => Some(ask - bid),
+            _ => None,
+        }
+    }
+
+    /// Snapshot of every level in the book, as of `ts`
+    pub fn snapshot(&self, ts: f64) -> Level2Snapshot {
+        let (bids, asks) = self.depth(usize::max_value());
+
+        Level2Snapshot {
+            bids,
+            asks,
+            symbol: self.symbol.clone(),
+            exchange: self.exchange,
+            ts,
+        }
+    }
+
+    /// Returns up to `levels` price levels as `(price, size)` for each side. Bids are sorted from the best
+    /// (highest) price down, and asks from the best (lowest) price up.
+    pub fn depth(&self, levels: usize) -> (Vec<(f64, f64)>, Vec<(f64, f64)>) {
+        let bids = self.bids.iter()
+            .rev()
+            .take(levels)
+            .map(|(price, size)| (price.into_inner(), *size))
+            .collect();
+
+        let asks = self.asks.iter()
+            .take(levels)
+            .map(|(price, size)| (price.into_inner(), *size))
+            .collect();
+
+        (bids, asks)
+    }
+}
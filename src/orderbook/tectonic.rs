@@ -1,181 +1,418 @@
-use std::net::TcpStream;
-use std::io::{Error, Read, Write};
-use std::time::Duration;
-use std::str;
-
-use orderbook::{self, Delta};
-
-/// Contains all fields necessary for a successful connection to TectonicDB.
-pub struct TectonicConnection {
-    /// TectonicDB host
-    host: String,
-    /// Port
-    port: u16,
-    
-    /// TCP client connection for internal use
-    pub connection: TcpStream,
-
-    /// Currently selected database
-    pub db: Option<String>,
-}
-
-impl TectonicConnection {
-    /// Clones the structure
-    pub fn clone(&self) -> Self {
-        Self {
-            host: self.host.clone(),
-            port: self.port.clone(),
-
-            connection: self.connection.try_clone().expect("Failed to clone tectonic TcpStream"),
-
-            db: Some(self.db.as_ref().unwrap_or(&String::from("")).clone())
-        }
-    }
-    /// Creates a new TectonicDB connection. If no host or port are provided, the connection defaults to `localhost:9001`
-    pub fn new(host: Option<String>, port: Option<u16>) -> Result<TectonicConnection, Error>{
-        let host = host.unwrap_or("127.0.0.1".into());
-        let port = port.unwrap_or(9001);
-
-        let connect_address = format!("{}:{}", host, port);
-
-        // Set socket timeout to 1s
-        let connection = TcpStream::connect_timeout(&connect_address.parse().unwrap(), Duration::new(1,0))?;
-        // Resolves issue #1. Please remove this comment if this line is changed
-        let _ = connection.set_read_timeout(Some(Duration::new(1, 0)));
-
-        return Ok(TectonicConnection {
-            host,
-            port,
-
-            connection,
-
-            db: None,
-        })
-    }
-
-    /// Sends a message to the TectonicDB server
-    pub fn cmd(&mut self, message: String) -> Result<String, Error> { 
-        // Convert the message into bytes using the `.as_bytes()` method
-        let _ = self.connection.write(format!("{}\n", message).as_bytes());
-
-        let mut buf = [0; 256];
-        let _ = self.connection.read(&mut buf);
-
-        Ok(str::from_utf8(&buf).unwrap().to_owned())
-    }
-    /// Return help dialog
-    pub fn help(&mut self) -> Result<String, Error> {
-        self.cmd("HELP".into())
-    }
-    /// Ping the server
-    pub fn ping(&mut self) -> Result<String, Error> {
-        self.cmd("PING".into())
-    }
-    /// Get server metrics and information
-    pub fn info(&mut self) -> Result<String, Error> {
-        self.cmd("INFO".into())
-    }
-    /// Get server performance metrics
-    pub fn perf(&mut self) -> Result<String, Error> {
-        self.cmd("PERF".into())
-    }
-    /// Write data in database to disk
-    pub fn flush(&mut self) -> Result<String, Error> {
-        self.cmd("FLUSH".into())
-    }
-    /// Write all data in every database to disk
-    pub fn flush_all(&mut self) -> Result<String, Error> {
-        self.cmd("FLUSH ALL".into())
-    }
-    /// Clear the current database of all entries
-    pub fn clear(&mut self) -> Result<String, Error> {
-        self.cmd("CLEAR".into())
-    }
-    /// Clear every database of all entries
-    pub fn clear_all(&mut self) -> Result<String, Error> {
-        self.cmd("CLEAR ALL".into())
-    }
-    /// Count entries in current database TODO: make it return an int value
-    pub fn count(&mut self) -> Result<String, Error> {
-        self.cmd("COUNT".into())
-    }
-    /// Count entries in all databases
-    pub fn count_all(&mut self) -> Result<String, Error> {
-        self.cmd("COUNT ALL".into())
-    }
-    /// Checks if `db_name` exists
-    pub fn exists(&mut self, db_name: String) -> Result<bool, Error> {
-        let result = self.cmd(format!("EXISTS {}", db_name))?;
-
-        Ok(result.chars().next().unwrap_or('0') == '1')
-    }
-    /// Bulk-add deltas to the tectonic server
-    pub fn bulk_add(&mut self, deltas: &Vec<Delta>) -> Result<String, Error> {
-        let _ = self.cmd("BULKADD".into());
-
-        for event in deltas {
-            let is_trade: String = if event.event & orderbook::TRADE == orderbook::TRADE {"t".into()} else {"f".into()};
-            let is_bid: String = if event.event & orderbook::BID == orderbook::BID {"t".into()} else {"f".into()};
-
-            let _ = self.cmd(format!("{:.3}, {}, {}, {}, {}, {};", event.ts, event.seq, is_trade, is_bid, event.price, event.size));
-        }
-
-        self.cmd("DDAKLUB".into())
-    }
-    /// Bulk-add deltas into a specified database `db_name`
-    pub fn bulk_add_into(&mut self, db_name: String, deltas: &Vec<Delta>) -> Result<String, Error> {
-        let _ = self.cmd(format!("BULKADD INTO {}", db_name));
-
-        for event in deltas {
-            let _ = self.cmd(format!("{:.3}, {}, {}, {}, {}, {};", 
-                event.ts, 
-                event.seq, 
-                if event.event & orderbook::TRADE == orderbook::TRADE {String::from("t")} else {String::from("f")},
-                if event.event & orderbook::BID == orderbook::BID {String::from("t")} else {String::from("f")},
-                event.price, 
-                event.size));
-        }
-
-        self.cmd("DDAKLUB".into())
-    }
-    /// Create new database `db_name`
-    pub fn create(&mut self, db_name: String) -> Result<String, Error> {
-        self.cmd(format!("CREATE {}", db_name))
-    }
-    /// Insert into the currently selected database
-    pub fn insert(&mut self, delta: &Delta) -> Result<String, Error> {
-        self.cmd(format!("INSERT {:.3}, {}, {}, {}, {}, {};", 
-            delta.ts, 
-            delta.seq, 
-            if delta.event & orderbook::TRADE == orderbook::TRADE {String::from("t")} else {String::from("f")},
-            if delta.event & orderbook::BID == orderbook::BID {String::from("t")} else {String::from("f")}, 
-            delta.price, 
-            delta.size))
-    }
-    /// Insert into the database `db_name`
-    pub fn insert_into(&mut self, db_name: String, delta: &Delta) -> Result<String, Error> {
-        self.cmd(format!("INSERT {:.3}, {}, {}, {}, {}, {}; INTO {}", 
-            delta.ts, 
-            delta.seq, 
-            if delta.event & orderbook::TRADE == orderbook::TRADE {String::from("t")} else {String::from("f")},
-            if delta.event & orderbook::BID == orderbook::BID {String::from("t")} else {String::from("f")}, 
-            delta.price, 
-            delta.size,
-            db_name))
-    }
-}
-
-impl Clone for TectonicConnection {
-    fn clone(&self) -> Self {
-        Self {
-            host: self.host.clone(),
-            port: self.port.clone(), 
-
-            connection: self.connection
-                .try_clone()
-                .expect("Failed to clone Tectonic TCP Connection"),
-
-            db: self.db.clone(),
-        }
-    }
+use std::error;
+use std::fmt;
+use std::net::TcpStream;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::thread;
+use std::time::Duration;
+use std::str;
+
+use serde_json;
+
+use exchange;
+use orderbook::{self, Delta, DeltaEvent};
+
+/// Number of times we try to reconnect to TectonicDB after the connection drops
+pub const RECONNECT_ATTEMPTS: u32 = 5;
+/// Delay before the first reconnection attempt. Every attempt after it waits this much longer than the last
+const RECONNECT_DELAY_MS: u64 = 500;
+
+/// Errors returned by a [`TectonicConnection`]
+#[derive(Debug)]
+pub enum TectonicError {
+    /// The command failed, but the connection is still up
+    Io(Error),
+    /// The connection to the server at the address dropped, and couldn't be reestablished.
+    /// Contains the error of the last reconnection attempt
+    Disconnected(String, Error),
+    /// The server returned an error, or a response we couldn't parse
+    InvalidResponse(String),
+}
+
+impl fmt::Display for TectonicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TectonicError::Io(e) => write!(f, "TectonicDB I/O error: {}", e),
+            TectonicError::Disconnected(address, e) => write!(f, "Lost connection to TectonicDB at {} after {} reconnection attempts: {}", address, RECONNECT_ATTEMPTS, e),
+            TectonicError::InvalidResponse(response) => write!(f, "Invalid TectonicDB response: {}", response),
+        }
+    }
+}
+
+impl error::Error for TectonicError {
+    fn description(&self) -> &str {
+        match self {
+            TectonicError::Io(_) => "TectonicDB I/O error",
+            TectonicError::Disconnected(_, _) => "Lost connection to TectonicDB",
+            TectonicError::InvalidResponse(_) => "Invalid TectonicDB response",
+        }
+    }
+}
+
+/// Row returned by `GET ... AS JSON`
+#[derive(Deserialize)]
+struct Row {
+    ts: f64,
+    seq: u32,
+    is_trade: bool,
+    is_bid: bool,
+    price: f32,
+    size: f32,
+}
+
+/// Parses the rows returned by `GET ... AS JSON` into deltas of `symbol`. TectonicDB doesn't store
+/// whether a level was removed, so rows with a size of zero are treated as removals.
+pub fn parse_rows(symbol: &str, response: &str) -> Result<Vec<Delta>, TectonicError> {
+    let response = response.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+
+    if response.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let rows: Vec<Row> = serde_json::from_str(response)
+        .map_err(|_| TectonicError::InvalidResponse(response.to_string()))?;
+
+    Ok(rows.into_iter()
+        .map(|row| Delta {
+            symbol: symbol.to_string(),
+            price: row.price,
+            size: row.size,
+            seq: row.seq,
+            event: match (row.is_trade, row.is_bid, row.size == 0.0) {
+                (true, true, _) => DeltaEvent::BidTrade,
+                (true, false, _) => DeltaEvent::AskTrade,
+                (false, true, true) => DeltaEvent::BidRemove,
+                (false, false, true) => DeltaEvent::AskRemove,
+                (false, true, false) => DeltaEvent::BidUpdate,
+                (false, false, false) => DeltaEvent::AskUpdate,
+            }.bits(),
+            ts: row.ts,
+            received_ts: None,
+        })
+        .collect())
+}
+
+/// Returns true if the error means that the connection to the server is gone, rather than the command failing
+pub fn is_disconnect(e: &Error) -> bool {
+    match e.kind() {
+        ErrorKind::BrokenPipe |
+        ErrorKind::ConnectionReset |
+        ErrorKind::ConnectionAborted |
+        ErrorKind::NotConnected |
+        ErrorKind::UnexpectedEof => true,
+        _ => false,
+    }
+}
+
+/// Opens a TCP connection to TectonicDB
+fn connect(host: &str, port: u16) -> Result<TcpStream, Error> {
+    let connect_address = format!("{}:{}", host, port);
+
+    // Set socket timeout to 1s
+    let connection = TcpStream::connect_timeout(&connect_address.parse().unwrap(), Duration::new(1,0))?;
+    // Resolves issue #1. Please remove this comment if this line is changed
+    let _ = connection.set_read_timeout(Some(Duration::new(1, 0)));
+
+    Ok(connection)
+}
+
+/// Contains all fields necessary for a successful connection to TectonicDB.
+pub struct TectonicConnection {
+    /// TectonicDB host
+    host: String,
+    /// Port
+    port: u16,
+    
+    /// TCP client connection for internal use
+    pub connection: TcpStream,
+
+    /// Currently selected database
+    pub db: Option<String>,
+
+    /// Set once a command fails because the connection dropped, and cleared once we've reconnected
+    connected: bool,
+}
+
+impl TectonicConnection {
+    /// Clones the structure
+    pub fn clone(&self) -> Self {
+        Self {
+            host: self.host.clone(),
+            port: self.port.clone(),
+
+            connection: self.connection.try_clone().expect("Failed to clone tectonic TcpStream"),
+
+            db: Some(self.db.as_ref().unwrap_or(&String::from("")).clone()),
+
+            connected: self.connected,
+        }
+    }
+    /// Creates a new TectonicDB connection. If no host or port are provided, the connection defaults to `localhost:9001`
+    pub fn new(host: Option<String>, port: Option<u16>) -> Result<TectonicConnection, Error>{
+        let host = host.unwrap_or("127.0.0.1".into());
+        let port = port.unwrap_or(9001);
+
+        let connection = connect(&host, port)?;
+
+        return Ok(TectonicConnection {
+            host,
+            port,
+
+            connection,
+
+            db: None,
+
+            connected: true,
+        })
+    }
+
+    /// Whether or not the connection was up the last time we talked to the server
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Reconnects to the server, waiting a little longer after every failed attempt.
+    /// The selected database, if any, is selected again on the new connection.
+    pub fn reconnect(&mut self) -> Result<(), TectonicError> {
+        let address = format!("{}:{}", self.host, self.port);
+        let mut last_error = Error::new(ErrorKind::NotConnected, "No reconnection attempts were made");
+
+        for attempt in 1..RECONNECT_ATTEMPTS + 1 {
+            thread::sleep(Duration::from_millis(RECONNECT_DELAY_MS * attempt as u64));
+
+            match connect(&self.host, self.port) {
+                Ok(connection) => {
+                    self.connection = connection;
+                    self.connected = true;
+
+                    if let Some(db) = self.db.clone().filter(|db| !db.is_empty()) {
+                        self.send(&format!("USE {}", db)).map_err(TectonicError::Io)?;
+                    }
+
+                    return Ok(());
+                },
+                Err(e) => {
+                    println!("Failed to reconnect to TectonicDB at {} (attempt {} of {}): {}", address, attempt, RECONNECT_ATTEMPTS, e);
+                    last_error = e;
+                }
+            }
+        }
+
+        Err(TectonicError::Disconnected(address, last_error))
+    }
+
+    /// Runs `f` against the connection. If the connection turns out to be gone, we reconnect
+    /// and run it one more time.
+    fn with_reconnect<T, F>(&mut self, mut f: F) -> Result<T, TectonicError>
+        where F: FnMut(&mut Self) -> Result<T, Error>
+    {
+        match f(self) {
+            Err(ref e) if is_disconnect(e) => {
+                println!("TectonicDB connection dropped ({}). Reconnecting...", e);
+                self.connected = false;
+                self.reconnect()?;
+
+                f(self).map_err(TectonicError::Io)
+            },
+            result => result.map_err(TectonicError::Io),
+        }
+    }
+
+    /// Writes a message to the server and reads its response, without reconnecting
+    fn send(&mut self, message: &str) -> Result<String, Error> {
+        // Convert the message into bytes using the `.as_bytes()` method
+        self.connection.write_all(format!("{}\n", message).as_bytes())?;
+
+        let mut buf = [0; 256];
+        match self.connection.read(&mut buf) {
+            Ok(0) => return Err(Error::new(ErrorKind::UnexpectedEof, "TectonicDB closed the connection")),
+            Ok(_) => (),
+            // Reads time out when the server has nothing to say (see issue #1)
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => (),
+            Err(e) => return Err(e),
+        }
+
+        Ok(str::from_utf8(&buf).unwrap().to_owned())
+    }
+
+    /// Writes a message to the server and reads its whole response, which may not fit in a single read.
+    /// The response ends with a newline, or once the server stops sending data.
+    fn send_and_read_to_end(&mut self, message: &str) -> Result<String, Error> {
+        self.connection.write_all(format!("{}\n", message).as_bytes())?;
+
+        let mut response = vec![];
+        let mut buf = [0; 4096];
+
+        loop {
+            match self.connection.read(&mut buf) {
+                Ok(0) if response.is_empty() => return Err(Error::new(ErrorKind::UnexpectedEof, "TectonicDB closed the connection")),
+                Ok(0) => break,
+                Ok(n) => {
+                    response.extend_from_slice(&buf[..n]);
+
+                    if response.last() == Some(&b'\n') {
+                        break;
+                    }
+                },
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        String::from_utf8(response).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    /// Sends a message to the TectonicDB server, reconnecting if the connection dropped
+    pub fn cmd(&mut self, message: String) -> Result<String, TectonicError> {
+        self.with_reconnect(|connection| connection.send(&message))
+    }
+    /// Return help dialog
+    pub fn help(&mut self) -> Result<String, TectonicError> {
+        self.cmd("HELP".into())
+    }
+    /// Ping the server
+    pub fn ping(&mut self) -> Result<String, TectonicError> {
+        self.cmd("PING".into())
+    }
+    /// Get server metrics and information
+    pub fn info(&mut self) -> Result<String, TectonicError> {
+        self.cmd("INFO".into())
+    }
+    /// Get server performance metrics
+    pub fn perf(&mut self) -> Result<String, TectonicError> {
+        self.cmd("PERF".into())
+    }
+    /// Write data in database to disk
+    pub fn flush(&mut self) -> Result<String, TectonicError> {
+        self.cmd("FLUSH".into())
+    }
+    /// Write all data in every database to disk
+    pub fn flush_all(&mut self) -> Result<String, TectonicError> {
+        self.cmd("FLUSH ALL".into())
+    }
+    /// Clear the current database of all entries
+    pub fn clear(&mut self) -> Result<String, TectonicError> {
+        self.cmd("CLEAR".into())
+    }
+    /// Clear every database of all entries
+    pub fn clear_all(&mut self) -> Result<String, TectonicError> {
+        self.cmd("CLEAR ALL".into())
+    }
+    /// Count entries in current database TODO: make it return an int value
+    pub fn count(&mut self) -> Result<String, TectonicError> {
+        self.cmd("COUNT".into())
+    }
+    /// Count entries in all databases
+    pub fn count_all(&mut self) -> Result<String, TectonicError> {
+        self.cmd("COUNT ALL".into())
+    }
+    /// Checks if `db_name` exists
+    pub fn exists(&mut self, db_name: String) -> Result<bool, TectonicError> {
+        let result = self.cmd(format!("EXISTS {}", db_name))?;
+
+        Ok(result.chars().next().unwrap_or('0') == '1')
+    }
+    /// Bulk-add deltas to the tectonic server
+    pub fn bulk_add(&mut self, deltas: &Vec<Delta>) -> Result<String, TectonicError> {
+        let _ = self.cmd("BULKADD".into());
+
+        for event in deltas {
+            let is_trade: String = if event.event & orderbook::TRADE == orderbook::TRADE {"t".into()} else {"f".into()};
+            let is_bid: String = if event.event & orderbook::BID == orderbook::BID {"t".into()} else {"f".into()};
+
+            let _ = self.cmd(format!("{:.3}, {}, {}, {}, {}, {};", event.ts, event.seq, is_trade, is_bid, event.price, event.size));
+        }
+
+        self.cmd("DDAKLUB".into())
+    }
+    /// Bulk-add deltas into a specified database `db_name`
+    pub fn bulk_add_into(&mut self, db_name: String, deltas: &Vec<Delta>) -> Result<String, TectonicError> {
+        self.insert_batch(&db_name, deltas)
+    }
+    /// Insert deltas into the database `db` in a single `BULKADD` transaction. The database has to exist.
+    /// If the connection drops partway through, the whole transaction is sent again once we've reconnected.
+    pub fn insert_batch(&mut self, db: &str, deltas: &[Delta]) -> Result<String, TectonicError> {
+        self.with_reconnect(|connection| connection.send_batch(db, deltas))
+    }
+    /// Sends a `BULKADD` transaction without reconnecting
+    fn send_batch(&mut self, db: &str, deltas: &[Delta]) -> Result<String, Error> {
+        self.send(&format!("BULKADD INTO {}", db))?;
+
+        for event in deltas {
+            self.send(&format!("{:.3}, {}, {}, {}, {}, {};", 
+                event.ts, 
+                event.seq, 
+                if event.event & orderbook::TRADE == orderbook::TRADE {String::from("t")} else {String::from("f")},
+                if event.event & orderbook::BID == orderbook::BID {String::from("t")} else {String::from("f")},
+                event.price, 
+                event.size))?;
+        }
+
+        self.send("DDAKLUB")
+    }
+    /// Reads back the deltas stored in database `db` with a timestamp (in seconds) between `from_ts` and `to_ts`, inclusive.
+    /// Selects `db` as the current database. The symbol of the deltas is taken from the database name (i.e. `XBTUSD` for
+    /// `bitmex_XBTUSD`, see [`exchange::split_db_name`]), or is the whole name if it doesn't start with an exchange's.
+    pub fn get_range(&mut self, db: &str, from_ts: f64, to_ts: f64) -> Result<Vec<Delta>, TectonicError> {
+        // Ranges are queried in whole seconds, so we widen it here and filter the rows afterwards
+        let query = format!("GET ALL FROM {} TO {} AS JSON", from_ts.floor() as u64, to_ts.ceil() as u64);
+
+        let response = self.with_reconnect(|connection| {
+            connection.send(&format!("USE {}", db))?;
+            connection.send_and_read_to_end(&query)
+        })?;
+        self.db = Some(db.to_string());
+
+        if response.starts_with("ERR") {
+            return Err(TectonicError::InvalidResponse(response.trim().to_string()));
+        }
+
+        let symbol = exchange::split_db_name(db).map(|(_, symbol)| symbol).unwrap_or(db);
+
+        Ok(parse_rows(symbol, &response)?
+            .into_iter()
+            .filter(|delta| delta.ts >= from_ts && delta.ts <= to_ts)
+            .collect())
+    }
+    /// Create new database `db_name`
+    pub fn create(&mut self, db_name: String) -> Result<String, TectonicError> {
+        self.cmd(format!("CREATE {}", db_name))
+    }
+    /// Insert into the currently selected database
+    pub fn insert(&mut self, delta: &Delta) -> Result<String, TectonicError> {
+        self.cmd(format!("INSERT {:.3}, {}, {}, {}, {}, {};", 
+            delta.ts, 
+            delta.seq, 
+            if delta.event & orderbook::TRADE == orderbook::TRADE {String::from("t")} else {String::from("f")},
+            if delta.event & orderbook::BID == orderbook::BID {String::from("t")} else {String::from("f")}, 
+            delta.price, 
+            delta.size))
+    }
+    /// Insert into the database `db_name`
+    pub fn insert_into(&mut self, db_name: String, delta: &Delta) -> Result<String, TectonicError> {
+        self.cmd(format!("INSERT {:.3}, {}, {}, {}, {}, {}; INTO {}", 
+            delta.ts, 
+            delta.seq, 
+            if delta.event & orderbook::TRADE == orderbook::TRADE {String::from("t")} else {String::from("f")},
+            if delta.event & orderbook::BID == orderbook::BID {String::from("t")} else {String::from("f")}, 
+            delta.price, 
+            delta.size,
+            db_name))
+    }
+}
+
+impl Clone for TectonicConnection {
+    fn clone(&self) -> Self {
+        Self {
+            host: self.host.clone(),
+            port: self.port.clone(), 
+
+            connection: self.connection
+                .try_clone()
+                .expect("Failed to clone Tectonic TCP Connection"),
+
+            db: self.db.clone(),
+
+            connected: self.connected,
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,167 @@
+use std::error;
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+use redis::{self, Commands};
+use serde_json;
+
+use exchange::{self, Exchange, MarketType};
+use orderbook::Delta;
+use orderbook::tectonic::{TectonicConnection, TectonicError};
+
+/// Replays the deltas of a symbol stored in TectonicDB, publishing them to Redis as if they were coming
+/// in live. Consumers can then be developed and tested against realistic data without an exchange connection.
+///
+/// Replaying blocks the calling thread: an `async` version would need the 2018 edition, like an async `run` mode would.
+pub struct ReplaySession {
+    /// Connection to the TectonicDB server the deltas are stored in
+    pub tectonic: TectonicConnection,
+    /// Exchange the deltas were collected from
+    pub exchange: Exchange,
+    /// Symbol of the deltas, as stored (i.e. `XBTUSD`, for the `bitmex_XBTUSD` database)
+    pub symbol: String,
+    /// Start of the replayed range, as UNIX epoch time in seconds (inclusive)
+    pub start_ts: f64,
+    /// End of the replayed range, as UNIX epoch time in seconds (inclusive)
+    pub end_ts: f64,
+    /// How much faster than real time we replay (i.e. 1.0 is real time, 10.0 ten times faster).
+    /// `f64::INFINITY` publishes everything without waiting.
+    pub speed_multiplier: f64,
+    /// Market type the deltas were collected for. Deltas are published to the same channel the live
+    /// collector publishes them to, i.e. `bitmex:inverse_perpetual` (see [`exchange::market_channel`]).
+    /// Defaults to the exchange's [`Exchange::default_market_type`].
+    pub market_type: MarketType,
+}
+
+impl ReplaySession {
+    /// Creates a session replaying the symbol's deltas in real time, on the channel of the exchange's default market type
+    pub fn new(tectonic: TectonicConnection, exchange: Exchange, symbol: &str, start_ts: f64, end_ts: f64) -> ReplaySession {
+        ReplaySession {
+            tectonic,
+            exchange,
+            symbol: symbol.to_string(),
+            start_ts,
+            end_ts,
+            speed_multiplier: 1.0,
+            market_type: exchange.default_market_type(),
+        }
+    }
+
+    /// Redis channel the deltas are published to
+    pub fn channel(&self) -> String {
+        exchange::market_channel(&self.exchange.to_string(), Some(self.market_type))
+    }
+
+    /// Fetches the deltas in the range and publishes them, waiting between them as long as the exchange did
+    /// (divided by `speed_multiplier`). Returns once every delta has been published. Trades are stored as
+    /// deltas, so they're published on the same channel with their `TRADE` flag set.
+    pub fn run(&mut self, redis: &redis::Connection) -> Result<(), ReplayError> {
+        if !(self.speed_multiplier > 0.0) {
+            return Err(ReplayError::InvalidSpeed(self.speed_multiplier));
+        }
+
+        let db_name = format!("{}_{}", self.exchange, self.symbol);
+        let deltas = self.tectonic.get_range(&db_name, self.start_ts, self.end_ts)?;
+        let channel = self.channel();
+
+        info!("Replaying {} deltas from {} on {}", deltas.len(), db_name, channel);
+
+        for (wait, batch) in schedule(deltas, self.speed_multiplier) {
+            thread::sleep(wait);
+
+            let _ = redis.publish::<&str, &str, u8>(&channel, &serde_json::to_string(&batch)?)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Sorts deltas by timestamp and groups the ones sharing a timestamp, like the messages they were received in.
+/// Each batch comes with how long to wait after the previous one, given the replay speed.
+pub fn schedule(mut deltas: Vec<Delta>, speed_multiplier: f64) -> Vec<(Duration, Vec<Delta>)> {
+    // The sort is stable, so deltas sharing a timestamp stay in the order they were stored in
+    deltas.sort_by(|a, b| a.ts.partial_cmp(&b.ts).unwrap_or(::std::cmp::Ordering::Equal));
+
+    let mut batches: Vec<(Duration, Vec<Delta>)> = vec![];
+    let mut last_ts: Option<f64> = None;
+
+    for delta in deltas {
+        if last_ts == Some(delta.ts) {
+            batches.last_mut().unwrap().1.push(delta);
+            continue;
+        }
+
+        let wait = match last_ts {
+            Some(last_ts) => duration_from_secs((delta.ts - last_ts) / speed_multiplier),
+            None => Duration::from_secs(0),
+        };
+
+        last_ts = Some(delta.ts);
+        batches.push((wait, vec![delta]));
+    }
+
+    batches
+}
+
+/// Converts seconds to a duration. Negative or non-finite waits (i.e. at infinite speed) don't wait at all
+fn duration_from_secs(secs: f64) -> Duration {
+    if !secs.is_finite() || secs <= 0.0 {
+        return Duration::from_secs(0);
+    }
+
+    Duration::new(secs.trunc() as u64, (secs.fract() * 1e9).round() as u32)
+}
+
+/// Error replaying stored deltas
+#[derive(Debug)]
+pub enum ReplayError {
+    /// Failed to read the deltas from TectonicDB
+    Tectonic(TectonicError),
+    /// Failed to publish the deltas to Redis
+    Redis(redis::RedisError),
+    /// Failed to serialize the deltas
+    Json(serde_json::Error),
+    /// The speed multiplier isn't a positive number
+    InvalidSpeed(f64),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Tectonic(e) => write!(f, "TectonicDB error: {}", e),
+            ReplayError::Redis(e) => write!(f, "Redis error: {}", e),
+            ReplayError::Json(e) => write!(f, "Failed to serialize deltas: {}", e),
+            ReplayError::InvalidSpeed(speed) => write!(f, "Replay speed must be positive, got {}", speed),
+        }
+    }
+}
+
+impl error::Error for ReplayError {
+    fn description(&self) -> &str {
+        match self {
+            ReplayError::Tectonic(_) => "TectonicDB error",
+            ReplayError::Redis(_) => "Redis error",
+            ReplayError::Json(_) => "Failed to serialize deltas",
+            ReplayError::InvalidSpeed(_) => "Invalid replay speed",
+        }
+    }
+}
+
+impl From<TectonicError> for ReplayError {
+    fn from(e: TectonicError) -> Self {
+        ReplayError::Tectonic(e)
+    }
+}
+
+impl From<redis::RedisError> for ReplayError {
+    fn from(e: redis::RedisError) -> Self {
+        ReplayError::Redis(e)
+    }
+}
+
+impl From<serde_json::Error> for ReplayError {
+    fn from(e: serde_json::Error) -> Self {
+        ReplayError::Json(e)
+    }
+}
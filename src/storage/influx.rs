@@ -0,0 +1,124 @@
+use reqwest;
+
+use orderbook;
+use storage::{StorageBackend, StorageError};
+
+/// Measurement deltas are written to
+pub const MEASUREMENT: &str = "orderbook";
+
+/// Stores deltas in InfluxDB through the HTTP `/write` endpoint, using the line protocol. Deltas are
+/// buffered and written once `batch_size` of them have been queued, or when flushed explicitly.
+pub struct InfluxBackend {
+    /// InfluxDB HTTP API (i.e. `http://localhost:8086`)
+    pub host: String,
+    /// Database to write to
+    pub database: String,
+    /// Number of deltas to buffer before writing them
+    pub batch_size: usize,
+
+    /// Exchange name, stored as a tag with every point
+    exchange: String,
+    /// Deltas waiting to be written
+    buffer: Vec<orderbook::Delta>,
+
+    /// HTTP client
+    client: reqwest::Client,
+}
+
+impl InfluxBackend {
+    /// Creates a backend writing `exchange`'s deltas to `database`. No request is made until the first write.
+    pub fn new(host: &str, database: &str, batch_size: usize, exchange: &str) -> InfluxBackend {
+        InfluxBackend {
+            host: host.trim_right_matches('/').to_string(),
+            database: database.to_string(),
+            batch_size,
+
+            exchange: exchange.to_string(),
+            buffer: Vec::with_capacity(batch_size),
+
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+/// Escapes commas, spaces, and equal signs in tag values, as required by the line protocol
+fn escape_tag(value: &str) -> String {
+    value.replace(",", "\\,").replace(" ", "\\ ").replace("=", "\\=")
+}
+
+/// Formats a delta as a single line protocol point, with a nanosecond timestamp. Example:
+/// `orderbook,exchange=bitmex,symbol=XBTUSD price=9000.0,size=100.0,seq=1i,event=36i 1535487251154000000`
+pub fn line_protocol(exchange: &str, delta: &orderbook::Delta) -> String {
+    // Deltas are timestamped with (at most) microsecond precision, so we round to the microsecond
+    // first to avoid writing the float error out as nanoseconds.
+    let ts = (delta.ts * 1_000_000.0).round() as u64 * 1_000;
+
+    format!("{},exchange={},symbol={} price={:?},size={:?},seq={}i,event={}i {}",
+        MEASUREMENT,
+        escape_tag(exchange),
+        escape_tag(&delta.symbol),
+        delta.price,
+        delta.size,
+        delta.seq,
+        delta.event,
+        ts)
+}
+
+impl StorageBackend for InfluxBackend {
+    fn create(&mut self, _: &str) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    fn insert(&mut self, deltas: &[orderbook::Delta]) -> Result<(), StorageError> {
+        self.buffer.extend_from_slice(deltas);
+
+        if self.buffer.len() >= self.batch_size {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), StorageError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let body = self.buffer.iter()
+            .map(|delta| line_protocol(&self.exchange, delta))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let mut response = self.client
+            .post(&format!("{}/write", self.host))
+            .query(&[("db", self.database.as_str()), ("precision", "ns")])
+            .body(body)
+            .send()?;
+
+        if !response.status().is_success() {
+            return Err(StorageError::InfluxError(response.status().as_u16(), response.text().unwrap_or_default()));
+        }
+
+        self.buffer.clear();
+
+        Ok(())
+    }
+
+    fn box_clone(&self) -> Box<dyn StorageBackend> {
+        // The buffer isn't cloned, otherwise its deltas would be written twice
+        Box::new(InfluxBackend {
+            host: self.host.clone(),
+            database: self.database.clone(),
+            batch_size: self.batch_size,
+
+            exchange: self.exchange.clone(),
+            buffer: Vec::with_capacity(self.batch_size),
+
+            client: self.client.clone(),
+        })
+    }
+
+    fn set_exchange(&mut self, exchange: &str) {
+        self.exchange = exchange.to_string();
+    }
+}
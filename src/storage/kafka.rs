@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use rdkafka::ClientContext;
+use rdkafka::config::ClientConfig;
+use rdkafka::message::Message;
+use rdkafka::producer::{BaseRecord, DeliveryResult, ProducerContext, ThreadedProducer};
+use serde_json;
+
+use orderbook;
+use storage::{StorageBackend, StorageError};
+
+/// Topic deltas are produced to, unless configured otherwise. `{exchange}` and `{symbol}`
+/// are replaced with the exchange name and the delta's symbol.
+pub const DEFAULT_TOPIC: &str = "orderbook.{exchange}.{symbol}";
+
+/// Receives the delivery reports of the producer, on librdkafka's background thread
+struct DeliveryContext {
+    /// Number of messages that couldn't be delivered
+    failures: Arc<AtomicUsize>,
+}
+
+impl ClientContext for DeliveryContext {}
+
+impl ProducerContext for DeliveryContext {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &DeliveryResult, _: Self::DeliveryOpaque) {
+        if let Err((e, message)) = result {
+            self.failures.fetch_add(1, Ordering::SeqCst);
+            error!("Failed to deliver delta to Kafka topic {}: {}", message.topic(), e);
+        }
+    }
+}
+
+/// Produces deltas to Kafka as JSON, keyed by symbol so that every update of a symbol lands on
+/// the same partition (and stays in order). Deliveries are acknowledged asynchronously by
+/// librdkafka's background thread, so producing never blocks the websocket handler. Failed
+/// deliveries are logged and counted (see [`KafkaBackend::delivery_failures`]).
+#[derive(Clone)]
+pub struct KafkaBackend {
+    /// Topic format. See [`DEFAULT_TOPIC`]
+    pub topic: String,
+
+    /// Exchange name, used to build topic names
+    exchange: String,
+
+    /// Number of messages that couldn't be delivered, shared with the producer's delivery callback
+    failures: Arc<AtomicUsize>,
+
+    /// Kafka producer. Clones share the same underlying librdkafka producer
+    producer: Arc<ThreadedProducer<DeliveryContext>>,
+}
+
+impl KafkaBackend {
+    /// Creates a producer from librdkafka configuration properties (i.e. `bootstrap.servers`,
+    /// `security.protocol`, `sasl.username`). No connection details are assumed.
+    pub fn new(config: HashMap<String, String>, topic: Option<String>, exchange: &str) -> Result<KafkaBackend, StorageError> {
+        let mut client_config = ClientConfig::new();
+
+        for (key, value) in &config {
+            client_config.set(key, value);
+        }
+
+        let failures = Arc::new(AtomicUsize::new(0));
+        let producer = client_config.create_with_context(DeliveryContext { failures: failures.clone() })?;
+
+        Ok(KafkaBackend {
+            topic: topic.unwrap_or(DEFAULT_TOPIC.into()),
+
+            exchange: exchange.to_string(),
+
+            failures,
+
+            producer: Arc::new(producer),
+        })
+    }
+
+    /// Number of deltas Kafka failed to acknowledge, once librdkafka gave up retrying them
+    pub fn delivery_failures(&self) -> usize {
+        self.failures.load(Ordering::SeqCst)
+    }
+}
+
+/// Builds the name of the topic a symbol's deltas are produced to (i.e. `orderbook.bitmex.XBTUSD`)
+pub fn topic_name(format: &str, exchange: &str, symbol: &str) -> String {
+    format.replace("{exchange}", exchange).replace("{symbol}", symbol)
+}
+
+impl StorageBackend for KafkaBackend {
+    fn create(&mut self, _: &str) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    fn insert(&mut self, deltas: &[orderbook::Delta]) -> Result<(), StorageError> {
+        let mut queued = 0;
+        let mut failed = 0;
+        let mut last_error = None;
+
+        for delta in deltas {
+            let topic = topic_name(&self.topic, &self.exchange, &delta.symbol);
+            let payload = serde_json::to_string(delta).unwrap();
+
+            // librdkafka retries and acknowledges the message in the background, reporting failed
+            // deliveries to `DeliveryContext`, and `flush` waits for what's still in flight. Sending
+            // itself only fails when the message can't be queued (i.e. the queue is full), in which
+            // case we keep going with the rest of the deltas.
+            match self.producer.send(BaseRecord::to(&topic).key(&delta.symbol).payload(&payload)) {
+                Ok(()) => queued += 1,
+                Err((e, _)) => {
+                    failed += 1;
+                    last_error = Some(e);
+                },
+            }
+        }
+
+        match last_error {
+            Some(e) => Err(StorageError::KafkaQueue(queued, failed, e)),
+            None => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), StorageError> {
+        self.producer.flush(Duration::from_secs(10));
+
+        Ok(())
+    }
+
+    fn box_clone(&self) -> Box<dyn StorageBackend> {
+        Box::new(self.clone())
+    }
+
+    fn set_exchange(&mut self, exchange: &str) {
+        self.exchange = exchange.to_string();
+    }
+}
@@ -0,0 +1,233 @@
+/// InfluxDB storage backend
+pub mod influx;
+/// Kafka storage backend
+#[cfg(feature = "kafka")]
+pub mod kafka;
+/// PostgreSQL storage backend
+#[cfg(feature = "postgres")]
+pub mod postgres;
+
+use std::collections::{HashMap, HashSet};
+use std::error;
+use std::fmt;
+use std::io;
+
+#[cfg(feature = "postgres")]
+use diesel;
+#[cfg(feature = "kafka")]
+use rdkafka::error::KafkaError;
+use reqwest;
+use ws;
+
+use orderbook;
+use orderbook::tectonic::{TectonicConnection, TectonicError};
+
+/// Storage the collected deltas are warehoused in. Every collector holds its own backend, so
+/// a backend is tied to the exchange it was created for (see [`StorageBackend::set_exchange`]).
+pub trait StorageBackend: Send {
+    /// Prepares the storage for a single `<exchange>_<symbol>` pair (i.e. creates the TectonicDB
+    /// database if it doesn't exist yet). Backends that store every pair together can ignore this.
+    fn create(&mut self, db_name: &str) -> Result<(), StorageError>;
+    /// Queues deltas for insertion. Deltas may not be written until [`StorageBackend::flush`] is called.
+    fn insert(&mut self, deltas: &[orderbook::Delta]) -> Result<(), StorageError>;
+    /// Writes every queued delta to the storage
+    fn flush(&mut self) -> Result<(), StorageError>;
+    /// Clones the backend, opening a new connection if needed
+    fn box_clone(&self) -> Box<dyn StorageBackend>;
+    /// Changes the exchange name deltas are stored under (i.e. `bitmex_testnet` to keep testnet data apart)
+    fn set_exchange(&mut self, exchange: &str);
+}
+
+impl Clone for Box<dyn StorageBackend> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// Stores deltas in TectonicDB, with a database for every `<exchange>_<symbol>` pair
+#[derive(Clone)]
+pub struct TectonicBackend {
+    /// TectonicDB connection
+    connection: TectonicConnection,
+    /// Exchange name, used as the prefix of database names
+    exchange: String,
+    /// Databases we know exist. Any other database is created before we insert into it
+    created: HashSet<String>,
+}
+
+impl TectonicBackend {
+    /// Connects to TectonicDB. If no host or port are provided, the connection defaults to `localhost:9001`
+    pub fn new(host: Option<String>, port: Option<u16>, exchange: &str) -> Result<TectonicBackend, StorageError> {
+        Ok(TectonicBackend::from_connection(TectonicConnection::new(host, port)?, exchange))
+    }
+
+    /// Wraps an existing TectonicDB connection
+    pub fn from_connection(connection: TectonicConnection, exchange: &str) -> TectonicBackend {
+        TectonicBackend {
+            connection,
+            exchange: exchange.to_string(),
+            created: HashSet::new(),
+        }
+    }
+}
+
+impl StorageBackend for TectonicBackend {
+    fn create(&mut self, db_name: &str) -> Result<(), StorageError> {
+        if !self.connection.exists(db_name.to_string())? {
+            self.connection.create(db_name.to_string())?;
+        }
+
+        self.created.insert(db_name.to_string());
+
+        Ok(())
+    }
+
+    fn insert(&mut self, deltas: &[orderbook::Delta]) -> Result<(), StorageError> {
+        // TectonicDB stores every symbol in its own database, so we bulk add each symbol separately
+        let mut symbols: HashMap<&str, Vec<orderbook::Delta>> = HashMap::new();
+
+        for delta in deltas {
+            symbols.entry(delta.symbol.as_str())
+                .or_insert_with(Vec::new)
+                .push(delta.clone());
+        }
+
+        for (symbol, deltas) in symbols {
+            let db_name = format!("{}_{}", self.exchange, symbol);
+
+            // Databases are created lazily, the first time a symbol's deltas are inserted
+            if !self.created.contains(&db_name) {
+                self.create(&db_name)?;
+            }
+
+            self.connection.insert_batch(&db_name, &deltas)?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), StorageError> {
+        self.connection.flush_all()?;
+
+        Ok(())
+    }
+
+    fn box_clone(&self) -> Box<dyn StorageBackend> {
+        Box::new(self.clone())
+    }
+
+    fn set_exchange(&mut self, exchange: &str) {
+        self.exchange = exchange.to_string();
+    }
+}
+
+/// Errors returned by a [`StorageBackend`]
+#[derive(Debug)]
+pub enum StorageError {
+    /// TectonicDB connection error
+    Io(io::Error),
+    /// TectonicDB command failed, or the connection couldn't be reestablished
+    Tectonic(TectonicError),
+    /// Failed to connect to Postgres
+    #[cfg(feature = "postgres")]
+    Connection(diesel::ConnectionError),
+    /// Postgres query error
+    #[cfg(feature = "postgres")]
+    Query(diesel::result::Error),
+    /// Failed to send a request to InfluxDB
+    Http(reqwest::Error),
+    /// InfluxDB rejected a write, with the HTTP status code and response body
+    InfluxError(u16, String),
+    /// Failed to create the Kafka producer
+    #[cfg(feature = "kafka")]
+    Kafka(KafkaError),
+    /// Some deltas couldn't be queued for Kafka, with the number of deltas queued and failed, and the last error
+    #[cfg(feature = "kafka")]
+    KafkaQueue(usize, usize, KafkaError),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StorageError::Io(e) => write!(f, "Storage I/O error: {}", e),
+            StorageError::Tectonic(e) => write!(f, "{}", e),
+            #[cfg(feature = "postgres")]
+            StorageError::Connection(e) => write!(f, "Failed to connect to storage: {}", e),
+            #[cfg(feature = "postgres")]
+            StorageError::Query(e) => write!(f, "Storage query failed: {}", e),
+            StorageError::Http(e) => write!(f, "Storage request failed: {}", e),
+            StorageError::InfluxError(status, body) => write!(f, "InfluxDB write failed with status {}: {}", status, body),
+            #[cfg(feature = "kafka")]
+            StorageError::Kafka(e) => write!(f, "Kafka producer error: {}", e),
+            #[cfg(feature = "kafka")]
+            StorageError::KafkaQueue(queued, failed, e) => {
+                write!(f, "Queued {} deltas for Kafka, {} failed: {}", queued, failed, e)
+            },
+        }
+    }
+}
+
+impl error::Error for StorageError {
+    fn description(&self) -> &str {
+        match self {
+            StorageError::Io(_) => "Storage I/O error",
+            StorageError::Tectonic(_) => "TectonicDB error",
+            #[cfg(feature = "postgres")]
+            StorageError::Connection(_) => "Failed to connect to storage",
+            #[cfg(feature = "postgres")]
+            StorageError::Query(_) => "Storage query failed",
+            StorageError::Http(_) => "Storage request failed",
+            StorageError::InfluxError(_, _) => "InfluxDB write failed",
+            #[cfg(feature = "kafka")]
+            StorageError::Kafka(_) => "Kafka producer error",
+            #[cfg(feature = "kafka")]
+            StorageError::KafkaQueue(_, _, _) => "Failed to queue deltas for Kafka",
+        }
+    }
+}
+
+impl From<io::Error> for StorageError {
+    fn from(e: io::Error) -> StorageError {
+        StorageError::Io(e)
+    }
+}
+
+impl From<TectonicError> for StorageError {
+    fn from(e: TectonicError) -> StorageError {
+        StorageError::Tectonic(e)
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<diesel::ConnectionError> for StorageError {
+    fn from(e: diesel::ConnectionError) -> StorageError {
+        StorageError::Connection(e)
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<diesel::result::Error> for StorageError {
+    fn from(e: diesel::result::Error) -> StorageError {
+        StorageError::Query(e)
+    }
+}
+
+impl From<reqwest::Error> for StorageError {
+    fn from(e: reqwest::Error) -> StorageError {
+        StorageError::Http(e)
+    }
+}
+
+#[cfg(feature = "kafka")]
+impl From<KafkaError> for StorageError {
+    fn from(e: KafkaError) -> StorageError {
+        StorageError::Kafka(e)
+    }
+}
+
+impl From<StorageError> for ws::Error {
+    /// Lets the websocket handlers use `?` on storage operations
+    fn from(e: StorageError) -> ws::Error {
+        ws::Error::new(ws::ErrorKind::Internal, e.to_string())
+    }
+}
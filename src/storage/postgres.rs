@@ -0,0 +1,143 @@
+use std::sync::{Arc, Mutex};
+
+use diesel::{self, Connection, ExpressionMethods, RunQueryDsl};
+use diesel::pg::PgConnection;
+
+use orderbook;
+use storage::{StorageBackend, StorageError};
+
+/// Table the Postgres backend writes deltas to
+pub const POSTGRES_TABLE: &str = "deltas";
+
+/// Rows written by a single INSERT. Postgres takes at most 65535 bind parameters per statement,
+/// and every row binds 7 of them.
+const ROWS_PER_INSERT: usize = 5000;
+
+mod schema {
+    table! {
+        deltas (id) {
+            id -> BigInt,
+            exchange -> Text,
+            symbol -> Text,
+            price -> Double,
+            size -> Double,
+            seq -> BigInt,
+            event -> SmallInt,
+            ts -> Double,
+        }
+    }
+}
+
+use self::schema::deltas;
+
+/// Stores deltas of every exchange in a single PostgreSQL table (see [`POSTGRES_TABLE`]). Deltas are
+/// buffered and written once `batch_size` of them have been queued, or when flushed explicitly, with
+/// multi-row INSERTs inside a single transaction.
+pub struct PostgresBackend {
+    /// Number of deltas to buffer before writing them
+    pub batch_size: usize,
+
+    /// Exchange name, stored with every row
+    exchange: String,
+    /// Deltas waiting to be written
+    buffer: Vec<orderbook::Delta>,
+
+    /// Postgres connection, shared with the backend's clones
+    connection: Arc<Mutex<PgConnection>>,
+}
+
+impl PostgresBackend {
+    /// Connects to Postgres and creates the deltas table if it doesn't exist yet
+    pub fn new(url: &str, batch_size: usize, exchange: &str) -> Result<PostgresBackend, StorageError> {
+        let connection = PgConnection::establish(url)?;
+
+        diesel::sql_query(format!("CREATE TABLE IF NOT EXISTS {} (
+            id BIGSERIAL PRIMARY KEY,
+            exchange TEXT NOT NULL,
+            symbol TEXT NOT NULL,
+            price DOUBLE PRECISION NOT NULL,
+            size DOUBLE PRECISION NOT NULL,
+            seq BIGINT NOT NULL,
+            event SMALLINT NOT NULL,
+            ts DOUBLE PRECISION NOT NULL
+        )", POSTGRES_TABLE)).execute(&connection)?;
+
+        Ok(PostgresBackend {
+            batch_size,
+
+            exchange: exchange.to_string(),
+            buffer: Vec::with_capacity(batch_size),
+
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+}
+
+impl StorageBackend for PostgresBackend {
+    fn create(&mut self, _: &str) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    fn insert(&mut self, deltas: &[orderbook::Delta]) -> Result<(), StorageError> {
+        self.buffer.extend_from_slice(deltas);
+
+        if self.buffer.len() >= self.batch_size {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), StorageError> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let exchange = &self.exchange;
+        let buffer = &self.buffer;
+        let connection = self.connection.lock().unwrap();
+
+        connection.transaction::<_, diesel::result::Error, _>(|| {
+            for chunk in buffer.chunks(ROWS_PER_INSERT) {
+                let rows: Vec<_> = chunk.iter()
+                    .map(|delta| (
+                        deltas::exchange.eq(exchange),
+                        deltas::symbol.eq(&delta.symbol),
+                        deltas::price.eq(delta.price as f64),
+                        deltas::size.eq(delta.size as f64),
+                        deltas::seq.eq(delta.seq as i64),
+                        deltas::event.eq(delta.event as i16),
+                        deltas::ts.eq(delta.ts),
+                    ))
+                    .collect();
+
+                diesel::insert_into(deltas::table)
+                    .values(rows)
+                    .execute(&*connection)?;
+            }
+
+            Ok(())
+        })?;
+
+        drop(connection);
+        self.buffer.clear();
+
+        Ok(())
+    }
+
+    fn box_clone(&self) -> Box<dyn StorageBackend> {
+        // The clone shares the connection, but buffers its own deltas
+        Box::new(PostgresBackend {
+            batch_size: self.batch_size,
+
+            exchange: self.exchange.clone(),
+            buffer: Vec::with_capacity(self.batch_size),
+
+            connection: self.connection.clone(),
+        })
+    }
+
+    fn set_exchange(&mut self, exchange: &str) {
+        self.exchange = exchange.to_string();
+    }
+}
@@ -0,0 +1,34 @@
+#[test]
+fn asset_name_round_trip() {
+    use strum::IntoEnumIterator;
+
+    use exchange::Asset;
+
+    for asset in Asset::iter() {
+        assert_eq!(asset.to_string().parse::<Asset>(), Ok(asset.clone()));
+        assert_eq!(asset.to_string().to_lowercase().parse::<Asset>(), Ok(asset));
+    }
+}
+
+#[test]
+fn asset_name_is_exchange_neutral() {
+    use exchange::{Asset, AssetParseError};
+
+    assert_eq!(Asset::BTC.to_string(), "BTC");
+    assert_eq!("usdc".parse::<Asset>(), Ok(Asset::USDC));
+    assert_eq!("DOGE".parse::<Asset>(), Err(AssetParseError("DOGE".into())));
+}
+
+#[test]
+fn asset_name_aliases() {
+    use exchange::Asset;
+
+    assert_eq!("XBT".parse::<Asset>(), Ok(Asset::BTC));
+    assert_eq!("xbt".parse::<Asset>(), Ok(Asset::BTC));
+    assert_eq!("XXBT".parse::<Asset>(), Ok(Asset::BTC));
+    assert_eq!("UST".parse::<Asset>(), Ok(Asset::USDT));
+    assert_eq!("ZUSD".parse::<Asset>(), Ok(Asset::USD));
+
+    // Aliases are only accepted, the canonical ticker is what's displayed
+    assert_eq!("XBT".parse::<Asset>().unwrap().to_string(), "BTC");
+}
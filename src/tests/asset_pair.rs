@@ -0,0 +1,205 @@
+#[test]
+fn asset_pair_formatting() {
+    use exchange::{self, Asset, Exchange, FuturesAsset};
+
+    assert_eq!(exchange::get_asset_pair(&[Asset::BTC, Asset::USD], Exchange::BitMEX).unwrap(), "XBTUSD");
+    assert_eq!(exchange::get_asset_pair(&[Asset::BTC, Asset::USD], Exchange::GDAX).unwrap(), "BTC-USD");
+    assert_eq!(exchange::get_asset_pair(&[Asset::BTC, Asset::USDT], Exchange::Poloniex).unwrap(), "USDT-BTC");
+    assert_eq!(exchange::get_asset_pair(&[Asset::BTC, Asset::USD], Exchange::Kraken).unwrap(), "XBT/USD");
+    assert_eq!(exchange::get_asset_pair(&[Asset::BTC, Asset::KRW], Exchange::Upbit).unwrap(), "KRW-BTC");
+    assert_eq!(exchange::get_asset_pair(&[Asset::BTC, Asset::USDT], Exchange::GateIO).unwrap(), "BTC_USDT");
+    assert_eq!(exchange::get_asset_pair(&[Asset::BTC, Asset::USDT], Exchange::CryptoCom).unwrap(), "BTC_USDT");
+    assert_eq!(exchange::get_asset_pair(&[Asset::BTC, Asset::USD], Exchange::DyDx).unwrap(), "BTC-USD");
+    assert_eq!(exchange::get_asset_pair(&[Asset::BTC, Asset::USD], Exchange::Phemex).unwrap(), "BTCUSD");
+    assert_eq!(exchange::get_asset_pair(&[Asset::ETH, Asset::USDT], Exchange::Bittrex).unwrap(), "ETH-USDT");
+    assert_eq!(exchange::get_asset_pair(&FuturesAsset::BTC.usdt_perpetual(), Exchange::BinanceFutures).unwrap(), "BTCUSDT");
+}
+
+#[test]
+fn batch_asset_pairs_mixed() {
+    use exchange::{self, Asset, AssetError, Exchange};
+
+    let pairs = vec![
+        [Asset::BTC, Asset::USD],
+        [Asset::ETH, Asset::USD],
+    ];
+
+    assert_eq!(exchange::get_batch_asset_pairs(&pairs, Exchange::GDAX).unwrap(), vec!["BTC-USD", "ETH-USD"]);
+
+    // The batch fails on the first pair that isn't listed
+    let pairs = vec![
+        [Asset::BTC, Asset::USD],
+        [Asset::JPY, Asset::USD],
+        [Asset::KRW, Asset::USD],
+    ];
+
+    match exchange::get_batch_asset_pairs(&pairs, Exchange::GDAX) {
+        Err(AssetError::Unsupported(ref asset, exch)) => {
+            assert_eq!(*asset, Asset::JPY);
+            assert_eq!(exch, Exchange::GDAX);
+        },
+        _ => panic!("Expected JPY to be unsupported on GDAX"),
+    }
+}
+
+#[test]
+fn binance_stream_names() {
+    use exchange::{self, Asset, Exchange};
+    use exchange::binance;
+
+    let pair = [Asset::BTC, Asset::USDT];
+
+    assert_eq!(exchange::get_asset_pair(&pair, Exchange::Binance).unwrap(), "BTCUSDT");
+    assert_eq!(binance::stream_name(&pair, "depth").unwrap(), "btcusdt@depth");
+
+    // USD isn't listed on Binance, so that pair is dropped from the subscription
+    let names = binance::stream_names(
+        &vec![pair, [Asset::BTC, Asset::USD]],
+        &vec!["depth".into(), "trade".into()]);
+
+    assert_eq!(names, vec!["btcusdt@depth", "btcusdt@trade"]);
+}
+
+#[test]
+fn normalize_unsupported_asset() {
+    use exchange::{Asset, Exchange};
+
+    assert_eq!(Exchange::BitMEX.normalize_asset(&Asset::BTC).unwrap(), "XBT");
+
+    let err = Exchange::GDAX.normalize_asset(&Asset::JPY).unwrap_err();
+    assert_eq!(format!("{}", err), "Asset JPY is not supported on exchange GDAX");
+}
+
+#[test]
+fn defi_assets() {
+    use exchange::{self, Asset, Exchange};
+
+    assert_eq!(exchange::get_asset_pair(&[Asset::UNI, Asset::USD], Exchange::GDAX).unwrap(), "UNI-USD");
+    assert_eq!(exchange::get_asset_pair(&[Asset::YFI, Asset::USD], Exchange::GDAX).unwrap(), "YFI-USD");
+    assert_eq!(Exchange::GDAX.parse_asset_pair("SUSHI-USD"), Some([Asset::SUSHI, Asset::USD]));
+    assert_eq!("aave".parse::<Asset>(), Ok(Asset::AAVE));
+
+    // Not every exchange lists them
+    assert!(Exchange::BitMEX.normalize_asset(&Asset::MKR).is_err());
+    assert!(exchange::get_asset_pair(&[Asset::CRV, Asset::USD], Exchange::BitMEX).is_err());
+
+    // Their precision differs from token to token
+    assert_eq!(Exchange::GDAX.price_precision(&[Asset::CRV, Asset::USD]), None);
+}
+
+#[test]
+fn layer1_assets() {
+    use exchange::{self, Asset, Exchange};
+
+    assert_eq!(exchange::get_asset_pair(&[Asset::SOL, Asset::USD], Exchange::GDAX).unwrap(), "SOL-USD");
+    assert_eq!(exchange::get_asset_pair(&[Asset::AVAX, Asset::USDT], Exchange::Binance).unwrap(), "AVAXUSDT");
+    assert_eq!(exchange::get_asset_pair(&[Asset::LINK, Asset::BUSD], Exchange::Binance).unwrap(), "LINKBUSD");
+    assert_eq!(Exchange::Binance.parse_asset_pair("ADABUSD"), Some([Asset::ADA, Asset::BUSD]));
+    assert_eq!(Exchange::Binance.parse_asset_pair("DOTUSDT"), Some([Asset::DOT, Asset::USDT]));
+}
+
+#[test]
+fn asset_discriminants_are_stable() {
+    use exchange::Asset;
+
+    // New assets are appended, so these never change
+    assert_eq!(Asset::BTC as u8, 0);
+    assert_eq!(Asset::USDC as u8, 4);
+    assert_eq!(Asset::USD as u8, 5);
+    assert_eq!(Asset::AUD as u8, 12);
+}
+
+#[test]
+fn unsupported_pair_does_not_panic() {
+    use exchange::{self, Asset, Exchange};
+
+    let pair = [Asset::JPY, Asset::KRW];
+
+    assert!(exchange::try_get_asset_pair(&pair, Exchange::BitMEX).is_err());
+    assert!(exchange::get_asset_pair(&pair, Exchange::BitMEX).is_err());
+    assert!(exchange::get_batch_asset_pairs(&vec![pair], Exchange::BitMEX).is_err());
+}
+
+#[test]
+fn try_batch_asset_pairs() {
+    use exchange::{self, Asset, AssetError, Exchange};
+
+    let pairs = [
+        [Asset::BTC, Asset::USD],
+        [Asset::JPY, Asset::KRW],
+        [Asset::ETH, Asset::USD],
+    ];
+    let results = exchange::try_get_batch_asset_pairs(&pairs, Exchange::BitMEX);
+
+    // Every pair gets a result of its own, so the unsupported one doesn't hide the others
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].as_ref().unwrap(), "XBTUSD");
+    match results[1] {
+        Err(AssetError::Unsupported(ref asset, exch)) => {
+            assert_eq!(*asset, Asset::JPY);
+            assert_eq!(exch, Exchange::BitMEX);
+        },
+        _ => panic!("Expected JPY to be unsupported on BitMEX"),
+    }
+    assert_eq!(results[2].as_ref().unwrap(), "ETHUSD");
+}
+
+#[test]
+fn parse_asset_pair_round_trip() {
+    use exchange::{self, Asset, Exchange};
+
+    assert_eq!(Exchange::BitMEX.denormalize_asset("XBT"), Some(Asset::BTC));
+    assert_eq!(Exchange::BitMEX.denormalize_asset("DOGE"), None);
+
+    assert_eq!(Exchange::BitMEX.parse_asset_pair("XBTUSD"), Some([Asset::BTC, Asset::USD]));
+    assert_eq!(Exchange::Binance.parse_asset_pair("BTCUSDT"), Some([Asset::BTC, Asset::USDT]));
+    assert_eq!(Exchange::GDAX.parse_asset_pair("ETH-USD"), Some([Asset::ETH, Asset::USD]));
+    assert_eq!(Exchange::Poloniex.parse_asset_pair("USDT-BTC"), Some([Asset::BTC, Asset::USDT]));
+    assert_eq!(Exchange::Kraken.parse_asset_pair("XBT/USD"), Some([Asset::BTC, Asset::USD]));
+    assert_eq!(Exchange::Bitstamp.parse_asset_pair("btcusd"), Some([Asset::BTC, Asset::USD]));
+    assert_eq!(Exchange::Gemini.parse_asset_pair("ETHUSD"), Some([Asset::ETH, Asset::USD]));
+    assert_eq!(Exchange::Upbit.parse_asset_pair("KRW-ETH"), Some([Asset::ETH, Asset::KRW]));
+    assert_eq!(Exchange::GDAX.parse_asset_pair("BTC-JPY"), None);
+
+    // Kraken databases are stored without the separator
+    assert_eq!(exchange::parse_db_name("bitmex_XBTUSD"), Some((Exchange::BitMEX, [Asset::BTC, Asset::USD])));
+    assert_eq!(exchange::parse_db_name("kraken_XBTUSD"), Some((Exchange::Kraken, [Asset::BTC, Asset::USD])));
+    assert_eq!(exchange::parse_db_name("XBTUSD"), None);
+
+    // Exchange names and environments can contain underscores as well
+    assert_eq!(exchange::parse_db_name("binance_futures_BTCUSDT"), Some((Exchange::BinanceFutures, [Asset::BTC, Asset::USDT])));
+    assert_eq!(exchange::parse_db_name("bitmex_testnet_XBTUSD"), Some((Exchange::BitMEX, [Asset::BTC, Asset::USD])));
+    assert_eq!(exchange::split_db_name("binance_futures_BTCUSDT"), Some((Exchange::BinanceFutures, "BTCUSDT")));
+    assert_eq!(exchange::split_db_name("binance_BTCUSDT"), Some((Exchange::Binance, "BTCUSDT")));
+    assert_eq!(exchange::split_db_name("bitmex_testnet_XBTUSD"), Some((Exchange::BitMEX, "XBTUSD")));
+    assert_eq!(exchange::split_db_name("bitmex_"), None);
+    assert_eq!(exchange::split_db_name("bitmexXBTUSD"), None);
+}
+
+#[test]
+fn price_precision_lookup() {
+    use exchange::{Asset, Exchange};
+
+    assert_eq!(Exchange::GDAX.price_precision(&[Asset::BTC, Asset::USD]), Some(2));
+    assert_eq!(Exchange::GDAX.price_precision(&[Asset::ETH, Asset::BTC]), Some(5));
+    assert_eq!(Exchange::GDAX.quantity_precision(&[Asset::BTC, Asset::USD]), Some(8));
+    assert_eq!(Exchange::Kraken.price_precision(&[Asset::BTC, Asset::USD]), Some(1));
+
+    // BitMEX precision is only available from its instrument endpoint
+    assert_eq!(Exchange::BitMEX.price_precision(&[Asset::BTC, Asset::USD]), None);
+    // Unlisted pairs have no precision
+    assert_eq!(Exchange::GDAX.price_precision(&[Asset::BTC, Asset::JPY]), None);
+}
+
+#[test]
+fn fee_schedule() {
+    use exchange::{Asset, Exchange};
+
+    assert_eq!(Exchange::BitMEX.fees().1, 0.00075);
+    assert!(Exchange::BitMEX.fees().0 < 0.0);
+    assert_eq!(Exchange::GDAX.fees(), (0.0, 0.003));
+
+    assert_eq!(Exchange::BitMEX.min_order_size(&[Asset::BTC, Asset::USD]), Some(1.0));
+    assert_eq!(Exchange::GDAX.min_order_size(&[Asset::BTC, Asset::USD]), Some(0.001));
+    assert_eq!(Exchange::GDAX.min_order_size(&[Asset::BTC, Asset::JPY]), None);
+}
@@ -0,0 +1,40 @@
+#[test]
+fn asset_serde_representation() {
+    use serde_json;
+
+    use exchange::{self, Asset, Exchange, FuturesAsset, OptionsAsset};
+
+    assert_eq!(serde_json::to_string(&Asset::BTC).unwrap(), "\"BTC\"");
+    assert_eq!(serde_json::to_string(&OptionsAsset::ETH).unwrap(), "\"ETH\"");
+    assert_eq!(serde_json::to_string(&FuturesAsset::BTC).unwrap(), "\"BTC\"");
+
+    // Exchanges are represented the same way as in `get_supported_exchanges()`
+    for exchange in exchange::get_supported_exchanges() {
+        let parsed: Exchange = serde_json::from_str(&format!("\"{}\"", exchange)).unwrap();
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), format!("\"{}\"", exchange));
+    }
+}
+
+#[test]
+fn asset_serde_metadata() {
+    use serde_json;
+
+    use exchange::{Asset, Exchange, FuturesAsset};
+
+    #[derive(Deserialize)]
+    struct MetaData {
+        exchange: Exchange,
+        asset_pair: Vec<[Asset; 2]>,
+        futures: Vec<FuturesAsset>,
+    }
+
+    let metadata: MetaData = serde_json::from_str(r#"{
+        "exchange": "bitmex",
+        "asset_pair": [["BTC", "USD"], ["ETH", "USD"]],
+        "futures": ["BTC"]
+    }"#).unwrap();
+
+    assert_eq!(metadata.exchange, Exchange::BitMEX);
+    assert_eq!(metadata.asset_pair, vec![[Asset::BTC, Asset::USD], [Asset::ETH, Asset::USD]]);
+    assert_eq!(metadata.futures, vec![FuturesAsset::BTC]);
+}
@@ -0,0 +1,50 @@
+#[test]
+fn binance_sequence_first_event_after_snapshot() {
+    use exchange::binance::{DepthSequence, SequenceCheck};
+
+    let mut sequence = DepthSequence::new(100);
+
+    // Events that end at or before the snapshot are stale
+    assert_eq!(sequence.check(90, 100), SequenceCheck::Drop);
+    // The first event to apply must straddle `lastUpdateId + 1`
+    assert_eq!(sequence.check(95, 105), SequenceCheck::Apply);
+    assert_eq!(sequence.last_update_id, 105);
+    assert_eq!(sequence.check(106, 110), SequenceCheck::Apply);
+}
+
+#[test]
+fn binance_sequence_gap() {
+    use exchange::binance::{DepthSequence, SequenceCheck};
+
+    let mut sequence = DepthSequence::new(100);
+
+    // Snapshot is too old for the first event we received
+    assert_eq!(sequence.check(102, 110), SequenceCheck::Gap);
+
+    let mut sequence = DepthSequence::new(100);
+
+    assert_eq!(sequence.check(101, 110), SequenceCheck::Apply);
+    assert_eq!(sequence.check(112, 120), SequenceCheck::Gap);
+    assert_eq!(sequence.last_update_id, 110);
+}
+
+#[test]
+fn binance_futures_sequence() {
+    use exchange::binance::SequenceCheck;
+    use exchange::binance_futures::FuturesDepthSequence;
+
+    let mut sequence = FuturesDepthSequence::new(100);
+
+    // Events that end before the snapshot are stale, and the first event must contain `lastUpdateId`
+    assert_eq!(sequence.check(90, 99, 89), SequenceCheck::Drop);
+    assert_eq!(sequence.check(98, 105, 97), SequenceCheck::Apply);
+
+    // After that, `pu` must match the `u` of the previous event
+    assert_eq!(sequence.check(106, 110, 105), SequenceCheck::Apply);
+    assert_eq!(sequence.check(115, 120, 112), SequenceCheck::Gap);
+    assert_eq!(sequence.last_update_id, 110);
+
+    // Snapshot is too old for the first event we received
+    let mut sequence = FuturesDepthSequence::new(100);
+    assert_eq!(sequence.check(101, 110, 100), SequenceCheck::Gap);
+}
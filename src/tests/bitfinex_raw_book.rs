@@ -0,0 +1,16 @@
+#[test]
+fn bitfinex_raw_book_aggregates_orders() {
+    use exchange::bitfinex::RawBook;
+
+    let mut book = RawBook::default();
+
+    assert_eq!(book.apply(1, 8000.0, 0.5), Some((8000.0, 0.5)));
+    assert_eq!(book.apply(2, 8000.0, 0.25), Some((8000.0, 0.75)));
+    // Amending an order replaces its previous amount
+    assert_eq!(book.apply(1, 8000.0, 1.0), Some((8000.0, 1.25)));
+    // A price of zero removes the order from whichever level it was on
+    assert_eq!(book.apply(2, 0.0, 1.0), Some((8000.0, 1.0)));
+    assert_eq!(book.apply(1, 0.0, 1.0), Some((8000.0, 0.0)));
+    // Removing an order we've never seen is a no-op
+    assert_eq!(book.apply(3, 0.0, -1.0), None);
+}
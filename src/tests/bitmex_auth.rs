@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use exchange::bitmex::{auth_message, is_private_table, parse_message, private_channel_name, signature, ParsedMessage, RawMessage};
+
+// Example from BitMEX's API key documentation
+const API_KEY: &str = "LAqUlngMIQkIUjXMUreyu3qn";
+const API_SECRET: &str = "chNOOS4KvNXR_Xq4k4c9qsfoKWvnDecLATCRlcBwyKDYnWgO";
+
+#[test]
+fn bitmex_signature() {
+    assert_eq!(
+        signature(API_SECRET, "GET", "/api/v1/instrument", 1518064236, ""),
+        "c7682d435d0cfe87c16098df34ef2eb5a549d4c5a3c2b1f0f77b8af73423bf00");
+}
+
+#[test]
+fn bitmex_auth_message() {
+    assert_eq!(
+        auth_message(API_KEY, API_SECRET, 1518064236),
+        format!(
+            r#"{{"op":"authKeyExpires","args":["{}",1518064236,"6d459dc02866d35a2b965edeecc68063d488e296b77982235fc6eca24b934945"]}}"#,
+            API_KEY));
+}
+
+#[test]
+fn bitmex_private_table() {
+    assert!(is_private_table("execution"));
+    assert!(is_private_table("order"));
+    assert!(is_private_table("position"));
+    assert!(!is_private_table("orderBookL2"));
+
+    assert_eq!(private_channel_name("bitmex_private", "execution"), "bitmex_private_execution");
+
+    let data = r#"{"table":"position","action":"update","data":[{"account":2,"symbol":"XBTUSD","currentQty":100}]}"#;
+    let raw = RawMessage {
+        table: "position".into(),
+        data: data.as_bytes().to_vec(),
+        ts: 1535487252.0,
+        book: None,
+    };
+
+    match parse_message(&raw, &RwLock::new(HashMap::new()), &RwLock::new(HashMap::new())) {
+        Some(ParsedMessage::Private { table, message }) => {
+            assert_eq!(table, "position");
+            assert_eq!(message, data);
+        },
+        _ => panic!("Expected a private message"),
+    }
+}
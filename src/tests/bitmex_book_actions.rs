@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use exchange::Exchange;
+use exchange::bitmex::{parse_message, ParsedMessage, RawMessage};
+use orderbook::{self, DeltaEvent, Level2Orderbook};
+
+fn deltas(data: &str) -> Vec<orderbook::Delta> {
+    let raw = RawMessage {
+        table: "orderBookL2".into(),
+        data: data.as_bytes().to_vec(),
+        ts: 1535487252.0,
+        book: None,
+    };
+
+    let mut indexes = HashMap::new();
+    indexes.insert("XBTUSD".to_string(), 88);
+    let mut tick_sizes = HashMap::new();
+    tick_sizes.insert("XBTUSD".to_string(), 0.01);
+
+    match parse_message(&raw, &RwLock::new(indexes), &RwLock::new(tick_sizes)) {
+        Some(ParsedMessage::Deltas(deltas)) => deltas,
+        _ => panic!("Expected deltas"),
+    }
+}
+
+// Recorded from an `orderBookL2:XBTUSD` subscription
+const INSERT: &str = r#"{"table":"orderBookL2","action":"insert","data":[{"symbol":"XBTUSD","id":8799297600,"side":"Sell","size":2000,"price":7024}]}"#;
+const UPDATE: &str = r#"{"table":"orderBookL2","action":"update","data":[{"symbol":"XBTUSD","id":8799297600,"side":"Sell","size":1500}]}"#;
+const DELETE: &str = r#"{"table":"orderBookL2","action":"delete","data":[{"symbol":"XBTUSD","id":8799297600,"side":"Sell"}]}"#;
+
+#[test]
+fn bitmex_book_insert() {
+    let deltas = deltas(INSERT);
+
+    assert_eq!(deltas.len(), 1);
+    assert_eq!(deltas[0].event, DeltaEvent::AskUpdate.bits());
+    assert_eq!(deltas[0].size, 2000.0);
+}
+
+#[test]
+fn bitmex_book_update() {
+    let deltas = deltas(UPDATE);
+
+    assert_eq!(deltas.len(), 1);
+    assert_eq!(deltas[0].event, DeltaEvent::AskUpdate.bits());
+    assert_eq!(deltas[0].size, 1500.0);
+}
+
+#[test]
+fn bitmex_book_delete() {
+    let deltas = deltas(DELETE);
+
+    assert_eq!(deltas.len(), 1);
+    assert_eq!(deltas[0].event, DeltaEvent::AskRemove.bits());
+    assert_eq!(deltas[0].size, 0.0);
+}
+
+#[test]
+fn bitmex_book_update_without_size() {
+    // Skipped rather than published as a removal, leaving the rest of the message
+    let deltas = deltas(r#"{"table":"orderBookL2","action":"update","data":[
+        {"symbol":"XBTUSD","id":8799297600,"side":"Sell"},
+        {"symbol":"XBTUSD","id":8799297900,"side":"Buy","size":45296}]}"#);
+
+    assert_eq!(deltas.len(), 1);
+    assert_eq!(deltas[0].event, DeltaEvent::BidUpdate.bits());
+}
+
+#[test]
+fn bitmex_book_actions_applied() {
+    let mut book = Level2Orderbook::new("XBTUSD", Exchange::BitMEX);
+
+    for delta in deltas(INSERT) {
+        book.apply(&delta);
+    }
+    assert_eq!(book.best_ask().map(|(_, size)| size), Some(2000.0));
+
+    for delta in deltas(UPDATE) {
+        book.apply(&delta);
+    }
+    assert_eq!(book.best_ask().map(|(_, size)| size), Some(1500.0));
+
+    for delta in deltas(DELETE) {
+        book.apply(&delta);
+    }
+    assert_eq!(book.best_ask(), None);
+}
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use exchange::bitmex::{apply_to_books, message_table, parse_message, seed_books, BookMessages, BookTracker, ParsedMessage, RawMessage};
+use exchange::bitmex::MAX_PENDING_BOOK_MESSAGES;
+
+fn raw(data: &str) -> RawMessage {
+    RawMessage {
+        table: "orderBookL2".into(),
+        data: data.as_bytes().to_vec(),
+        ts: 1535487252.0,
+        book: None,
+    }
+}
+
+fn ready(messages: BookMessages) -> Vec<String> {
+    match messages {
+        BookMessages::Ready(messages) => messages.into_iter()
+            .map(|message| String::from_utf8(message.data).unwrap())
+            .collect(),
+        _ => panic!("Expected messages to be ready"),
+    }
+}
+
+fn parse_xbtusd(raw: &RawMessage) -> Option<ParsedMessage> {
+    let mut indexes = HashMap::new();
+    indexes.insert("XBTUSD".to_string(), 88);
+    let mut tick_sizes = HashMap::new();
+    tick_sizes.insert("XBTUSD".to_string(), 0.01);
+
+    parse_message(raw, &RwLock::new(indexes), &RwLock::new(tick_sizes))
+}
+
+// Recorded from an `orderBookL2:XBTUSD` subscription
+const INSERT: &str = r#"{"table":"orderBookL2","action":"insert","data":[{"symbol":"XBTUSD","id":8799297600,"side":"Sell","size":2000,"price":7024}]}"#;
+const STALE_UPDATE: &str = r#"{"table":"orderBookL2","action":"update","data":[{"symbol":"XBTUSD","id":8799297700,"side":"Sell","size":1500}]}"#;
+const PARTIAL: &str = r#"{"table":"orderBookL2","action":"partial","keys":["symbol","id","side"],"filter":{"symbol":"XBTUSD"},"data":[{"symbol":"XBTUSD","id":8799297850,"side":"Sell","size":101873,"price":7021.5},{"symbol":"XBTUSD","id":8799297900,"side":"Buy","size":45296,"price":7021}]}"#;
+const UPDATE: &str = r#"{"table":"orderBookL2","action":"update","data":[{"symbol":"XBTUSD","id":8799297850,"side":"Sell","size":100000}]}"#;
+const DELETE: &str = r#"{"table":"orderBookL2","action":"delete","data":[{"symbol":"XBTUSD","id":8799297900,"side":"Buy"}]}"#;
+
+#[test]
+fn bitmex_book_buffers_until_partial() {
+    let mut tracker = BookTracker::new(true);
+
+    // Deltas that arrive before the partial are held back
+    match tracker.track(raw(INSERT)) {
+        BookMessages::Buffered => (),
+        _ => panic!("Expected the insert to be buffered"),
+    }
+    match tracker.track(raw(STALE_UPDATE)) {
+        BookMessages::Buffered => (),
+        _ => panic!("Expected the update to be buffered"),
+    }
+    assert!(!tracker.has_partial("XBTUSD"));
+
+    // And replayed after it. The update of a level the partial doesn't have is already reflected by it
+    assert_eq!(ready(tracker.track(raw(PARTIAL))), vec![PARTIAL, INSERT]);
+    assert!(tracker.has_partial("XBTUSD"));
+
+    // Deltas following the partial are let through, including the ones to the level inserted before it
+    assert_eq!(ready(tracker.track(raw(UPDATE))), vec![UPDATE]);
+    assert_eq!(ready(tracker.track(raw(DELETE))), vec![DELETE]);
+    let update_inserted = r#"{"table":"orderBookL2","action":"update","data":[{"symbol":"XBTUSD","id":8799297600,"side":"Sell","size":1000}]}"#;
+    assert_eq!(ready(tracker.track(raw(update_inserted))), vec![update_inserted]);
+}
+
+#[test]
+fn bitmex_book_pending_limit() {
+    let mut tracker = BookTracker::new(true);
+    let insert = |id: usize| format!(
+        r#"{{"table":"orderBookL2","action":"insert","data":[{{"symbol":"XBTUSD","id":{},"side":"Sell","size":10,"price":7024}}]}}"#,
+        8799297000 + id);
+
+    for id in 0..MAX_PENDING_BOOK_MESSAGES + 1 {
+        tracker.track(raw(&insert(id)));
+    }
+
+    // Once full, the oldest messages make room for the new ones
+    let ready = ready(tracker.track(raw(PARTIAL)));
+    assert_eq!(ready.len(), MAX_PENDING_BOOK_MESSAGES + 1);
+    assert_eq!(ready[1], insert(1));
+    assert_eq!(ready[MAX_PENDING_BOOK_MESSAGES], insert(MAX_PENDING_BOOK_MESSAGES));
+}
+
+#[test]
+fn bitmex_book_gap() {
+    let mut tracker = BookTracker::new(true);
+    ready(tracker.track(raw(PARTIAL)));
+
+    // The level was deleted, so updating it means we missed a message
+    ready(tracker.track(raw(DELETE)));
+    match tracker.track(raw(r#"{"table":"orderBookL2","action":"update","data":[{"symbol":"XBTUSD","id":8799297900,"side":"Buy","size":10}]}"#)) {
+        BookMessages::Gap(symbol) => assert_eq!(symbol, "XBTUSD"),
+        _ => panic!("Expected a gap"),
+    }
+
+    // Until the new partial arrives, deltas are held back again
+    assert!(!tracker.has_partial("XBTUSD"));
+    match tracker.track(raw(UPDATE)) {
+        BookMessages::Buffered => (),
+        _ => panic!("Expected the update to be buffered"),
+    }
+    assert_eq!(ready(tracker.track(raw(PARTIAL))), vec![PARTIAL, UPDATE]);
+}
+
+#[test]
+fn bitmex_book_gap_detection_disabled() {
+    let mut tracker = BookTracker::new(false);
+    ready(tracker.track(raw(PARTIAL)));
+
+    assert_eq!(ready(tracker.track(raw(STALE_UPDATE))), vec![STALE_UPDATE]);
+}
+
+#[test]
+fn bitmex_partial_snapshot() {
+    match parse_xbtusd(&raw(PARTIAL)) {
+        Some(ParsedMessage::Snapshot(deltas)) => assert_eq!(deltas.len(), 2),
+        _ => panic!("Expected the partial to be parsed as a snapshot"),
+    }
+
+    match parse_xbtusd(&raw(UPDATE)) {
+        Some(ParsedMessage::Deltas(deltas)) => assert_eq!(deltas.len(), 1),
+        _ => panic!("Expected the update to be parsed as deltas"),
+    }
+}
+
+#[test]
+fn bitmex_book_parsed_once() {
+    let mut tracker = BookTracker::new(true);
+
+    let mut messages = match tracker.track(raw(PARTIAL)) {
+        BookMessages::Ready(messages) => messages,
+        _ => panic!("Expected the partial to be ready"),
+    };
+    assert!(messages[0].book.is_some());
+
+    // The worker builds the deltas from the message the tracker parsed, rather than parsing the data again
+    messages[0].data.clear();
+    match parse_xbtusd(&messages[0]) {
+        Some(ParsedMessage::Snapshot(deltas)) => assert_eq!(deltas.len(), 2),
+        _ => panic!("Expected the partial to be parsed as a snapshot"),
+    }
+}
+
+#[test]
+fn bitmex_message_table() {
+    assert_eq!(message_table(PARTIAL.as_bytes()), Some("orderBookL2".to_string()));
+    assert_eq!(message_table(br#"{"table":"trade","action":"insert","data":[]}"#), Some("trade".to_string()));
+
+    // Subscription responses don't start with a table
+    assert_eq!(message_table(br#"{"success":true,"subscribe":"orderBookL2:XBTUSD"}"#), None);
+    assert_eq!(message_table(br#"{"table":"orderBo"#), None);
+}
+
+// Prices are decoded from level IDs as `f32`
+fn rounded(level: Option<(f64, f64)>) -> Option<(f64, f64)> {
+    level.map(|(price, size)| ((price * 100.0).round() / 100.0, size))
+}
+
+#[test]
+fn bitmex_book_seeded_from_partial() {
+    let parse = |data: &str| match parse_xbtusd(&raw(data)) {
+        Some(ParsedMessage::Snapshot(deltas)) | Some(ParsedMessage::Deltas(deltas)) => deltas,
+        _ => panic!("Expected deltas"),
+    };
+    let books = RwLock::new(HashMap::new());
+
+    // Deltas can't be applied before the snapshot
+    apply_to_books(&books, &parse(UPDATE));
+    assert!(books.read().unwrap().is_empty());
+
+    let snapshots = seed_books(&books, &parse(PARTIAL));
+    assert_eq!(snapshots.len(), 1);
+    assert_eq!(snapshots[0].symbol, "XBTUSD");
+    assert_eq!(snapshots[0].ts, 1535487252.0);
+    assert_eq!(snapshots[0].bids.iter().map(|&level| rounded(Some(level)).unwrap()).collect::<Vec<_>>(), vec![(7021.0, 45296.0)]);
+    assert_eq!(snapshots[0].asks.iter().map(|&level| rounded(Some(level)).unwrap()).collect::<Vec<_>>(), vec![(7021.5, 101873.0)]);
+
+    {
+        let books = books.read().unwrap();
+        let book = &books["XBTUSD"];
+        assert_eq!(rounded(book.best_ask()), Some((7021.5, 101873.0)));
+        assert_eq!(rounded(book.best_bid()), Some((7021.0, 45296.0)));
+    }
+
+    apply_to_books(&books, &parse(UPDATE));
+    apply_to_books(&books, &parse(DELETE));
+
+    let books = books.read().unwrap();
+    let book = &books["XBTUSD"];
+    assert_eq!(rounded(book.best_ask()), Some((7021.5, 100000.0)));
+    assert_eq!(book.best_bid(), None);
+}
@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use exchange::bitmex::{notify, spawn_worker, DeltaCallback, ParsedMessage, RawMessage};
+use orderbook;
+
+#[test]
+fn bitmex_callback_counts_deltas() {
+    let count = Arc::new(AtomicUsize::new(0));
+    let count_ref = count.clone();
+
+    // This is all a user has to set as `WSExchange::callback`
+    let callback: DeltaCallback = Arc::new(move |delta: &orderbook::Delta| {
+        assert_eq!(delta.symbol, "XBTUSD");
+        count_ref.fetch_add(1, Ordering::SeqCst);
+    });
+
+    let mut indexes = HashMap::new();
+    indexes.insert("XBTUSD".to_string(), 88);
+    let mut tick_sizes = HashMap::new();
+    tick_sizes.insert("XBTUSD".to_string(), 0.01);
+
+    let (worker, thread) = spawn_worker(
+        Arc::new(RwLock::new(indexes)),
+        Arc::new(RwLock::new(tick_sizes)),
+        move |parsed| if let ParsedMessage::Deltas(deltas) = parsed {
+            notify(Some(&callback), &deltas);
+        });
+
+    for _ in 0..10 {
+        worker.send(RawMessage {
+            table: "orderBookL2".into(),
+            data: br#"{"table":"orderBookL2","action":"update","data":[
+                {"symbol":"XBTUSD","id":8799360000,"side":"Buy","size":100},
+                {"symbol":"XBTUSD","id":8799359950,"side":"Sell","size":50}]}"#.to_vec(),
+            ts: 1536000000.0,
+            book: None,
+        }).unwrap();
+    }
+
+    drop(worker);
+    thread.join().unwrap();
+
+    assert_eq!(count.load(Ordering::SeqCst), 20);
+}
+
+#[test]
+fn bitmex_callback_unset() {
+    // Without a callback, deltas are only published
+    notify(None, &[orderbook::Delta {
+        symbol: "XBTUSD".into(),
+        price: 6400.0,
+        size: 100.0,
+        seq: 0,
+        event: orderbook::BID ^ orderbook::UPDATE,
+        ts: 1536000000.0,
+        received_ts: None,
+    }]);
+}
@@ -0,0 +1,20 @@
+#[test]
+fn bitmex_environment_urls() {
+    use exchange::bitmex::Environment;
+
+    assert_eq!(Environment::Production.host(), "wss://www.bitmex.com/realtime");
+    assert_eq!(Environment::Production.instrument_url(0),
+        "https://www.bitmex.com/api/v1/instrument?columns=symbol,listing,tickSize&start=0&count=500");
+
+    assert_eq!(Environment::Testnet.host(), "wss://testnet.bitmex.com/realtime");
+    assert_eq!(Environment::Testnet.instrument_url(500),
+        "https://testnet.bitmex.com/api/v1/instrument?columns=symbol,listing,tickSize&start=500&count=500");
+}
+
+#[test]
+fn bitmex_environment_suffix() {
+    use exchange::bitmex::Environment;
+
+    assert_eq!(Environment::Production.suffix("bitmex"), "bitmex");
+    assert_eq!(Environment::Testnet.suffix("bitmex"), "bitmex_testnet");
+}
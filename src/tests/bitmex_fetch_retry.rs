@@ -0,0 +1,62 @@
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::Mutex;
+use std::thread;
+
+use exchange::{RateLimiter, ReconnectPolicy};
+use exchange::bitmex::{fetch_json, Instrument};
+
+const UNAVAILABLE: (&str, &str) = ("503 Service Unavailable", "<html><body>Service Unavailable</body></html>");
+const INSTRUMENTS: (&str, &str) = ("200 OK", r#"[{"symbol":"XBTUSD","listing":null,"tickSize":0.5}]"#);
+
+/// Answers every connection with the next (status, body), closing it afterwards
+fn serve(responses: Vec<(&'static str, &'static str)>) -> (String, thread::JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://127.0.0.1:{}/api/v1/instrument", listener.local_addr().unwrap().port());
+
+    let server = thread::spawn(move || {
+        for response in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf);
+            let (status, body) = response;
+            write!(stream, "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body).unwrap();
+        }
+    });
+
+    (url, server)
+}
+
+fn retry_policy(max_attempts: u32) -> ReconnectPolicy {
+    ReconnectPolicy {
+        base_delay_ms: 1,
+        max_delay_ms: 1,
+        jitter: false,
+        max_attempts: Some(max_attempts),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn bitmex_fetch_retries_unavailable_endpoint() {
+    let (url, server) = serve(vec![UNAVAILABLE, UNAVAILABLE, INSTRUMENTS]);
+    let rate_limiter = Mutex::new(RateLimiter::new(1_000.0, 10));
+
+    let instruments: Vec<Instrument> = fetch_json(&url, &rate_limiter, &retry_policy(5)).unwrap();
+    assert_eq!(instruments.len(), 1);
+    assert_eq!(instruments[0].symbol, "XBTUSD");
+
+    server.join().unwrap();
+}
+
+#[test]
+fn bitmex_fetch_gives_up() {
+    let (url, server) = serve(vec![UNAVAILABLE, UNAVAILABLE]);
+    let rate_limiter = Mutex::new(RateLimiter::new(1_000.0, 10));
+
+    // The error is the status, not a failure to parse the error page
+    let error = fetch_json::<Vec<Instrument>>(&url, &rate_limiter, &retry_policy(1)).unwrap_err();
+    assert!(error.status().is_some());
+
+    server.join().unwrap();
+}
@@ -0,0 +1,130 @@
+use serde_json;
+
+use exchange::bitmex::{instrument_indexes, level_price, Instrument};
+
+/// Every instrument listed up to XBTUSD, which was the 89th, followed by a few listed after it. They're returned
+/// in an order that has nothing to do with their listing.
+fn instruments() -> Vec<Instrument> {
+    let mut instruments: Vec<Instrument> = (0..88)
+        .map(|i| Instrument {
+            symbol: format!("OLD{}", i),
+            listing: Some(format!("2015-{:02}-{:02}T12:00:00.000Z", i / 28 + 1, i % 28 + 1)),
+            tick_size: 0.01,
+        })
+        .collect();
+
+    instruments.push(serde_json::from_str(r#"{"symbol":"XBTUSD","listing":"2016-05-04T12:00:00.000Z","tickSize":0.5}"#).unwrap());
+    instruments.push(serde_json::from_str(r#"{"symbol":"ETHUSD","listing":"2018-07-31T12:00:00.000Z","tickSize":0.05}"#).unwrap());
+    instruments.push(serde_json::from_str(r#"{"symbol":"XRPUSD","listing":"2020-02-21T12:00:00.000Z","tickSize":0.0001}"#).unwrap());
+    instruments.push(serde_json::from_str(r#"{"symbol":"UNLISTED","listing":null,"tickSize":1}"#).unwrap());
+
+    instruments.reverse();
+    instruments.swap(0, 50);
+
+    instruments
+}
+
+#[test]
+fn bitmex_instrument_indexes() {
+    let (indexes, tick_sizes) = instrument_indexes(&instruments());
+
+    assert_eq!(indexes["OLD0"], 0);
+    assert_eq!(indexes["OLD87"], 87);
+    assert_eq!(indexes["XBTUSD"], 88);
+    assert_eq!(indexes["ETHUSD"], 89);
+    assert_eq!(indexes["XRPUSD"], 90);
+    assert_eq!(indexes["UNLISTED"], 91);
+
+    // Tick sizes are the instruments' own, until levels with prices tell us otherwise (see `bitmex_encoded_tick_size`)
+    assert_eq!(tick_sizes["XBTUSD"], 0.5);
+    assert_eq!(tick_sizes["ETHUSD"], 0.05);
+}
+
+#[test]
+fn bitmex_instrument_prices() {
+    let (indexes, tick_sizes) = instrument_indexes(&instruments());
+    let price = |symbol: &str, id: u64| level_price(id, indexes[symbol], tick_sizes[symbol]).unwrap();
+
+    // (symbol, id, price) of levels BitMEX sent prices along with
+    for &(symbol, id, expected) in &[
+        ("ETHUSD", 8899996264u64, 186.8f32),
+        ("XRPUSD", 8999997000, 0.3),
+    ] {
+        assert!((price(symbol, id) - expected).abs() < 0.0001, "{} level {} isn't at {}", symbol, id, expected);
+    }
+}
+
+#[test]
+fn bitmex_encoded_tick_size() {
+    use std::collections::HashMap;
+    use std::sync::RwLock;
+
+    use exchange::bitmex::{load_instruments, parse_message, ParsedMessage, RawMessage};
+
+    let asset_indexes = RwLock::new(HashMap::new());
+    let asset_tick_size = RwLock::new(HashMap::new());
+    load_instruments(&instruments(), &asset_indexes, &asset_tick_size);
+
+    let parse = |data: &[u8]| match parse_message(&RawMessage { table: "orderBookL2".into(), data: data.to_vec(), ts: 1536000000.0, book: None },
+                                                  &asset_indexes, &asset_tick_size) {
+        Some(ParsedMessage::Snapshot(deltas)) | Some(ParsedMessage::Deltas(deltas)) => deltas,
+        _ => panic!("Expected deltas"),
+    };
+
+    // XBTUSD's partial comes with prices, which show that its IDs aren't encoded with its 0.5 tick
+    let partial = parse(br#"{"table":"orderBookL2","action":"partial","data":[
+        {"symbol":"XBTUSD","id":8799297850,"side":"Sell","size":50,"price":7021.5},
+        {"symbol":"XBTUSD","id":8799297900,"side":"Buy","size":100,"price":7021}]}"#);
+    assert_eq!(partial[0].price, 7021.5);
+    assert!((asset_tick_size.read().unwrap()["XBTUSD"] - 0.01).abs() < 1e-6);
+
+    // Updates, which don't, are decoded with the tick size we've learned
+    let update = parse(br#"{"table":"orderBookL2","action":"update","data":[{"symbol":"XBTUSD","id":8799360000,"side":"Buy","size":10}]}"#);
+    assert!((update[0].price - 6400.0).abs() < 0.01);
+
+    // Instruments whose IDs are encoded with their own tick size keep it
+    parse(br#"{"table":"orderBookL2","action":"insert","data":[{"symbol":"ETHUSD","id":8899996264,"side":"Buy","size":1,"price":186.8}]}"#);
+    assert_eq!(asset_tick_size.read().unwrap()["ETHUSD"], 0.05);
+}
+
+#[test]
+fn bitmex_unknown_instrument() {
+    use std::collections::HashMap;
+    use std::sync::{Arc, RwLock};
+    use std::sync::mpsc;
+
+    use exchange::bitmex::{load_instruments, spawn_worker, ParsedMessage, RawMessage};
+
+    let asset_indexes = Arc::new(RwLock::new(HashMap::new()));
+    let asset_tick_size = Arc::new(RwLock::new(HashMap::new()));
+    load_instruments(&instruments(), &asset_indexes, &asset_tick_size);
+
+    let (sender, receiver) = mpsc::channel();
+    let (worker, thread) = spawn_worker(asset_indexes, asset_tick_size, move |parsed| {
+        if let ParsedMessage::Deltas(deltas) = parsed {
+            sender.send(deltas).unwrap();
+        }
+    });
+
+    // A symbol listed after the instruments were fetched is skipped, and the worker keeps going
+    for data in &[
+        br#"{"table":"orderBookL2","action":"update","data":[{"symbol":"NEWUSD","id":9199999000,"side":"Buy","size":10},
+            {"symbol":"XBTUSD","id":8799360000,"side":"Buy","size":100}]}"#.to_vec(),
+        br#"{"table":"orderBookL2","action":"update","data":[{"symbol":"XBTUSD","id":8799297850,"side":"Sell","size":50}]}"#.to_vec(),
+    ] {
+        worker.send(RawMessage {
+            table: "orderBookL2".into(),
+            data: data.clone(),
+            ts: 1536000000.0,
+            book: None,
+        }).unwrap();
+    }
+
+    drop(worker);
+    thread.join().unwrap();
+
+    let deltas: Vec<Vec<String>> = receiver.iter()
+        .map(|deltas| deltas.into_iter().map(|delta| delta.symbol).collect())
+        .collect();
+    assert_eq!(deltas, vec![vec!["XBTUSD".to_string()], vec!["XBTUSD".to_string()]]);
+}
@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use exchange::bitmex::{Keepalive, KeepaliveAction};
+
+#[test]
+fn bitmex_keepalive_waits_while_messages_arrive() {
+    let mut keepalive = Keepalive::new(5_000, 2_000);
+
+    // The check is pushed back to 5 seconds after the last message, however many arrived
+    assert_eq!(keepalive.on_timeout(Some(Duration::from_millis(1_500))), KeepaliveAction::Wait(3_501));
+    keepalive.on_message();
+    assert_eq!(keepalive.on_timeout(Some(Duration::from_millis(10))), KeepaliveAction::Wait(4_991));
+}
+
+#[test]
+fn bitmex_keepalive_pings_idle_connection() {
+    let mut keepalive = Keepalive::new(5_000, 2_000);
+
+    assert_eq!(keepalive.on_timeout(Some(Duration::from_millis(5_000))), KeepaliveAction::Ping(2_000));
+
+    // The pong (or any other message) re-arms the check as if nothing happened
+    keepalive.on_message();
+    assert_eq!(keepalive.on_timeout(Some(Duration::from_millis(1_000))), KeepaliveAction::Wait(4_001));
+
+    // Nothing arrived since the connection opened
+    let mut keepalive = Keepalive::new(5_000, 2_000);
+    assert_eq!(keepalive.on_timeout(None), KeepaliveAction::Ping(2_000));
+}
+
+#[test]
+fn bitmex_keepalive_expires_without_pong() {
+    let mut keepalive = Keepalive::new(5_000, 2_000);
+
+    assert_eq!(keepalive.on_timeout(Some(Duration::from_millis(6_000))), KeepaliveAction::Ping(2_000));
+    assert_eq!(keepalive.on_timeout(Some(Duration::from_millis(8_000))), KeepaliveAction::Expired);
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use exchange::bitmex::{parse_message, ParsedMessage, RawMessage};
+use orderbook::{Liquidation, LiquidationAction};
+
+fn parse(action: &str, data: &str) -> Option<ParsedMessage> {
+    let raw = RawMessage {
+        table: "liquidation".into(),
+        data: format!(r#"{{"table":"liquidation","action":"{}","data":{}}}"#, action, data).into_bytes(),
+        ts: 1536000000.5,
+        book: None,
+    };
+
+    parse_message(&raw, &RwLock::new(HashMap::new()), &RwLock::new(HashMap::new()))
+}
+
+fn liquidations(parsed: Option<ParsedMessage>) -> Vec<Liquidation> {
+    match parsed {
+        Some(ParsedMessage::Liquidations(liquidations)) => liquidations,
+        _ => panic!("Expected liquidations"),
+    }
+}
+
+#[test]
+fn bitmex_liquidation_lifecycle() {
+    // An order is inserted, and deleted once it's completely filled
+    let inserted = liquidations(parse("insert", r#"[{"orderID":"f3f8e2a2","symbol":"XBTUSD","side":"Sell","price":6391.5,"leavesQty":2500}]"#));
+    let deleted = liquidations(parse("delete", r#"[{"orderID":"f3f8e2a2","symbol":"XBTUSD"}]"#));
+
+    assert_eq!(inserted, vec![Liquidation {
+        order_id: "f3f8e2a2".into(),
+        action: LiquidationAction::Insert,
+        symbol: "XBTUSD".into(),
+        side: Some("Sell".into()),
+        price: Some(6391.5),
+        leaves_qty: Some(2500.0),
+        ts: 1536000000.5,
+    }]);
+    assert_eq!(deleted, vec![Liquidation {
+        order_id: "f3f8e2a2".into(),
+        action: LiquidationAction::Delete,
+        symbol: "XBTUSD".into(),
+        side: None,
+        price: None,
+        leaves_qty: None,
+        ts: 1536000000.5,
+    }]);
+}
+
+#[test]
+fn bitmex_liquidation_partial_updates() {
+    // Updates only carry what changed, i.e. the remaining quantity
+    let updated = liquidations(parse("update", r#"[{"orderID":"f3f8e2a2","symbol":"XBTUSD","leavesQty":1000}]"#));
+
+    assert_eq!(updated.len(), 1);
+    assert_eq!(updated[0].action, LiquidationAction::Update);
+    assert_eq!(updated[0].leaves_qty, Some(1000.0));
+    assert_eq!(updated[0].price, None);
+
+    // Liquidations in progress when we subscribe are inserts as far as consumers are concerned
+    let partial = liquidations(parse("partial", r#"[{"orderID":"0a1b2c3d","symbol":"ETHUSD","side":"Buy","price":210.05,"leavesQty":10}]"#));
+    assert_eq!(partial[0].action, LiquidationAction::Insert);
+    assert!(liquidations(parse("partial", "[]")).is_empty());
+}
+
+#[test]
+fn bitmex_liquidation_serde() {
+    use serde_json;
+
+    let liquidation = Liquidation {
+        order_id: "f3f8e2a2".into(),
+        action: LiquidationAction::Update,
+        symbol: "XBTUSD".into(),
+        side: None,
+        price: None,
+        leaves_qty: Some(100.0),
+        ts: 1536000000.0,
+    };
+
+    let json = serde_json::to_string(&liquidation).unwrap();
+    assert_eq!(json, r#"{"order_id":"f3f8e2a2","action":"update","symbol":"XBTUSD","leaves_qty":100.0,"ts":1536000000.0}"#);
+    assert_eq!(serde_json::from_str::<Liquidation>(&json).unwrap(), liquidation);
+}
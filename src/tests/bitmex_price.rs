@@ -0,0 +1,27 @@
+#[test]
+fn bitmex_level_price() {
+    use exchange::bitmex::{encoded_tick_size, level_price};
+
+    // XBTUSD is the 88th instrument. Its IDs are encoded with a 0.01 tick, even though it trades in 0.5 increments,
+    // which a level BitMEX sent along with its price tells us
+    let tick_size = encoded_tick_size(8799297850, 88, 7021.5).unwrap();
+    assert!((tick_size - 0.01).abs() < 1e-6);
+
+    // Level IDs of a recorded `orderBookL2:XBTUSD` partial decode to the prices BitMEX sent along with them,
+    // the same as the `(8800000000 - id) * 0.01` formula we used to special case XBTUSD with
+    for &(id, price) in &[(8799297850u64, 7021.5f32), (8799297900, 7021.0), (8799360000, 6400.0)] {
+        let decoded = level_price(id, 88, tick_size).unwrap();
+
+        assert!((decoded - (8800000000 - id) as f32 * 0.01).abs() < 0.01);
+        assert!((decoded - price).abs() < 0.01);
+    }
+
+    // Other instruments use their own tick size
+    assert!((encoded_tick_size(299995000, 3, 250.0).unwrap() - 0.05).abs() < 1e-6);
+    assert!((level_price(299995000, 3, 0.05).unwrap() - 250.0).abs() < 0.01);
+
+    // IDs of another instrument don't decode
+    assert_eq!(level_price(8799297850, 3, 0.05), None);
+    assert_eq!(encoded_tick_size(8799297850, 3, 7021.5), None);
+    assert_eq!(encoded_tick_size(300000000, 3, 0.0), None);
+}
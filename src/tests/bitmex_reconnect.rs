@@ -0,0 +1,102 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ws;
+use ws::{Handler, Handshake, Message, Sender};
+
+use exchange::{ConnectionHealth, Exchange, ReconnectPolicy};
+use exchange::bitmex::supervise;
+
+/// Number of connections the server drops right after they subscribe
+const DROPS: usize = 3;
+
+/// Drops the first `DROPS` connections once they've subscribed, and keeps the next one open
+struct Server {
+    out: Sender,
+    subscribed: bool,
+    subscriptions: Arc<AtomicUsize>,
+    active: Arc<AtomicUsize>,
+}
+
+impl Handler for Server {
+    fn on_message(&mut self, _: Message) -> ws::Result<()> {
+        self.subscribed = true;
+        self.active.fetch_add(1, Ordering::SeqCst);
+
+        if self.subscriptions.fetch_add(1, Ordering::SeqCst) < DROPS {
+            return self.out.close(ws::CloseCode::Away);
+        }
+
+        Ok(())
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.subscribed {
+            self.active.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Subscribes as soon as it's connected, like the BitMEX handler does
+struct Client {
+    out: Sender,
+}
+
+impl Handler for Client {
+    fn on_open(&mut self, _: Handshake) -> ws::Result<()> {
+        self.out.send(r#"{"op":"subscribe","args":["orderBookL2:XBTUSD"]}"#)
+    }
+}
+
+#[test]
+fn bitmex_reconnects_outside_of_handler() {
+    let subscriptions = Arc::new(AtomicUsize::new(0));
+    let active = Arc::new(AtomicUsize::new(0));
+
+    let (server_subscriptions, server_active) = (subscriptions.clone(), active.clone());
+    let server = ws::WebSocket::new(move |out| Server {
+        out,
+        subscribed: false,
+        subscriptions: server_subscriptions.clone(),
+        active: server_active.clone(),
+    }).unwrap().bind("127.0.0.1:0").unwrap();
+
+    let url = format!("ws://{}", server.local_addr().unwrap());
+    let broadcaster = server.broadcaster();
+    thread::spawn(move || server.run());
+
+    let health = ConnectionHealth::new(Exchange::BitMEX);
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let policy = ReconnectPolicy {
+        base_delay_ms: 1,
+        max_delay_ms: 10,
+        jitter: false,
+        ..Default::default()
+    };
+
+    let (client_health, client_shutdown) = (health.clone(), shutdown.clone());
+    let client = thread::spawn(move || supervise(&client_health, &policy, &client_shutdown, || {
+        ws::connect(url.clone(), |out| Client { out })
+    }));
+
+    let started = Instant::now();
+    while subscriptions.load(Ordering::SeqCst) <= DROPS {
+        assert!(started.elapsed() < Duration::from_secs(10), "the client didn't reconnect");
+        thread::sleep(Duration::from_millis(10));
+    }
+    thread::sleep(Duration::from_millis(100));
+
+    // Every dropped connection is gone, and their subscriptions with them
+    assert_eq!(subscriptions.load(Ordering::SeqCst), DROPS + 1);
+    assert_eq!(active.load(Ordering::SeqCst), 1);
+    assert_eq!(health.reconnect_count.load(Ordering::SeqCst), DROPS);
+
+    // Shutting down stops the client once its connection closes, instead of reconnecting
+    shutdown.store(true, Ordering::SeqCst);
+    broadcaster.close(ws::CloseCode::Normal).unwrap();
+
+    assert!(client.join().unwrap().is_ok());
+    assert_eq!(subscriptions.load(Ordering::SeqCst), DROPS + 1);
+}
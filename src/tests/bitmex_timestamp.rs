@@ -0,0 +1,70 @@
+#[test]
+fn bitmex_parse_timestamp() {
+    use exchange::bitmex::parse_timestamp;
+
+    assert_eq!(parse_timestamp("2018-08-28T20:14:11.154Z"), Some(1535487251.154));
+    assert_eq!(parse_timestamp("1970-01-01T00:00:01.000Z"), Some(1.0));
+    assert_eq!(parse_timestamp("not a timestamp"), None);
+}
+
+#[test]
+fn bitmex_parse_timestamp_fraction_and_timezone() {
+    use exchange::bitmex::parse_timestamp;
+
+    // Offsets are normalized to UTC
+    assert_eq!(parse_timestamp("2018-08-28T22:14:11.154+02:00"), Some(1535487251.154));
+    assert_eq!(parse_timestamp("2018-08-28T15:14:11.154-05:00"), Some(1535487251.154));
+    // Fractions are optional, and kept to the millisecond
+    assert_eq!(parse_timestamp("2018-08-28T20:14:11Z"), Some(1535487251.0));
+    assert_eq!(parse_timestamp("2018-08-28T20:14:11.154999Z"), Some(1535487251.154));
+    // A timezone is required
+    assert_eq!(parse_timestamp("2018-08-28T20:14:11.154"), None);
+}
+
+#[test]
+fn bitmex_exchange_and_receive_timestamps() {
+    use std::collections::HashMap;
+    use std::sync::RwLock;
+
+    use exchange::bitmex::{parse_message, ParsedMessage, RawMessage};
+
+    let mut indexes = HashMap::new();
+    indexes.insert("XBTUSD".to_string(), 88);
+    let mut tick_sizes = HashMap::new();
+    tick_sizes.insert("XBTUSD".to_string(), 0.01);
+
+    let indexes = RwLock::new(indexes);
+    let tick_sizes = RwLock::new(tick_sizes);
+
+    let trade = RawMessage {
+        table: "trade".into(),
+        data: br#"{"table":"trade","action":"insert","data":[{"symbol":"XBTUSD","side":"Buy","size":100,
+            "price":6400.5,"trdMatchID":"a1","timestamp":"2018-08-28T20:14:11.154Z"}]}"#.to_vec(),
+        ts: 1535487251.5,
+        book: None,
+    };
+
+    match parse_message(&trade, &indexes, &tick_sizes) {
+        Some(ParsedMessage::Trades(trades)) => {
+            assert_eq!(trades[0].ts, 1535487251.154);
+            assert_eq!(trades[0].received_ts, Some(1535487251.5));
+        },
+        _ => panic!("Expected trades"),
+    }
+
+    // Book updates without a timestamp keep the time we received them
+    let update = RawMessage {
+        table: "orderBookL2".into(),
+        data: br#"{"table":"orderBookL2","action":"update","data":[{"symbol":"XBTUSD","id":8799360000,"side":"Buy","size":10}]}"#.to_vec(),
+        ts: 1535487251.5,
+        book: None,
+    };
+
+    match parse_message(&update, &indexes, &tick_sizes) {
+        Some(ParsedMessage::Deltas(deltas)) => {
+            assert_eq!(deltas[0].ts, 1535487251.5);
+            assert_eq!(deltas[0].received_ts, None);
+        },
+        _ => panic!("Expected deltas"),
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use exchange::bitmex::{parse_message, ParsedMessage, RawMessage};
+use orderbook;
+
+fn parse(table: &str, data: &[u8]) -> Option<ParsedMessage> {
+    let raw = RawMessage {
+        table: table.into(),
+        data: data.to_vec(),
+        ts: 1535487252.0,
+        book: None,
+    };
+
+    let mut asset_indexes = HashMap::new();
+    asset_indexes.insert("XBTUSD".to_string(), 88);
+    let mut asset_tick_size = HashMap::new();
+    asset_tick_size.insert("XBTUSD".to_string(), 0.01);
+
+    parse_message(&raw, &RwLock::new(asset_indexes), &RwLock::new(asset_tick_size))
+}
+
+#[test]
+fn bitmex_trade_flags() {
+    // Captured from the `trade:XBTUSD` subscription
+    let parsed = parse("trade", br#"{"table":"trade","action":"insert","data":[
+        {"timestamp":"2018-08-28T20:14:11.154Z","symbol":"XBTUSD","side":"Sell","size":1500,"price":7021.5,
+         "tickDirection":"ZeroMinusTick","trdMatchID":"9e8c2e0c-8a1f-4a6a-9a8b-1c2c6e0f6c3d",
+         "grossValue":21364500,"homeNotional":0.213645,"foreignNotional":1500},
+        {"timestamp":"2018-08-28T20:14:11.154Z","symbol":"XBTUSD","side":"Buy","size":20,"price":7022,
+         "tickDirection":"PlusTick","trdMatchID":"1a2b3c4d-5e6f-4a1b-8c2d-3e4f5a6b7c8d",
+         "grossValue":284820,"homeNotional":0.0028482,"foreignNotional":20}]}"#);
+
+    let trades = match parsed {
+        Some(ParsedMessage::Trades(trades)) => trades,
+        _ => panic!("Expected trades"),
+    };
+    let deltas: Vec<orderbook::Delta> = trades.iter().map(orderbook::Delta::from).collect();
+
+    assert_eq!(deltas.len(), 2);
+    // The trade's own price is used, there is no level ID to decode
+    assert_eq!(deltas[0].price, 7021.5);
+    assert_eq!(deltas[0].size, 1500.0);
+    assert_eq!(deltas[0].event, orderbook::TRADE | orderbook::ASK);
+    assert_eq!(deltas[1].price, 7022.0);
+    assert_eq!(deltas[1].event, orderbook::TRADE | orderbook::BID);
+}
+
+#[test]
+fn bitmex_book_update_flags() {
+    // Captured from the `orderBookL2:XBTUSD` subscription
+    let parsed = parse("orderBookL2", br#"{"table":"orderBookL2","action":"update","data":[
+        {"symbol":"XBTUSD","id":8799297850,"side":"Sell","size":101873},
+        {"symbol":"XBTUSD","id":8799297900,"side":"Buy","size":45296}]}"#);
+
+    let deltas = match parsed {
+        Some(ParsedMessage::Deltas(deltas)) => deltas,
+        _ => panic!("Expected deltas"),
+    };
+
+    assert_eq!(deltas.len(), 2);
+    assert_eq!(deltas[0].event, orderbook::UPDATE | orderbook::ASK);
+    assert_eq!(deltas[1].event, orderbook::UPDATE | orderbook::BID);
+    assert!(deltas.iter().all(|delta| delta.event & orderbook::TRADE == 0));
+    assert!((deltas[0].price - 7021.5).abs() < 0.01);
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock, mpsc};
+use std::thread;
+use std::time::Instant;
+
+use exchange::bitmex::{parse_message, spawn_worker, ParsedMessage, RawMessage, WORKER_QUEUE_CAPACITY};
+
+/// Canned `orderBookL2` update of a single bid level
+fn update(symbol: &str, id: u64, size: f32) -> RawMessage {
+    RawMessage {
+        table: "orderBookL2".into(),
+        data: format!(
+            r#"{{"table":"orderBookL2","action":"update","data":[{{"symbol":"{}","id":{},"side":"Buy","size":{}}}]}}"#,
+            symbol, id, size).into_bytes(),
+        ts: 1536000000.0,
+        book: None,
+    }
+}
+
+fn instruments() -> (Arc<RwLock<HashMap<String, u64>>>, Arc<RwLock<HashMap<String, f32>>>) {
+    let mut indexes = HashMap::new();
+    let mut tick_sizes = HashMap::new();
+
+    indexes.insert("XBTUSD".to_string(), 88);
+    tick_sizes.insert("XBTUSD".to_string(), 0.01);
+    indexes.insert("ETHUSD".to_string(), 3);
+    tick_sizes.insert("ETHUSD".to_string(), 0.05);
+
+    (Arc::new(RwLock::new(indexes)), Arc::new(RwLock::new(tick_sizes)))
+}
+
+#[test]
+fn bitmex_worker_preserves_order() {
+    let (indexes, tick_sizes) = instruments();
+    let (deltas_tx, deltas_rx) = mpsc::channel();
+
+    let (worker, thread) = spawn_worker(indexes, tick_sizes, move |parsed| {
+        if let ParsedMessage::Deltas(deltas) = parsed {
+            for delta in deltas {
+                deltas_tx.send(delta).unwrap();
+            }
+        }
+    });
+
+    for i in 0..50_000u64 {
+        let message = if i % 2 == 0 {
+            update("XBTUSD", 8799360000, i as f32)
+        } else {
+            update("ETHUSD", 299990000, i as f32)
+        };
+
+        worker.send(message).unwrap();
+    }
+
+    // The worker exits once the queue is closed and drained
+    drop(worker);
+    thread.join().unwrap();
+
+    let deltas: Vec<_> = deltas_rx.iter().collect();
+
+    assert_eq!(deltas.len(), 50_000);
+    for (i, delta) in deltas.iter().enumerate() {
+        assert_eq!(delta.size, i as f32);
+        assert_eq!(delta.symbol, if i % 2 == 0 { "XBTUSD" } else { "ETHUSD" });
+    }
+    assert!((deltas[0].price - 6400.0).abs() < 0.01);
+    assert!((deltas[1].price - 500.0).abs() < 0.01);
+}
+
+#[test]
+fn bitmex_worker_queue_is_bounded() {
+    let (indexes, tick_sizes) = instruments();
+    let (worker, thread) = spawn_worker(indexes.clone(), tick_sizes, |_| {});
+
+    // ETHUSD prices need the instrument indexes, so the worker stalls while we hold them
+    let stall = indexes.write().unwrap();
+
+    let mut queued = 0;
+    while worker.try_send(update("ETHUSD", 299990000, 1.0)).is_ok() {
+        queued += 1;
+        assert!(queued <= WORKER_QUEUE_CAPACITY + 1, "worker queue grew past its capacity");
+    }
+    // The worker may or may not have taken the first message off the queue before stalling
+    assert!(queued >= WORKER_QUEUE_CAPACITY);
+
+    drop(stall);
+    drop(worker);
+    thread.join().unwrap();
+}
+
+/// Compares the worker against the thread per message we used to spawn. Run with
+/// `cargo test bitmex_worker_throughput -- --ignored --nocapture`
+#[test]
+#[ignore]
+fn bitmex_worker_throughput() {
+    const MESSAGES: u64 = 50_000;
+
+    let (indexes, tick_sizes) = instruments();
+
+    let start = Instant::now();
+    let handles: Vec<_> = (0..MESSAGES)
+        .map(|i| {
+            let indexes = indexes.clone();
+            let tick_sizes = tick_sizes.clone();
+
+            thread::spawn(move || {
+                let _ = parse_message(&update("ETHUSD", 299990000, i as f32), &indexes, &tick_sizes);
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let per_message = start.elapsed();
+
+    let start = Instant::now();
+    let (worker, thread) = spawn_worker(indexes, tick_sizes, |_| {});
+    for i in 0..MESSAGES {
+        worker.send(update("ETHUSD", 299990000, i as f32)).unwrap();
+    }
+    drop(worker);
+    thread.join().unwrap();
+    let worker = start.elapsed();
+
+    let rate = |elapsed: ::std::time::Duration| MESSAGES as f64 / (elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9);
+
+    println!("Thread per message: {:.0} messages/s", rate(per_message));
+    println!("Worker:             {:.0} messages/s", rate(worker));
+}
@@ -0,0 +1,69 @@
+use std::io::Write;
+
+use base64;
+use flate2::Compression;
+use flate2::write::DeflateEncoder;
+use serde_json;
+
+use exchange::bittrex::{connect_url, decode_payload, BookDelta, SequenceGap, SequenceSync};
+
+/// Compresses and encodes a payload the way Bittrex does
+fn encode_payload(payload: &str) -> String {
+    let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+    encoder.write_all(payload.as_bytes()).unwrap();
+
+    base64::encode(&encoder.finish().unwrap())
+}
+
+fn delta(sequence: u64) -> BookDelta {
+    serde_json::from_str(&format!(r#"{{
+        "marketSymbol": "BTC-USD",
+        "depth": 25,
+        "sequence": {},
+        "bidDeltas": [{{"quantity": "0.5", "rate": "38000.1"}}],
+        "askDeltas": []
+    }}"#, sequence)).unwrap()
+}
+
+#[test]
+fn bittrex_decode_payload() {
+    let payload = r#"{"marketSymbol":"BTC-USD","depth":25,"sequence":7,"bidDeltas":[],"askDeltas":[{"quantity":"0","rate":"38010"}]}"#;
+    let data = decode_payload(&encode_payload(payload)).unwrap();
+
+    let delta: BookDelta = serde_json::from_slice(&data).unwrap();
+    assert_eq!(delta.market_symbol, "BTC-USD");
+    assert_eq!(delta.sequence, 7);
+    assert_eq!(delta.ask_deltas[0].rate, "38010");
+
+    assert!(decode_payload("not base64!").is_err());
+}
+
+#[test]
+fn bittrex_sequence_sync() {
+    let mut book = SequenceSync::new();
+
+    // Deltas are buffered until the snapshot arrives, and the ones it already covers are dropped
+    assert!(book.push(delta(10)).unwrap().is_empty());
+    assert!(book.push(delta(11)).unwrap().is_empty());
+    assert!(!book.is_seeded());
+
+    let deltas = book.seed(10).unwrap();
+    assert_eq!(deltas.iter().map(|delta| delta.sequence).collect::<Vec<_>>(), vec![11]);
+
+    assert_eq!(book.push(delta(12)).unwrap().len(), 1);
+    assert!(book.push(delta(12)).unwrap().is_empty());
+    assert_eq!(book.push(delta(14)).unwrap_err(), SequenceGap);
+
+    // A snapshot older than the buffer can't be stitched onto
+    let mut book = SequenceSync::new();
+    book.push(delta(20)).unwrap();
+    assert_eq!(book.seed(17).unwrap_err(), SequenceGap);
+}
+
+#[test]
+fn bittrex_connect_url() {
+    let url = connect_url("https://socket-v3.bittrex.com/signalr", "a+b/c");
+
+    assert!(url.starts_with("wss://socket-v3.bittrex.com/signalr/connect?transport=webSockets"));
+    assert!(url.contains("connectionToken=a%2Bb%2Fc"));
+}
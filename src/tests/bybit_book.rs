@@ -0,0 +1,40 @@
+#[test]
+fn bybit_legacy_book_deltas() {
+    use exchange::bybit::legacy_book_deltas;
+    use orderbook::{self, DeltaEvent};
+    use serde_json;
+
+    let snapshot = serde_json::from_str(r#"[
+        {"price": "2999.00", "symbol": "BTCUSD", "id": 29990000, "side": "Buy", "size": 9},
+        {"price": "3001.00", "symbol": "BTCUSD", "id": 30010000, "side": "Sell", "size": 10}
+    ]"#).unwrap();
+
+    let deltas = legacy_book_deltas(snapshot, true, 0.0).unwrap();
+    assert_eq!(deltas.len(), 2);
+    assert_eq!((deltas[0].price, deltas[0].size), (2999.0, 9.0));
+    assert_eq!(deltas[0].event_kind(), Some(DeltaEvent::BidUpdate));
+    assert_eq!(deltas[1].event_kind(), Some(DeltaEvent::AskUpdate));
+
+    // Some products wrap the snapshot in `order_book`
+    let wrapped = serde_json::from_str(r#"{"order_book": [
+        {"price": "2999.00", "symbol": "BTCUSDT", "id": 29990000, "side": "Buy", "size": 9}
+    ]}"#).unwrap();
+    assert_eq!(legacy_book_deltas(wrapped, true, 0.0).unwrap().len(), 1);
+
+    let delta = serde_json::from_str(r#"{
+        "delete": [{"price": "3001.00", "symbol": "BTCUSD", "id": 30010000, "side": "Sell"}],
+        "update": [{"price": "2999.00", "symbol": "BTCUSD", "id": 29990000, "side": "Buy", "size": 8}],
+        "insert": [{"price": "2998.50", "symbol": "BTCUSD", "id": 29985000, "side": "Buy", "size": 1}],
+        "transactTimeE6": 0
+    }"#).unwrap();
+
+    let deltas = legacy_book_deltas(delta, false, 0.0).unwrap();
+    assert_eq!(deltas.len(), 3);
+
+    // Deletes become size-0 removals
+    assert_eq!((deltas[0].price, deltas[0].size), (3001.0, 0.0));
+    assert_eq!(deltas[0].event, orderbook::ASK ^ orderbook::REMOVE);
+    assert_eq!((deltas[1].price, deltas[1].size), (2999.0, 8.0));
+    assert_eq!((deltas[2].price, deltas[2].size), (2998.5, 1.0));
+    assert_eq!(deltas.iter().map(|delta| delta.seq).collect::<Vec<u32>>(), vec![1, 2, 3]);
+}
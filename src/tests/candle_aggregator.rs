@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use aggregation::candle::{candle_channel, interval_name, CandleAggregator};
+use exchange::Exchange;
+use orderbook::{Trade, TradeSide};
+
+fn trade(price: f64, size: f64, ts: f64) -> Trade {
+    Trade {
+        symbol: "XBTUSD".into(),
+        price,
+        size,
+        side: TradeSide::Buy,
+        ts,
+        exchange: Exchange::BitMEX,
+        trade_id: None,
+        received_ts: None,
+    }
+}
+
+#[test]
+fn candle_aggregator_ohlcv() {
+    let mut aggregator = CandleAggregator::new(vec![Duration::from_secs(60)]);
+
+    assert!(aggregator.add(&trade(6400.0, 10.0, 1536000000.0)).is_empty());
+    assert!(aggregator.add(&trade(6410.5, 5.0, 1536000010.0)).is_empty());
+    assert!(aggregator.add(&trade(6395.0, 1.0, 1536000030.0)).is_empty());
+    assert!(aggregator.add(&trade(6401.0, 2.0, 1536000059.9)).is_empty());
+
+    // The first trade of the next minute closes the candle
+    let closed = aggregator.add(&trade(6402.0, 3.0, 1536000060.0));
+    assert_eq!(closed.len(), 1);
+
+    let (interval, ref candle) = closed[0];
+    assert_eq!(interval, Duration::from_secs(60));
+    assert_eq!(candle.interval_start_ts, 1536000000.0);
+    assert_eq!((candle.open, candle.high, candle.low, candle.close), (6400.0, 6410.5, 6395.0, 6401.0));
+    assert_eq!(candle.volume, 18.0);
+    assert!(!candle.partial);
+
+    // The trade that closed it opened the next candle, which is flushed as partial
+    let flushed = aggregator.flush();
+    assert_eq!(flushed.len(), 1);
+    assert_eq!(flushed[0].1.interval_start_ts, 1536000060.0);
+    assert_eq!(flushed[0].1.open, 6402.0);
+    assert!(flushed[0].1.partial);
+    assert!(aggregator.flush().is_empty());
+}
+
+#[test]
+fn candle_aggregator_intervals() {
+    let mut aggregator = CandleAggregator::new(vec![Duration::from_secs(60), Duration::from_secs(300)]);
+
+    aggregator.add(&trade(6400.0, 1.0, 1536000000.0));
+    aggregator.add(&trade(6401.0, 1.0, 1536000100.0));
+    let closed = aggregator.add(&trade(6402.0, 1.0, 1536000300.0));
+
+    // Both the second minute and the first five minutes close
+    assert_eq!(closed.len(), 2);
+    let five_minutes = closed.iter().find(|(interval, _)| *interval == Duration::from_secs(300)).unwrap();
+    assert_eq!(five_minutes.1.volume, 2.0);
+    assert_eq!(five_minutes.1.close, 6401.0);
+
+    // Trades arriving late for a closed candle are dropped
+    assert!(aggregator.add(&trade(1.0, 1.0, 1536000000.0)).is_empty());
+    assert!(aggregator.flush().iter().all(|(_, candle)| candle.low == 6402.0));
+}
+
+#[test]
+fn candle_aggregator_channel() {
+    let mut aggregator = CandleAggregator::new(vec![Duration::from_secs(3600)]);
+    aggregator.add(&trade(6400.0, 1.0, 1536000000.0));
+    let (interval, candle) = aggregator.flush().remove(0);
+
+    assert_eq!(candle_channel(&candle, interval), "bitmex:candle:1h:XBTUSD");
+
+    assert_eq!(interval_name(Duration::from_secs(30)), "30s");
+    assert_eq!(interval_name(Duration::from_secs(300)), "5m");
+    assert_eq!(interval_name(Duration::from_secs(86400)), "1d");
+}
@@ -0,0 +1,67 @@
+#[test]
+fn config_parse_sections() {
+    use config::Config;
+    use exchange::Asset;
+
+    let config = Config::parse(r#"
+[redis]
+url = "redis://127.0.0.1:6379/0"
+password = "hunter2"
+
+[tectonic]
+host = "10.0.0.5"
+port = 9002
+
+[channels]
+names = ["orderBookL2", "trade"]
+
+[assets]
+pairs = [["BTC", "USD"], ["eth", "usd"]]
+"#).unwrap();
+
+    assert_eq!(config.redis.url, Some("redis://127.0.0.1:6379/0".into()));
+    assert_eq!(config.redis.password, Some("hunter2".into()));
+    assert_eq!(config.tectonic.host, Some("10.0.0.5".into()));
+    assert_eq!(config.tectonic.port, Some(9002));
+    assert_eq!(config.channels.names, Some(vec!["orderBookL2".to_string(), "trade".to_string()]));
+    assert_eq!(config.asset_pairs().unwrap(), Some(vec![
+        [Asset::BTC, Asset::USD],
+        [Asset::ETH, Asset::USD],
+    ]));
+}
+
+#[test]
+fn config_missing_sections() {
+    use config::Config;
+
+    let config = Config::parse("[assets]\npairs = [[\"BTC\",\"USD\"]]").unwrap();
+
+    assert_eq!(config.redis, Default::default());
+    assert_eq!(config.channels.names, None);
+    assert!(config.storage("bitmex").unwrap().is_none());
+
+    assert_eq!(Config::parse("").unwrap(), Config::default());
+    assert_eq!(Config::default().asset_pairs().unwrap(), None);
+}
+
+#[test]
+fn config_errors() {
+    use std::path::Path;
+
+    use config::{Config, ConfigError};
+
+    match Config::parse("[assets]\npairs = [[\"BTC\",\"NOTACOIN\"]]").unwrap().asset_pairs() {
+        Err(ConfigError::UnknownAsset(ref ticker)) => assert_eq!(ticker, "NOTACOIN"),
+        _ => panic!("Expected an unknown asset error"),
+    }
+
+    match Config::parse("[assets]\npairs = \"BTC\"") {
+        Err(ConfigError::Toml(_)) => (),
+        _ => panic!("Expected a TOML error"),
+    }
+
+    match Config::from_file(Path::new("/nonexistent/rusty_road.toml")) {
+        Err(ConfigError::Io(_)) => (),
+        _ => panic!("Expected an I/O error"),
+    }
+}
@@ -0,0 +1,39 @@
+#[test]
+fn connection_health_counters() {
+    use std::sync::atomic::Ordering;
+
+    use exchange::{ConnectionHealth, Exchange};
+
+    let health = ConnectionHealth::new(Exchange::BitMEX);
+    let shared = health.clone();
+
+    assert!(health.since_last_message().is_none());
+
+    shared.record_message();
+    shared.record_reconnect();
+    shared.record_reconnect();
+
+    // Clones share their counters with the original
+    assert!(health.since_last_message().is_some());
+    assert_eq!(health.reconnect_count.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn connection_health_latency() {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use exchange::{ConnectionHealth, Exchange};
+
+    let health = ConnectionHealth::new(Exchange::GDAX);
+
+    // Unsolicited pongs don't carry our timestamp
+    health.record_pong(b"");
+    assert!(health.latency_ms.lock().unwrap().is_none());
+
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let sent_ms = since_epoch.as_secs() * 1_000 + since_epoch.subsec_millis() as u64 - 250;
+    health.record_pong(sent_ms.to_string().as_bytes());
+
+    let latency_ms = health.latency_ms.lock().unwrap().unwrap();
+    assert!(latency_ms >= 250.0 && latency_ms < 10_000.0);
+}
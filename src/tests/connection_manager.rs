@@ -0,0 +1,128 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use redis;
+use ws;
+
+use config::{Config, ConfigError};
+use exchange::{AssetExchange, ConnectionHealth, Exchange, ExchangeError, ReconnectPolicy};
+use manager::ConnectionManager;
+
+/// Exchange whose connection fails a given number of times before exiting on its own. Failures
+/// alternate between panicking and returning an error.
+struct FlakyExchange {
+    health: ConnectionHealth,
+    failures: usize,
+    runs: Arc<AtomicUsize>,
+}
+
+impl AssetExchange for FlakyExchange {
+    fn default_settings() -> Result<Box<Self>, String> {
+        Err("FlakyExchange has no default settings".into())
+    }
+
+    fn configure(&mut self, _: &Config) -> Result<(), ConfigError> {
+        Ok(())
+    }
+
+    fn init_redis(&mut self) -> Result<redis::Connection, redis::RedisError> {
+        Err(redis::RedisError::from((redis::ErrorKind::IoError, "FlakyExchange doesn't use Redis")))
+    }
+
+    fn run_with_settings(settings: Option<&Self>) -> Result<(), ExchangeError> {
+        let settings = settings.expect("FlakyExchange has no default settings");
+
+        let run = settings.runs.fetch_add(1, Ordering::SeqCst);
+        if run < settings.failures {
+            if run % 2 == 0 {
+                panic!("FlakyExchange connection failed");
+            }
+            return Err(ExchangeError::Websocket(ws::Error::new(ws::ErrorKind::Internal, "FlakyExchange connection failed")));
+        }
+        settings.health.record_message();
+
+        Ok(())
+    }
+
+    fn health(&self) -> &ConnectionHealth {
+        &self.health
+    }
+
+    fn start(&self) -> Result<(), ExchangeError> {
+        FlakyExchange::run_with_settings(Some(self))
+    }
+}
+
+fn flaky(exchange: Exchange, failures: usize) -> (Box<FlakyExchange>, Arc<AtomicUsize>) {
+    let runs = Arc::new(AtomicUsize::new(0));
+
+    (Box::new(FlakyExchange { health: ConnectionHealth::new(exchange), failures, runs: runs.clone() }), runs)
+}
+
+#[test]
+fn socket_manager_restarts_failed_connections() {
+    let (bitmex, bitmex_runs) = flaky(Exchange::BitMEX, 2);
+    let (gdax, gdax_runs) = flaky(Exchange::GDAX, 0);
+
+    let mut manager = ConnectionManager::new(vec![bitmex, gdax]);
+    manager.restart_policy = Some(ReconnectPolicy {
+        base_delay_ms: 1,
+        max_delay_ms: 1,
+        jitter: false,
+        ..Default::default()
+    });
+
+    manager.run_all();
+
+    assert_eq!(bitmex_runs.load(Ordering::SeqCst), 3);
+    assert_eq!(gdax_runs.load(Ordering::SeqCst), 1);
+
+    let status = manager.status();
+    assert_eq!(status.len(), 2);
+    assert_eq!(status[0].exchange, Exchange::BitMEX);
+    assert_eq!(status[0].reconnect_count, 2);
+    assert!(status[0].since_last_message.is_some());
+    assert_eq!(status[1].exchange, Exchange::GDAX);
+    assert_eq!(status[1].reconnect_count, 0);
+}
+
+#[test]
+fn socket_manager_gives_up() {
+    let (bitmex, bitmex_runs) = flaky(Exchange::BitMEX, 10);
+
+    // Without a policy, failed connections stay stopped
+    ConnectionManager::new(vec![bitmex]).run_all();
+    assert_eq!(bitmex_runs.load(Ordering::SeqCst), 1);
+
+    let (bitmex, bitmex_runs) = flaky(Exchange::BitMEX, 10);
+
+    let mut manager = ConnectionManager::new(vec![bitmex]);
+    manager.restart_policy = Some(ReconnectPolicy {
+        base_delay_ms: 1,
+        max_delay_ms: 1,
+        jitter: false,
+        max_attempts: Some(3),
+        ..Default::default()
+    });
+
+    manager.run_all();
+    assert_eq!(bitmex_runs.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn socket_manager_doesnt_restart_stopped_connections() {
+    let (bitmex, bitmex_runs) = flaky(Exchange::BitMEX, 10);
+
+    let mut manager = ConnectionManager::new(vec![bitmex]);
+    manager.restart_policy = Some(ReconnectPolicy {
+        base_delay_ms: 1,
+        max_delay_ms: 1,
+        jitter: false,
+        ..Default::default()
+    });
+
+    manager.stop_all();
+    manager.run_all();
+    assert_eq!(bitmex_runs.load(Ordering::SeqCst), 1);
+}
+
@@ -0,0 +1,135 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ws;
+use ws::{Handler, Handshake, Sender};
+
+use exchange::{self, ConnectionHealth, Exchange};
+use orderbook;
+use storage::{StorageBackend, StorageError};
+
+/// Storage counting how many times it was flushed
+#[derive(Clone)]
+struct CountingStorage {
+    flushes: Arc<AtomicUsize>,
+}
+
+impl StorageBackend for CountingStorage {
+    fn create(&mut self, _: &str) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    fn insert(&mut self, _: &[orderbook::Delta]) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), StorageError> {
+        self.flushes.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn box_clone(&self) -> Box<dyn StorageBackend> {
+        Box::new(self.clone())
+    }
+
+    fn set_exchange(&mut self, _: &str) {}
+}
+
+/// Keeps every connection open until the client closes it
+struct Server {
+    open: Arc<AtomicUsize>,
+}
+
+impl Handler for Server {
+    fn on_open(&mut self, _: Handshake) -> ws::Result<()> {
+        self.open.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        self.open.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Handles shutdown the way the exchanges' handlers do
+struct Collector {
+    out: Sender,
+    health: ConnectionHealth,
+    storage: Box<dyn StorageBackend>,
+}
+
+impl Handler for Collector {
+    fn on_open(&mut self, _: Handshake) -> ws::Result<()> {
+        self.health.track_connection(&self.out);
+        Ok(())
+    }
+
+    fn on_close(&mut self, _: ws::CloseCode, _: &str) {
+        if self.health.is_stopped() {
+            exchange::flush_storage(self.health.exchange, &mut *self.storage);
+        }
+    }
+}
+
+#[test]
+fn stopping_a_collector_closes_its_connection() {
+    let open = Arc::new(AtomicUsize::new(0));
+
+    let server_open = open.clone();
+    let server = ws::WebSocket::new(move |_| Server { open: server_open.clone() })
+        .unwrap()
+        .bind("127.0.0.1:0")
+        .unwrap();
+    let url = format!("ws://{}", server.local_addr().unwrap());
+    thread::spawn(move || server.run());
+
+    let health = ConnectionHealth::new(Exchange::Binance);
+    let flushes = Arc::new(AtomicUsize::new(0));
+    let (exited_tx, exited_rx) = mpsc::channel();
+
+    let (client_health, client_flushes) = (health.clone(), flushes.clone());
+    let client = thread::spawn(move || {
+        let result = ws::connect(url, |out| Collector {
+            out,
+            health: client_health.clone(),
+            storage: Box::new(CountingStorage { flushes: client_flushes.clone() }),
+        });
+        exited_tx.send(()).unwrap();
+        result
+    });
+
+    let started = Instant::now();
+    while open.load(Ordering::SeqCst) == 0 {
+        assert!(started.elapsed() < Duration::from_secs(10), "the collector didn't connect");
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(!health.is_stopped());
+
+    health.stop();
+
+    // The event loop (and the thread running it) exits once the connection is closed
+    exited_rx.recv_timeout(Duration::from_secs(10)).expect("the collector is still running");
+    assert!(client.join().unwrap().is_ok());
+    assert!(health.is_stopped());
+    assert_eq!(flushes.load(Ordering::SeqCst), 1);
+
+    let started = Instant::now();
+    while open.load(Ordering::SeqCst) > 0 {
+        assert!(started.elapsed() < Duration::from_secs(10), "the server's connection wasn't closed");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn stopping_before_connecting_is_harmless() {
+    let health = ConnectionHealth::new(Exchange::Binance);
+
+    health.stop();
+    health.stop();
+
+    // Clones share the flag, so the handlers see it as well
+    assert!(health.clone().is_stopped());
+}
@@ -0,0 +1,45 @@
+#[test]
+fn cryptocom_book_diff() {
+    use serde_json;
+
+    use exchange::cryptocom::{BookData, LocalBook};
+
+    let first: BookData = serde_json::from_str(r#"{
+        "bids": [[11746.488, 128, 8], [11746.0, 0.5, 1]],
+        "asks": [[11747.488, 201, 12], [11748.0, "1.5", 2]],
+        "t": 1587523078844
+    }"#).unwrap();
+
+    let mut book = LocalBook::default();
+
+    // The first snapshot is published in full. Sizes sent as strings are read as well.
+    let (asks, bids) = book.update(&first);
+    assert_eq!(asks, vec![(11747.488, 201.0), (11748.0, 1.5)]);
+    assert_eq!(bids.len(), 2);
+
+    // Resending the same book doesn't produce any changes
+    let (asks, bids) = book.update(&first);
+    assert!(asks.is_empty() && bids.is_empty());
+
+    // The best bid resized and the second ask level left the book
+    let second: BookData = serde_json::from_str(r#"{
+        "bids": [[11746.488, 100, 7], [11746.0, 0.5, 1]],
+        "asks": [[11747.488, 201, 12]],
+        "t": 1587523079844
+    }"#).unwrap();
+
+    let (asks, bids) = book.update(&second);
+    assert_eq!(asks, vec![(11748.0, 0.0)]);
+    assert_eq!(bids, vec![(11746.488, 100.0)]);
+}
+
+#[test]
+fn cryptocom_parse_levels() {
+    use serde_json;
+
+    use exchange::cryptocom::parse_levels;
+
+    let levels: Vec<Vec<serde_json::Value>> = serde_json::from_str(r#"[[1.5, 2, 1], ["3.25", "4", 2], [null, 1, 1], [5.0]]"#).unwrap();
+
+    assert_eq!(parse_levels(&levels), vec![[1.5, 2.0], [3.25, 4.0]]);
+}
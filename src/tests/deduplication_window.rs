@@ -0,0 +1,70 @@
+use std::sync::atomic::Ordering;
+
+use exchange::{ConnectionHealth, DeduplicationWindow, Exchange, DEFAULT_DEDUP_CAPACITY};
+use orderbook::{self, Delta};
+
+fn delta(price: f32, ts: f64) -> Delta {
+    Delta {
+        symbol: "XBTUSD".into(),
+        price,
+        size: 100.0,
+        seq: 0,
+        event: orderbook::BID | orderbook::UPDATE,
+        ts,
+        received_ts: None,
+    }
+}
+
+#[test]
+fn deduplication_window_drops_replays() {
+    let mut window = DeduplicationWindow::default();
+
+    assert!(!window.is_duplicate(&delta(6500.0, 1.0)));
+    assert!(window.is_duplicate(&delta(6500.0, 1.0)));
+
+    // Deltas sharing a sequence number aren't duplicates unless everything else matches too
+    assert!(!window.is_duplicate(&delta(6500.5, 1.0)));
+    assert!(!window.is_duplicate(&Delta { size: 200.0, ..delta(6500.0, 1.0) }));
+    assert_eq!(window.len(), 3);
+}
+
+#[test]
+fn deduplication_window_replay_received_later() {
+    let mut window = DeduplicationWindow::default();
+
+    // Untimestamped BitMEX book updates keep the time we received them, which differs for a replay
+    assert!(!window.is_duplicate(&delta(6500.0, 1536000000.125)));
+    assert!(window.is_duplicate(&delta(6500.0, 1536000030.5)));
+
+    // Timestamped deltas are told apart by the exchange's timestamp, not the time we received them
+    let timestamped = |ts: f64, received_ts: f64| Delta { received_ts: Some(received_ts), ..delta(6400.0, ts) };
+
+    assert!(!window.is_duplicate(&timestamped(1536000001.0, 1536000001.1)));
+    assert!(window.is_duplicate(&timestamped(1536000001.0, 1536000031.7)));
+    assert!(!window.is_duplicate(&timestamped(1536000002.0, 1536000002.1)));
+}
+
+#[test]
+fn deduplication_window_capacity() {
+    let mut window = DeduplicationWindow::new(2);
+
+    assert!(!window.is_duplicate(&delta(1.0, 1.0)));
+    assert!(!window.is_duplicate(&delta(2.0, 1.0)));
+    assert!(!window.is_duplicate(&delta(3.0, 1.0)));
+    assert_eq!(window.len(), 2);
+
+    // The oldest delta was forgotten to make room
+    assert!(!window.is_duplicate(&delta(1.0, 1.0)));
+    assert!(window.is_duplicate(&delta(3.0, 1.0)));
+
+    assert_eq!(DeduplicationWindow::default().len(), 0);
+    assert_eq!(DEFAULT_DEDUP_CAPACITY, 1000);
+}
+
+#[test]
+fn connection_health_duplicates() {
+    let health = ConnectionHealth::new(Exchange::BitMEX);
+
+    health.clone().record_duplicate();
+    assert_eq!(health.duplicates_skipped.load(Ordering::SeqCst), 1);
+}
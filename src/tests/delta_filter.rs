@@ -0,0 +1,56 @@
+use std::sync::atomic::Ordering;
+
+use exchange::{ConnectionHealth, DeltaFilter, Exchange};
+use orderbook::{self, Delta};
+
+fn delta(symbol: &str, price: f32, size: f32, event: u8) -> Delta {
+    Delta {
+        symbol: symbol.into(),
+        price,
+        size,
+        seq: 0,
+        event: orderbook::BID | event,
+        ts: 1.0,
+        received_ts: None,
+    }
+}
+
+#[test]
+fn delta_filter_drops_noise() {
+    let filter = DeltaFilter {
+        min_size: 0.01,
+        min_price: 1.0,
+        allowed_symbols: Some(vec!["XBTUSD".to_string()].into_iter().collect()),
+    };
+
+    assert!(filter.allows(&delta("XBTUSD", 6500.0, 0.5, orderbook::UPDATE)));
+    assert!(!filter.allows(&delta("XBTUSD", 6500.0, 0.001, orderbook::UPDATE)));
+    assert!(!filter.allows(&delta("XBTUSD", 0.5, 0.5, orderbook::UPDATE)));
+    assert!(!filter.allows(&delta("ETHUSD", 200.0, 0.5, orderbook::UPDATE)));
+
+    // Removals have no size, but consumers still need them to keep their books right
+    assert!(filter.allows(&delta("XBTUSD", 6500.0, 0.0, orderbook::REMOVE)));
+    assert!(!filter.allows(&delta("ETHUSD", 200.0, 0.0, orderbook::REMOVE)));
+}
+
+#[test]
+fn delta_filter_counts_dropped_deltas() {
+    let health = ConnectionHealth::new(Exchange::BitMEX);
+    let filter = DeltaFilter { min_size: 1.0, ..Default::default() };
+
+    let mut deltas = vec![
+        delta("XBTUSD", 6500.0, 10.0, orderbook::UPDATE),
+        delta("XBTUSD", 6500.5, 0.5, orderbook::UPDATE),
+        delta("ETHUSD", 200.0, 0.1, orderbook::TRADE),
+    ];
+    filter.apply(&mut deltas, &health);
+
+    assert_eq!(deltas.len(), 1);
+    assert_eq!(deltas[0].price, 6500.0);
+    assert_eq!(health.filtered_count.load(Ordering::SeqCst), 2);
+
+    // The default filter lets everything through
+    let mut deltas = vec![delta("ETHUSD", 0.0, 0.0, orderbook::UPDATE)];
+    DeltaFilter::default().apply(&mut deltas, &health);
+    assert_eq!(deltas.len(), 1);
+}
@@ -0,0 +1,30 @@
+#[test]
+fn deribit_change_id_continuity() {
+    use exchange::deribit::ChangeIds;
+
+    let mut change_ids = ChangeIds::default();
+
+    // Changes are rejected until we've received a snapshot
+    assert!(!change_ids.change("BTC-PERPETUAL", 10, 9));
+
+    change_ids.snapshot("BTC-PERPETUAL", 10);
+    assert!(change_ids.change("BTC-PERPETUAL", 11, 10));
+    assert!(change_ids.change("BTC-PERPETUAL", 15, 11));
+
+    // A gap invalidates the book until the next snapshot
+    assert!(!change_ids.change("BTC-PERPETUAL", 20, 17));
+    assert!(!change_ids.change("BTC-PERPETUAL", 21, 20));
+
+    change_ids.snapshot("BTC-PERPETUAL", 21);
+    assert!(change_ids.change("BTC-PERPETUAL", 22, 21));
+}
+
+#[test]
+fn deribit_level_event() {
+    use exchange::deribit::level_event;
+    use orderbook;
+
+    assert_eq!(level_event(orderbook::BID, "new"), orderbook::BID ^ orderbook::INSERT);
+    assert_eq!(level_event(orderbook::ASK, "change"), orderbook::ASK ^ orderbook::UPDATE);
+    assert_eq!(level_event(orderbook::ASK, "delete"), orderbook::ASK ^ orderbook::REMOVE);
+}
@@ -0,0 +1,57 @@
+use serde_json;
+
+use exchange::dydx::{BookSnapshot, BookUpdate, LevelOffsets};
+use orderbook;
+
+#[test]
+fn dydx_offsets_ignore_stale_updates() {
+    let snapshot: BookSnapshot = serde_json::from_str(r#"{
+        "asks": [{"size": "1.2", "price": "57010", "offset": "120"}, {"size": "0.4", "price": "57020", "offset": "100"}],
+        "bids": [{"size": "2.5", "price": "57000", "offset": "115"}]
+    }"#).unwrap();
+
+    let mut offsets = LevelOffsets::default();
+    offsets.seed(&snapshot);
+
+    assert_eq!(offsets.asks["57010"], 120);
+    assert_eq!(offsets.bids["57000"], 115);
+
+    // 57010 was already updated at offset 120, but 57020 and the new bid level apply
+    let update: BookUpdate = serde_json::from_str(r#"{
+        "offset": "118",
+        "asks": [["57010", "0"], ["57020", "0.9"]],
+        "bids": [["56990", "3.0"]]
+    }"#).unwrap();
+
+    let (asks, bids) = offsets.update(&update);
+    assert_eq!(asks, vec![["57020".to_string(), "0.9".to_string()]]);
+    assert_eq!(bids, vec![["56990".to_string(), "3.0".to_string()]]);
+
+    // Replaying the same offset is stale everywhere
+    let (asks, bids) = offsets.update(&update);
+    assert!(asks.is_empty() && bids.is_empty());
+
+    // A removed level keeps its offset, so an older update can't bring it back
+    let remove: BookUpdate = serde_json::from_str(r#"{"offset": "130", "asks": [["57010", "0"]], "bids": []}"#).unwrap();
+    assert_eq!(offsets.update(&remove).0.len(), 1);
+
+    let stale: BookUpdate = serde_json::from_str(r#"{"offset": "125", "asks": [["57010", "5.0"]]}"#).unwrap();
+    assert!(offsets.update(&stale).0.is_empty());
+}
+
+#[test]
+fn dydx_offsets_reseed() {
+    let mut offsets = LevelOffsets::default();
+
+    assert!(offsets.apply(orderbook::BID, "57000", 200));
+
+    // A new initial book replaces every offset, even if they're lower
+    let snapshot: BookSnapshot = serde_json::from_str(r#"{
+        "asks": [],
+        "bids": [{"size": "1.0", "price": "57000", "offset": "50"}]
+    }"#).unwrap();
+    offsets.seed(&snapshot);
+
+    assert_eq!(offsets.bids["57000"], 50);
+    assert!(offsets.apply(orderbook::BID, "57000", 51));
+}
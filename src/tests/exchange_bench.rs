@@ -3,13 +3,11 @@ fn bitmex_bench() {
     use std::env;
     use std::thread;
 
-    use redis;
-
     use exchange::{Asset, AssetExchange};
     use exchange::bitmex;
 
-    // Redis client is setup here so that we can provide it a host, password, and database
-    let r = redis::Client::open("redis://127.0.0.1:6379/0").unwrap();
+    // Redis URL is setup here so that we can provide it a host, port, and database
+    let redis_url = "redis://127.0.0.1:6379/0";
     let r_password = match env::var_os("REDIS_AUTH") {
         Some(password) => Some(password.into_string().unwrap()),
         None => None   
@@ -19,10 +17,10 @@ fn bitmex_bench() {
     bitmex_settings.metadata.asset_pair = Some(vec![
         [Asset::BTC, Asset::USD],]);
 
-    bitmex_settings.r = r.clone();
+    bitmex_settings.redis_url = redis_url.into();
     bitmex_settings.r_password = r_password.as_ref().cloned();
 
-    let exchange = thread::spawn(move || bitmex::WSExchange::run(Some(&bitmex_settings)));
+    let exchange = thread::spawn(move || bitmex::WSExchange::run_with_settings(Some(&bitmex_settings)));
     let _ = exchange.join();
 }
 
@@ -31,13 +29,11 @@ fn gdax_bench() {
     use std::env;
     use std::thread;
 
-    use redis;
-
     use exchange::{Asset, AssetExchange};
     use exchange::gdax_l2;
 
-    // Redis client is setup here so that we can provide it a host, password, and database
-    let r = redis::Client::open("redis://127.0.0.1:6379/0").unwrap();
+    // Redis URL is setup here so that we can provide it a host, port, and database
+    let redis_url = "redis://127.0.0.1:6379/0";
     let r_password = match env::var_os("REDIS_AUTH") {
         Some(password) => Some(password.into_string().unwrap()),
         None => None   
@@ -50,9 +46,9 @@ fn gdax_bench() {
         [Asset::LTC, Asset::USD],
         [Asset::BTC, Asset::USDC],
     ]);
-    gdax_settings.r = r.clone();
+    gdax_settings.redis_url = redis_url.into();
     gdax_settings.r_password = r_password.as_ref().cloned();
 
-    let exchange = thread::spawn(move || gdax_l2::WSExchange::run(Some(&gdax_settings)));
+    let exchange = thread::spawn(move || gdax_l2::WSExchange::run_with_settings(Some(&gdax_settings)));
     let _ = exchange.join();
 }
\ No newline at end of file
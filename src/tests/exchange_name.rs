@@ -0,0 +1,57 @@
+#[test]
+fn exchange_name_round_trip() {
+    use exchange::Exchange;
+
+    for exch in Exchange::all_variants() {
+        assert_eq!(exch.to_string().parse::<Exchange>(), Ok(exch));
+        assert_eq!(exch.to_string().to_uppercase().parse::<Exchange>(), Ok(exch));
+    }
+}
+
+#[test]
+fn exchange_serde_matches_name() {
+    use serde_json;
+    use strum::IntoEnumIterator;
+
+    use exchange::Exchange;
+
+    // Serialized exchanges (i.e. in trades) must use the same names as the Redis channels and databases
+    for exch in Exchange::iter() {
+        let serialized = serde_json::to_string(&exch).unwrap();
+
+        assert_eq!(serialized, format!("\"{}\"", exch));
+        assert_eq!(serde_json::from_str::<Exchange>(&serialized).unwrap(), exch);
+    }
+}
+
+#[test]
+fn exchange_name_aliases() {
+    use exchange::{self, Exchange, ExchangeParseError};
+
+    assert_eq!("Coinbase".parse::<Exchange>(), Ok(Exchange::GDAX));
+    assert_eq!("BitMEX".parse::<Exchange>(), Ok(Exchange::BitMEX));
+    assert_eq!("mtgox".parse::<Exchange>(), Err(ExchangeParseError("mtgox".into())));
+
+    assert_eq!(exchange::get_supported_exchanges(), vec!["poloniex", "gdax", "bitmex", "kraken", "binance", "okx", "bitfinex", "ftx", "deribit", "bitstamp", "bybit", "huobi", "gemini", "kucoin", "upbit", "hitbtc", "gateio", "cryptocom", "dydx", "phemex", "bittrex", "binance_futures"]);
+}
+
+#[test]
+fn exchange_all_variants() {
+    use exchange::{self, Exchange};
+
+    assert_eq!(Exchange::all_variants().len(), exchange::get_supported_exchanges().len());
+    assert_eq!(Exchange::all_variants()[0], Exchange::Poloniex);
+}
+
+#[test]
+fn bybit_market_type_hosts() {
+    use exchange::bybit::BybitMarketType;
+
+    assert_eq!(BybitMarketType::Spot.host(), "wss://stream.bybit.com/v5/public/spot");
+    assert_eq!(BybitMarketType::Linear.host(), "wss://stream.bybit.com/v5/public/linear");
+    assert_eq!(BybitMarketType::Inverse.host(), "wss://stream.bybit.com/v5/public/inverse");
+    assert_eq!(BybitMarketType::Realtime.host(), "wss://stream.bybit.com/realtime");
+
+    assert_eq!(BybitMarketType::Realtime.channels(true), vec!["orderBook_200.100ms", "trade"]);
+    assert_eq!(BybitMarketType::Linear.channels(false), vec!["orderbook.50", "publicTrade"]);
+}
@@ -0,0 +1,29 @@
+#[test]
+fn ftx_checksum_float_formatting() {
+    use exchange::ftx::checksum_float;
+
+    assert_eq!(checksum_float(5000.0), "5000.0");
+    assert_eq!(checksum_float(0.1234), "0.1234");
+    assert_eq!(checksum_float(0.00001), "1e-05");
+    assert_eq!(checksum_float(0.00000015), "1.5e-07");
+    assert_eq!(checksum_float(2e16), "2e+16");
+}
+
+#[test]
+fn ftx_checksum_interleaves_levels() {
+    use exchange::ftx::LocalBook;
+    use orderbook::{ASK, BID};
+
+    let mut book = LocalBook::default();
+
+    book.apply(BID, 5000.5, 1.0);
+    book.apply(BID, 4999.0, 0.00001);
+    book.apply(BID, 4998.0, 3.0);
+    book.apply(ASK, 5001.0, 2.5);
+    book.apply(ASK, 5002.25, 10.0);
+    book.apply(ASK, 5003.0, 1.0);
+    book.apply(BID, 4998.0, 0.0);
+
+    // "5000.5:1.0:5001.0:2.5:4999.0:1e-05:5002.25:10.0:5003.0:1.0"
+    assert_eq!(book.checksum(), 912152142);
+}
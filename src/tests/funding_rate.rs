@@ -0,0 +1,77 @@
+#[test]
+fn funding_rate_bitmex_interval() {
+    use exchange::bitmex::parse_funding_interval;
+
+    assert_eq!(parse_funding_interval("2000-01-01T08:00:00.000Z"), Some(28800.0));
+    assert_eq!(parse_funding_interval("2000-01-01T00:00:00.000Z"), Some(0.0));
+    assert_eq!(parse_funding_interval("eight hours"), None);
+}
+
+#[test]
+fn funding_rate_support() {
+    use strum::IntoEnumIterator;
+
+    use exchange::Exchange;
+
+    let supported: Vec<Exchange> = Exchange::iter()
+        .filter(|exchange| exchange.supports_funding_rates())
+        .collect();
+
+    assert_eq!(supported, vec![Exchange::BitMEX]);
+}
+
+#[test]
+fn funding_rate_serde() {
+    use serde_json;
+
+    use exchange::Exchange;
+    use orderbook::FundingRate;
+
+    let funding = FundingRate {
+        symbol: "XBTUSD".into(),
+        exchange: Exchange::BitMEX,
+        rate: -0.000375,
+        daily_rate: None,
+        next_funding_ts: 1535529600.0,
+        ts: 1535500800.0,
+    };
+
+    let json = serde_json::to_string(&funding).unwrap();
+    assert!(json.contains("\"exchange\":\"bitmex\""));
+    assert!(!json.contains("daily_rate"));
+    assert_eq!(serde_json::from_str::<FundingRate>(&json).unwrap(), funding);
+}
+
+#[test]
+fn funding_rate_bitmex_message() {
+    use std::collections::HashMap;
+    use std::sync::RwLock;
+
+    use exchange::bitmex::{parse_message, ParsedMessage, RawMessage};
+
+    let raw = RawMessage {
+        table: "funding".into(),
+        data: br#"{"table":"funding","action":"insert","data":[{"timestamp":"2018-08-29T04:00:00.000Z","symbol":"XBTUSD","fundingInterval":"2000-01-01T08:00:00.000Z","fundingRate":-0.000375,"fundingRateDaily":-0.001125}]}"#.to_vec(),
+        ts: 1535515200.0,
+        book: None,
+    };
+
+    let funding = match parse_message(&raw, &RwLock::new(HashMap::new()), &RwLock::new(HashMap::new())) {
+        Some(ParsedMessage::Funding(funding)) => funding,
+        _ => panic!("funding message wasn't parsed as funding rates"),
+    };
+
+    assert_eq!(funding.len(), 1);
+    assert_eq!(funding[0].symbol, "XBTUSD");
+    assert_eq!(funding[0].rate, -0.000375);
+    assert_eq!(funding[0].daily_rate, Some(-0.001125));
+    assert_eq!(funding[0].ts, 1535515200.0);
+    assert_eq!(funding[0].next_funding_ts, 1535544000.0);
+
+    // Stored in its own database, next to the symbol's book
+    let delta = funding[0].to_delta();
+    assert_eq!(delta.symbol, "funding_XBTUSD");
+    assert_eq!(delta.price, -0.000375f32);
+    assert_eq!(delta.size, -0.001125f32);
+    assert_eq!(delta.ts, 1535515200.0);
+}
@@ -0,0 +1,45 @@
+use serde_json;
+
+use exchange::gateio::{BookStitcher, BookUpdate, StitchGap};
+
+fn update(json: &str) -> BookUpdate {
+    serde_json::from_str(json).unwrap()
+}
+
+#[test]
+fn gateio_stitch_buffered_updates() {
+    let mut stitcher = BookStitcher::new();
+
+    // Events received before the snapshot are buffered
+    assert_eq!(stitcher.push(update(r#"{"t":1606294781123,"e":"depthUpdate","E":1606294781,"s":"BTC_USDT","U":48776301,"u":48776306,"b":[["19137.74","0.0001"]],"a":[["19137.75","0.6135"]]}"#)).unwrap().len(), 0);
+    assert_eq!(stitcher.push(update(r#"{"t":1606294781223,"e":"depthUpdate","E":1606294781,"s":"BTC_USDT","U":48776307,"u":48776310,"b":[["19137.70","0"]],"a":[]}"#)).unwrap().len(), 0);
+    assert_eq!(stitcher.push(update(r#"{"t":1606294781323,"e":"depthUpdate","E":1606294781,"s":"BTC_USDT","U":48776311,"u":48776312,"b":[],"a":[["19138.00","1.2"]]}"#)).unwrap().len(), 0);
+    assert!(!stitcher.is_seeded());
+
+    // The snapshot lands in the middle of the second event. The first event is older than the book.
+    let updates = stitcher.seed(48776308).unwrap();
+    assert_eq!(updates.len(), 2);
+    assert_eq!(updates[0].first_update_id, 48776307);
+    assert_eq!(updates[1].last_update_id, 48776312);
+    assert_eq!(updates[0].b, vec![["19137.70".to_string(), "0".to_string()]]);
+
+    // Live events apply while they're contiguous
+    assert_eq!(stitcher.push(update(r#"{"t":1606294781423,"s":"BTC_USDT","U":48776313,"u":48776313,"b":[],"a":[]}"#)).unwrap().len(), 1);
+    assert_eq!(stitcher.last_update_id, Some(48776313));
+
+    // Duplicates are dropped
+    assert_eq!(stitcher.push(update(r#"{"t":1606294781423,"s":"BTC_USDT","U":48776313,"u":48776313,"b":[],"a":[]}"#)).unwrap().len(), 0);
+
+    // A missed event is a gap
+    assert_eq!(stitcher.push(update(r#"{"t":1606294781523,"s":"BTC_USDT","U":48776315,"u":48776316,"b":[],"a":[]}"#)), Err(StitchGap));
+}
+
+#[test]
+fn gateio_stitch_stale_snapshot() {
+    let mut stitcher = BookStitcher::new();
+
+    stitcher.push(update(r#"{"t":1606294781123,"s":"BTC_USDT","U":48776301,"u":48776306,"b":[],"a":[]}"#)).unwrap();
+
+    // The snapshot is older than the first buffered event, so the events can't be stitched onto it
+    assert_eq!(stitcher.seed(48776290), Err(StitchGap));
+}
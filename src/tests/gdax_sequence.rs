@@ -0,0 +1,22 @@
+use std::collections::HashMap;
+
+use exchange::gdax_l2::is_level2_gap;
+
+#[test]
+fn gdax_level2_sequence_gap() {
+    let mut sequences = HashMap::new();
+
+    // The first update of a product starts its sequence
+    assert!(!is_level2_gap(&mut sequences, "BTC-USD", 100));
+    assert!(!is_level2_gap(&mut sequences, "BTC-USD", 101));
+    // Products are tracked separately
+    assert!(!is_level2_gap(&mut sequences, "ETH-USD", 5));
+
+    assert!(is_level2_gap(&mut sequences, "BTC-USD", 103));
+    assert!(!sequences.contains_key("BTC-USD"));
+    assert_eq!(sequences.get("ETH-USD"), Some(&5));
+
+    // The sequence starts over after a gap
+    assert!(!is_level2_gap(&mut sequences, "BTC-USD", 200));
+    assert_eq!(sequences.get("BTC-USD"), Some(&200));
+}
@@ -0,0 +1,22 @@
+#[test]
+fn hitbtc_sequence() {
+    use exchange::hitbtc::{check_sequence, SequenceCheck};
+
+    assert_eq!(check_sequence(8073827, 8073828), SequenceCheck::Apply);
+    assert_eq!(check_sequence(8073827, 8073827), SequenceCheck::Drop);
+    assert_eq!(check_sequence(8073827, 8073800), SequenceCheck::Drop);
+    assert_eq!(check_sequence(8073827, 8073830), SequenceCheck::Gap);
+}
+
+#[test]
+fn hitbtc_parse_decimal() {
+    use exchange::hitbtc::parse_decimal;
+
+    assert_eq!(parse_decimal("0.054588"), Some(0.054588));
+    assert_eq!(parse_decimal("6400.00"), Some(6400.0));
+    assert_eq!(parse_decimal("0.000"), Some(0.0));
+
+    assert_eq!(parse_decimal(""), None);
+    assert_eq!(parse_decimal("NaN"), None);
+    assert_eq!(parse_decimal("inf"), None);
+}
@@ -0,0 +1,37 @@
+#[test]
+fn huobi_mbp_sequence() {
+    use exchange::huobi::{MbpCheck, MbpSequence};
+
+    let mut sequence = MbpSequence::default();
+
+    // Updates are dropped until we have a snapshot
+    assert_eq!(sequence.check(99, 100), MbpCheck::Drop);
+
+    sequence.snapshot(100);
+    assert_eq!(sequence.check(98, 100), MbpCheck::Drop);
+    assert_eq!(sequence.check(100, 105), MbpCheck::Apply);
+    assert_eq!(sequence.check(105, 106), MbpCheck::Apply);
+
+    // Gaps invalidate the book until the next snapshot
+    assert_eq!(sequence.check(107, 110), MbpCheck::Gap);
+    assert_eq!(sequence.check(110, 111), MbpCheck::Drop);
+
+    sequence.snapshot(111);
+    assert_eq!(sequence.check(111, 112), MbpCheck::Apply);
+}
+
+#[test]
+fn huobi_depth_diff() {
+    use exchange::huobi::{diff_levels, BookChannel};
+
+    assert_eq!(BookChannel::Depth(0).channel("btcusdt"), "market.btcusdt.depth.step0");
+    assert_eq!(BookChannel::Mbp(150).channel("btcusdt"), "market.btcusdt.mbp.150");
+
+    let previous = vec![[100.0, 1.0], [99.5, 2.0], [99.0, 3.0]];
+    let current = vec![[100.0, 1.0], [99.5, 2.5], [98.5, 4.0]];
+
+    // Changed and new levels are updates, and missing levels are removals
+    assert_eq!(diff_levels(&previous, &current), vec![(99.5, 2.5), (98.5, 4.0), (99.0, 0.0)]);
+    assert_eq!(diff_levels(&[], &previous).len(), 3);
+    assert!(diff_levels(&current, &current).is_empty());
+}
@@ -0,0 +1,22 @@
+#[test]
+fn influx_line_protocol() {
+    use orderbook::{self, DeltaEvent};
+    use storage::influx::line_protocol;
+
+    let mut delta = orderbook::Delta {
+        symbol: "XBTUSD".into(),
+        price: 9000.0,
+        size: 100.0,
+        seq: 1,
+        event: DeltaEvent::BidUpdate.into(),
+        ts: 1535487251.154,
+        received_ts: None,
+    };
+
+    assert_eq!(line_protocol("bitmex", &delta),
+        "orderbook,exchange=bitmex,symbol=XBTUSD price=9000.0,size=100.0,seq=1i,event=36i 1535487251154000000");
+
+    // Tag values are escaped
+    delta.symbol = "BTC USD,1=2".into();
+    assert!(line_protocol("bitmex", &delta).starts_with("orderbook,exchange=bitmex,symbol=BTC\\ USD\\,1\\=2 "));
+}
@@ -0,0 +1,8 @@
+#[test]
+fn kafka_topic_name() {
+    use storage::kafka::{topic_name, DEFAULT_TOPIC};
+
+    assert_eq!(topic_name(DEFAULT_TOPIC, "bitmex", "XBTUSD"), "orderbook.bitmex.XBTUSD");
+    assert_eq!(topic_name("deltas", "bitmex", "XBTUSD"), "deltas");
+    assert_eq!(topic_name("{exchange}-{symbol}", "binance", "BTCUSDT"), "binance-BTCUSDT");
+}
@@ -0,0 +1,39 @@
+#[test]
+fn kraken_checksum_matches_snapshot() {
+    use exchange::kraken::LocalBook;
+    use orderbook::{ASK, BID};
+
+    let mut book = LocalBook::default();
+
+    book.apply(ASK, "5541.30000", "2.50700000");
+    book.apply(ASK, "5541.80000", "0.33000000");
+    book.apply(ASK, "5542.70000", "0.64700000");
+    book.apply(BID, "5541.20000", "1.52900000");
+    book.apply(BID, "5539.90000", "0.30000000");
+    book.apply(BID, "5539.50000", "5.00000000");
+
+    assert_eq!(book.checksum(), 1710400350);
+}
+
+#[test]
+fn kraken_checksum_after_updates() {
+    use exchange::kraken::LocalBook;
+    use orderbook::{ASK, BID};
+
+    let mut book = LocalBook::default();
+
+    book.apply(ASK, "5541.30000", "2.50700000");
+    book.apply(ASK, "5541.80000", "0.33000000");
+    book.apply(ASK, "5542.70000", "0.64700000");
+    book.apply(BID, "5541.20000", "1.52900000");
+    book.apply(BID, "5539.90000", "0.30000000");
+    book.apply(BID, "5539.50000", "5.00000000");
+
+    // Remove the best ask, insert a new best bid, and push the worst bid out of a depth of 3
+    book.apply(ASK, "5541.30000", "0.00000000");
+    book.apply(BID, "5541.25000", "1.00000000");
+    book.apply(BID, "5539.00000", "1.00000000");
+    book.truncate(3);
+
+    assert_eq!(book.checksum(), 3318863536);
+}
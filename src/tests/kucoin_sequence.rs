@@ -0,0 +1,43 @@
+#[test]
+fn kucoin_sequence_first_event_after_snapshot() {
+    use exchange::kucoin::{Level2Sequence, SequenceCheck};
+
+    let mut sequence = Level2Sequence::new(100);
+
+    // Events that end at or before the snapshot are stale
+    assert_eq!(sequence.check(90, 100), SequenceCheck::Drop);
+    // The first event may overlap the snapshot, but only its newer changes apply
+    assert_eq!(sequence.check(95, 105), SequenceCheck::Apply);
+    assert!(!sequence.applies(100));
+    assert!(sequence.applies(101));
+
+    sequence.advance(105);
+    assert_eq!(sequence.check(106, 110), SequenceCheck::Apply);
+}
+
+#[test]
+fn kucoin_sequence_gap() {
+    use exchange::kucoin::{Level2Sequence, SequenceCheck};
+
+    // Snapshot is too old for the first event we received
+    let sequence = Level2Sequence::new(100);
+    assert_eq!(sequence.check(102, 110), SequenceCheck::Gap);
+
+    let mut sequence = Level2Sequence::new(100);
+    assert_eq!(sequence.check(101, 110), SequenceCheck::Apply);
+    sequence.advance(110);
+    assert_eq!(sequence.check(112, 120), SequenceCheck::Gap);
+    assert_eq!(sequence.sequence, 110);
+}
+
+#[test]
+fn kucoin_connect_url_and_topic() {
+    use exchange::{kucoin, Asset};
+
+    assert_eq!(
+        kucoin::connect_url("wss://ws-api-spot.kucoin.com/", "abc123", 42),
+        "wss://ws-api-spot.kucoin.com?token=abc123&connectId=42");
+
+    let pairs = vec![[Asset::BTC, Asset::USDT], [Asset::ETH, Asset::USDT]];
+    assert_eq!(kucoin::topic(&pairs, "/market/level2"), "/market/level2:BTC-USDT,ETH-USDT");
+}
@@ -0,0 +1,42 @@
+#[test]
+fn market_type_channel() {
+    use exchange::{market_channel, MarketType};
+
+    assert_eq!(market_channel("bitmex", Some(MarketType::InversePerpetual)), "bitmex:inverse_perpetual");
+    assert_eq!(market_channel("binance_futures", Some(MarketType::LinearPerpetual)), "binance_futures:linear_perpetual");
+    assert_eq!(market_channel("gdax", Some(MarketType::Spot)), "gdax:spot");
+    assert_eq!(market_channel("gdax", None), "gdax");
+}
+
+#[test]
+fn market_type_serde() {
+    use serde_json;
+
+    use exchange::MarketType;
+
+    assert_eq!(serde_json::to_string(&MarketType::LinearFutures).unwrap(), "\"linear_futures\"");
+    assert_eq!(serde_json::from_str::<MarketType>("\"option\"").unwrap(), MarketType::Option);
+    assert_eq!(MarketType::InverseFutures.name(), "inverse_futures");
+}
+
+#[test]
+fn market_type_listener_channel() {
+    use listener::channel_exchange;
+
+    assert_eq!(channel_exchange("bitmex:inverse_perpetual"), "bitmex");
+    assert_eq!(channel_exchange("bitmex:inverse_perpetual:trades"), "bitmex");
+    assert_eq!(channel_exchange("gdax"), "gdax");
+}
+
+#[test]
+fn market_type_exchange_default() {
+    use exchange::{market_channel, Exchange, MarketType};
+
+    assert_eq!(Exchange::BitMEX.default_market_type(), MarketType::InversePerpetual);
+    assert_eq!(Exchange::BinanceFutures.default_market_type(), MarketType::LinearPerpetual);
+    assert_eq!(Exchange::GDAX.default_market_type(), MarketType::Spot);
+
+    // Replays default to the channel the live collector publishes on
+    let exchange = Exchange::BitMEX;
+    assert_eq!(market_channel(&exchange.to_string(), Some(exchange.default_market_type())), "bitmex:inverse_perpetual");
+}
@@ -0,0 +1,60 @@
+use std::io::Read;
+use std::sync::atomic::Ordering;
+
+use reqwest;
+
+use exchange::{ConnectionHealth, Exchange};
+use metrics::{self, MetricsServer};
+
+fn bitmex_health() -> ConnectionHealth {
+    let health = ConnectionHealth::new(Exchange::BitMEX);
+
+    health.record_reconnect();
+    health.record_reconnect();
+    health.record_symbol_message("XBTUSD");
+    health.record_symbol_message("XBTUSD");
+    health.record_published("XBTUSD", 25);
+    health.record_publish_error();
+    *health.latency_ms.lock().unwrap() = Some(12.5);
+
+    health
+}
+
+#[test]
+fn metrics_render() {
+    let gdax = ConnectionHealth::new(Exchange::GDAX);
+    let text = metrics::render(&[bitmex_health(), gdax]).unwrap();
+
+    assert!(text.contains(r#"chocolate_road_messages_total{exchange="bitmex",symbol="XBTUSD"} 2"#));
+    assert!(text.contains(r#"chocolate_road_deltas_published_total{exchange="bitmex",symbol="XBTUSD"} 25"#));
+    assert!(text.contains(r#"chocolate_road_reconnects_total{exchange="bitmex"} 2"#));
+    assert!(text.contains(r#"chocolate_road_redis_publish_errors_total{exchange="bitmex"} 1"#));
+    assert!(text.contains(r#"chocolate_road_websocket_latency_ms{exchange="bitmex"} 12.5"#));
+
+    // Connections that haven't measured their latency yet have no gauge
+    assert!(text.contains(r#"chocolate_road_reconnects_total{exchange="gdax"} 0"#));
+    assert!(!text.contains(r#"chocolate_road_websocket_latency_ms{exchange="gdax"}"#));
+}
+
+#[test]
+fn metrics_server() {
+    let health = bitmex_health();
+    let server = MetricsServer::start(0, vec![health.clone()]).unwrap();
+    let url = format!("http://127.0.0.1:{}", server.local_addr().port());
+
+    let mut body = String::new();
+    reqwest::get(&format!("{}/metrics", url)).unwrap().read_to_string(&mut body).unwrap();
+    assert!(body.contains(r#"chocolate_road_reconnects_total{exchange="bitmex"} 2"#));
+
+    // Every scrape reflects the current health
+    health.record_reconnect();
+    assert_eq!(health.reconnect_count.load(Ordering::SeqCst), 3);
+
+    let mut body = String::new();
+    reqwest::get(&format!("{}/metrics", url)).unwrap().read_to_string(&mut body).unwrap();
+    assert!(body.contains(r#"chocolate_road_reconnects_total{exchange="bitmex"} 3"#));
+
+    assert_eq!(reqwest::get(&format!("{}/health", url)).unwrap().status().as_u16(), 404);
+
+    server.stop();
+}
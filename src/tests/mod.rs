@@ -1,4 +1,62 @@
+mod asset_name;
+mod asset_pair;
+mod asset_serde;
+mod binance_sequence;
+mod bitfinex_raw_book;
+mod bitmex_auth;
+mod bitmex_book_actions;
+mod bitmex_book_tracker;
+mod bitmex_callback;
+mod bitmex_environment;
+mod bitmex_fetch_retry;
+mod bitmex_instruments;
+mod bitmex_keepalive;
+mod bitmex_liquidation;
+mod bitmex_price;
+mod bitmex_reconnect;
+mod bitmex_timestamp;
+mod bitmex_trade_detection;
+mod bitmex_worker;
+mod bittrex_signalr;
+mod bybit_book;
+mod candle_aggregator;
+mod config_file;
+mod cryptocom_book;
+mod connection_health;
+mod connection_manager;
+mod connection_shutdown;
+mod deduplication_window;
+mod delta_filter;
+mod deribit_change_id;
+mod dydx_offsets;
 mod exchange_bench;
+mod exchange_name;
+mod ftx_checksum;
+mod funding_rate;
+mod gateio_stitch;
+mod gdax_sequence;
+mod hitbtc_sequence;
+mod huobi_sequence;
+mod influx_line_protocol;
+#[cfg(feature = "kafka")]
+mod kafka_topic;
+mod kraken_checksum;
+mod kucoin_sequence;
 mod listener;
+mod market_type;
+mod metrics;
+mod okx_checksum;
 mod orderbook_state;
+mod phemex_book;
+mod poloniex_book;
+mod rate_limiter;
+mod reconnect_policy;
+mod redis_channel;
+mod redis_url;
+mod replay;
+mod sequence_gap;
+mod socket_manager;
+mod tectonic_range;
+mod tectonic_reconnect;
+mod upbit_book;
 mod uploader;
\ No newline at end of file
@@ -0,0 +1,17 @@
+#[test]
+fn okx_checksum_interleaves_levels() {
+    use exchange::okx::LocalBook;
+    use orderbook::{ASK, BID};
+
+    let mut book = LocalBook::default();
+
+    book.apply(BID, "3366.1", "7");
+    book.apply(BID, "3366", "6");
+    book.apply(BID, "3365", "2");
+    book.apply(ASK, "3366.8", "9");
+    book.apply(ASK, "3368", "8");
+    book.apply(BID, "3365", "0");
+
+    // "3366.1:7:3366.8:9:3366:6:3368:8"
+    assert_eq!(book.checksum(), -1881014294);
+}
@@ -30,19 +30,19 @@ fn orderbook_initialize() {
     new_ob.initialize(&fake_snapshot);
 
     // Orderbook state tests
-    assert_eq!(new_ob.state[604], Some(50.0));
-    assert_eq!(new_ob.state[606], Some(100.0));
-    assert_eq!(new_ob.state[608], Some(11111.0));
+    assert_eq!(new_ob.state.get(&604), Some(&50.0));
+    assert_eq!(new_ob.state.get(&606), Some(&100.0));
+    assert_eq!(new_ob.state.get(&608), Some(&11111.0));
 
-    assert!(new_ob.state[302].is_none());
-    assert!(new_ob.state[303].is_none());
-    assert!(new_ob.state[305].is_none());
-    assert!(new_ob.state[605].is_none());
-    assert!(new_ob.state[615].is_none());
+    assert!(new_ob.state.get(&302).is_none());
+    assert!(new_ob.state.get(&303).is_none());
+    assert!(new_ob.state.get(&305).is_none());
+    assert!(new_ob.state.get(&605).is_none());
+    assert!(new_ob.state.get(&615).is_none());
 
-    assert_eq!(new_ob.state[610], Some(20.5));
-    assert_eq!(new_ob.state[612], Some(1.0));
-    assert_eq!(new_ob.state[614], Some(154.25));
+    assert_eq!(new_ob.state.get(&610), Some(&20.5));
+    assert_eq!(new_ob.state.get(&612), Some(&1.0));
+    assert_eq!(new_ob.state.get(&614), Some(&154.25));
 
     assert_eq!(new_ob.best_bid, 608);
     assert_eq!(new_ob.best_bid_size, 11111.0);
@@ -63,8 +63,8 @@ fn orderbook_initialize() {
     assert_eq!(new_ob.best_ask, (306.0 / new_ob.tick_size) as u64); // new updated best ask
 
     // Use a negative to force a failed test in the case that the best bid/ask sizes didn't get updated
-    assert_eq!(new_ob.best_bid_size, new_ob.state[(304.5 / new_ob.tick_size) as usize].unwrap_or(-1.0));
-    assert_eq!(new_ob.best_ask_size, new_ob.state[(306.0 / new_ob.tick_size) as usize].unwrap_or(-1.0));
+    assert_eq!(new_ob.best_bid_size, new_ob.state.get(&((304.5 / new_ob.tick_size) as u64)).cloned().unwrap_or(-1.0));
+    assert_eq!(new_ob.best_ask_size, new_ob.state.get(&((306.0 / new_ob.tick_size) as u64)).cloned().unwrap_or(-1.0));
 
     let orders = vec![
         ((304.5 / new_ob.tick_size) as u64, 0.00, true),    // Void the best bid
@@ -79,8 +79,8 @@ fn orderbook_initialize() {
 
     // Assert that the best bid is the one it previously was when we first initialized it all,
     // and also that our ask has also been updated to the previous bid
-    assert_eq!(new_ob.best_bid_size, new_ob.state[(304.0 / new_ob.tick_size) as usize].unwrap_or(-1.0));
-    assert_eq!(new_ob.best_ask_size, new_ob.state[(304.5 / new_ob.tick_size) as usize].unwrap_or(-1.0));
+    assert_eq!(new_ob.best_bid_size, new_ob.state.get(&((304.0 / new_ob.tick_size) as u64)).cloned().unwrap_or(-1.0));
+    assert_eq!(new_ob.best_ask_size, new_ob.state.get(&((304.5 / new_ob.tick_size) as u64)).cloned().unwrap_or(-1.0));
 
     // And finally, one last go around just to be sure I didn't cheat around the tests
 
@@ -97,6 +97,211 @@ fn orderbook_initialize() {
 
     // Assert that the best bid is the one it previously was when we first initialized it all,
     // and also that our ask has also been updated to the previous bid
-    assert_eq!(new_ob.best_bid_size, new_ob.state[(303.0 / new_ob.tick_size) as usize].unwrap_or(-1.0));
-    assert_eq!(new_ob.best_ask_size, new_ob.state[(304.0 / new_ob.tick_size) as usize].unwrap_or(-1.0));
+    assert_eq!(new_ob.best_bid_size, new_ob.state.get(&((303.0 / new_ob.tick_size) as u64)).cloned().unwrap_or(-1.0));
+    assert_eq!(new_ob.best_ask_size, new_ob.state.get(&((304.0 / new_ob.tick_size) as u64)).cloned().unwrap_or(-1.0));
+}
+
+#[test]
+fn delta_event_round_trip() {
+    use orderbook::{self, DeltaEvent};
+
+    for event in vec![DeltaEvent::BidUpdate, DeltaEvent::AskUpdate, DeltaEvent::BidRemove,
+                      DeltaEvent::AskRemove, DeltaEvent::BidTrade, DeltaEvent::AskTrade] {
+        assert_eq!(DeltaEvent::from_bits(event.into()), Some(event));
+    }
+
+    // Matches the flags existing consumers already decode
+    assert_eq!(u8::from(DeltaEvent::BidTrade), orderbook::BID ^ orderbook::TRADE);
+    assert!(DeltaEvent::BidTrade.is_bid() && DeltaEvent::BidTrade.is_trade());
+
+    // Ambiguous combinations are rejected instead of silently picking a side
+    assert_eq!(DeltaEvent::from_bits(orderbook::BID ^ orderbook::ASK ^ orderbook::UPDATE), None);
+    assert_eq!(DeltaEvent::from_bits(orderbook::BID ^ orderbook::TRADE ^ orderbook::UPDATE), None);
+    assert_eq!(DeltaEvent::from_bits(orderbook::UPDATE), None);
+}
+
+#[test]
+fn orderbook_apply_deltas() {
+    use orderbook::{self, DeltaEvent};
+
+    let delta = |price: f32, size: f32, event: DeltaEvent| orderbook::Delta {
+        symbol: "BTCUSD".into(),
+        price,
+        size,
+        seq: 0,
+        event: event.into(),
+        ts: 0.0,
+        received_ts: None,
+    };
+
+    let mut book = orderbook::Book {
+        tick_size: 0.5,
+        ..Default::default()
+    };
+
+    assert!(book.best_bid().is_none());
+    assert!(book.best_ask().is_none());
+
+    // Snapshot
+    for update in vec![
+        delta(302.0, 50.0, DeltaEvent::BidUpdate),
+        delta(303.0, 100.0, DeltaEvent::BidUpdate),
+        delta(304.0, 11111.0, DeltaEvent::BidUpdate),
+        delta(305.0, 20.5, DeltaEvent::AskUpdate),
+        delta(306.0, 1.0, DeltaEvent::AskUpdate),
+        delta(307.0, 154.25, DeltaEvent::AskUpdate),
+    ] {
+        book.apply(&update);
+    }
+
+    assert_eq!(book.best_bid(), Some((304.0, 11111.0)));
+    assert_eq!(book.best_ask(), Some((305.0, 20.5)));
+
+    // New best bid, removal of the best ask by size, and removal of a bid by event
+    book.apply(&delta(304.5, 400.5, DeltaEvent::BidUpdate));
+    book.apply(&delta(305.0, 0.0, DeltaEvent::AskUpdate));
+    book.apply(&delta(302.0, 50.0, DeltaEvent::BidRemove));
+    // Trades leave the resting levels alone
+    book.apply(&delta(306.0, 1.0, DeltaEvent::AskTrade));
+
+    assert_eq!(book.best_bid(), Some((304.5, 400.5)));
+    assert_eq!(book.best_ask(), Some((306.0, 1.0)));
+
+    let (bids, asks) = book.depth(10);
+    assert_eq!(bids, vec![(304.5, 400.5), (304.0, 11111.0), (303.0, 100.0)]);
+    assert_eq!(asks, vec![(306.0, 1.0), (307.0, 154.25)]);
+
+    let (bids, asks) = book.depth(1);
+    assert_eq!(bids.len() + asks.len(), 2);
+
+    // Removing the last ask empties that side of the book
+    book.apply(&delta(306.0, 0.0, DeltaEvent::AskRemove));
+    book.apply(&delta(307.0, 0.0, DeltaEvent::AskUpdate));
+    assert!(book.best_ask().is_none());
+}
+
+#[test]
+fn orderbook_apply_high_price() {
+    use orderbook::{self, DeltaEvent};
+
+    // At the default tick size, BTC's price is hundreds of millions of ticks away from zero
+    let mut book = orderbook::Book { ..Default::default() };
+
+    book.apply(&orderbook::Delta {
+        symbol: "XBTUSD".into(),
+        price: 60000.0,
+        size: 2.5,
+        seq: 0,
+        event: DeltaEvent::BidUpdate.into(),
+        ts: 0.0,
+        received_ts: None,
+    });
+
+    // Only the level itself is stored
+    assert_eq!(book.state.len(), 1);
+
+    let (price, size) = book.best_bid().unwrap();
+    assert!((price - 60000.0).abs() < 0.01);
+    assert_eq!(size, 2.5);
+}
+
+#[test]
+fn orderbook_checksum() {
+    use orderbook;
+
+    let mut book = orderbook::Book {
+        tick_size: 0.5,
+        ..Default::default()
+    };
+
+    book.initialize(&orderbook::Snapshot {
+        market: None,
+        asset: None,
+
+        bids: vec![(303.0, 100.0), (304.0, 11111.0)],
+        asks: vec![(305.0, 20.5), (306.0, 1.0)],
+    });
+
+    // crc32("304:11111:305:20.5:303:100:306:1")
+    assert_eq!(book.checksum(), 3071383128);
+    assert!(book.verify_checksum(3071383128));
+    assert!(!book.verify_checksum(0));
+}
+
+#[test]
+fn level2_orderbook_apply() {
+    use exchange::Exchange;
+    use orderbook::{self, DeltaEvent, Level2Orderbook};
+
+    let delta = |price: f32, size: f32, event: u8| orderbook::Delta {
+        symbol: "XBTUSD".into(),
+        price,
+        size,
+        seq: 0,
+        event,
+        ts: 0.0,
+        received_ts: None,
+    };
+
+    let mut book = Level2Orderbook::new("XBTUSD", Exchange::BitMEX);
+
+    assert!(book.best_bid().is_none());
+    assert!(book.spread().is_none());
+
+    book.apply(&delta(6400.0, 10.0, orderbook::BID | orderbook::UPDATE));
+    book.apply(&delta(6400.5, 5.0, orderbook::BID | orderbook::UPDATE));
+    book.apply(&delta(6401.0, 7.0, orderbook::ASK | orderbook::UPDATE));
+    book.apply(&delta(6402.0, 3.0, orderbook::ASK | orderbook::INSERT));
+
+    assert_eq!(book.best_bid(), Some((6400.5, 5.0)));
+    assert_eq!(book.best_ask(), Some((6401.0, 7.0)));
+    assert_eq!(book.spread(), Some(0.5));
+
+    // Trades leave the book alone
+    book.apply(&delta(6401.0, 7.0, DeltaEvent::AskTrade.into()));
+    assert_eq!(book.best_ask(), Some((6401.0, 7.0)));
+
+    // Removal by size and by event
+    book.apply(&delta(6400.5, 0.0, orderbook::BID | orderbook::UPDATE));
+    book.apply(&delta(6401.0, 7.0, orderbook::ASK | orderbook::REMOVE));
+
+    let (bids, asks) = book.depth(5);
+    assert_eq!(bids, vec![(6400.0, 10.0)]);
+    assert_eq!(asks, vec![(6402.0, 3.0)]);
+    assert_eq!(book.spread(), Some(2.0));
+}
+
+#[test]
+fn level2_orderbook_snapshot() {
+    use serde_json;
+
+    use exchange::Exchange;
+    use orderbook::{self, Level2Orderbook, Level2Snapshot};
+
+    let mut book = Level2Orderbook::new("XBTUSD", Exchange::BitMEX);
+
+    for &(price, size, side) in &[(6400.0, 10.0, orderbook::BID), (6400.5, 5.0, orderbook::BID), (6402.0, 3.0, orderbook::ASK), (6401.0, 7.0, orderbook::ASK)] {
+        book.apply(&orderbook::Delta {
+            symbol: "XBTUSD".into(),
+            price,
+            size,
+            seq: 0,
+            event: side | orderbook::INSERT,
+            ts: 0.0,
+            received_ts: None,
+        });
+    }
+
+    let snapshot = book.snapshot(1536000000.0);
+    assert_eq!(snapshot, Level2Snapshot {
+        bids: vec![(6400.5, 5.0), (6400.0, 10.0)],
+        asks: vec![(6401.0, 7.0), (6402.0, 3.0)],
+        symbol: "XBTUSD".into(),
+        exchange: Exchange::BitMEX,
+        ts: 1536000000.0,
+    });
+
+    // Consumers rebuild the book from the snapshot alone
+    let rebuilt: Level2Snapshot = serde_json::from_str(&serde_json::to_string(&snapshot).unwrap()).unwrap();
+    assert_eq!(rebuilt, snapshot);
 }
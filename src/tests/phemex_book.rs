@@ -0,0 +1,41 @@
+use serde_json;
+
+use exchange::phemex::{book_to_deltas, unscale_price, BookMessage};
+use orderbook;
+
+#[test]
+fn phemex_unscale_price() {
+    assert_eq!(unscale_price(87_135_000), 8713.5);
+    assert_eq!(unscale_price(10_000), 1.0);
+    assert_eq!(unscale_price(0), 0.0);
+}
+
+#[test]
+fn phemex_book_to_deltas() {
+    let book: BookMessage = serde_json::from_str(r#"{
+        "book": {
+            "asks": [[87135000, 2000]],
+            "bids": [[87130000, 0], [87125000, 150]]
+        },
+        "depth": 30,
+        "sequence": 1678643,
+        "symbol": "BTCUSD",
+        "timestamp": 1590269939965000000,
+        "type": "incremental"
+    }"#).unwrap();
+
+    assert_eq!(book.sequence, 1678643);
+
+    let deltas = book_to_deltas(&book);
+    assert_eq!(deltas.len(), 3);
+
+    assert_eq!(deltas[0].price, 8713.5);
+    assert_eq!(deltas[0].size, 2000.0);
+    assert_eq!(deltas[0].event, orderbook::ASK ^ orderbook::UPDATE);
+
+    assert_eq!(deltas[1].price, 8713.0);
+    assert_eq!(deltas[1].event, orderbook::BID ^ orderbook::REMOVE);
+
+    assert_eq!(deltas[2].seq, 3);
+    assert!((deltas[2].ts - 1590269939.965).abs() < 0.001);
+}
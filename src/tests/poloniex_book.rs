@@ -0,0 +1,44 @@
+use std::collections::HashMap;
+
+use serde_json::{self, Value};
+
+use exchange::poloniex::parse_updates;
+use orderbook::{self, TradeSide};
+
+fn updates(json: &str) -> Vec<Value> {
+    serde_json::from_str(json).unwrap()
+}
+
+#[test]
+fn poloniex_parses_book_and_trades() {
+    let mut channel_symbols = HashMap::new();
+
+    // Updates of a channel we haven't received the snapshot of are skipped
+    let (deltas, trades) = parse_updates(&updates(r#"[["o", 1, "0.0750", "1.5"]]"#), 148, 1, 1.0, &mut channel_symbols);
+    assert!(deltas.is_empty() && trades.is_empty());
+
+    let snapshot = updates(r#"[["i", {"currencyPair": "USDT_BTC", "orderBook": [{"6501.0": "0.5"}, {"6500.0": "2.0", "6499.5": "1.0"}]}]]"#);
+    let (deltas, _) = parse_updates(&snapshot, 148, 2, 1.0, &mut channel_symbols);
+
+    assert_eq!(channel_symbols.get(&148).map(|symbol| symbol.as_str()), Some("USDT-BTC"));
+    assert_eq!(deltas.len(), 3);
+    assert_eq!(deltas.iter().filter(|delta| delta.event == orderbook::ASK | orderbook::UPDATE).count(), 1);
+    assert_eq!(deltas.iter().filter(|delta| delta.event == orderbook::BID | orderbook::UPDATE).count(), 2);
+
+    let (deltas, trades) = parse_updates(
+        &updates(r#"[["o", 1, "6500.0", "0.00000000"], ["o", 0, "6502.0", "3.0"], ["t", "42", 0, "6500.0", "0.25", 1535500000]]"#),
+        148, 3, 2.0, &mut channel_symbols);
+
+    assert_eq!(deltas.len(), 2);
+    assert_eq!(deltas[0].symbol, "USDT-BTC");
+    assert_eq!(deltas[0].event, orderbook::BID | orderbook::REMOVE);
+    assert_eq!(deltas[1].event, orderbook::ASK | orderbook::UPDATE);
+    assert_eq!(deltas[1].price, 6502.0);
+    assert_eq!(deltas[1].seq, 3);
+
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0].side, TradeSide::Sell);
+    assert_eq!(trades[0].size, 0.25);
+    assert_eq!(trades[0].ts, 1535500000.0);
+    assert_eq!(trades[0].trade_id.as_ref().map(|id| id.as_str()), Some("42"));
+}
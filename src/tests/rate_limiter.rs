@@ -0,0 +1,40 @@
+#[test]
+fn rate_limiter_burst() {
+    use std::time::{Duration, Instant};
+
+    use exchange::RateLimiter;
+
+    let start = Instant::now();
+    let mut limiter = RateLimiter::new(2.0, 3);
+
+    // The bucket starts full
+    for _ in 0..3 {
+        assert_eq!(limiter.acquire_at(start), Duration::from_secs(0));
+    }
+
+    // Then requests wait for their token, one after the other
+    assert_eq!(limiter.acquire_at(start), Duration::from_millis(500));
+    assert_eq!(limiter.acquire_at(start), Duration::from_millis(1_000));
+}
+
+#[test]
+fn rate_limiter_refill() {
+    use std::time::{Duration, Instant};
+
+    use exchange::RateLimiter;
+
+    let start = Instant::now();
+    let mut limiter = RateLimiter::new(2.0, 2);
+
+    limiter.acquire_at(start);
+    limiter.acquire_at(start);
+
+    // Half a second refills one token
+    assert_eq!(limiter.acquire_at(start + Duration::from_millis(500)), Duration::from_secs(0));
+
+    // The bucket never holds more than `burst` tokens, however long it sits idle
+    let later = start + Duration::from_secs(60);
+    assert_eq!(limiter.acquire_at(later), Duration::from_secs(0));
+    assert_eq!(limiter.acquire_at(later), Duration::from_secs(0));
+    assert_eq!(limiter.acquire_at(later), Duration::from_millis(500));
+}
@@ -0,0 +1,53 @@
+#[test]
+fn reconnect_policy_backoff() {
+    use std::time::Duration;
+
+    use exchange::ReconnectPolicy;
+
+    let policy = ReconnectPolicy {
+        base_delay_ms: 500,
+        max_delay_ms: 4_000,
+        jitter: false,
+        ..Default::default()
+    };
+
+    assert_eq!(policy.delay(0), Duration::from_millis(500));
+    assert_eq!(policy.delay(1), Duration::from_millis(1_000));
+    assert_eq!(policy.delay(3), Duration::from_millis(4_000));
+    // Capped, even when the multiplier would overflow
+    assert_eq!(policy.delay(4), Duration::from_millis(4_000));
+    assert_eq!(policy.delay(100), Duration::from_millis(4_000));
+
+    // Jitter keeps the delay between half and all of its value
+    let policy = ReconnectPolicy {
+        jitter: true,
+        ..policy
+    };
+
+    for attempt in 0..8 {
+        let delay = policy.delay(attempt);
+        let expected = ReconnectPolicy { jitter: false, ..policy.clone() }.delay(attempt);
+
+        assert!(delay >= expected / 2 && delay <= expected);
+    }
+}
+
+#[test]
+fn reconnect_policy_limits() {
+    use std::time::Duration;
+
+    use exchange::ReconnectPolicy;
+
+    let policy = ReconnectPolicy {
+        max_attempts: Some(3),
+        reset_after_ms: 10_000,
+        ..Default::default()
+    };
+
+    assert!(!policy.exhausted(2));
+    assert!(policy.exhausted(3));
+    assert!(!ReconnectPolicy::default().exhausted(u32::max_value()));
+
+    assert!(!policy.is_stable(Duration::from_millis(9_999)));
+    assert!(policy.is_stable(Duration::from_secs(10)));
+}
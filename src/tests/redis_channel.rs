@@ -0,0 +1,116 @@
+use std::cell::RefCell;
+use std::sync::atomic::Ordering;
+
+use redis::{ConnectionLike, ErrorKind, RedisError, RedisResult, Value};
+
+use exchange::{publish, publish_deltas, publish_trades, redis_channel_name, ConnectionHealth, Exchange};
+use orderbook;
+
+/// Records the commands sent to it instead of sending them to a server
+#[derive(Default)]
+struct RecordingConnection {
+    commands: RefCell<Vec<String>>,
+}
+
+impl ConnectionLike for RecordingConnection {
+    fn req_packed_command(&self, cmd: &[u8]) -> RedisResult<Value> {
+        self.commands.borrow_mut().push(String::from_utf8_lossy(cmd).into_owned());
+
+        Ok(Value::Int(1))
+    }
+
+    fn req_packed_commands(&self, _: &[u8], _: usize, _: usize) -> RedisResult<Vec<Value>> {
+        Ok(vec![])
+    }
+
+    fn get_db(&self) -> i64 {
+        0
+    }
+}
+
+/// Refuses every command, like a connection to a Redis server that went away
+struct BrokenConnection;
+
+impl ConnectionLike for BrokenConnection {
+    fn req_packed_command(&self, _: &[u8]) -> RedisResult<Value> {
+        Err(RedisError::from((ErrorKind::IoError, "Connection reset")))
+    }
+
+    fn req_packed_commands(&self, _: &[u8], _: usize, _: usize) -> RedisResult<Vec<Value>> {
+        Err(RedisError::from((ErrorKind::IoError, "Connection reset")))
+    }
+
+    fn get_db(&self) -> i64 {
+        0
+    }
+}
+
+fn delta(symbol: &str, price: f32) -> orderbook::Delta {
+    orderbook::Delta {
+        symbol: symbol.into(),
+        price,
+        size: 100.0,
+        seq: 0,
+        event: orderbook::BID ^ orderbook::UPDATE,
+        ts: 1536000000.0,
+        received_ts: None,
+    }
+}
+
+#[test]
+fn redis_channel_configured_name() {
+    let r = RecordingConnection::default();
+    let deltas = vec![delta("XBTUSD", 6400.5), delta("ETHUSD", 210.05)];
+
+    publish(&r, "collector-1", &deltas, |delta| delta.symbol.as_str()).unwrap();
+
+    // Without a placeholder, every delta goes out in a single message
+    let commands = r.commands.borrow();
+    assert_eq!(commands.len(), 1);
+    assert!(commands[0].contains("PUBLISH"));
+    assert!(commands[0].contains("\r\ncollector-1\r\n"));
+    assert!(commands[0].contains("XBTUSD") && commands[0].contains("ETHUSD"));
+}
+
+#[test]
+fn redis_channel_per_symbol() {
+    let r = RecordingConnection::default();
+    let deltas = vec![delta("XBTUSD", 6400.5), delta("ETHUSD", 210.05), delta("XBTUSD", 6401.0)];
+
+    publish(&r, "bitmex:{symbol}", &deltas, |delta| delta.symbol.as_str()).unwrap();
+
+    let commands = r.commands.borrow();
+    assert_eq!(commands.len(), 2);
+    assert!(commands[0].contains("\r\nbitmex:XBTUSD\r\n"));
+    assert!(commands[0].contains("6401") && !commands[0].contains("ETHUSD"));
+    assert!(commands[1].contains("\r\nbitmex:ETHUSD\r\n"));
+
+    assert_eq!(redis_channel_name("bitmex", "XBTUSD"), "bitmex");
+    assert_eq!(redis_channel_name("bitmex:{symbol}:trades", "XBTUSD"), "bitmex:XBTUSD:trades");
+}
+
+#[test]
+fn redis_channel_publish_counts() {
+    let r = RecordingConnection::default();
+    let health = ConnectionHealth::new(Exchange::Binance);
+    let deltas = vec![delta("BTCUSDT", 6400.5), delta("ETHUSDT", 210.05), delta("BTCUSDT", 6401.0)];
+
+    publish_deltas(&r, "binance:spot", &deltas, &health);
+
+    let symbols = health.symbols.lock().unwrap();
+    assert_eq!(symbols["BTCUSDT"].deltas_published, 2);
+    assert_eq!(symbols["ETHUSDT"].deltas_published, 1);
+    assert_eq!(health.redis_publish_errors.load(Ordering::SeqCst), 0);
+}
+
+#[test]
+fn redis_channel_publish_error() {
+    let health = ConnectionHealth::new(Exchange::Binance);
+
+    // A failed publish is counted instead of panicking, and nothing is counted as published
+    publish_deltas(&BrokenConnection, "binance:spot", &[delta("BTCUSDT", 6400.5)], &health);
+    publish_trades(&BrokenConnection, "binance:spot:trades", &[], &health);
+
+    assert_eq!(health.redis_publish_errors.load(Ordering::SeqCst), 1);
+    assert!(health.symbols.lock().unwrap().is_empty());
+}
@@ -0,0 +1,13 @@
+#[test]
+fn redis_url_password() {
+    use exchange::redis_connection_url;
+
+    assert_eq!(redis_connection_url("redis://127.0.0.1:6379/0", None).unwrap(), "redis://127.0.0.1:6379/0");
+    assert_eq!(redis_connection_url("redis://127.0.0.1:6379/2", Some("hunter2")).unwrap(), "redis://:hunter2@127.0.0.1:6379/2");
+
+    // The configured password wins over the one in the URL
+    assert_eq!(redis_connection_url("redis://:old@10.0.0.5:6380/1", Some("new")).unwrap(), "redis://:new@10.0.0.5:6380/1");
+    assert_eq!(redis_connection_url("redis://:old@10.0.0.5:6380/1", None).unwrap(), "redis://:old@10.0.0.5:6380/1");
+
+    assert!(redis_connection_url("localhost:6379", Some("hunter2")).is_err());
+}
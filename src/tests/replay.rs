@@ -0,0 +1,45 @@
+use std::time::Duration;
+
+use orderbook::{self, Delta};
+use replay::schedule;
+
+fn delta(price: f32, ts: f64) -> Delta {
+    Delta {
+        symbol: "XBTUSD".into(),
+        price,
+        size: 100.0,
+        seq: 0,
+        event: orderbook::BID | orderbook::UPDATE,
+        ts,
+        received_ts: None,
+    }
+}
+
+#[test]
+fn replay_schedule() {
+    let deltas = vec![
+        delta(6501.0, 1536000002.0),
+        delta(6500.0, 1536000000.0),
+        delta(6500.5, 1536000000.0),
+        delta(6502.0, 1536000000.5),
+    ];
+
+    let batches = schedule(deltas.clone(), 1.0);
+
+    // Deltas sharing a timestamp are published together, in the order they were stored in
+    assert_eq!(batches.len(), 3);
+    assert_eq!(batches[0].0, Duration::from_secs(0));
+    assert_eq!(batches[0].1.iter().map(|delta| delta.price).collect::<Vec<f32>>(), vec![6500.0, 6500.5]);
+    assert_eq!(batches[1].0, Duration::from_millis(500));
+    assert_eq!(batches[2].0, Duration::from_millis(1500));
+    assert_eq!(batches[2].1[0].price, 6501.0);
+
+    // Ten times faster
+    let batches = schedule(deltas.clone(), 10.0);
+    assert_eq!(batches[1].0, Duration::from_millis(50));
+    assert_eq!(batches[2].0, Duration::from_millis(150));
+
+    // As fast as possible
+    let batches = schedule(deltas, ::std::f64::INFINITY);
+    assert!(batches.iter().all(|&(wait, _)| wait == Duration::from_secs(0)));
+}
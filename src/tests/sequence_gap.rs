@@ -0,0 +1,17 @@
+#[test]
+fn sequence_gap() {
+    use exchange;
+
+    assert!(!exchange::is_sequence_gap(100, 101));
+    // Repeated and stale messages aren't gaps
+    assert!(!exchange::is_sequence_gap(100, 100));
+    assert!(!exchange::is_sequence_gap(100, 50));
+
+    assert!(exchange::is_sequence_gap(100, 102));
+    assert!(exchange::is_sequence_gap(0, 2));
+
+    // Nothing follows the largest sequence number, so it can't overflow into a gap
+    assert!(!exchange::is_sequence_gap(u64::max_value(), u64::max_value()));
+    assert!(!exchange::is_sequence_gap(u64::max_value(), 0));
+    assert!(exchange::is_sequence_gap(u64::max_value() - 2, u64::max_value()));
+}
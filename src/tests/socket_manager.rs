@@ -0,0 +1,68 @@
+#[test]
+fn handoff_dedup() {
+    use exchange::bitmex::HandoffDedup;
+    use orderbook::{self, DeltaEvent};
+
+    let delta = |price: f32, ts: f64| orderbook::Delta {
+        symbol: "XBTUSD".into(),
+        price,
+        size: 100.0,
+        seq: 0,
+        event: DeltaEvent::BidUpdate.into(),
+        ts,
+        received_ts: None,
+    };
+
+    let mut dedup = HandoffDedup::default();
+
+    // Everything goes through outside of a handoff
+    assert!(!dedup.active());
+    assert!(dedup.is_new(&delta(6500.0, 1.0)));
+    assert!(dedup.is_new(&delta(6500.0, 1.0)));
+
+    // Both connections deliver the same deltas while they overlap
+    dedup.begin();
+    assert!(dedup.active());
+    assert!(dedup.is_new(&delta(6500.0, 2.0)));
+    assert!(dedup.is_new(&delta(6500.5, 2.0)));
+    assert!(!dedup.is_new(&delta(6500.0, 2.0)));
+    assert!(!dedup.is_new(&delta(6500.5, 2.0)));
+    assert!(dedup.is_new(&orderbook::Delta { size: 200.0, ..delta(6500.0, 2.0) }));
+
+    dedup.end();
+    assert!(dedup.is_new(&delta(6500.0, 2.0)));
+}
+
+#[test]
+fn handoff_dedup_receive_time() {
+    use exchange::bitmex::HandoffDedup;
+    use orderbook::{self, DeltaEvent};
+
+    // Untimestamped book updates keep the time each connection received them
+    let received = |ts: f64| orderbook::Delta {
+        symbol: "XBTUSD".into(),
+        price: 6500.0,
+        size: 100.0,
+        seq: 0,
+        event: DeltaEvent::AskUpdate.into(),
+        ts,
+        received_ts: None,
+    };
+    // Whereas timestamped ones carry BitMEX's time, along with the time we received them
+    let timestamped = |ts: f64, received_ts: f64| orderbook::Delta {
+        received_ts: Some(received_ts),
+        ..received(ts)
+    };
+
+    let mut dedup = HandoffDedup::default();
+    dedup.begin();
+
+    // The same update, received by both connections at different times
+    assert!(dedup.is_new(&received(1536000000.125)));
+    assert!(!dedup.is_new(&received(1536000000.250)));
+
+    // BitMEX's timestamp tells genuine repeats apart, whenever the connections received them
+    assert!(dedup.is_new(&timestamped(1536000001.0, 1536000001.1)));
+    assert!(!dedup.is_new(&timestamped(1536000001.0, 1536000001.2)));
+    assert!(dedup.is_new(&timestamped(1536000002.0, 1536000002.1)));
+}
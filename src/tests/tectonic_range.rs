@@ -0,0 +1,53 @@
+use orderbook::{self, DeltaEvent};
+use orderbook::tectonic::{parse_rows, TectonicError};
+
+#[test]
+fn tectonic_parse_rows() {
+    let response = r#"[{"ts":1505177459.685,"seq":139010,"is_trade":false,"is_bid":true,"price":0.0703620,"size":7.65064240},
+        {"ts":1505177459.686,"seq":139011,"is_trade":false,"is_bid":false,"price":0.0703630,"size":0.0},
+        {"ts":1505177459.700,"seq":139012,"is_trade":true,"is_bid":false,"price":0.0703630,"size":1.5}]"#;
+
+    let deltas = parse_rows("XBTUSD", &format!("{}\n\0\0", response)).unwrap();
+
+    assert_eq!(deltas.len(), 3);
+    assert_eq!(deltas[0].symbol, "XBTUSD");
+    assert_eq!(deltas[0].seq, 139010);
+    assert_eq!(deltas[0].event_kind(), Some(DeltaEvent::BidUpdate));
+    assert_eq!(deltas[1].event_kind(), Some(DeltaEvent::AskRemove));
+    assert_eq!(deltas[2].event, orderbook::ASK | orderbook::TRADE);
+
+    assert!(parse_rows("XBTUSD", "").unwrap().is_empty());
+
+    match parse_rows("XBTUSD", "ERR: DB bitmex_XBTUSD not found") {
+        Err(TectonicError::InvalidResponse(_)) => (),
+        other => panic!("Expected an invalid response, got {:?}", other),
+    }
+}
+
+#[cfg(feature = "tectonic-integration")]
+#[test]
+fn tectonic_get_range() {
+    use chrono::prelude::*;
+    use orderbook::Delta;
+    use orderbook::tectonic::TectonicConnection;
+
+    let mut connection = TectonicConnection::new(None, None).expect("No TectonicDB server on localhost:9001");
+    let db = format!("test_RANGE{}", Utc::now().timestamp());
+    connection.create(db.clone()).unwrap();
+
+    let deltas: Vec<Delta> = (0..3)
+        .map(|i| Delta {
+            symbol: "RANGE".into(),
+            price: 6400.0 + i as f32,
+            size: 1.0,
+            seq: i,
+            event: orderbook::BID | orderbook::UPDATE,
+            ts: 1_500_000_000.0 + i as f64 * 10.0,
+            received_ts: None,
+        })
+        .collect();
+    connection.insert_batch(&db, &deltas).unwrap();
+
+    let range = connection.get_range(&db, 1_500_000_005.0, 1_500_000_020.0).unwrap();
+    assert_eq!(range.iter().map(|delta| delta.seq).collect::<Vec<_>>(), vec![1, 2]);
+}
@@ -0,0 +1,40 @@
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::TcpListener;
+use std::thread;
+
+use orderbook::tectonic::{is_disconnect, TectonicConnection};
+
+#[test]
+fn tectonic_reconnects_after_server_restart() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    // Every connection answers a single command and is then closed, as if the server had restarted
+    let server = thread::spawn(move || {
+        for response in &["1\n", "2\n"] {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 64];
+            let _ = stream.read(&mut buf);
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+
+    let mut connection = TectonicConnection::new(Some("127.0.0.1".into()), Some(port)).unwrap();
+    assert!(connection.is_connected());
+
+    assert!(connection.cmd("PING".into()).unwrap().starts_with('1'));
+    assert!(connection.cmd("PING".into()).unwrap().starts_with('2'));
+    assert!(connection.is_connected());
+
+    server.join().unwrap();
+}
+
+#[test]
+fn tectonic_is_disconnect() {
+    assert!(is_disconnect(&Error::new(ErrorKind::BrokenPipe, "")));
+    assert!(is_disconnect(&Error::new(ErrorKind::ConnectionReset, "")));
+    assert!(is_disconnect(&Error::new(ErrorKind::UnexpectedEof, "")));
+
+    assert!(!is_disconnect(&Error::new(ErrorKind::TimedOut, "")));
+    assert!(!is_disconnect(&Error::new(ErrorKind::InvalidData, "")));
+}
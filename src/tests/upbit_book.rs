@@ -0,0 +1,31 @@
+#[test]
+fn upbit_book_diff() {
+    use serde_json;
+
+    use exchange::upbit::{LocalBook, OrderbookUnit};
+
+    let units: Vec<OrderbookUnit> = serde_json::from_str(r#"[
+        {"ask_price": 8150000.0, "bid_price": 8149000.0, "ask_size": 0.5, "bid_size": 1.25},
+        {"ask_price": 8151000.0, "bid_price": 8148000.0, "ask_size": 2.0, "bid_size": 0.75}
+    ]"#).unwrap();
+
+    let mut book = LocalBook::default();
+
+    // The first snapshot is published in full
+    let (asks, bids) = book.update(&units);
+    assert_eq!(asks.len(), 2);
+    assert_eq!(bids.len(), 2);
+
+    // Resending the same book doesn't produce any changes
+    let (asks, bids) = book.update(&units);
+    assert!(asks.is_empty() && bids.is_empty());
+
+    // The best ask resized, and the second bid level was replaced by a new one
+    let mut next = units.clone();
+    next[0].ask_size = 0.25;
+    next[1].bid_price = 8147000.0;
+
+    let (asks, bids) = book.update(&next);
+    assert_eq!(asks, vec![(8150000.0, 0.25)]);
+    assert_eq!(bids, vec![(8147000.0, 0.75), (8148000.0, 0.0)]);
+}